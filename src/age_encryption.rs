@@ -0,0 +1,72 @@
+use age::secrecy::SecretString;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// Every binary age file starts with this line
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Whether `data` is an age file, as opposed to the two-round AES format.
+pub fn is_age(data: &[u8]) -> bool {
+    data.starts_with(AGE_MAGIC)
+}
+
+/// Encrypts to the given X25519 recipients (`age1...`), or to the pack
+/// passphrase when there are none. The result is a standard age file, so
+/// `age -d` can decrypt it without this tool.
+pub fn encrypt(
+    recipients: &[String],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let encryptor = if recipients.is_empty() {
+        let passphrase = crate::passphrase::pack_passphrase()?;
+        age::Encryptor::with_user_passphrase(SecretString::from(passphrase.to_string()))
+    } else {
+        let parsed = recipients
+            .iter()
+            .map(|recipient| {
+                age::x25519::Recipient::from_str(recipient)
+                    .map_err(|e| format!("Invalid age recipient {}: {}", recipient, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        age::Encryptor::with_recipients(parsed.iter().map(|r| r as &dyn age::Recipient))?
+    };
+
+    let mut ciphertext = Vec::with_capacity(plaintext.len());
+    let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+
+    Ok(ciphertext)
+}
+
+/// Decrypts an age file with the identities in `identity_file`, or with the
+/// pack passphrase if it was encrypted to a passphrase.
+pub fn decrypt(
+    identity_file: Option<&Path>,
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let decryptor = age::Decryptor::new_buffered(ciphertext)?;
+
+    let mut reader = if decryptor.is_scrypt() {
+        let passphrase = crate::passphrase::pack_passphrase()?;
+        let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_string()));
+        decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .map_err(|e| format!("age decryption failed (wrong passphrase?): {}", e))?
+    } else {
+        let path = identity_file.ok_or(
+            "Data was encrypted to age recipients; set sync.AgeIdentityFile to decrypt it",
+        )?;
+        let identities = age::IdentityFile::from_file(path.display().to_string())
+            .map_err(|e| format!("Failed to read age identity file {}: {}", path.display(), e))?
+            .into_identities()?;
+        decryptor
+            .decrypt(identities.iter().map(|i| i.as_ref() as &dyn age::Identity))
+            .map_err(|e| format!("age decryption failed: {}", e))?
+    };
+
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
@@ -0,0 +1,121 @@
+use git2::{Oid, Repository, Status};
+
+/// What bare `packer` decided to do after comparing local and remote state.
+enum Decision {
+    Upload(&'static str),
+    Download(&'static str),
+    UpToDate,
+    Diverged(&'static str),
+}
+
+/// Runs when no subcommand is given: checks status, then uploads, downloads
+/// or warns about divergence, whichever is the obviously right thing.
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(std::env::current_dir()?)?;
+    let status = crate::collect_status()?;
+
+    println!("Branch: {} ({})", status.branch, status.head);
+    if let Some(snapshot) = &status.remote_snapshot {
+        println!("Remote snapshot: {}", snapshot.commit);
+    }
+
+    let head = Oid::from_str(&status.head)?;
+    let remote = status
+        .remote_snapshot
+        .as_ref()
+        .map(|snapshot| Oid::from_str(&snapshot.commit))
+        .transpose()?;
+    let last_uploaded = status
+        .last_uploaded
+        .as_deref()
+        .map(Oid::from_str)
+        .transpose()?;
+    let staged = has_staged_changes(&repo)?;
+    let receive_only = crate::config::Config::load_for_repo(&repo)?
+        .oss
+        .receive_only;
+
+    let decision = match remote {
+        None if staged || status.ahead != Some(0) => Decision::Upload("nothing uploaded yet"),
+        None => Decision::UpToDate,
+        // The remote holds nothing this checkout lacks
+        Some(remote) if Some(remote) == last_uploaded || contains(&repo, head, remote) => {
+            if staged {
+                Decision::Upload("staged changes not uploaded yet")
+            } else if !is_at(&repo, head, remote) {
+                Decision::Upload("local commits not uploaded yet")
+            } else {
+                Decision::UpToDate
+            }
+        }
+        // Another machine uploaded something newer
+        Some(_) => {
+            let synced = status.ahead == Some(0)
+                || last_uploaded.is_some_and(|uploaded| is_at(&repo, head, uploaded));
+            if status.dirty {
+                Decision::Diverged("the remote is newer and the working tree has local changes")
+            } else if !synced {
+                Decision::Diverged("the remote is newer and local commits were never uploaded")
+            } else {
+                Decision::Download("the remote is newer and the working tree is clean")
+            }
+        }
+    };
+
+    match decision {
+        Decision::Upload(reason) if receive_only => {
+            println!("Not uploading ({}): this machine is receive-only", reason);
+            Ok(())
+        }
+        Decision::Upload(reason) => {
+            println!("Uploading: {}", reason);
+            crate::cmd_up(&crate::UpOptions::DEFAULT)
+        }
+        Decision::Download(reason) => {
+            println!("Downloading: {}", reason);
+            crate::cmd_down(&crate::DownOptions::DEFAULT)
+        }
+        Decision::UpToDate => {
+            if status.dirty {
+                println!("Unstaged changes are not uploaded; stage them with `git add` first");
+            }
+            println!("Up to date");
+            Ok(())
+        }
+        Decision::Diverged(reason) => {
+            println!("Warning: local and remote have diverged: {}", reason);
+            println!("Run `packer down --dry-run` to see the remote changes, then `packer up` or `packer down` explicitly");
+            Ok(())
+        }
+    }
+}
+
+// Whether the index differs from HEAD, i.e. whether `up` has something to capture
+fn has_staged_changes(repo: &Repository) -> Result<bool, git2::Error> {
+    let staged = Status::INDEX_NEW
+        | Status::INDEX_MODIFIED
+        | Status::INDEX_DELETED
+        | Status::INDEX_RENAMED
+        | Status::INDEX_TYPECHANGE;
+    Ok(repo
+        .statuses(None)?
+        .iter()
+        .any(|entry| entry.status().intersects(staged)))
+}
+
+// Whether `head` already has `snapshot`'s history
+fn contains(repo: &Repository, head: Oid, snapshot: Oid) -> bool {
+    head == snapshot || repo.graph_descendant_of(head, snapshot).unwrap_or(false)
+}
+
+// Whether HEAD is at `snapshot` or at the commit it was taken from. `up`
+// uploads a temporary commit on top of HEAD, so after uploading HEAD is its
+// parent rather than the snapshot itself.
+fn is_at(repo: &Repository, head: Oid, snapshot: Oid) -> bool {
+    head == snapshot
+        || repo
+            .find_commit(snapshot)
+            .ok()
+            .and_then(|commit| commit.parent_id(0).ok())
+            == Some(head)
+}
@@ -0,0 +1,261 @@
+use crate::config;
+use crate::passphrase;
+use crate::paths;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// File layout: magic | salt (16) | nonce (12) | AES-GCM ciphertext of the TOML bundle
+const MAGIC: &[u8] = b"SYNCBAK1";
+
+/// Everything a new machine needs to pick up where this one left off.
+#[derive(Serialize, Deserialize, Default)]
+struct Bundle {
+    created: String,
+    hostname: String,
+    /// Contents of config.toml, decrypted if the file was encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<String>,
+    /// The config file as it was on disk when it was encrypted, restored
+    /// verbatim so it stays under its passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_config: Option<String>,
+    /// AccessKeySecret when it lives in the system keyring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    access_key_secret: Option<String>,
+    /// Pack passphrase from the system keyring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pack_passphrase: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_file: Option<BundledFile>,
+    /// Cached local state, keyed by path relative to the cache directory
+    #[serde(default)]
+    state: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundledFile {
+    path: String,
+    /// Base64 of the file contents
+    content: String,
+}
+
+/// Writes config, keyring secrets, the key file and local state to `output`,
+/// encrypted with a passphrase asked for here.
+pub fn backup(output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = config::config_file_path()?;
+    let table = config::read_table(&config_path)?;
+    let string_key = |key: &str| {
+        config::get_key(&table, key)
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+
+    let mut bundle = Bundle {
+        created: chrono::Utc::now().to_rfc3339(),
+        hostname: hostname::get()
+            .unwrap_or_else(|_| "unknown".into())
+            .to_string_lossy()
+            .to_string(),
+        ..Default::default()
+    };
+
+    if config_path.is_file() {
+        bundle.config = Some(toml::to_string_pretty(&table)?);
+        if config::is_encrypted_file(&config_path) {
+            bundle.encrypted_config = Some(std::fs::read_to_string(&config_path)?);
+        }
+        println!("Including config: {}", config_path.display());
+    }
+
+    if string_key("oss.SecretStorage").as_deref() == Some("keyring") {
+        if let Some(access_key_id) = string_key("oss.AccessKeyId") {
+            match config::keyring_entry(&access_key_id)?.get_password() {
+                Ok(secret) => {
+                    bundle.access_key_secret = Some(secret);
+                    println!("Including access key secret from the system keyring");
+                }
+                Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    match config::passphrase_keyring_entry()?.get_password() {
+        Ok(passphrase) => {
+            bundle.pack_passphrase = Some(passphrase);
+            println!("Including pack passphrase from the system keyring");
+        }
+        Err(keyring::Error::NoEntry) => {}
+        // Machines without a keyring service get the passphrase from the environment
+        Err(e) => println!("Skipping pack passphrase, keyring unavailable: {}", e),
+    }
+
+    if let Some(key_file) = string_key("sync.KeyFile") {
+        let content = std::fs::read(&key_file)
+            .map_err(|e| format!("Failed to read key file {}: {}", key_file, e))?;
+        bundle.key_file = Some(BundledFile {
+            path: key_file.clone(),
+            content: general_purpose::STANDARD.encode(content),
+        });
+        println!("Including key file: {}", key_file);
+    }
+
+    if let Some(cache_dir) = paths::cache_dir() {
+        for path in files_under(&cache_dir)? {
+            let relative = path
+                .strip_prefix(&cache_dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            bundle.state.insert(
+                relative,
+                general_purpose::STANDARD.encode(std::fs::read(&path)?),
+            );
+        }
+        println!("Including {} local state files", bundle.state.len());
+    }
+
+    let passphrase = backup_passphrase(true)?;
+    let salt = passphrase::random_salt();
+    let key = passphrase::derive_key(&passphrase, &salt)?;
+
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&passphrase::seal_with_key(
+        &key,
+        toml::to_string(&bundle)?.as_bytes(),
+    )?);
+    config::write_private(output, data)?;
+
+    println!("Backup written to {}", output.display());
+    Ok(())
+}
+
+/// Restores a bundle written by [`backup`]. Refuses to replace an existing
+/// config file or key file unless `force` is set.
+pub fn restore(input: &Path, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(input)?;
+    if data.len() <= MAGIC.len() + passphrase::SALT_SIZE || !data.starts_with(MAGIC) {
+        return Err(format!("{} is not a packer backup", input.display()).into());
+    }
+    let salt = &data[MAGIC.len()..MAGIC.len() + passphrase::SALT_SIZE];
+    let sealed = &data[MAGIC.len() + passphrase::SALT_SIZE..];
+
+    let key = passphrase::derive_key(&backup_passphrase(false)?, salt)?;
+    let plaintext = passphrase::open_with_key(&key, sealed)?;
+    let bundle: Bundle = toml::from_str(&String::from_utf8(plaintext)?)?;
+    println!(
+        "Restoring backup made on {} at {}",
+        bundle.hostname, bundle.created
+    );
+
+    if let Some(content) = &bundle.config {
+        let config_path = config::config_file_path()?;
+        if config_path.is_file() && !force {
+            return Err(format!(
+                "{} already exists; pass --force to replace it",
+                config_path.display()
+            )
+            .into());
+        }
+        match &bundle.encrypted_config {
+            Some(encrypted) => {
+                config::write_private(&config_path, encrypted)?;
+                println!(
+                    "Restored config: {} (encrypted with its original passphrase)",
+                    config_path.display()
+                );
+            }
+            None => {
+                config::write_private(&config_path, content)?;
+                println!("Restored config: {}", config_path.display());
+            }
+        }
+    }
+
+    let table = match &bundle.config {
+        Some(content) => content.parse::<toml::Table>()?,
+        None => toml::Table::new(),
+    };
+    if let Some(secret) = &bundle.access_key_secret {
+        let access_key_id = config::get_key(&table, "oss.AccessKeyId")
+            .and_then(|v| v.as_str())
+            .ok_or("Backup has an access key secret but no oss.AccessKeyId")?;
+        config::keyring_entry(access_key_id)?.set_password(secret)?;
+        println!("Restored access key secret to the system keyring");
+    }
+    if let Some(passphrase) = &bundle.pack_passphrase {
+        config::passphrase_keyring_entry()?.set_password(passphrase)?;
+        println!("Restored pack passphrase to the system keyring");
+    }
+
+    if let Some(key_file) = &bundle.key_file {
+        let path = Path::new(&key_file.path);
+        if path.exists() && !force {
+            println!(
+                "Key file {} already exists, leaving it (use --force to replace it)",
+                path.display()
+            );
+        } else {
+            config::write_private(path, general_purpose::STANDARD.decode(&key_file.content)?)?;
+            println!("Restored key file: {}", path.display());
+        }
+    }
+
+    if !bundle.state.is_empty() {
+        let cache_dir = paths::cache_dir().ok_or("Could not determine the cache directory")?;
+        for (relative, content) in &bundle.state {
+            // Don't let a crafted bundle write outside the cache directory
+            if relative
+                .split('/')
+                .any(|part| part == ".." || part.is_empty())
+            {
+                return Err(format!("Invalid state path in backup: {}", relative).into());
+            }
+            config::write_private(
+                &cache_dir.join(relative),
+                general_purpose::STANDARD.decode(content)?,
+            )?;
+        }
+        println!("Restored {} local state files", bundle.state.len());
+    }
+
+    Ok(())
+}
+
+// SYNC_BACKUP_PASSPHRASE for scripted provisioning, otherwise a prompt
+// (asked twice when creating a backup)
+fn backup_passphrase(confirm: bool) -> Result<String, Box<dyn std::error::Error>> {
+    if let Ok(passphrase) = std::env::var("SYNC_BACKUP_PASSPHRASE") {
+        if !passphrase.is_empty() {
+            return Ok(passphrase);
+        }
+    }
+
+    let passphrase = rpassword::prompt_password("Backup passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err("Passphrase must not be empty".into());
+    }
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        return Err("Passphrases do not match".into());
+    }
+    Ok(passphrase)
+}
+
+fn files_under(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(files_under(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,319 @@
+use crate::config::Config;
+use crate::layers::{self, Snapshot};
+use crate::storage::ObjectHeaders;
+use crate::{deadline, progress, units, RepoInfo};
+use git2::{Buf, ObjectType, Oid, Repository};
+use std::path::{Path, PathBuf};
+
+// Payload layout as `git bundle create` writes it: this line, one
+// "-<sha> <subject>" line per commit the pack builds on, one "<sha> <ref>"
+// line per ref, an empty line, then the pack. A downloaded bundle can be
+// cloned, or fetched from, with plain git too.
+const HEADER: &str = "# v2 git bundle";
+
+// Newer git writes this when asked to, with "@<capability>" lines after it
+const HEADER_V3: &str = "# v3 git bundle";
+
+// Ref naming the temporary commits with the uncommitted work. Only
+// `init-from` reads it; `git clone` leaves it out.
+const TIP_REF: &str = "refs/sync/tip";
+
+struct BundleRef {
+    oid: Oid,
+    name: String,
+}
+
+/// Bundles every branch and tag with their whole history, plus the
+/// uncommitted work `snapshot` takes, and uploads it, so `init-from` can
+/// set the repository up on a machine that has no clone of it.
+pub fn up(
+    repo: &Repository,
+    config: &Config,
+    repo_info: &RepoInfo,
+    snapshot: Snapshot,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let head = repo.head()?;
+    let head_commit = head.peel_to_commit()?;
+    let stack = layers::commit(repo, Some(&head_commit), snapshot)?;
+    if stack.unstaged_count > 0 {
+        println!("{}", t!("up.unstaged", stack.unstaged_count));
+    }
+    if stack.untracked_count > 0 {
+        println!("{}", t!("up.untracked", stack.untracked_count));
+    }
+
+    // The checked-out branch comes right after HEAD, so `init-from` picks it
+    // even when other branches point at the same commit
+    let mut refs = vec![BundleRef {
+        oid: head_commit.id(),
+        name: "HEAD".to_string(),
+    }];
+    let current = head.name().filter(|_| head.is_branch());
+    if let Some(name) = current {
+        refs.push(BundleRef {
+            oid: head_commit.id(),
+            name: name.to_string(),
+        });
+    }
+    let mut packbuilder = repo.packbuilder()?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(stack.tip)?;
+    for reference in repo.references()? {
+        let reference = reference?;
+        // Symbolic refs have no target of their own
+        let (Some(name), Some(target)) = (reference.name(), reference.target()) else {
+            continue;
+        };
+        if !name.starts_with("refs/heads/") && !name.starts_with("refs/tags/") {
+            continue;
+        }
+        // Annotated tags, possibly of other tags
+        let mut object = repo.find_object(target, None)?;
+        while let Some(tag) = object.as_tag() {
+            packbuilder.insert_object(tag.id(), None)?;
+            object = tag.target()?;
+        }
+        match object.kind() {
+            Some(ObjectType::Commit) => revwalk.push(object.id())?,
+            _ => packbuilder.insert_recursive(object.id(), None)?,
+        }
+        if Some(name) != current {
+            refs.push(BundleRef {
+                oid: target,
+                name: name.to_string(),
+            });
+        }
+    }
+    refs.push(BundleRef {
+        oid: stack.tip,
+        name: TIP_REF.to_string(),
+    });
+
+    progress::start("pack", None);
+    packbuilder.insert_walk(&mut revwalk)?;
+    let mut pack = Buf::new();
+    packbuilder.write_buf(&mut pack)?;
+    progress::finish("pack", pack.len() as u64);
+    let object_count = packbuilder.object_count();
+    // Leaving out HEAD and the tip
+    let ref_count = refs.len() - 2;
+
+    let payload = write(&[], &refs, &pack);
+
+    // Bundles belong to the repository rather than a branch
+    let key = crate::repo_key(config, repo_info, "repo.bundle");
+    if dry_run {
+        println!(
+            "{}",
+            t!(
+                "bundle.would_upload",
+                ref_count,
+                object_count,
+                units::format_size(payload.len() as u64),
+                key
+            )
+        );
+        return Ok(());
+    }
+
+    let data = crate::seal_payload(&config.sync, payload, Some(&key))?;
+    let size = units::format_size(data.len() as u64);
+    let repo_name = repo_info.to_string();
+    crate::upload_object(
+        &config.oss,
+        &key,
+        data,
+        config.sync.hash_algorithm,
+        &ObjectHeaders {
+            tags: vec![("repo", &repo_name), ("kind", "bundle")],
+            ..Default::default()
+        },
+    )?;
+    println!(
+        "{}",
+        t!("bundle.uploaded", ref_count, object_count, size, key)
+    );
+    if let Some(url) = repo.find_remote("origin")?.url() {
+        println!("{}", t!("bundle.init_hint", url));
+    }
+    Ok(())
+}
+
+/// Creates a repository in `directory`, named after the repository by
+/// default, from the bundle `up --bundle` uploaded for `url`: every branch
+/// and tag, origin set to `url`, and the branch that was checked out with
+/// its uncommitted work. Like `git clone`, but from the bucket.
+pub fn init_from(url: &str, directory: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let repo_info = crate::repo_info_from_url(url);
+    let directory = directory
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(&repo_info.name));
+    if directory.exists() && std::fs::read_dir(&directory)?.next().is_some() {
+        return Err(t!("bundle.not_empty", directory.display()).into());
+    }
+    // The repository's own .sync.toml only exists once it is checked out
+    let config = Config::load()?;
+    deadline::set_default("init-from", &config.sync)?;
+
+    let key = crate::existing_repo_key(&config, &repo_info, "repo.bundle")?;
+    println!("{}", t!("down.downloading", key));
+    let data = crate::download_pack(&config.oss, &key)?;
+    let payload = crate::open_payload(&config.sync, data, Some(&key))?;
+    let (_, refs, pack) = parse(&payload).ok_or_else(|| t!("bundle.malformed", key))?;
+
+    let repo = Repository::init(&directory)?;
+    repo.remote("origin", url)?;
+    crate::index_pack(&repo, pack)?;
+    let mut head = None;
+    let mut tip = None;
+    let mut ref_count = 0;
+    for bundle_ref in &refs {
+        match bundle_ref.name.as_str() {
+            "HEAD" => head = Some(bundle_ref.oid),
+            TIP_REF => tip = Some(bundle_ref.oid),
+            name => {
+                repo.reference(name, bundle_ref.oid, true, "packer init-from")?;
+                ref_count += 1;
+            }
+        }
+    }
+    let head = head.ok_or_else(|| t!("bundle.malformed", key))?;
+    // Like `git clone`, check out the first branch at HEAD's commit
+    let branch = refs
+        .iter()
+        .find(|bundle_ref| bundle_ref.oid == head && bundle_ref.name.starts_with("refs/heads/"));
+    match branch {
+        Some(bundle_ref) => repo.set_head(&bundle_ref.name)?,
+        None => repo.set_head_detached(head)?,
+    }
+    crate::checkout_pack(&repo, &tip.unwrap_or(head).to_string())?;
+
+    println!(
+        "{}",
+        t!("bundle.initialized", directory.display(), ref_count)
+    );
+    Ok(())
+}
+
+/// Payload of `up --format bundle`: a branch's pack as an incremental
+/// bundle, which needs `base` to be there already. The branch and HEAD name
+/// its real commit, so `git fetch` on a machine without packer takes what
+/// was committed; `down` also applies the temporary commits above it.
+pub fn incremental(
+    repo: &Repository,
+    branch_name: &str,
+    tip: Oid,
+    base: Option<Oid>,
+    pack: &[u8],
+) -> Result<Vec<u8>, git2::Error> {
+    let prerequisites = match base {
+        Some(base) => vec![repo.find_commit(base)?],
+        None => Vec::new(),
+    };
+    // Nothing committed yet when only temporary commits were packed
+    let head = layers::base(repo, tip)?.unwrap_or(tip);
+    let mut refs = vec![
+        BundleRef {
+            oid: head,
+            name: "HEAD".to_string(),
+        },
+        BundleRef {
+            oid: head,
+            name: format!("refs/heads/{}", branch_name),
+        },
+    ];
+    if tip != head {
+        refs.push(BundleRef {
+            oid: tip,
+            name: TIP_REF.to_string(),
+        });
+    }
+    Ok(write(&prerequisites, &refs, pack))
+}
+
+/// Turns a bundle `down --format bundle` downloaded into the commit SHA and
+/// pack that packs are applied from. The commit is the tip `up` recorded,
+/// or HEAD for a bundle `git bundle create` wrote.
+pub fn to_pack_payload(
+    repo: &Repository,
+    payload: &[u8],
+    key: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (prerequisites, refs, pack) = parse(payload).ok_or_else(|| t!("bundle.malformed", key))?;
+    // Indexing would succeed, leaving commits whose parents are missing
+    if let Some(missing) = prerequisites
+        .iter()
+        .find(|oid| repo.find_commit(**oid).is_err())
+    {
+        return Err(t!("bundle.missing_prerequisite", missing).into());
+    }
+    let tip = tip_ref(&refs).ok_or_else(|| t!("bundle.malformed", key))?;
+    let mut pack_payload = tip.oid.to_string().into_bytes();
+    pack_payload.extend_from_slice(pack);
+    Ok(pack_payload)
+}
+
+/// The commit a bundle payload applies: the tip `up` recorded, or HEAD.
+/// `None` when `payload` is not a bundle.
+pub fn tip(payload: &[u8]) -> Option<Oid> {
+    let (_, refs, _) = parse(payload)?;
+    tip_ref(&refs).map(|tip| tip.oid)
+}
+
+fn tip_ref(refs: &[BundleRef]) -> Option<&BundleRef> {
+    refs.iter()
+        .find(|bundle_ref| bundle_ref.name == TIP_REF)
+        .or_else(|| refs.iter().find(|bundle_ref| bundle_ref.name == "HEAD"))
+        .or(refs.first())
+}
+
+fn write(prerequisites: &[git2::Commit], refs: &[BundleRef], pack: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{}\n", HEADER);
+    for commit in prerequisites {
+        payload.push_str(&format!(
+            "-{} {}\n",
+            commit.id(),
+            commit.summary().unwrap_or("")
+        ));
+    }
+    for bundle_ref in refs {
+        payload.push_str(&format!("{} {}\n", bundle_ref.oid, bundle_ref.name));
+    }
+    payload.push('\n');
+    let mut payload = payload.into_bytes();
+    payload.extend_from_slice(pack);
+    payload
+}
+
+// The prerequisites, the refs and the pack
+fn parse(payload: &[u8]) -> Option<(Vec<Oid>, Vec<BundleRef>, &[u8])> {
+    let end = payload.windows(2).position(|window| window == b"\n\n")?;
+    let header = std::str::from_utf8(&payload[..end]).ok()?;
+    let mut lines = header.lines();
+    let version = lines.next()?;
+    if version != HEADER && version != HEADER_V3 {
+        return None;
+    }
+    let mut prerequisites = Vec::new();
+    let mut refs = Vec::new();
+    for line in lines {
+        if let Some(capability) = line.strip_prefix('@') {
+            // Only SHA-1 repositories are supported
+            if capability.starts_with("object-format=") && capability != "object-format=sha1" {
+                return None;
+            }
+        } else if let Some(prerequisite) = line.strip_prefix('-') {
+            // The subject after the SHA is only a comment
+            let sha = prerequisite.split(' ').next()?;
+            prerequisites.push(Oid::from_str(sha).ok()?);
+        } else {
+            let (sha, name) = line.split_once(' ')?;
+            refs.push(BundleRef {
+                oid: Oid::from_str(sha).ok()?,
+                name: name.to_string(),
+            });
+        }
+    }
+    Some((prerequisites, refs, &payload[end + 2..]))
+}
@@ -0,0 +1,172 @@
+use git2::{Delta, Diff, DiffFindOptions, DiffOptions, Repository, Status, Tree};
+use std::path::Path;
+
+/// Changes between the upstream tree (or nothing, for a branch without one)
+/// and the tree that `up` would upload.
+pub fn diff_for_upload<'r>(
+    repo: &'r Repository,
+    upstream: Option<&Tree>,
+    staged: &Tree,
+) -> Result<Diff<'r>, git2::Error> {
+    let mut diff = repo.diff_tree_to_tree(upstream, Some(staged), None)?;
+    detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// Changes `reset --hard` to `target` would make to the index and working
+/// tree. Untracked files are left alone by the reset and so aren't listed.
+pub fn diff_for_apply<'r>(repo: &'r Repository, target: &Tree) -> Result<Diff<'r>, git2::Error> {
+    let mut opts = DiffOptions::new();
+    // Diff from what's on disk now to the target, not the other way round
+    opts.reverse(true);
+    let mut diff = repo.diff_tree_to_workdir_with_index(Some(target), Some(&mut opts))?;
+    detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// Changes pointing the local branch `branch` at `target` would make, for a
+/// branch that isn't checked out, as in a bare repository. All of `target`
+/// when the branch doesn't exist yet.
+pub fn diff_for_branch<'r>(
+    repo: &'r Repository,
+    branch: &str,
+    target: &Tree,
+) -> Result<Diff<'r>, git2::Error> {
+    let current = match repo.find_branch(branch, git2::BranchType::Local) {
+        Ok(branch) => Some(branch.get().peel_to_tree()?),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let mut diff = repo.diff_tree_to_tree(current.as_ref(), Some(target), None)?;
+    detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// Tracked files whose staged or working-directory version a reset to
+/// `target` would throw away, as `git status --short` lines. A version that
+/// HEAD, `target` or one of `received` (packs applied earlier) has isn't
+/// lost, so what the last `down` restored can be replaced freely. Files on
+/// disk are compared as `git status` does, through git's filters (line
+/// endings, clean filters) and by their target for symlinks; submodules are
+/// left out, as a reset doesn't touch their checkouts.
+pub fn lost_changes(
+    repo: &Repository,
+    target: &Tree,
+    received: &[Tree],
+) -> Result<Vec<String>, git2::Error> {
+    if repo.is_bare() {
+        return Ok(Vec::new());
+    }
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e),
+    };
+    let trees: Vec<&Tree> = head.iter().chain([target]).chain(received).collect();
+    let index = repo.index()?;
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(false).exclude_submodules(true);
+    let mut lost = Vec::new();
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        let Some(path) = entry.path() else {
+            continue;
+        };
+        let status = entry.status();
+        let in_tree = |tree: &Tree| tree.get_path(Path::new(path)).ok().map(|e| e.id());
+        let staged = index.get_path(Path::new(path), 0).map(|entry| entry.id);
+        let staged_known = trees.iter().any(|tree| in_tree(tree) == staged);
+        let on_disk_known = if status.contains(Status::WT_DELETED) {
+            trees.iter().any(|tree| in_tree(tree).is_none())
+        } else if status.intersects(Status::WT_MODIFIED | Status::WT_TYPECHANGE) {
+            let mut known = false;
+            for tree in &trees {
+                if in_tree(tree).is_some() && matches_on_disk(repo, tree, path)? {
+                    known = true;
+                    break;
+                }
+            }
+            known
+        } else {
+            // What is on disk is what is staged
+            staged_known
+        };
+        if !staged_known || !on_disk_known {
+            lost.push(format!("{} {}", short_status(status), path));
+        }
+    }
+    Ok(lost)
+}
+
+// Whether the file at `path`, which `tree` has, is the same on disk
+fn matches_on_disk(repo: &Repository, tree: &Tree, path: &str) -> Result<bool, git2::Error> {
+    let mut options = DiffOptions::new();
+    options.pathspec(path).disable_pathspec_match(true);
+    let diff = repo.diff_tree_to_workdir(Some(tree), Some(&mut options))?;
+    Ok(diff.deltas().len() == 0)
+}
+
+// The two letters `git status --short` shows
+fn short_status(status: Status) -> String {
+    let staged = match status {
+        s if s.contains(Status::INDEX_NEW) => 'A',
+        s if s.contains(Status::INDEX_DELETED) => 'D',
+        s if s.contains(Status::INDEX_RENAMED) => 'R',
+        s if s.intersects(Status::INDEX_MODIFIED | Status::INDEX_TYPECHANGE) => 'M',
+        _ => ' ',
+    };
+    let unstaged = match status {
+        s if s.contains(Status::WT_DELETED) => 'D',
+        s if s.intersects(Status::WT_MODIFIED | Status::WT_TYPECHANGE) => 'M',
+        s if s.contains(Status::CONFLICTED) => 'U',
+        _ => ' ',
+    };
+    format!("{}{}", staged, unstaged)
+}
+
+// Without this a moved file shows up as an unrelated delete and add, which
+// makes a harmless rename look like lost work
+fn detect_renames(diff: &mut Diff) -> Result<(), git2::Error> {
+    let mut find = DiffFindOptions::new();
+    find.renames(true).copies(true);
+    diff.find_similar(Some(&mut find))
+}
+
+/// Prints one line per changed file followed by git's usual totals line.
+pub fn print_summary(diff: &Diff) -> Result<(), git2::Error> {
+    for delta in diff.deltas() {
+        let old_path = delta
+            .old_file()
+            .path()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        let new_path = delta
+            .new_file()
+            .path()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+
+        match delta.status() {
+            Delta::Added => println!("  added     {}", new_path),
+            Delta::Deleted => println!("  deleted   {}", old_path),
+            Delta::Modified => println!("  modified  {}", new_path),
+            Delta::Renamed => println!("  renamed   {} → {}", old_path, new_path),
+            Delta::Copied => println!("  copied    {} → {}", old_path, new_path),
+            Delta::Typechange => println!("  typechange {}", new_path),
+            _ => {}
+        }
+    }
+
+    let stats = diff.stats()?;
+    if stats.files_changed() == 0 {
+        println!("  no changes");
+    } else {
+        println!(
+            "  {} files changed, {} insertions(+), {} deletions(-)",
+            stats.files_changed(),
+            stats.insertions(),
+            stats.deletions()
+        );
+    }
+
+    Ok(())
+}
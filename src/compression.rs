@@ -0,0 +1,137 @@
+use serde::Deserialize;
+use std::io::{Read, Write};
+
+/// Codec applied to payloads before encryption.
+///
+/// zstd is the default. lz4 trades ratio for speed where the CPU rather than
+/// the network is the bottleneck (LAN, local MinIO); xz squeezes history packs
+/// that are uploaded once and kept for a long time. The codec is recorded in
+/// the envelope, so decoding never depends on the local setting.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Zstd,
+    Lz4,
+    Xz,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+            Compression::Xz => "xz",
+        }
+    }
+
+    /// Identifier stored in the envelope header. Never reuse a retired id.
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Lz4 => 2,
+            Compression::Xz => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Compression> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Zstd),
+            2 => Some(Compression::Lz4),
+            3 => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Compression::None => data.to_vec(),
+            Compression::Zstd => zstd::encode_all(data, 3)?,
+            Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+        })
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Compression::None => data.to_vec(),
+            Compression::Zstd => zstd::decode_all(data)?,
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| format!("lz4 decompression failed: {}", e))?,
+            Compression::Xz => {
+                let mut decompressed = Vec::new();
+                xz2::read::XzDecoder::new(data).read_to_end(&mut decompressed)?;
+                decompressed
+            }
+        })
+    }
+}
+
+/// `Content-Encoding` for files shared with `s`. Browsers opening the link
+/// undo it themselves, so recipients don't need packer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    /// Smaller, but only recent browsers accept it
+    Zstd,
+}
+
+impl ContentEncoding {
+    pub fn from_name(name: &str) -> Result<ContentEncoding, Box<dyn std::error::Error>> {
+        match name {
+            "gzip" => Ok(ContentEncoding::Gzip),
+            "zstd" => Ok(ContentEncoding::Zstd),
+            _ => Err(format!("Unknown content encoding {:?}; use gzip or zstd", name).into()),
+        }
+    }
+
+    /// Parses a `Content-Encoding` header value; `None` for identity or
+    /// anything unknown.
+    pub fn from_header(value: &str) -> Option<ContentEncoding> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "zstd" => Some(ContentEncoding::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ContentEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            // Browsers accept zstd windows up to 8 MB (RFC 8878); level 19
+            // uses exactly that
+            ContentEncoding::Zstd => zstd::encode_all(data, 19)?,
+        })
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ContentEncoding::Gzip => {
+                let mut decoded = Vec::new();
+                flate2::read::GzDecoder::new(data).read_to_end(&mut decoded)?;
+                decoded
+            }
+            ContentEncoding::Zstd => zstd::decode_all(data)?,
+        })
+    }
+}
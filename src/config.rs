@@ -0,0 +1,1000 @@
+use crate::compression::Compression;
+use crate::hash::HashAlgorithm;
+use crate::manifest::DEFAULT_GIT_CONFIG_PATTERNS;
+use crate::passphrase;
+use crate::paths;
+use base64::{engine::general_purpose, Engine as _};
+use git2::Repository;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use zeroize::Zeroizing;
+
+// Credentials embedded at compile time, only used when no config file is found
+#[cfg(feature = "embedded-config")]
+const EMBEDDED_CONFIG: Option<&str> = Some(include_str!("cred.toml"));
+#[cfg(not(feature = "embedded-config"))]
+const EMBEDDED_CONFIG: Option<&str> = None;
+
+// The only keys a repository's tracked .sync.toml may set. It comes with
+// whatever was cloned, so storage, encryption, trust and commands to run
+// only come from the global config and .git/sync.toml.
+const WORKTREE_KEYS: &[&str] = &[
+    "oss.ReceiveOnly",
+    "sync.Compression",
+    "sync.HashAlgorithm",
+    "sync.Deadlines",
+    "sync.Language",
+    "sync.MirrorRefs",
+    "sync.VerifyCommits",
+    "sync.SnapshotGitConfig",
+];
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub oss: OssConfig,
+    #[serde(default)]
+    pub sync: SyncConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct OssConfig {
+    /// Storage service: `s3` (also Aliyun OSS, MinIO, R2, ...), `azure`, `gcs`,
+    /// `sftp` or `fs`
+    #[serde(rename = "Type", alias = "type")]
+    pub backend: Backend,
+    /// Bucket, the container on Azure, or the root directory for `sftp` and `fs`
+    #[serde(rename = "BucketName")]
+    pub bucket_name: String,
+    #[serde(rename = "Endpoint")]
+    pub endpoint: String,
+    /// The storage account name on Azure
+    #[serde(rename = "AccessKeyId")]
+    pub access_key_id: String,
+    /// The (base64) account key on Azure
+    #[serde(rename = "AccessKeySecret")]
+    pub access_key_secret: String,
+    /// Where credentials come from; `chain` ignores the static keys above
+    #[serde(rename = "CredentialSource")]
+    pub credential_source: CredentialSource,
+    /// Where `AccessKeySecret` is kept; `keyring` reads it from the OS keychain
+    #[serde(rename = "SecretStorage")]
+    pub secret_storage: SecretStorage,
+    /// Only ever read from the bucket: uploads and deletes are refused, so
+    /// read-only credentials and the decryption key are all this machine needs
+    #[serde(rename = "ReceiveOnly")]
+    pub receive_only: bool,
+    /// Service-account key (JSON) for `gcs`; empty uses the application
+    /// default credentials
+    #[serde(rename = "CredentialsFile")]
+    pub credentials_file: String,
+    /// Private key for `sftp`; empty uses ssh's defaults and agent
+    #[serde(rename = "SshKey")]
+    pub ssh_key: String,
+    /// HTTPS URL serving the `sftp` or `fs` directory, with `{key}` standing for the
+    /// object key, e.g. `https://files.example.com/packer/{key}`. Empty makes
+    /// share links an scp command or a file:// URL instead
+    #[serde(rename = "PublicUrl")]
+    pub public_url: String,
+    /// S3 objects bigger than this go up in parts and come down in ranges
+    /// of this size, e.g. `16M` (the default); at least `5M`
+    #[serde(rename = "PartSize")]
+    pub part_size: String,
+    /// Parts of a multipart upload sent at once; 0 means 4
+    #[serde(rename = "UploadConcurrency")]
+    pub upload_concurrency: usize,
+    /// Ranges of a large download fetched at once; 0 means 4
+    #[serde(rename = "DownloadConcurrency")]
+    pub download_concurrency: usize,
+    /// Accept the charges of a requester-pays S3 bucket (`x-amz-request-payer`).
+    /// Share links can't carry the header, so the bucket refuses them unless
+    /// they are signed with the owner's credentials
+    #[serde(rename = "RequesterPays")]
+    pub requester_pays: bool,
+    /// Tag uploads with `repo`, `branch` and `kind` (`pack` or `file`) for
+    /// lifecycle rules and cost reports; needs s3:PutObjectTagging on S3
+    /// and blob index tags on Azure, so it is off by default
+    #[serde(rename = "Tagging")]
+    pub tagging: bool,
+    /// Server-side encryption requested on every S3 upload, on top of the
+    /// client-side encryption: `AES256` (SSE-S3) or `aws:kms` (SSE-KMS)
+    #[serde(rename = "ServerSideEncryption")]
+    pub server_side_encryption: ServerSideEncryption,
+    /// KMS key for `aws:kms`; empty uses the bucket's default key
+    #[serde(rename = "SseKmsKeyId")]
+    pub sse_kms_key_id: String,
+    /// Proxy for reaching the storage service: `http://[user:pass@]host:port`,
+    /// `socks5://...` or `socks5h://...` (names resolved by the proxy).
+    /// Empty honors `https_proxy`/`all_proxy` and `no_proxy`; `direct`
+    /// ignores them. `sftp` uses ssh's own ProxyJump/ProxyCommand instead
+    #[serde(rename = "Proxy")]
+    pub proxy: String,
+    /// Time allowed to open a connection to the storage service, e.g. `10s`
+    /// (the default); `0` waits forever
+    #[serde(rename = "ConnectTimeout")]
+    pub connect_timeout: String,
+    /// How long a connection may go without sending or receiving anything
+    /// before it is abandoned, `60s` by default; `0` waits forever
+    #[serde(rename = "ReadTimeout")]
+    pub read_timeout: String,
+    /// How often failed storage requests are retried (`[oss.Retry]`)
+    #[serde(rename = "Retry")]
+    pub retry: RetryPolicy,
+    /// Further storage targets (`[[oss.Mirrors]]` tables with the keys
+    /// above) that every upload is also written to, in parallel. Downloads
+    /// fall back to them in order when the ones before fail
+    #[serde(rename = "Mirrors")]
+    pub mirrors: Vec<OssConfig>,
+}
+
+impl OssConfig {
+    fn apply_defaults(&mut self) {
+        if self.backend == Backend::Gcs && self.endpoint.is_empty() {
+            self.endpoint = crate::gcs::DEFAULT_ENDPOINT.to_string();
+        }
+        for mirror in &mut self.mirrors {
+            mirror.apply_defaults();
+        }
+    }
+
+    /// Fails on receive-only machines before anything is written to the bucket.
+    pub fn ensure_writable(&self) -> Result<(), String> {
+        if self.receive_only {
+            return Err(
+                "This machine is receive-only (oss.ReceiveOnly); uploads and deletes are disabled"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Tries per request, the first included; 1 disables retrying
+    #[serde(rename = "Attempts")]
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it
+    #[serde(rename = "BaseDelayMs")]
+    pub base_delay_ms: u64,
+    /// Upper bound for any single wait
+    #[serde(rename = "MaxDelayMs")]
+    pub max_delay_ms: u64,
+    /// Wait a random part of each delay, so machines that failed together
+    /// don't all retry at the same moment
+    #[serde(rename = "Jitter")]
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 4,
+            base_delay_ms: 500,
+            max_delay_ms: 20_000,
+            jitter: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The S3 API, at `Endpoint`
+    #[default]
+    S3,
+    /// Azure Blob Storage; `Endpoint` is `https://<account>.blob.core.windows.net`
+    Azure,
+    /// Google Cloud Storage; `Endpoint` defaults to `https://storage.googleapis.com`
+    Gcs,
+    /// Files on any SSH server, through the `ssh` client; `Endpoint` is
+    /// `sftp://[user@]host[:port]`
+    Sftp,
+    /// Files under a local directory such as a USB drive or an SMB/NFS
+    /// mount; `Endpoint` is not used
+    Fs,
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::S3 => "s3",
+            Backend::Azure => "azure",
+            Backend::Gcs => "gcs",
+            Backend::Sftp => "sftp",
+            Backend::Fs => "fs",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerSideEncryption {
+    /// Whatever the bucket's default encryption does
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    /// SSE-S3, keys managed by the storage service
+    #[serde(rename = "AES256", alias = "sse-s3")]
+    Aes256,
+    /// SSE-KMS, with `SseKmsKeyId` or the bucket's default KMS key
+    #[serde(rename = "aws:kms", alias = "sse-kms")]
+    Kms,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretStorage {
+    /// Plaintext in the config file (or `SYNC_ACCESS_KEY_SECRET`)
+    #[default]
+    Config,
+    /// Windows Credential Manager, macOS Keychain or Secret Service, keyed by `AccessKeyId`
+    Keyring,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// Two-round AES-256-GCM with a passphrase-derived or key-file outer key
+    #[default]
+    Aes,
+    /// Standard age files, to `AgeRecipients` or to the pack passphrase
+    Age,
+    /// OpenPGP messages to `GpgRecipients`, via the `gpg` binary
+    Gpg,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialSource {
+    /// `AccessKeyId` / `AccessKeySecret` from the config or environment
+    #[default]
+    Static,
+    /// The standard AWS provider chain (env vars, ~/.aws/credentials, SSO, IAM roles)
+    Chain,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// Prefix prepended to every pack object key, e.g. `work` → `work/{author}/{repo}/...`
+    #[serde(rename = "Prefix")]
+    pub prefix: String,
+    /// Encrypt packs and manifests before upload
+    #[serde(rename = "Encrypt")]
+    pub encrypt: bool,
+    /// Team mode: sync under `{repo}/{branch}/users/{User}/` so teammates
+    /// sharing a bucket don't overwrite each other
+    #[serde(rename = "User")]
+    pub user: String,
+    /// Algorithm used for the payload checksum stored with every upload
+    #[serde(rename = "HashAlgorithm")]
+    pub hash_algorithm: HashAlgorithm,
+    /// Codec applied to packs before encryption: zstd (default), lz4, xz or none.
+    /// Set it per repository in .sync.toml, e.g. xz for archival history packs
+    #[serde(rename = "Compression")]
+    pub compression: Compression,
+    /// Encryption scheme for new uploads; downloads detect it per object
+    #[serde(rename = "Encryption")]
+    pub encryption: Encryption,
+    /// age X25519 recipients (`age1...`); empty means the pack passphrase
+    #[serde(rename = "AgeRecipients")]
+    pub age_recipients: Vec<String>,
+    /// age identity file used to decrypt packs encrypted to recipients
+    #[serde(rename = "AgeIdentityFile")]
+    pub age_identity_file: String,
+    /// Keys the data key of each object is wrapped for: `passphrase`,
+    /// `hardware-key` for the enrolled security key, a key file path,
+    /// `x25519:<hex>` from `keys generate`, which only needs the public half
+    /// here, or `aws-kms:<key>` / `aliyun-kms:<key>` for a KMS master key
+    /// (via the `aws` / `aliyun` CLI). Empty keeps the single key file /
+    /// passphrase format.
+    #[serde(rename = "Recipients")]
+    pub recipients: Vec<String>,
+    /// Default --deadline per command, e.g. `{ up = "2m", down = "5m" }`
+    #[serde(rename = "Deadlines")]
+    pub deadlines: BTreeMap<String, String>,
+    /// GPG key IDs, fingerprints or emails to encrypt packs and shares to
+    #[serde(rename = "GpgRecipients")]
+    pub gpg_recipients: Vec<String>,
+    /// Ed25519 public keys (hex, from `keys device`) whose signed packs `down`
+    /// accepts, by device name. Empty skips verification
+    #[serde(rename = "TrustedDevices")]
+    pub trusted_devices: BTreeMap<String, String>,
+    /// File holding the 32-byte outer encryption key (raw, or 64 hex characters),
+    /// shared out-of-band by a team. When set it is used instead of the passphrase
+    #[serde(rename = "KeyFile")]
+    pub key_file: String,
+    /// Always include local git config fragments in the manifest (same as `up --git-config`)
+    #[serde(rename = "SnapshotGitConfig")]
+    pub snapshot_git_config: bool,
+    /// Regexes selecting which local config keys to snapshot; `{branch}` expands
+    /// to the current branch
+    #[serde(rename = "GitConfigPatterns")]
+    pub git_config_patterns: Vec<String>,
+    /// FIDO2 credential id (base64) from `keys enroll-token`
+    #[serde(rename = "HardwareKeyCredential")]
+    pub hardware_key_credential: String,
+    /// Salt (base64) sent to the security key's hmac-secret extension
+    #[serde(rename = "HardwareKeySalt")]
+    pub hardware_key_salt: String,
+    /// Device path of the security key; empty uses the first one found
+    #[serde(rename = "HardwareKeyDevice")]
+    pub hardware_key_device: String,
+    /// Also ask for the security key's PIN, not just a touch
+    #[serde(rename = "HardwareKeyPin")]
+    pub hardware_key_pin: bool,
+    /// Language of messages: `en` or `zh-CN`. Empty follows LANG
+    #[serde(rename = "Language")]
+    pub language: String,
+    /// After `down`, record the received commit as
+    /// `refs/sync/hosts/<uploading host>/<branch>` for comparing with plain git.
+    /// On by default
+    #[serde(rename = "MirrorRefs")]
+    pub mirror_refs: bool,
+    /// Have `down` refuse packs whose temporary commits don't pass
+    /// `git verify-commit`; `up` signs them when git's commit.gpgSign is set
+    #[serde(rename = "VerifyCommits")]
+    pub verify_commits: bool,
+    /// Shell command `up` runs in the working directory before packing, e.g.
+    /// `cargo fmt`; `up` stops if it fails. Like PostDown, only read from
+    /// the global config and .git/sync.toml, never a cloned .sync.toml
+    #[serde(rename = "PreUp")]
+    pub pre_up: String,
+    /// Shell command `down` runs after applying a pack, e.g. `pnpm install`
+    #[serde(rename = "PostDown")]
+    pub post_down: String,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            prefix: String::new(),
+            encrypt: true,
+            user: String::new(),
+            hash_algorithm: HashAlgorithm::default(),
+            compression: Compression::default(),
+            key_file: String::new(),
+            encryption: Encryption::default(),
+            age_recipients: Vec::new(),
+            age_identity_file: String::new(),
+            recipients: Vec::new(),
+            deadlines: BTreeMap::new(),
+            gpg_recipients: Vec::new(),
+            trusted_devices: BTreeMap::new(),
+            snapshot_git_config: false,
+            git_config_patterns: Vec::new(),
+            hardware_key_credential: String::new(),
+            hardware_key_salt: String::new(),
+            hardware_key_device: String::new(),
+            hardware_key_pin: false,
+            language: String::new(),
+            mirror_refs: true,
+            verify_commits: false,
+            pre_up: String::new(),
+            post_down: String::new(),
+        }
+    }
+}
+
+impl SyncConfig {
+    pub fn user(&self) -> Option<&str> {
+        Some(self.user.as_str()).filter(|user| !user.is_empty())
+    }
+
+    pub fn age_identity_file(&self) -> Option<&Path> {
+        Some(self.age_identity_file.as_str())
+            .filter(|path| !path.is_empty())
+            .map(Path::new)
+    }
+
+    pub fn key_file(&self) -> Option<&Path> {
+        Some(self.key_file.as_str())
+            .filter(|path| !path.is_empty())
+            .map(Path::new)
+    }
+
+    /// Prepends the configured prefix (if any) to an object key
+    pub fn object_key(&self, key: &str) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", prefix, key)
+        }
+    }
+
+    pub fn git_config_patterns(&self) -> Vec<String> {
+        if self.git_config_patterns.is_empty() {
+            DEFAULT_GIT_CONFIG_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect()
+        } else {
+            self.git_config_patterns.clone()
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the first config file found on disk,
+    /// falling back to the embedded credentials if the binary was built with them.
+    /// `SYNC_*` environment variables override whatever was loaded.
+    pub fn load() -> Result<Config, Box<dyn std::error::Error>> {
+        Self::load_with_overrides(Vec::new(), true)
+    }
+
+    /// Like [`Config::load`], but merges the repository's `.sync.toml` (at the
+    /// worktree root) and then `.git/sync.toml` over the global config, so a
+    /// single repository can use a different bucket, prefix or encryption
+    /// setting. The tracked `.sync.toml` is limited to [`WORKTREE_KEYS`].
+    pub fn load_for_repo(repo: &Repository) -> Result<Config, Box<dyn std::error::Error>> {
+        Self::load_with_overrides(repo_overrides(repo)?, true)
+    }
+
+    /// Like [`Config::load_for_repo`], but doesn't require (or fetch) storage
+    /// credentials, for commands that never talk to the bucket.
+    pub fn load_local_for_repo(repo: &Repository) -> Result<Config, Box<dyn std::error::Error>> {
+        Self::load_with_overrides(repo_overrides(repo)?, false)
+    }
+
+    fn load_with_overrides(
+        overrides: Vec<toml::Table>,
+        needs_storage: bool,
+    ) -> Result<Config, Box<dyn std::error::Error>> {
+        let candidates = config_file_candidates();
+
+        let mut table = match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => read_table(path)?,
+            // An explicitly requested file must exist; silently falling back to
+            // the embedded config would run against the wrong bucket
+            None if explicit_config_path().is_some() => {
+                return Err(format!("Config file not found: {}", candidates[0].display()).into())
+            }
+            None => match EMBEDDED_CONFIG {
+                Some(content) => content.parse::<toml::Table>()?,
+                // No file at all is fine as long as the environment provides everything
+                None => toml::Table::new(),
+            },
+        };
+
+        // A repository's .sync.toml may turn receive-only mode and commit
+        // verification on, never off
+        let receive_only = get_key(&table, "oss.ReceiveOnly") == Some(&toml::Value::Boolean(true));
+        let verify_commits =
+            get_key(&table, "sync.VerifyCommits") == Some(&toml::Value::Boolean(true));
+        for override_table in overrides {
+            merge_tables(&mut table, override_table);
+        }
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Invalid configuration: {}", e))?;
+
+        config.apply_env_overrides();
+        config.oss.receive_only |= receive_only;
+        config.sync.verify_commits |= verify_commits;
+        config.oss.apply_defaults();
+        crate::i18n::configure(&config.sync.language)?;
+        if let Some(path) = explicit_key_file() {
+            config.sync.key_file = path.display().to_string();
+        }
+
+        if !needs_storage {
+            return Ok(config);
+        }
+
+        // An explicit SYNC_ACCESS_KEY_SECRET still wins over the keyring
+        if config.oss.secret_storage == SecretStorage::Keyring
+            && config.oss.access_key_secret.is_empty()
+            && !config.oss.access_key_id.is_empty()
+        {
+            config.oss.access_key_secret = keyring_entry(&config.oss.access_key_id)?
+                .get_password()
+                .map_err(|e| match e {
+                    keyring::Error::NoEntry => format!(
+                        "No secret for {} in the system keyring, run `packer config store-secret`",
+                        config.oss.access_key_id
+                    ),
+                    e => format!("Failed to read secret from the system keyring: {}", e),
+                })?;
+        }
+
+        if let Err(e) = config.validate() {
+            return Err(format!(
+                "{} (config files looked up: {})",
+                e,
+                candidates
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self) {
+        let overrides = [
+            ("SYNC_ACCESS_KEY_ID", &mut self.oss.access_key_id),
+            ("SYNC_ACCESS_KEY_SECRET", &mut self.oss.access_key_secret),
+            ("SYNC_BUCKET", &mut self.oss.bucket_name),
+            ("SYNC_ENDPOINT", &mut self.oss.endpoint),
+            ("SYNC_USER", &mut self.sync.user),
+            ("SYNC_KEY_FILE", &mut self.sync.key_file),
+            ("SYNC_LANG", &mut self.sync.language),
+        ];
+
+        for (name, field) in overrides {
+            if let Ok(value) = std::env::var(name) {
+                if !value.is_empty() {
+                    *field = value;
+                }
+            }
+        }
+
+        // Like the config setting, the environment can only enable it
+        if matches!(
+            std::env::var("SYNC_RECEIVE_ONLY").as_deref(),
+            Ok("1") | Ok("true")
+        ) {
+            self.oss.receive_only = true;
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut required = vec![("BucketName", "SYNC_BUCKET", &self.oss.bucket_name)];
+        if self.oss.backend != Backend::Fs {
+            required.push(("Endpoint", "SYNC_ENDPOINT", &self.oss.endpoint));
+        }
+
+        if self.oss.backend == Backend::Azure
+            && self.oss.credential_source == CredentialSource::Chain
+        {
+            return Err(
+                "oss.CredentialSource = \"chain\" is only supported with oss.Type = \"s3\""
+                    .to_string(),
+            );
+        }
+
+        for oss in std::iter::once(&self.oss).chain(&self.oss.mirrors) {
+            if oss.server_side_encryption != ServerSideEncryption::None
+                && oss.backend != Backend::S3
+            {
+                return Err(format!(
+                    "oss.ServerSideEncryption is only supported with oss.Type = \"s3\", not {:?}",
+                    oss.backend.name()
+                ));
+            }
+            if !oss.sse_kms_key_id.is_empty()
+                && oss.server_side_encryption != ServerSideEncryption::Kms
+            {
+                return Err(
+                    "oss.SseKmsKeyId needs oss.ServerSideEncryption = \"aws:kms\"".to_string(),
+                );
+            }
+            crate::proxy::validate(&oss.proxy).map_err(|e| format!("oss.Proxy: {}", e))?;
+            if !oss.part_size.trim().is_empty() {
+                let part_size = crate::units::parse_size(&oss.part_size)
+                    .map_err(|e| format!("oss.PartSize: {}", e))?;
+                if part_size < 5 << 20 {
+                    return Err("oss.PartSize must be at least 5M, S3's smallest part".to_string());
+                }
+            }
+            if oss.requester_pays && oss.backend != Backend::S3 {
+                return Err(format!(
+                    "oss.RequesterPays is only supported with oss.Type = \"s3\", not {:?}",
+                    oss.backend.name()
+                ));
+            }
+            if oss.tagging && !matches!(oss.backend, Backend::S3 | Backend::Azure) {
+                return Err(format!(
+                    "oss.Tagging needs oss.Type = \"s3\" or \"azure\"; {} has no object tags",
+                    oss.backend.name()
+                ));
+            }
+            crate::timeouts::Timeouts::new(oss)?;
+            if oss.retry.attempts == 0 {
+                return Err("oss.Retry.Attempts must be at least 1".to_string());
+            }
+        }
+
+        // The provider chain, like Google's credentials and ssh, brings its own
+        if self.oss.credential_source == CredentialSource::Static
+            && matches!(self.oss.backend, Backend::S3 | Backend::Azure)
+        {
+            required.push(("AccessKeyId", "SYNC_ACCESS_KEY_ID", &self.oss.access_key_id));
+            required.push((
+                "AccessKeySecret",
+                "SYNC_ACCESS_KEY_SECRET",
+                &self.oss.access_key_secret,
+            ));
+        }
+
+        let mut missing: Vec<String> = required
+            .iter()
+            .filter(|(_, _, value)| value.is_empty())
+            .map(|(key, env, _)| format!("oss.{} (or {})", key, env))
+            .collect();
+        for (i, mirror) in self.oss.mirrors.iter().enumerate() {
+            if mirror.bucket_name.is_empty() {
+                missing.push(format!("oss.Mirrors[{}].BucketName", i));
+            }
+            if mirror.endpoint.is_empty() && mirror.backend != Backend::Fs {
+                missing.push(format!("oss.Mirrors[{}].Endpoint", i));
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Missing configuration: {}", missing.join(", ")))
+        }
+    }
+}
+
+/// Keyring entry holding the secret for an access key ID
+pub fn keyring_entry(access_key_id: &str) -> Result<keyring::Entry, keyring::Error> {
+    keyring::Entry::new("sync", access_key_id)
+}
+
+/// Keyring entry holding the pack encryption passphrase.
+pub fn passphrase_keyring_entry() -> Result<keyring::Entry, keyring::Error> {
+    keyring::Entry::new("sync", "pack-passphrase")
+}
+
+/// Returns the repository config files that exist, in merge order.
+pub fn repo_config_files(repo: &Repository) -> Vec<PathBuf> {
+    let mut repo_files = Vec::new();
+    if let Some(workdir) = repo.workdir() {
+        repo_files.push(workdir.join(".sync.toml"));
+    }
+    repo_files.push(repo.path().join("sync.toml"));
+
+    repo_files
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+// The repository config files' tables, in merge order, with keys outside
+// WORKTREE_KEYS dropped from the tracked .sync.toml
+fn repo_overrides(repo: &Repository) -> Result<Vec<toml::Table>, Box<dyn std::error::Error>> {
+    let worktree_file = repo.workdir().map(|workdir| workdir.join(".sync.toml"));
+    let mut overrides = Vec::new();
+    for path in repo_config_files(repo) {
+        let mut table = read_table(&path)?;
+        if Some(&path) == worktree_file.as_ref() {
+            let mut allowed = toml::Table::new();
+            for key in WORKTREE_KEYS {
+                if let Some(value) = get_key(&table, key) {
+                    set_key(&mut allowed, key, value.clone())?;
+                }
+            }
+            for key in dotted_keys(&table) {
+                if get_key(&allowed, &key).is_none() {
+                    println!(
+                        "Warning: ignoring {} in {}; set it in .git/sync.toml or the global config",
+                        key,
+                        path.display()
+                    );
+                }
+            }
+            table = allowed;
+        }
+        overrides.push(table);
+    }
+    Ok(overrides)
+}
+
+// The keys of each section as dotted keys, e.g. `oss.BucketName`; nested
+// tables such as sync.Deadlines count as one key
+fn dotted_keys(table: &toml::Table) -> Vec<String> {
+    let mut keys = Vec::new();
+    for (section, value) in table {
+        match value.as_table() {
+            Some(entries) => keys.extend(entries.keys().map(|key| format!("{}.{}", section, key))),
+            None => keys.push(section.clone()),
+        }
+    }
+    keys
+}
+
+/// Returns the config file to edit: the first existing one, or the
+/// preferred location if none exists yet.
+pub fn config_file_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let candidates = config_file_candidates();
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => candidates
+            .into_iter()
+            .next()
+            .ok_or_else(|| "Could not determine a config file location (HOME is not set)".into()),
+    }
+}
+
+/// Reads a config file as a raw TOML table, or an empty table if it doesn't exist.
+/// Passphrase-encrypted files are decrypted transparently.
+pub fn read_table(path: &Path) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    if !path.is_file() {
+        return Ok(toml::Table::new());
+    }
+    let mut content = std::fs::read_to_string(path)?;
+
+    if let Some(sealed) = encrypted_payload(&content)? {
+        let salt = &sealed[..passphrase::SALT_SIZE];
+        let key = config_key(salt)?;
+        let plaintext = match passphrase::open_with_key(&key, &sealed[passphrase::SALT_SIZE..]) {
+            Ok(plaintext) => plaintext,
+            Err(e) => {
+                // Don't keep offering a cached key that doesn't work
+                forget_session_key();
+                return Err(format!("Failed to decrypt {}: {}", path.display(), e).into());
+            }
+        };
+        content = String::from_utf8(plaintext)?;
+    }
+
+    content
+        .parse::<toml::Table>()
+        .map_err(|e| format!("Failed to parse config file {}: {}", path.display(), e).into())
+}
+
+/// Writes a raw TOML table to the config file, readable only by the owner.
+/// A config file that is currently encrypted stays encrypted.
+pub fn write_table(path: &Path, table: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+    let content = toml::to_string_pretty(table)?;
+
+    let existing = std::fs::read_to_string(path).unwrap_or_default();
+    match encrypted_payload(&existing)? {
+        Some(sealed) => {
+            let salt = sealed[..passphrase::SALT_SIZE].to_vec();
+            let key = config_key(&salt)?;
+            write_private(path, &armor_config(&salt, &key, &content)?)
+        }
+        None => write_private(path, &content),
+    }
+}
+
+/// Writes the table to the config file unencrypted (`config decrypt`).
+pub fn write_table_plain(
+    path: &Path,
+    table: &toml::Table,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_private(path, &toml::to_string_pretty(table)?)
+}
+
+/// Writes the table encrypted under a new passphrase (`config encrypt`).
+pub fn write_table_encrypted(
+    path: &Path,
+    table: &toml::Table,
+    passphrase: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let salt = passphrase::random_salt();
+    let key = passphrase::derive_key(passphrase, &salt)?;
+    write_private(
+        path,
+        &armor_config(&salt, &key, &toml::to_string_pretty(table)?)?,
+    )?;
+    store_session_key(&salt, &key);
+    Ok(())
+}
+
+pub fn is_encrypted_file(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .map(|content| content.starts_with(ENCRYPTED_CONFIG_HEADER))
+        .unwrap_or(false)
+}
+
+/// Drops the cached config key so the next command prompts again.
+pub fn forget_session_key() {
+    if let Some(path) = session_key_path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Writes a file readable only by the owner, creating its directory.
+pub fn write_private(
+    path: &Path,
+    content: impl AsRef<[u8]>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+
+    // The file holds credentials
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(())
+}
+
+// Encrypted config files are text: a header line, then base64 of
+// salt | nonce | AES-GCM ciphertext of the TOML
+const ENCRYPTED_CONFIG_HEADER: &str = "# sync encrypted config v1";
+
+fn armor_config(
+    salt: &[u8],
+    key: &[u8; 32],
+    content: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut sealed = salt.to_vec();
+    sealed.extend_from_slice(&passphrase::seal_with_key(key, content.as_bytes())?);
+    Ok(format!(
+        "{}\n{}\n",
+        ENCRYPTED_CONFIG_HEADER,
+        general_purpose::STANDARD.encode(sealed)
+    ))
+}
+
+fn encrypted_payload(content: &str) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let Some(body) = content.strip_prefix(ENCRYPTED_CONFIG_HEADER) else {
+        return Ok(None);
+    };
+    let sealed = general_purpose::STANDARD.decode(body.trim())?;
+    if sealed.len() <= passphrase::SALT_SIZE {
+        return Err("Encrypted config file is truncated".into());
+    }
+    Ok(Some(sealed))
+}
+
+// Key for an encrypted config: SYNC_CONFIG_PASSPHRASE, then the key cached for
+// this login session, then an interactive prompt (whose result gets cached)
+fn config_key(salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+    if let Ok(passphrase) = std::env::var("SYNC_CONFIG_PASSPHRASE") {
+        return passphrase::derive_key(&passphrase, salt);
+    }
+
+    if let Some(key) = read_session_key(salt) {
+        return Ok(Zeroizing::new(key));
+    }
+
+    let passphrase = rpassword::prompt_password("Config passphrase: ")?;
+    let key = passphrase::derive_key(&passphrase, salt)?;
+    store_session_key(salt, &key);
+    Ok(key)
+}
+
+// The runtime dir is a per-user tmpfs cleared at logout, which makes it the
+// natural lifetime for "prompt once per session". Without it we always prompt.
+fn session_key_path() -> Option<PathBuf> {
+    paths::runtime_dir().map(|dir| dir.join("config.key"))
+}
+
+fn read_session_key(salt: &[u8]) -> Option<[u8; 32]> {
+    let content = std::fs::read_to_string(session_key_path()?).ok()?;
+    let cached = general_purpose::STANDARD.decode(content.trim()).ok()?;
+    // The key is only valid for the config it was derived for
+    if cached.len() != salt.len() + 32 || &cached[..salt.len()] != salt {
+        return None;
+    }
+    cached[salt.len()..].try_into().ok()
+}
+
+fn store_session_key(salt: &[u8], key: &[u8; 32]) {
+    let Some(path) = session_key_path() else {
+        return;
+    };
+    let mut cached = salt.to_vec();
+    cached.extend_from_slice(key);
+    // Caching is a convenience; failing to cache just means prompting again
+    let _ = write_private(&path, general_purpose::STANDARD.encode(cached));
+}
+
+// Recursively merges `overrides` into `base`; values in `overrides` win
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(override_table)) => {
+                merge_tables(base_table, override_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Looks up a dotted key such as `oss.BucketName` in a raw config table.
+pub fn get_key<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+/// Sets a dotted key such as `oss.BucketName`, creating intermediate tables.
+pub fn set_key(
+    table: &mut toml::Table,
+    key: &str,
+    value: toml::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(format!("Invalid config key: {}", key).into());
+    }
+
+    let (last, sections) = parts.split_last().unwrap();
+    let mut current = table;
+    for section in sections {
+        current = current
+            .entry(section.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("Config key {} is not a table", section))?;
+    }
+    current.insert(last.to_string(), value);
+
+    Ok(())
+}
+
+/// Parses a command line value as a TOML value (`true`, `42`, `["a"]`),
+/// treating anything that isn't valid TOML as a plain string.
+pub fn parse_value(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+// Set from `--config` / SYNC_CONFIG before any command runs
+static EXPLICIT_CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses `path` as the only config file, instead of searching the default locations.
+pub fn set_config_path(path: PathBuf) {
+    let _ = EXPLICIT_CONFIG_PATH.set(path);
+}
+
+pub fn explicit_config_path() -> Option<&'static Path> {
+    EXPLICIT_CONFIG_PATH.get().map(PathBuf::as_path)
+}
+
+// Set from `--key-file`, which beats both sync.KeyFile and SYNC_KEY_FILE
+static EXPLICIT_KEY_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_key_file(path: PathBuf) {
+    let _ = EXPLICIT_KEY_FILE.set(path);
+}
+
+pub fn explicit_key_file() -> Option<&'static Path> {
+    EXPLICIT_KEY_FILE.get().map(PathBuf::as_path)
+}
+
+// Config file locations in order of precedence
+fn config_file_candidates() -> Vec<PathBuf> {
+    if let Some(path) = explicit_config_path() {
+        return vec![path.to_path_buf()];
+    }
+
+    let mut candidates = Vec::new();
+
+    if let Some(dir) = paths::config_dir() {
+        candidates.push(dir.join("config.toml"));
+    }
+
+    // Where earlier versions looked on every platform; still read so existing
+    // macOS and Windows setups keep working. USERPROFILE covers Windows.
+    if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        let path = PathBuf::from(home)
+            .join(".config")
+            .join("sync")
+            .join("config.toml");
+        if !candidates.contains(&path) {
+            candidates.push(path);
+        }
+    }
+
+    candidates
+}
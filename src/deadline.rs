@@ -0,0 +1,89 @@
+use crate::config::SyncConfig;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Time allowed for undoing partial state once the deadline has passed
+const CLEANUP_GRACE: Duration = Duration::from_secs(10);
+
+static LIMIT: OnceLock<Duration> = OnceLock::new();
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+static CLEANING_UP: AtomicBool = AtomicBool::new(false);
+
+/// Bounds the operation to `limit` from now (`--deadline`).
+pub fn set(limit: Duration) {
+    if LIMIT.set(limit).is_ok() {
+        restart();
+    }
+}
+
+/// Applies sync.Deadlines[`command`] unless `--deadline` already set one.
+/// The clock still counts from process start.
+pub fn set_default(command: &str, sync: &SyncConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(limit) = sync.deadlines.get(command) {
+        let limit = crate::units::parse_duration(limit)
+            .map_err(|e| format!("sync.Deadlines.{}: {}", command, e))?;
+        let _ = LIMIT.set(limit);
+    }
+    Ok(())
+}
+
+/// Starts the clock again, for long-running commands that bound each round.
+pub fn restart() {
+    *STARTED.lock().unwrap() = Some(Instant::now());
+}
+
+/// Records the process start; deadlines from the config count from here.
+pub fn start() {
+    STARTED.lock().unwrap().get_or_insert_with(Instant::now);
+}
+
+/// Time left until the deadline; `None` when there is none.
+pub fn remaining() -> Option<Duration> {
+    let limit = *LIMIT.get()?;
+    let started = (*STARTED.lock().unwrap())?;
+    let remaining = limit.saturating_sub(started.elapsed());
+    if CLEANING_UP.load(Ordering::Relaxed) {
+        return Some(remaining.max(CLEANUP_GRACE));
+    }
+    Some(remaining)
+}
+
+fn exceeded(phase: &str) -> Box<dyn std::error::Error> {
+    t!(
+        "deadline.exceeded",
+        crate::units::format_duration(*LIMIT.get().unwrap_or(&Duration::ZERO)),
+        phase
+    )
+    .into()
+}
+
+/// Fails if the deadline has passed, so the next phase doesn't start.
+pub fn check(phase: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match remaining() {
+        Some(remaining) if remaining.is_zero() => Err(exceeded(phase)),
+        _ => Ok(()),
+    }
+}
+
+/// Runs `future`, abandoning it if the deadline passes first.
+pub async fn bound<T>(
+    phase: &str,
+    future: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    match remaining() {
+        None => future.await,
+        Some(remaining) => tokio::time::timeout(remaining, future)
+            .await
+            .unwrap_or_else(|_| Err(exceeded(phase))),
+    }
+}
+
+/// Runs `undo` with a short grace period even if the deadline has passed.
+pub fn cleanup<T>(undo: impl FnOnce() -> T) -> T {
+    CLEANING_UP.store(true, Ordering::Relaxed);
+    let result = undo();
+    CLEANING_UP.store(false, Ordering::Relaxed);
+    result
+}
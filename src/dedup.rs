@@ -0,0 +1,119 @@
+use crate::config::{Config, Encryption, SyncConfig};
+
+/// What this machine last stored under each object key: the payload's
+/// checksum (from the envelope), how it was sealed, and the size and time
+/// the backend reported afterwards. `up` skips sealing and uploading a pack
+/// whose payload the bucket already holds, which for multi-GB packs is most
+/// of its time. One file per storage location, as the same keys exist in
+/// each.
+pub struct Index {
+    path: std::path::PathBuf,
+    sealing: String,
+}
+
+impl Index {
+    pub fn open(config: &Config) -> Result<Index, Box<dyn std::error::Error>> {
+        let target = format!(
+            "{} {} {}",
+            config.oss.backend.name(),
+            config.oss.endpoint,
+            config.oss.bucket_name
+        );
+        let path = crate::paths::dedup_index_file(&blake3::hash(target.as_bytes()).to_hex()[..16])
+            .ok_or("Could not determine the cache directory")?;
+        Ok(Index {
+            path,
+            sealing: sealing(&config.sync),
+        })
+    }
+
+    /// Whether `key` still holds the payload with `checksum`, sealed the way
+    /// it would be now. Anything else touching the object, such as another
+    /// machine uploading or `keys rotate`, changes its size or time.
+    pub fn holds(
+        &self,
+        config: &Config,
+        key: &str,
+        checksum: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(entry) = self.read().remove(key) else {
+            return Ok(false);
+        };
+        if entry.get("checksum").and_then(|v| v.as_str()) != Some(checksum)
+            || entry.get("sealing").and_then(|v| v.as_str()) != Some(self.sealing.as_str())
+        {
+            return Ok(false);
+        }
+        let Some(info) = crate::storage::backend(&config.oss).head(key)? else {
+            return Ok(false);
+        };
+        Ok(
+            entry.get("size").and_then(|v| v.as_integer()) == Some(info.size)
+                && info.last_modified.is_some()
+                && entry.get("modified").and_then(|v| v.as_str()) == info.last_modified.as_deref(),
+        )
+    }
+
+    /// Records that `key` now holds the payload with `checksum`. Only an
+    /// optimization, so failing to record just means uploading again.
+    pub fn record(&self, config: &Config, key: &str, checksum: &str) {
+        let _ = self.try_record(config, key, checksum);
+    }
+
+    fn try_record(
+        &self,
+        config: &Config,
+        key: &str,
+        checksum: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let info = crate::storage::backend(&config.oss)
+            .head(key)?
+            .ok_or("object disappeared after the upload")?;
+        let _lock = crate::lock::state_file(&self.path)?;
+
+        let mut entry = toml::Table::new();
+        entry.insert("checksum".into(), checksum.into());
+        entry.insert("sealing".into(), self.sealing.as_str().into());
+        entry.insert("size".into(), info.size.into());
+        if let Some(modified) = info.last_modified {
+            entry.insert("modified".into(), modified.into());
+        }
+        let mut table = self.read();
+        table.insert(key.to_string(), entry.into());
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, toml::to_string(&table)?)?;
+        Ok(())
+    }
+
+    fn read(&self) -> toml::Table {
+        std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| content.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+// Everything that decides how a payload gets sealed; after a change the stored
+// object is no longer what an upload would put there
+fn sealing(sync: &SyncConfig) -> String {
+    let cipher = match sync.encryption {
+        _ if !sync.encrypt => "none",
+        Encryption::Aes => "aes",
+        Encryption::Age => "age",
+        Encryption::Gpg => "gpg",
+    };
+    let settings = format!(
+        "{} {} {:?} {:?} {:?} {} {}",
+        cipher,
+        sync.compression.name(),
+        sync.recipients,
+        sync.age_recipients,
+        sync.gpg_recipients,
+        sync.key_file,
+        sync.hardware_key_credential
+    );
+    blake3::hash(settings.as_bytes()).to_hex()[..16].to_string()
+}
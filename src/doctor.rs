@@ -0,0 +1,373 @@
+use crate::config::{self, Backend, Config, CredentialSource, OssConfig};
+use crate::storage::{self, ObjectHeaders};
+use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
+use git2::Repository;
+use tokio::runtime::Runtime;
+
+// Tallies results while the checks print them
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, check: &str, detail: &str) {
+        println!("[ok]   {}: {}", check, detail);
+    }
+
+    fn warn(&mut self, check: &str, problem: &str, hint: &str) {
+        self.warnings += 1;
+        println!("[warn] {}: {}", check, problem);
+        println!("       hint: {}", hint);
+    }
+
+    fn fail(&mut self, check: &str, problem: &str, hint: &str) {
+        self.failures += 1;
+        println!("[FAIL] {}: {}", check, problem);
+        println!("       hint: {}", hint);
+    }
+}
+
+/// Runs every diagnostic, printing one line per check plus a hint for each
+/// problem. Fails if any check failed; warnings alone don't.
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut report = Report::default();
+
+    check_git_binary(&mut report);
+    let repo = check_repository(&mut report);
+
+    if let Some(config) = check_config(&mut report, repo.as_ref()) {
+        check_bucket(&mut report, &config);
+        for (i, mirror) in config.oss.mirrors.iter().enumerate() {
+            let check = format!("mirror {}", i + 1);
+            check_container(&mut report, mirror, &probe_key(&config), [&check, &check]);
+        }
+    }
+
+    println!();
+    if report.failures > 0 {
+        return Err(format!(
+            "{} check(s) failed, {} warning(s)",
+            report.failures, report.warnings
+        )
+        .into());
+    }
+    println!("All checks passed ({} warning(s))", report.warnings);
+    Ok(())
+}
+
+fn check_git_binary(report: &mut Report) {
+    // `down` shells out to git for merges, rebases and checkouts libgit2
+    // can't do (sparse-checkout, Git LFS), and `stash-down` to store entries
+    match std::process::Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            report.ok("git", String::from_utf8_lossy(&output.stdout).trim())
+        }
+        Ok(output) => report.warn(
+            "git",
+            &format!("`git --version` exited with {}", output.status),
+            "reinstall git; `down --strategy` and `stash-down` need it",
+        ),
+        Err(e) => report.warn(
+            "git",
+            &format!("git binary not found: {}", e),
+            "plain `up` and `down` work without it; install git for the rest",
+        ),
+    }
+}
+
+fn check_repository(report: &mut Report) -> Option<Repository> {
+    let repo = match std::env::current_dir()
+        .map_err(|e| e.to_string())
+        .and_then(|dir| Repository::open(dir).map_err(|e| e.message().to_string()))
+    {
+        Ok(repo) => repo,
+        Err(e) => {
+            report.warn(
+                "repository",
+                &format!("not in a git repository ({})", e),
+                "run doctor from the root of the repository you sync to check it too",
+            );
+            return None;
+        }
+    };
+    report.ok(
+        "repository",
+        &repo.workdir().unwrap_or(repo.path()).display().to_string(),
+    );
+
+    let branch = match repo.head() {
+        Ok(head) if head.is_branch() => head.shorthand().map(str::to_string),
+        Ok(_) => {
+            report.fail(
+                "branch",
+                "HEAD is detached",
+                "check out a branch; packs are stored per branch",
+            );
+            None
+        }
+        Err(e) => {
+            report.fail(
+                "branch",
+                &format!("cannot read HEAD: {}", e.message()),
+                "make at least one commit before syncing",
+            );
+            None
+        }
+    };
+
+    match repo.find_remote("origin") {
+        Ok(remote) => {
+            report.ok("remote", remote.url().unwrap_or("origin (no URL)"));
+            if let Some(branch) = &branch {
+                let upstream = format!("refs/remotes/origin/{}", branch);
+                if repo.find_reference(&upstream).is_ok() {
+                    report.ok("upstream", &upstream);
+                } else {
+                    report.warn(
+                        "upstream",
+                        &format!("{} not found", upstream),
+                        "`up` will pack every commit; push the branch or run `git fetch` to keep packs small",
+                    );
+                }
+            }
+        }
+        Err(_) => report.warn(
+            "remote",
+            "no `origin` remote",
+            "packs will be stored under unknown/unknown/; add an origin remote to keep repositories apart",
+        ),
+    }
+
+    Some(repo)
+}
+
+fn check_config(report: &mut Report, repo: Option<&Repository>) -> Option<Config> {
+    let loaded = match repo {
+        Some(repo) => Config::load_for_repo(repo),
+        None => Config::load(),
+    };
+
+    let config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(
+                "config",
+                &e.to_string(),
+                "run `packer config init`, or fix the file with `packer config set`",
+            );
+            return None;
+        }
+    };
+
+    let source = config::config_file_path()
+        .ok()
+        .filter(|path| path.is_file())
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "environment / embedded defaults".to_string());
+    report.ok("config", &source);
+
+    if config.oss.backend == Backend::Fs {
+        report.ok("endpoint", &format!("directory {}", config.oss.bucket_name));
+        return Some(config);
+    } else if config.oss.backend == Backend::Sftp {
+        if !config.oss.endpoint.starts_with("sftp://") {
+            report.fail(
+                "endpoint",
+                &format!("{:?} is not an sftp:// URL", config.oss.endpoint),
+                "set it with `packer config set oss.Endpoint sftp://user@host`",
+            );
+            return None;
+        }
+    } else if !config.oss.endpoint.starts_with("http://")
+        && !config.oss.endpoint.starts_with("https://")
+    {
+        report.fail(
+            "endpoint",
+            &format!("{:?} is not an http(s) URL", config.oss.endpoint),
+            "set it with `packer config set oss.Endpoint https://...`",
+        );
+        return None;
+    }
+    report.ok("endpoint", &config.oss.endpoint);
+
+    Some(config)
+}
+
+fn check_bucket(report: &mut Report, config: &Config) {
+    if config.oss.backend != Backend::S3 {
+        check_container(
+            report,
+            &config.oss,
+            &probe_key(config),
+            ["bucket", "credentials"],
+        );
+        return;
+    }
+
+    let rt = match Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            report.fail(
+                "bucket",
+                &e.to_string(),
+                "could not start the async runtime",
+            );
+            return;
+        }
+    };
+
+    rt.block_on(async {
+        let client = crate::s3::create_s3_client(&config.oss).await;
+        let bucket = &config.oss.bucket_name;
+
+        let credentials_hint = match config.oss.credential_source {
+            CredentialSource::Static => {
+                "check oss.AccessKeyId / AccessKeySecret and the bucket policy"
+            }
+            CredentialSource::Chain => {
+                "check the AWS credential chain (AWS_PROFILE, ~/.aws/credentials, instance role) and the bucket policy"
+            }
+        };
+
+        match client.head_bucket().bucket(bucket).send().await {
+            Ok(_) => report.ok("bucket", &format!("{} is reachable", bucket)),
+            Err(SdkError::ServiceError(e)) if e.err().is_not_found() => {
+                report.fail(
+                    "bucket",
+                    &format!("bucket {} does not exist at {}", bucket, config.oss.endpoint),
+                    "check oss.BucketName and that the endpoint is in the bucket's region",
+                );
+                return;
+            }
+            Err(e @ (SdkError::DispatchFailure(_) | SdkError::TimeoutError(_))) => {
+                report.fail(
+                    "bucket",
+                    &format!("cannot connect: {}", DisplayErrorContext(&e)),
+                    "check oss.Endpoint, network access and any proxy settings",
+                );
+                return;
+            }
+            Err(e) => {
+                report.fail(
+                    "bucket",
+                    &format!("HEAD bucket failed: {}", DisplayErrorContext(&e)),
+                    credentials_hint,
+                );
+                return;
+            }
+        }
+
+        if config.oss.receive_only {
+            report.ok("credentials", "receive-only; write probe skipped");
+            return;
+        }
+
+        // Reading bucket metadata doesn't prove we can write; probe with a tiny object
+        let probe_key = probe_key(config);
+
+        // Buckets that mandate SSE reject uploads without the headers
+        let probe = client
+            .put_object()
+            .bucket(bucket)
+            .key(&probe_key)
+            .set_request_payer(crate::s3::request_payer(&config.oss))
+            .body(b"packer doctor probe".to_vec().into());
+        if let Err(e) = crate::s3::with_server_side_encryption(probe, &config.oss)
+            .send()
+            .await
+        {
+            report.fail(
+                "credentials",
+                &format!("cannot write {}: {}", probe_key, DisplayErrorContext(&e)),
+                credentials_hint,
+            );
+            return;
+        }
+
+        match client
+            .delete_object()
+            .bucket(bucket)
+            .key(&probe_key)
+            .set_request_payer(crate::s3::request_payer(&config.oss))
+            .send()
+            .await
+        {
+            Ok(_) => report.ok("credentials", "write and delete permitted"),
+            Err(e) => report.warn(
+                "credentials",
+                &format!(
+                    "wrote {} but cannot delete it: {}",
+                    probe_key,
+                    DisplayErrorContext(&e)
+                ),
+                "autosave pruning needs delete permission; remove the probe object manually",
+            ),
+        }
+    });
+}
+
+// Other backends and mirrors are checked through the storage trait: a listing
+// proves the container is reachable, and the same probe object as on S3 that
+// we can write. `checks` names the reachability and the credentials check.
+fn check_container(report: &mut Report, oss: &OssConfig, probe_key: &str, checks: [&str; 2]) {
+    let [reachable, credentials] = checks;
+    let backend = storage::single(oss);
+    let container = &oss.bucket_name;
+    let credentials_hint = match oss.backend {
+        Backend::Gcs => {
+            "check oss.CredentialsFile or the application default credentials and the bucket's IAM roles"
+        }
+        Backend::Fs => "check that oss.BucketName is mounted and writable",
+        Backend::Sftp => {
+            "check that `ssh` logs in without a password (oss.SshKey or the agent) and that the directory is writable"
+        }
+        _ => {
+            "check oss.AccessKeyId (the storage account), oss.AccessKeySecret (its key) and the container's access policy"
+        }
+    };
+
+    if let Err(e) = backend.list(probe_key) {
+        report.fail(
+            reachable,
+            &format!("cannot list {} at {}: {}", container, oss.endpoint, e),
+            credentials_hint,
+        );
+        return;
+    }
+    report.ok(reachable, &format!("{} is reachable", container));
+
+    if oss.receive_only {
+        report.ok(credentials, "receive-only; write probe skipped");
+        return;
+    }
+
+    let probe = b"packer doctor probe".to_vec();
+    if let Err(e) = backend.put(probe_key, probe, &ObjectHeaders::default()) {
+        report.fail(
+            credentials,
+            &format!("cannot write {}: {}", probe_key, e),
+            credentials_hint,
+        );
+        return;
+    }
+    match backend.delete(probe_key) {
+        Ok(()) => report.ok(credentials, "write and delete permitted"),
+        Err(e) => report.warn(
+            credentials,
+            &format!("wrote {} but cannot delete it: {}", probe_key, e),
+            "autosave pruning needs delete permission; remove the probe object manually",
+        ),
+    }
+}
+
+fn probe_key(config: &Config) -> String {
+    let hostname = hostname::get()
+        .unwrap_or_else(|_| "unknown".into())
+        .to_string_lossy()
+        .to_string();
+    config
+        .sync
+        .object_key(&format!(".packer-doctor/{}", hostname))
+}
@@ -0,0 +1,194 @@
+use crate::compression::Compression;
+
+// Header layout (version 3):
+//   magic (4) | version (1) | cipher id (1) | compression id (1) | flags (1)
+//   | checksum length (1) | checksum
+// where the checksum is the payload's, as `hash::HashAlgorithm::checksum`
+// writes it, or empty. Version 2 had no checksum; version 1 had no cipher id
+// either and marked encryption in the flags instead.
+const MAGIC: &[u8; 4] = b"SYNC";
+const VERSION: u8 = 3;
+const V2_HEADER_SIZE: usize = 8;
+const V1_HEADER_SIZE: usize = 7;
+
+// Version 1 flags: the body is encrypted, and if so whether with GPG
+const V1_FLAG_ENCRYPTED: u8 = 0x01;
+const V1_FLAG_GPG: u8 = 0x02;
+
+/// The AES payload was encrypted with [`associated_data`] for its object key,
+/// so it only opens under the key it was uploaded to.
+pub const FLAG_BOUND_TO_KEY: u8 = 0x01;
+const KNOWN_FLAGS: u8 = FLAG_BOUND_TO_KEY;
+
+/// How the body is encrypted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    None,
+    /// Two-round AES-256-GCM, see `keys`
+    Aes,
+    Age,
+    Gpg,
+}
+
+impl Cipher {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cipher::None => "none",
+            Cipher::Aes => "aes",
+            Cipher::Age => "age",
+            Cipher::Gpg => "gpg",
+        }
+    }
+
+    /// Identifier stored in the header. Never reuse a retired id.
+    pub fn id(&self) -> u8 {
+        match self {
+            Cipher::None => 0,
+            Cipher::Aes => 1,
+            Cipher::Age => 2,
+            Cipher::Gpg => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            0 => Some(Cipher::None),
+            1 => Some(Cipher::Aes),
+            2 => Some(Cipher::Age),
+            3 => Some(Cipher::Gpg),
+            _ => None,
+        }
+    }
+}
+
+/// What the uploader did to the payload, stored in front of it so any machine
+/// can decode the object regardless of its own settings.
+pub struct Header {
+    pub cipher: Cipher,
+    pub compression: Compression,
+    /// `FLAG_*` bits
+    pub flags: u8,
+    /// Checksum of the payload before compression and encryption, e.g.
+    /// `blake3:<hex>`; objects written before version 3 have none
+    pub checksum: Option<String>,
+}
+
+/// A parsed object: its header and the (possibly encrypted) compressed body.
+pub struct Envelope<'a> {
+    /// Header version the object was written with
+    pub version: u8,
+    pub header: Header,
+    pub body: &'a [u8],
+}
+
+/// Prepends the header to an already compressed (and possibly encrypted) body.
+pub fn wrap(header: &Header, body: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let checksum = header.checksum.as_deref().unwrap_or("").as_bytes();
+    let checksum_len = u8::try_from(checksum.len()).map_err(|_| {
+        format!(
+            "Checksum too long for the envelope: {} bytes",
+            checksum.len()
+        )
+    })?;
+
+    let mut data = Vec::with_capacity(V2_HEADER_SIZE + 1 + checksum.len() + body.len());
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.push(header.cipher.id());
+    data.push(header.compression.id());
+    data.push(header.flags);
+    data.push(checksum_len);
+    data.extend_from_slice(checksum);
+    data.extend_from_slice(&body);
+    Ok(data)
+}
+
+/// Splits an object into its header and body.
+///
+/// Returns `Ok(None)` for objects uploaded before the envelope existed, which
+/// the caller decodes the old way.
+pub fn unwrap(data: &[u8]) -> Result<Option<Envelope<'_>>, Box<dyn std::error::Error>> {
+    if data.len() < V1_HEADER_SIZE || &data[..4] != MAGIC {
+        return Ok(None);
+    }
+
+    let (cipher, compression_id, flags, checksum, body) = match data[4] {
+        1 => {
+            let flags = data[6];
+            let body = &data[V1_HEADER_SIZE..];
+            let cipher = if flags & V1_FLAG_ENCRYPTED == 0 {
+                Cipher::None
+            } else if flags & V1_FLAG_GPG != 0 {
+                Cipher::Gpg
+            } else if crate::age_encryption::is_age(body) {
+                Cipher::Age
+            } else {
+                Cipher::Aes
+            };
+            (cipher, data[5], 0, None, body)
+        }
+        2 | VERSION if data.len() >= V2_HEADER_SIZE => {
+            let cipher = Cipher::from_id(data[5]).ok_or_else(|| {
+                format!(
+                    "Unknown cipher id {} in envelope; upgrade packer to read this object \
+                     (see `packer help envelope`)",
+                    data[5]
+                )
+            })?;
+            if data[7] & !KNOWN_FLAGS != 0 {
+                return Err(format!(
+                    "Unknown envelope flags {:#04x}; upgrade packer to read this object \
+                     (see `packer help envelope`)",
+                    data[7]
+                )
+                .into());
+            }
+            let (checksum, body) = match data[4] {
+                2 => (None, &data[V2_HEADER_SIZE..]),
+                _ => {
+                    let rest = &data[V2_HEADER_SIZE..];
+                    let len = *rest.first().ok_or("Envelope is truncated")? as usize;
+                    let checksum = rest.get(1..1 + len).ok_or("Envelope is truncated")?;
+                    let checksum = std::str::from_utf8(checksum)
+                        .map_err(|_| "Envelope checksum is not text")?;
+                    let checksum = Some(checksum.to_string()).filter(|c| !c.is_empty());
+                    (checksum, &rest[1 + len..])
+                }
+            };
+            (cipher, data[6], data[7], checksum, body)
+        }
+        version => {
+            return Err(format!(
+                "Unsupported envelope version {} (this build reads versions 1 to {}; see \
+                 `packer help envelope`)",
+                version, VERSION
+            )
+            .into())
+        }
+    };
+
+    let compression = Compression::from_id(compression_id).ok_or_else(|| {
+        format!(
+            "Unknown compression id {} in envelope; upgrade packer to read this object",
+            compression_id
+        )
+    })?;
+
+    Ok(Some(Envelope {
+        version: data[4],
+        header: Header {
+            cipher,
+            compression,
+            flags,
+            checksum,
+        },
+        body,
+    }))
+}
+
+/// AES-GCM associated data tying a payload to its object key. The key spells
+/// out prefix, repository owner and name, branch and user, so a blob copied
+/// to another path or swapped between branches fails authentication.
+pub fn associated_data(object_key: &str) -> Vec<u8> {
+    format!("packer object key v1\n{}", object_key).into_bytes()
+}
@@ -0,0 +1,208 @@
+use crate::config::OssConfig;
+use crate::progress;
+use crate::rest::encode;
+use crate::retry;
+use crate::storage::{
+    ListedObject, ObjectHeaders, ObjectInfo, Sidecar, StorageBackend, StoredObject, META_SUFFIX,
+};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Objects as files under oss.BucketName on this machine: a USB drive, an
+/// SMB/NFS mount or a synced folder. Two machines sharing the drive sync
+/// fully offline.
+pub struct FsBackend<'a> {
+    config: &'a OssConfig,
+}
+
+impl<'a> FsBackend<'a> {
+    pub fn new(config: &'a OssConfig) -> FsBackend<'a> {
+        FsBackend { config }
+    }
+
+    fn root(&self) -> &Path {
+        Path::new(&self.config.bucket_name)
+    }
+
+    // Keys are relative paths that must stay under the root
+    fn path(&self, key: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(retry::permanent(format!(
+                "Invalid object key for the fs backend: {:?}",
+                key
+            )));
+        }
+        Ok(self.root().join(relative))
+    }
+
+    fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(META_SUFFIX);
+        PathBuf::from(name)
+    }
+
+    fn read_sidecar(&self, key: &str, path: &Path) -> Result<Sidecar, Box<dyn std::error::Error>> {
+        match std::fs::read(Self::sidecar_path(path)) {
+            Ok(json) => Sidecar::parse(key, &json),
+            // Files copied onto the drive by hand have no sidecar
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Sidecar::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl StorageBackend for FsBackend<'_> {
+    fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        headers: &ObjectHeaders,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let size = data.len() as u64;
+        progress::start("upload", Some(size));
+
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(
+            Self::sidecar_path(&path),
+            serde_json::to_vec(&Sidecar::new(headers))?,
+        )?;
+
+        // Readers on the other machine never see a partly written object
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        std::fs::write(&partial, &data)
+            .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        std::fs::rename(&partial, &path)?;
+
+        println!("Upload response: stored {}", path.display());
+        progress::finish("upload", size);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn std::error::Error>> {
+        let path = self.path(key)?;
+        progress::start("download", None);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e).into()),
+        };
+        progress::finish("download", data.len() as u64);
+
+        Ok(Some(StoredObject {
+            data,
+            metadata: self.read_sidecar(key, &path)?.metadata,
+        }))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error>> {
+        let path = self.path(key)?;
+        let file = match std::fs::metadata(&path) {
+            Ok(file) if file.is_file() => file,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let sidecar = self.read_sidecar(key, &path)?;
+        Ok(Some(ObjectInfo {
+            size: file.len() as i64,
+            last_modified: file.modified().ok().map(|time| {
+                chrono::DateTime::<chrono::Utc>::from(time)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            }),
+            content_encoding: sidecar.content_encoding,
+            metadata: sidecar.metadata,
+        }))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>> {
+        // Only walk the directory the prefix points into
+        let dir = prefix.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let mut pending = vec![self.root().join(dir)];
+        let mut objects = Vec::new();
+
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Cannot list {}: {}", dir.display(), e).into()),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending.push(entry.path());
+                    continue;
+                }
+                let path = entry.path();
+                let Some(key) = path
+                    .strip_prefix(self.root())
+                    .ok()
+                    .and_then(|relative| relative.to_str())
+                    .map(|relative| relative.replace('\\', "/"))
+                else {
+                    continue;
+                };
+                if !key.starts_with(prefix) || key.ends_with(META_SUFFIX) || key.ends_with(".part")
+                {
+                    continue;
+                }
+                objects.push(ListedObject {
+                    key,
+                    last_modified: entry
+                        .metadata()?
+                        .modified()
+                        .ok()
+                        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                        .map(|age| age.as_secs() as i64),
+                });
+            }
+        }
+
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.path(key)?;
+        for path in [Self::sidecar_path(&path), path] {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                // Like S3, deleting what isn't there succeeds
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Cannot delete {}: {}", path.display(), e).into()),
+            }
+        }
+        Ok(())
+    }
+
+    // A file:// URL for whoever shares the drive, or oss.PublicUrl when a
+    // web server publishes the directory; neither expires
+    fn sign_url(
+        &self,
+        key: &str,
+        _expires: Duration,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if !self.config.public_url.is_empty() {
+            return Ok(self.config.public_url.replace("{key}", &encode(key, "/")));
+        }
+        let path = std::path::absolute(self.path(key)?)?;
+        let path = path.to_string_lossy().replace('\\', "/");
+        let path = encode(&path, "/:");
+        Ok(match path.starts_with('/') {
+            true => format!("file://{}", path),
+            false => format!("file:///{}", path),
+        })
+    }
+}
@@ -0,0 +1,617 @@
+use crate::config::OssConfig;
+use crate::deadline;
+use crate::hash::{self, HashAlgorithm};
+use crate::progress;
+use crate::rest::{self, content_length, elements, encode, unescape};
+use crate::retry;
+use crate::storage::{ListedObject, ObjectHeaders, ObjectInfo, StorageBackend, StoredObject};
+use base64::{engine::general_purpose, Engine as _};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use zeroize::Zeroize;
+
+pub const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+const METADATA_PREFIX: &str = "x-goog-meta-";
+const METADATA_SERVER: &str = "http://metadata.google.internal/computeMetadata/v1";
+
+// V4 signed URLs can't be valid for longer
+const MAX_SIGNED_URL_LIFETIME: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Google Cloud Storage through its XML API. Requests carry an OAuth token
+/// for the service account in oss.CredentialsFile, or for the application
+/// default credentials when that is empty; share links are V4 signed URLs.
+pub struct GcsBackend<'a> {
+    config: &'a OssConfig,
+}
+
+// A service-account key, or the `authorized_user` file `gcloud auth
+// application-default login` writes
+#[derive(Deserialize)]
+struct CredentialsFile {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    client_email: String,
+    #[serde(default)]
+    private_key: String,
+    #[serde(default)]
+    token_uri: String,
+    #[serde(default)]
+    client_id: String,
+    #[serde(default)]
+    client_secret: String,
+    #[serde(default)]
+    refresh_token: String,
+}
+
+impl Drop for CredentialsFile {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+        self.client_secret.zeroize();
+        self.refresh_token.zeroize();
+    }
+}
+
+enum Credentials {
+    File(CredentialsFile),
+    // The service account of the GCE / GKE / Cloud Run instance we run on
+    MetadataServer,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default = "default_token_lifetime")]
+    expires_in: u64,
+}
+
+fn default_token_lifetime() -> u64 {
+    3600
+}
+
+impl<'a> GcsBackend<'a> {
+    pub fn new(config: &'a OssConfig) -> GcsBackend<'a> {
+        GcsBackend { config }
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/{}", self.bucket_url(), encode(key, "/"))
+    }
+
+    fn bucket_url(&self) -> String {
+        format!(
+            "{}/{}",
+            self.config.endpoint.trim_end_matches('/'),
+            self.config.bucket_name
+        )
+    }
+
+    // oss.CredentialsFile, then GOOGLE_APPLICATION_CREDENTIALS, then gcloud's
+    // application default credentials, then the metadata server
+    fn credentials(&self) -> Result<Credentials, Box<dyn std::error::Error>> {
+        let path = Some(PathBuf::from(&self.config.credentials_file))
+            .filter(|path| !path.as_os_str().is_empty())
+            .or_else(|| {
+                std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS")
+                    .filter(|path| !path.is_empty())
+                    .map(PathBuf::from)
+            })
+            .or_else(|| gcloud_credentials_file().filter(|path| path.is_file()));
+
+        let Some(path) = path else {
+            return Ok(Credentials::MetadataServer);
+        };
+        let content = zeroize::Zeroizing::new(
+            std::fs::read(&path)
+                .map_err(|e| retry::permanent(format!("Cannot read {}: {}", path.display(), e)))?,
+        );
+        let file: CredentialsFile = serde_json::from_slice(&content).map_err(|e| {
+            retry::permanent(format!(
+                "{} is not a Google credentials file: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        match file.kind.as_str() {
+            "service_account" | "authorized_user" => Ok(Credentials::File(file)),
+            kind => Err(retry::permanent(format!(
+                "{}: credentials of type {:?} are not supported; use a service-account key",
+                path.display(),
+                kind
+            ))),
+        }
+    }
+
+    // An OAuth access token, reused until shortly before it expires
+    async fn access_token(&self) -> Result<String, Box<dyn std::error::Error>> {
+        static TOKEN: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+        if let Some((token, expires)) = &*TOKEN.lock().unwrap() {
+            if Instant::now() < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let response = match self.credentials()? {
+            Credentials::File(file) if file.kind == "service_account" => {
+                let token_uri = match file.token_uri.is_empty() {
+                    true => "https://oauth2.googleapis.com/token",
+                    false => &file.token_uri,
+                };
+                let assertion = service_account_jwt(&file, token_uri)?;
+                post_form(
+                    self.config,
+                    token_uri,
+                    &[
+                        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                        ("assertion", &assertion),
+                    ],
+                )
+                .await?
+            }
+            Credentials::File(file) => {
+                post_form(
+                    self.config,
+                    "https://oauth2.googleapis.com/token",
+                    &[
+                        ("grant_type", "refresh_token"),
+                        ("client_id", &file.client_id),
+                        ("client_secret", &file.client_secret),
+                        ("refresh_token", &file.refresh_token),
+                    ],
+                )
+                .await?
+            }
+            Credentials::MetadataServer => {
+                let body = metadata_server("instance/service-accounts/default/token")
+                    .await
+                    .map_err(|e| {
+                        format!(
+                            "No Google credentials: set oss.CredentialsFile, \
+                             GOOGLE_APPLICATION_CREDENTIALS or run \
+                             `gcloud auth application-default login` ({})",
+                            e
+                        )
+                    })?;
+                serde_json::from_slice(&body)?
+            }
+        };
+
+        let lifetime = Duration::from_secs(response.expires_in.saturating_sub(60));
+        *TOKEN.lock().unwrap() = Some((response.access_token.clone(), Instant::now() + lifetime));
+        Ok(response.access_token)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Result<Response<Body>, Box<dyn std::error::Error>> {
+        let token = self.access_token().await?;
+        let mut request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(hyper::header::AUTHORIZATION, format!("Bearer {}", token))
+            // Otherwise objects stored with Content-Encoding: gzip come back
+            // decompressed and no longer match their checksum
+            .header(hyper::header::ACCEPT_ENCODING, "gzip");
+        for (name, value) in &headers {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::from(body))?;
+        Ok(rest::client(self.config).request(request).await?)
+    }
+
+    // Signs with the service account's own key, or has IAM sign for the
+    // instance's service account `email`
+    async fn sign(
+        &self,
+        credentials: &Credentials,
+        email: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match credentials {
+            Credentials::File(file) if file.kind == "service_account" => {
+                rsa_sign(&file.private_key, data)
+            }
+            Credentials::File(_) => Err(
+                "Signed URLs need a service account; set oss.CredentialsFile to a service-account key"
+                    .into(),
+            ),
+            Credentials::MetadataServer => {
+                let url = format!(
+                    "https://iamcredentials.googleapis.com/v1/projects/-/serviceAccounts/{}:signBlob",
+                    email
+                );
+                let request = Request::post(url)
+                    .header(
+                        hyper::header::AUTHORIZATION,
+                        format!("Bearer {}", self.access_token().await?),
+                    )
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "payload": general_purpose::STANDARD.encode(data) })
+                            .to_string(),
+                    ))?;
+                let response = check(rest::client(self.config).request(request).await?, "signBlob").await?;
+                let body = hyper::body::to_bytes(response.into_body()).await?;
+
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct SignBlobResponse {
+                    signed_blob: String,
+                }
+                let signed: SignBlobResponse = serde_json::from_slice(&body)?;
+                Ok(general_purpose::STANDARD.decode(signed.signed_blob)?)
+            }
+        }
+    }
+}
+
+impl StorageBackend for GcsBackend<'_> {
+    fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        headers: &ObjectHeaders,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let size = data.len() as u64;
+        progress::start("upload", Some(size));
+
+        let mut request_headers = Vec::new();
+        for (name, value) in &headers.metadata {
+            request_headers.push((format!("{}{}", METADATA_PREFIX, name), value.to_string()));
+        }
+        if let Some(content_type) = headers.content_type {
+            request_headers.push(("content-type".to_string(), content_type.to_string()));
+        }
+        if let Some(content_encoding) = headers.content_encoding {
+            request_headers.push(("content-encoding".to_string(), content_encoding.to_string()));
+        }
+
+        let rt = Runtime::new()?;
+        rt.block_on(deadline::bound("upload", async {
+            let response = self
+                .send(Method::PUT, &self.object_url(key), request_headers, data)
+                .await?;
+            let response = check(response, "upload").await?;
+
+            println!("Upload response: {}", response.status());
+            progress::finish("upload", size);
+
+            Ok(())
+        }))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn std::error::Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(deadline::bound("download", async {
+            let response = self
+                .send(Method::GET, &self.object_url(key), Vec::new(), Vec::new())
+                .await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let response = check(response, "download").await?;
+            progress::start("download", content_length(&response));
+
+            let metadata = metadata(&response);
+            let data = rest::read_body(response.into_body()).await?;
+            progress::finish("download", data.len() as u64);
+
+            Ok(Some(StoredObject { data, metadata }))
+        }))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(deadline::bound("head", async {
+            let response = self
+                .send(Method::HEAD, &self.object_url(key), Vec::new(), Vec::new())
+                .await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let response = check(response, "head").await?;
+
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            };
+            Ok(Some(ObjectInfo {
+                size: content_length(&response).unwrap_or_default() as i64,
+                last_modified: header(hyper::header::LAST_MODIFIED)
+                    .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+                    .map(|date| {
+                        date.with_timezone(&chrono::Utc)
+                            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    }),
+                content_encoding: header(hyper::header::CONTENT_ENCODING).map(str::to_string),
+                metadata: metadata(&response),
+            }))
+        }))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(deadline::bound("listing", async {
+            let mut objects = Vec::new();
+            let mut continuation_token: Option<String> = None;
+            loop {
+                let mut url = format!(
+                    "{}?list-type=2&prefix={}",
+                    self.bucket_url(),
+                    encode(prefix, "")
+                );
+                if let Some(token) = &continuation_token {
+                    url.push_str(&format!("&continuation-token={}", encode(token, "")));
+                }
+                let response = self.send(Method::GET, &url, Vec::new(), Vec::new()).await?;
+                let response = check(response, "listing").await?;
+                let body = hyper::body::to_bytes(response.into_body()).await?;
+                let xml = String::from_utf8_lossy(&body);
+
+                for object in elements(&xml, "Contents") {
+                    let Some(key) = elements(object, "Key").first().map(|key| unescape(key)) else {
+                        continue;
+                    };
+                    objects.push(ListedObject {
+                        key,
+                        last_modified: elements(object, "LastModified")
+                            .first()
+                            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+                            .map(|date| date.timestamp()),
+                    });
+                }
+
+                match elements(&xml, "NextContinuationToken").first() {
+                    Some(token) if !token.is_empty() => continuation_token = Some(unescape(token)),
+                    _ => break,
+                }
+            }
+            Ok(objects)
+        }))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(deadline::bound("delete", async {
+            let response = self
+                .send(
+                    Method::DELETE,
+                    &self.object_url(key),
+                    Vec::new(),
+                    Vec::new(),
+                )
+                .await?;
+            // Like S3, deleting what isn't there succeeds
+            if response.status() != StatusCode::NOT_FOUND {
+                check(response, "delete").await?;
+            }
+            Ok(())
+        }))
+    }
+
+    fn sign_url(&self, key: &str, expires: Duration) -> Result<String, Box<dyn std::error::Error>> {
+        if expires > MAX_SIGNED_URL_LIFETIME {
+            return Err("Google Cloud Storage links can be valid for at most 7 days".into());
+        }
+        let endpoint: Uri = self.config.endpoint.parse()?;
+        let host = endpoint
+            .authority()
+            .ok_or("oss.Endpoint has no host")?
+            .to_string();
+
+        let now = chrono::Utc::now();
+        let datetime = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{}/auto/storage/goog4_request", now.format("%Y%m%d"));
+        let path = format!("/{}/{}", self.config.bucket_name, encode(key, "/"));
+
+        let rt = Runtime::new()?;
+        rt.block_on(async {
+            // The signer's email is part of the signed request, so only the
+            // signature itself can come from IAM
+            let credentials = self.credentials()?;
+            let email = match &credentials {
+                Credentials::File(file) => file.client_email.clone(),
+                Credentials::MetadataServer => String::from_utf8(
+                    metadata_server("instance/service-accounts/default/email").await?,
+                )?,
+            };
+            let query = format!(
+                "X-Goog-Algorithm=GOOG4-RSA-SHA256&X-Goog-Credential={}&X-Goog-Date={}\
+                 &X-Goog-Expires={}&X-Goog-SignedHeaders=host",
+                encode(&format!("{}/{}", email, scope), ""),
+                datetime,
+                expires.as_secs()
+            );
+            let canonical_request = format!(
+                "GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+                path, query, host
+            );
+            let string_to_sign = format!(
+                "GOOG4-RSA-SHA256\n{}\n{}\n{}",
+                datetime,
+                scope,
+                HashAlgorithm::Sha256.digest(canonical_request.as_bytes())
+            );
+            let signature = self
+                .sign(&credentials, &email, string_to_sign.as_bytes())
+                .await?;
+
+            Ok(format!(
+                "{}{}?{}&X-Goog-Signature={}",
+                self.config.endpoint.trim_end_matches('/'),
+                path,
+                query,
+                hash::to_hex(&signature)
+            ))
+        })
+    }
+}
+
+// Where `gcloud auth application-default login` keeps its credentials
+fn gcloud_credentials_file() -> Option<PathBuf> {
+    let dir = match cfg!(windows) {
+        true => PathBuf::from(std::env::var_os("APPDATA")?),
+        false => directories::BaseDirs::new()?.home_dir().join(".config"),
+    };
+    Some(
+        dir.join("gcloud")
+            .join("application_default_credentials.json"),
+    )
+}
+
+// The signed assertion a service account trades for an access token
+fn service_account_jwt(
+    file: &CredentialsFile,
+    token_uri: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let now = chrono::Utc::now().timestamp();
+    let header = serde_json::json!({ "alg": "RS256", "typ": "JWT" });
+    let claims = serde_json::json!({
+        "iss": file.client_email,
+        "scope": SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    });
+    let unsigned = format!(
+        "{}.{}",
+        general_purpose::URL_SAFE_NO_PAD.encode(header.to_string()),
+        general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let signature = rsa_sign(&file.private_key, unsigned.as_bytes())?;
+    Ok(format!(
+        "{}.{}",
+        unsigned,
+        general_purpose::URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+// RSASSA-PKCS1-v1_5 with SHA-256, using the PEM (PKCS#8) private key of a
+// service-account key file
+fn rsa_sign(private_key_pem: &str, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let base64: String = private_key_pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = zeroize::Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(base64.trim())
+            .map_err(|_| "The service-account private_key is not valid PEM")?,
+    );
+    let key_pair = ring::signature::RsaKeyPair::from_pkcs8(&der)
+        .map_err(|e| format!("Unusable service-account private_key: {}", e))?;
+
+    let mut signature = vec![0u8; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &ring::signature::RSA_PKCS1_SHA256,
+            &ring::rand::SystemRandom::new(),
+            data,
+            &mut signature,
+        )
+        .map_err(|_| "Signing with the service-account key failed")?;
+    Ok(signature)
+}
+
+async fn post_form(
+    config: &OssConfig,
+    url: &str,
+    fields: &[(&str, &str)],
+) -> Result<TokenResponse, Box<dyn std::error::Error>> {
+    let body = fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, encode(value, "")))
+        .collect::<Vec<_>>()
+        .join("&");
+    let request = Request::post(url)
+        .header(
+            hyper::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        )
+        .body(Body::from(body))?;
+    let response = check(
+        rest::client(config).request(request).await?,
+        "token request",
+    )
+    .await?;
+    let body = zeroize::Zeroizing::new(hyper::body::to_bytes(response.into_body()).await?.to_vec());
+    Ok(serde_json::from_slice(&body)?)
+}
+
+async fn metadata_server(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let request = Request::get(format!("{}/{}", METADATA_SERVER, path))
+        .header("Metadata-Flavor", "Google")
+        .body(Body::empty())?;
+    let response = tokio::time::timeout(
+        Duration::from_secs(3),
+        rest::direct_client().request(request),
+    )
+    .await
+    // Off Google Cloud there is no metadata server, and retrying won't add one
+    .map_err(|_| retry::permanent("the metadata server did not answer"))?
+    .map_err(retry::permanent)?;
+    let response = check(response, "metadata server").await?;
+    Ok(hyper::body::to_bytes(response.into_body()).await?.to_vec())
+}
+
+// Passes successful responses through; otherwise fails with the error code
+// from the response body, if there is one
+async fn check(
+    response: Response<Body>,
+    action: &str,
+) -> Result<Response<Body>, Box<dyn std::error::Error>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .unwrap_or_default();
+    let body = String::from_utf8_lossy(&body);
+    let code = elements(&body, "Code")
+        .first()
+        .map(|code| code.to_string())
+        // OAuth and IAM errors are JSON
+        .or_else(|| {
+            serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|json| match &json["error"] {
+                    serde_json::Value::String(error) => Some(error.clone()),
+                    error => error["status"].as_str().map(str::to_string),
+                })
+        })
+        .unwrap_or_else(|| "no error code".to_string());
+    let error = format!(
+        "Google Cloud Storage {} failed: {} ({})",
+        action, status, code
+    );
+    match retry::is_permanent_status(status.as_u16()) {
+        true => Err(retry::permanent(error)),
+        false => Err(error.into()),
+    }
+}
+
+fn metadata(response: &Response<Body>) -> HashMap<String, String> {
+    response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = name.as_str().strip_prefix(METADATA_PREFIX)?;
+            Some((name.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,54 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Encrypts to the given GPG recipients (key IDs, fingerprints or emails)
+/// with the `gpg` binary, so keys can live in the user's keyring or on a
+/// smartcard. The output is a binary OpenPGP message that `gpg -d` reads.
+pub fn encrypt(
+    recipients: &[String],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if recipients.is_empty() {
+        return Err("sync.Encryption is gpg but sync.GpgRecipients is empty".into());
+    }
+
+    let mut args = vec!["--batch", "--quiet", "--encrypt"];
+    for recipient in recipients {
+        args.push("--recipient");
+        args.push(recipient);
+    }
+    run(&args, plaintext).map_err(|e| format!("GPG encryption failed: {}", e).into())
+}
+
+/// Decrypts an OpenPGP message with whatever secret key gpg-agent can use,
+/// prompting for a PIN or passphrase through the agent if needed.
+pub fn decrypt(ciphertext: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    run(&["--batch", "--quiet", "--decrypt"], ciphertext)
+        .map_err(|e| format!("GPG decryption failed: {}", e).into())
+}
+
+fn run(args: &[&str], input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("gpg")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run gpg ({}); is it installed and on PATH?", e))?;
+
+    // Feed stdin from another thread so a full stdout pipe can't deadlock us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let output = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(input));
+        let output = child.wait_with_output();
+        // gpg may exit before reading all input; its status says why
+        let _ = writer.join();
+        output
+    })
+    .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(output.stdout)
+}
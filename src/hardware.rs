@@ -0,0 +1,194 @@
+use crate::config::{self, SyncConfig};
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base64::{engine::general_purpose, Engine as _};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+// FIDO2 relying party the credential is bound to
+const RELYING_PARTY: &str = "packer";
+// Turns the token's hmac-secret output into the outer key
+const KEY_CONTEXT: &str = "packer 2024 fido2 hmac-secret outer key";
+
+/// A FIDO2 security key enrolled with `keys enroll-token`.
+#[derive(Clone)]
+pub struct Token {
+    /// Base64 credential id
+    credential: String,
+    /// Base64 hmac-secret salt
+    salt: String,
+    device: String,
+    pin: bool,
+}
+
+impl Token {
+    pub fn from_config(sync: &SyncConfig) -> Option<Token> {
+        if sync.hardware_key_credential.is_empty() {
+            return None;
+        }
+        Some(Token {
+            credential: sync.hardware_key_credential.clone(),
+            salt: sync.hardware_key_salt.clone(),
+            device: sync.hardware_key_device.clone(),
+            pin: sync.hardware_key_pin,
+        })
+    }
+
+    /// The 32-byte outer key from the security key's hmac-secret extension.
+    /// The token asks for a touch (and its PIN with sync.HardwareKeyPin); the
+    /// key is kept for the rest of the process so that happens once per run.
+    pub fn outer_key(&self) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+        static KEY: OnceLock<[u8; 32]> = OnceLock::new();
+        if let Some(key) = KEY.get() {
+            return Ok(*key);
+        }
+
+        let salt = general_purpose::STANDARD
+            .decode(&self.salt)
+            .ok()
+            .filter(|salt| salt.len() == 32)
+            .ok_or("sync.HardwareKeySalt must be 32 bytes of base64; re-run `keys enroll-token`")?;
+        let device = match self.device.as_str() {
+            "" => first_device()?,
+            device => device.to_string(),
+        };
+
+        let input = format!(
+            "{}\n{}\n{}\n{}\n",
+            random_base64(),
+            RELYING_PARTY,
+            self.credential,
+            general_purpose::STANDARD.encode(salt)
+        );
+        let mut args = vec!["-G", "-h", "-p"];
+        if self.pin {
+            args.push("-v");
+        }
+        args.push(&device);
+
+        info!(
+            "Touch your security key ({}) to unlock the pack key",
+            device
+        );
+        let output = run("fido2-assert", &args, &input)?;
+        // The hmac-secret is the last line of the assertion
+        let secret = output
+            .lines()
+            .last()
+            .and_then(|line| general_purpose::STANDARD.decode(line.trim()).ok())
+            .filter(|secret| secret.len() == 32)
+            .ok_or("fido2-assert returned no hmac-secret; is this the enrolled security key?")?;
+
+        Ok(*KEY.get_or_init(|| blake3::derive_key(KEY_CONTEXT, &secret)))
+    }
+}
+
+/// Creates a (non-resident) FIDO2 credential with the hmac-secret extension on
+/// the security key and records it in the config file.
+pub fn enroll(device: Option<&str>, pin: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let device = match device {
+        Some(device) => device.to_string(),
+        None => first_device()?,
+    };
+
+    let path = config::config_file_path()?;
+    let mut table = config::read_table(&path)?;
+    if config::get_key(&table, "sync.HardwareKeyCredential").is_some() {
+        return Err(format!(
+            "A security key is already enrolled in {}; remove sync.HardwareKeyCredential \
+             first (objects wrapped only for it become unreadable)",
+            path.display()
+        )
+        .into());
+    }
+
+    // client data hash, relying party, user name, user id
+    let input = format!(
+        "{}\n{}\npacker\n{}\n",
+        random_base64(),
+        RELYING_PARTY,
+        random_base64()
+    );
+    let mut args = vec!["-M", "-h"];
+    if pin {
+        args.push("-v");
+    }
+    args.push(&device);
+    args.push("es256");
+
+    println!(
+        "Touch your security key ({}) to create the credential",
+        device
+    );
+    let output = run("fido2-cred", &args, &input)?;
+    // client data hash, relying party, format, authenticator data, credential id, ...
+    let credential = output
+        .lines()
+        .nth(4)
+        .map(str::trim)
+        .filter(|id| general_purpose::STANDARD.decode(id).is_ok())
+        .ok_or("Unexpected output from fido2-cred")?
+        .to_string();
+
+    for (key, value) in [
+        (
+            "sync.HardwareKeyCredential",
+            toml::Value::String(credential),
+        ),
+        ("sync.HardwareKeySalt", toml::Value::String(random_base64())),
+        ("sync.HardwareKeyPin", toml::Value::Boolean(pin)),
+    ] {
+        config::set_key(&mut table, key, value)?;
+    }
+    config::write_table(&path, &table)?;
+
+    println!("Security key enrolled in {}", path.display());
+    println!(
+        "Add \"hardware-key\" to sync.Recipients to wrap pack keys for it; losing the token \
+         loses access to objects only it can open"
+    );
+    Ok(())
+}
+
+// Used when sync.HardwareKeyDevice is empty
+fn first_device() -> Result<String, Box<dyn std::error::Error>> {
+    // Lines look like `/dev/hidraw4: vendor=0x1050, product=0x0407 (Yubico YubiKey)`
+    let listing = run("fido2-token", &["-L"], "")?;
+    listing
+        .lines()
+        .find_map(|line| {
+            line.split_once(": ")
+                .map(|(path, _)| path.trim().to_string())
+        })
+        .ok_or_else(|| {
+            "No FIDO2 security key found; plug one in or set sync.HardwareKeyDevice".into()
+        })
+}
+
+fn random_base64() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::STANDARD.encode(bytes)
+}
+
+// Runs a libfido2 tool. Its PIN prompt and touch notices go straight to the
+// terminal, so stderr is inherited rather than captured.
+fn run(program: &str, args: &[&str], input: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("cannot run {} ({}); install the libfido2 tools", program, e))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(input.as_bytes())?;
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!("{} failed ({})", program, output.status).into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,74 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Hash algorithm used for payload checksums.
+///
+/// BLAKE3 is the default because hashing multi-GB payloads with SHA-256 is a
+/// measurable part of `up`. SHA-256 stays available for backends that require
+/// it in their own checksum headers.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Returns the lowercase hex digest of `data`.
+    pub fn digest(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+            HashAlgorithm::Sha256 => to_hex(&Sha256::digest(data)),
+        }
+    }
+
+    /// Returns a self-describing checksum such as `blake3:<hex>`, so the
+    /// receiving side can verify it without knowing the uploader's settings.
+    pub fn checksum(&self, data: &[u8]) -> String {
+        format!("{}:{}", self.name(), self.digest(data))
+    }
+}
+
+/// Verifies `data` against a checksum produced by [`HashAlgorithm::checksum`].
+///
+/// Returns `Ok(false)` when the checksum uses an algorithm this build doesn't know.
+pub fn verify_checksum(checksum: &str, data: &[u8]) -> Result<bool, String> {
+    let (name, expected) = checksum
+        .split_once(':')
+        .ok_or_else(|| format!("Malformed checksum: {}", checksum))?;
+
+    let algorithm = match HashAlgorithm::from_name(name) {
+        Some(algorithm) => algorithm,
+        None => return Ok(false),
+    };
+
+    let actual = algorithm.digest(data);
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(true)
+    } else {
+        Err(format!(
+            "Checksum mismatch ({}): expected {}, got {}",
+            name, expected, actual
+        ))
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,142 @@
+use crate::Cli;
+use clap::CommandFactory;
+
+// name, summary for `help topics`, text
+const TOPICS: &[(&str, &str, &str)] = &[
+    (
+        "encryption",
+        "How pack keys are protected and rotated",
+        "\
+Encrypted objects are sealed in two rounds. The payload is encrypted with a
+fresh random AES-256-GCM data key, and the data key is then wrapped for each
+entry of sync.Recipients:
+
+  passphrase     the pack passphrase (SYNC_PASSPHRASE, the OS keyring or a
+                 prompt), stretched with Argon2id
+  hardware-key   the FIDO2 security key registered with `keys enroll-token`
+  x25519:<hex>   a public key from `keys generate`; machines holding the
+                 private key as their key file can decrypt
+  aws-kms:<key>  a KMS master key (id, ARN or alias); each upload gets a
+  aliyun-kms:<key>
+                 data key from GenerateDataKey and stores only its encrypted
+                 copy, which downloads hand back to the KMS to Decrypt. The
+                 `aws` / `aliyun` CLI and its credentials are used.
+  <path>         a 32-byte key file (sync.KeyFile or --key-file)
+
+Any one recipient opens the object. With an empty sync.Recipients the data
+key is wrapped once, with the first of the security key, key file or
+passphrase that is configured.
+
+Changing the recipients doesn't touch objects already uploaded; run
+`packer keys rotate` to re-wrap their data keys (pass the old key file with
+--old-key-file, or --old-passphrase). The payload is not re-encrypted.
+
+sync.Encryption picks aes (the above), age or gpg instead, and
+sync.Encrypt = false uploads plaintext.
+
+Packs from `up` (except --raw) are also signed by the uploading device. List
+the devices you trust (their `packer keys device` output) in
+sync.TrustedDevices and `down` refuses packs not signed by one of them.
+
+With git's commit.gpgSign set, the temporary commits `up` makes are signed
+like any other commit (gpg.format, user.signingKey). sync.VerifyCommits =
+true makes `down` check them with `git verify-commit`, against the gpg
+keyring or gpg.ssh.allowedSignersFile, before it touches the branch.",
+    ),
+    (
+        "envelope",
+        "The header in front of every stored pack and manifest",
+        "\
+Every object packer stores starts with a header:
+
+  magic \"SYNC\" (4) | version (1) | cipher (1) | compression (1) | flags (1)
+  | checksum length (1) | checksum
+
+cipher is 0 none, 1 aes, 2 age or 3 gpg; compression is the algorithm the
+payload was compressed with before encryption (sync.Compression). Flag 0x01
+means the AES payload is bound to its object key: it only decrypts under the
+key it was uploaded to, so an object copied or renamed in the bucket fails to
+open instead of being applied in the wrong place. The checksum, e.g.
+blake3:<hex> (sync.HashAlgorithm), is of the payload before compression and
+encryption; reading an object verifies it, whichever backend stored it.
+
+`up` remembers the checksum of each pack it stored and skips the upload when
+the bucket still holds the same payload, sealed the same way.
+
+Objects without the magic are read as they were written before the header
+existed. Headers of version 2 had no checksum, and those of version 1 no
+cipher byte either. A newer packer may write
+versions, ciphers or flags this build doesn't know; it then stops with an
+error asking you to upgrade rather than guess.
+
+`packer audit` downloads objects and checks that their envelopes still
+decode.",
+    ),
+    (
+        "layout",
+        "Where objects are stored in the bucket",
+        "\
+All keys start with sync.Prefix when it is set.
+
+  <owner>/<repo>/<branch>/head.pack           encrypted pack from `up`
+  <owner>/<repo>/<branch>/head.manifest       what the pack contains
+  <owner>/<repo>/<branch>/head.mbox           patches from
+                                              `up --format patch`
+  <owner>/<repo>/<branch>/head.bundle         incremental bundle from
+                                              `up --format bundle`, with
+                                              head.bundle.manifest
+  <owner>/<repo>/<branch>/head-<sha>.pack     pack from `up --raw`
+  <owner>/<repo>/<branch>/users/<user>/...    the same, in team mode
+                                              (sync.User)
+  <owner>/<repo>/repo.bundle                  whole repository from
+                                              `up --bundle`
+  autosave/<owner>/<repo>/<branch>/<time>-<sha>.pack
+                                              snapshots from `autosave`
+  from/<hostname>/<file>                      default key for `s`
+
+<owner> and <repo> come from the origin remote's URL, lowercased. The pack
+payload is the 40-character commit SHA followed by a git pack holding the
+commits the upstream branch doesn't have. Host and file names that aren't
+valid UTF-8, and `%` itself, are stored as %XX escapes.
+
+Owner, repository, branch and user names are Unicode-normalized (NFC) and
+keep each to one key segment: `/` becomes %2F, so feature/foo is stored as
+feature%2Ffoo. `down` still finds packs uploaded before that under
+feature/foo.",
+    ),
+];
+
+/// `packer help [TOPIC | COMMAND...]`: a concept topic, the long help of a
+/// (nested) command, or the list of topics.
+pub fn show(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = Cli::command();
+    command.build();
+
+    let Some(first) = args.first() else {
+        command.print_long_help()?;
+        return Ok(());
+    };
+    if first == "topics" {
+        println!("Help topics (packer help <topic>):\n");
+        for (name, summary, _) in TOPICS {
+            println!("  {:<12}{}", name, summary);
+        }
+        return Ok(());
+    }
+    if let Some((_, _, text)) = TOPICS.iter().find(|(name, _, _)| name == first) {
+        println!("{}", text);
+        return Ok(());
+    }
+
+    let mut current = &mut command;
+    for name in args {
+        current = current.find_subcommand_mut(name).ok_or_else(|| {
+            format!(
+                "No command or help topic named '{}'; see `packer help topics`",
+                args.join(" ")
+            )
+        })?;
+    }
+    current.print_long_help()?;
+    Ok(())
+}
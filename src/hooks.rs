@@ -0,0 +1,136 @@
+use git2::Repository;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Marks the git hooks `hooks install` writes, so `uninstall` only removes
+// its own
+const MARKER: &str = "# Installed by `packer hooks install`";
+
+// Git hooks that upload: after every commit, and after switching branches
+const GIT_HOOKS: [&str; 2] = ["post-commit", "post-checkout"];
+
+/// Runs a hook command from the config (sync.PreUp, sync.PostDown) through
+/// the shell, in the working directory. The command sees the branch and
+/// commit as SYNC_BRANCH and SYNC_COMMIT, and which hook it is as SYNC_HOOK;
+/// nothing happens when it is empty. `command` must come from the global
+/// config or .git/sync.toml, which `Config::load_for_repo` ensures: running
+/// one from a tracked .sync.toml would run whatever a cloned repository
+/// says, on every commit once `install` has run.
+pub fn run(
+    repo: &Repository,
+    name: &str,
+    command: &str,
+    branch: &str,
+    commit: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if command.trim().is_empty() {
+        return Ok(());
+    }
+    println!("{}", t!("hook.running", name, command));
+    let mut shell = match cfg!(windows) {
+        true => {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        }
+        false => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        }
+    };
+    let status = shell
+        .arg(command)
+        .current_dir(repo.workdir().unwrap_or(repo.path()))
+        .env("SYNC_HOOK", name)
+        .env("SYNC_BRANCH", branch)
+        .env("SYNC_COMMIT", commit)
+        .status()?;
+    if !status.success() {
+        return Err(t!("hook.failed", name, status).into());
+    }
+    Ok(())
+}
+
+/// Writes post-commit and post-checkout hooks that run `packer up` in the
+/// background after every commit and branch switch, its output going to
+/// packer-hook.log in the git directory. Hooks packer didn't write are left
+/// alone.
+pub fn install(repo: &Repository) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = hooks_dir(repo)?;
+    std::fs::create_dir_all(&dir)?;
+    // The hook runs with git's environment, which may have another PATH
+    let packer = std::env::current_exe()?;
+    let packer = packer
+        .to_str()
+        .ok_or_else(|| t!("hooks.unquotable", packer.display()))?;
+    for name in GIT_HOOKS {
+        let path = dir.join(name);
+        if !is_ours(&path)? {
+            println!("{}", t!("hooks.foreign", path.display()));
+            continue;
+        }
+        // post-checkout gets 1 as its third argument for a branch switch
+        // and 0 for checking out files
+        let guard = match name {
+            "post-checkout" => "[ \"$3\" = 1 ] || exit 0\n",
+            _ => "",
+        };
+        let script = format!(
+            "#!/bin/sh\n{}\n{}({} up >>\"$(git rev-parse --git-dir)/packer-hook.log\" 2>&1 &)\n",
+            MARKER,
+            guard,
+            shell_quote(packer)
+        );
+        std::fs::write(&path, script)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        println!("{}", t!("hooks.installed", path.display()));
+    }
+    Ok(())
+}
+
+/// Removes the hooks `install` wrote.
+pub fn uninstall(repo: &Repository) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = hooks_dir(repo)?;
+    for name in GIT_HOOKS {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        if !is_ours(&path)? {
+            println!("{}", t!("hooks.foreign", path.display()));
+            continue;
+        }
+        std::fs::remove_file(&path)?;
+        println!("{}", t!("hooks.removed", path.display()));
+    }
+    Ok(())
+}
+
+// Single-quotes a word for sh, where nothing inside is special except the
+// closing quote itself: `it's` becomes 'it'\''s'
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// core.hooksPath, relative to the working directory as git takes it, or the
+// hooks directory every worktree shares
+fn hooks_dir(repo: &Repository) -> Result<PathBuf, git2::Error> {
+    match repo.config()?.get_path("core.hooksPath") {
+        Ok(path) => Ok(repo.workdir().unwrap_or(repo.path()).join(path)),
+        Err(_) => Ok(crate::git_common_dir(repo).join("hooks")),
+    }
+}
+
+// Missing hooks count as ours to write
+fn is_ours(path: &Path) -> Result<bool, std::io::Error> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().any(|line| line == MARKER)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    }
+}
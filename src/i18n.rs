@@ -0,0 +1,751 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Languages the catalog has messages for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Locale {
+    En,
+    ZhCn,
+}
+
+// 0 until resolved, then 1 + the Locale discriminant
+static LOCALE: AtomicU8 = AtomicU8::new(0);
+
+impl Locale {
+    /// Parses `en`, `zh-CN`, `zh_CN.UTF-8` and the like; any Chinese
+    /// variant maps to Simplified Chinese.
+    pub fn parse(value: &str) -> Option<Locale> {
+        let language = value
+            .split(['.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match language.split(['-', '_']).next() {
+            Some("zh") => Some(Locale::ZhCn),
+            Some("en") | Some("c") | Some("posix") => Some(Locale::En),
+            _ => None,
+        }
+    }
+}
+
+/// The locale messages are shown in: SYNC_LANG or sync.Language once the
+/// config is loaded, otherwise the usual LC_ALL / LC_MESSAGES / LANG.
+pub fn locale() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        1 => Locale::En,
+        2 => Locale::ZhCn,
+        _ => {
+            let locale = ["SYNC_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find(|value| !value.is_empty())
+                .and_then(|value| Locale::parse(&value))
+                .unwrap_or(Locale::En);
+            set(locale);
+            locale
+        }
+    }
+}
+
+/// Applies sync.Language (already overridden by SYNC_LANG). Empty keeps the
+/// locale from the environment.
+pub fn configure(language: &str) -> Result<(), String> {
+    if language.is_empty() {
+        return Ok(());
+    }
+    let locale = Locale::parse(language).ok_or_else(|| {
+        format!(
+            "Unsupported sync.Language {:?}; use \"en\" or \"zh-CN\"",
+            language
+        )
+    })?;
+    set(locale);
+    Ok(())
+}
+
+fn set(locale: Locale) {
+    LOCALE.store(locale as u8 + 1, Ordering::Relaxed);
+}
+
+/// Looks up message `id` in the current locale and fills in `args`: `{}`
+/// takes the next argument, `{0}`, `{1}`... a specific one, so translations
+/// can reorder them.
+pub fn format(id: &str, args: &[&dyn Display]) -> String {
+    let template = MESSAGES
+        .iter()
+        .find(|(message_id, _, _)| *message_id == id)
+        .map(|(_, en, zh_cn)| match locale() {
+            Locale::En => *en,
+            Locale::ZhCn => *zh_cn,
+        });
+    debug_assert!(template.is_some(), "message {} is not in the catalog", id);
+    let template = template.unwrap_or(id);
+
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let index = match &after[..end] {
+            "" => {
+                next += 1;
+                Some(next - 1)
+            }
+            digits => digits.parse::<usize>().ok(),
+        };
+        match index.and_then(|index| args.get(index)) {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// id, English, Simplified Chinese
+const MESSAGES: &[(&str, &str, &str)] = &[
+    // up
+    (
+        "up.pack_generated",
+        "Pack data generated, size: {}",
+        "已生成 pack 数据，大小：{}",
+    ),
+    (
+        "up.pack_contents",
+        "Pack contains {} objects, {} commits ahead of upstream",
+        "pack 包含 {} 个对象，领先上游 {} 个提交",
+    ),
+    (
+        "up.unstaged",
+        "Including unstaged changes to {} files",
+        "包含 {} 个文件的未暂存修改",
+    ),
+    (
+        "up.untracked",
+        "Including {} untracked files (--no-include-untracked leaves them out)",
+        "包含 {} 个未跟踪的文件（--no-include-untracked 可排除）",
+    ),
+    (
+        "up.no_branches",
+        "No branch has commits its upstream doesn't have; nothing to upload",
+        "没有分支包含上游缺少的提交；无需上传",
+    ),
+    (
+        "up.tags_included",
+        "Including {} tags on the uploaded commits",
+        "包含已上传提交上的 {} 个标签",
+    ),
+    (
+        "up.would_upload_submodule",
+        "Would also upload submodule {}",
+        "还将上传子模块 {}",
+    ),
+    (
+        "up.submodules_raw",
+        "Warning: {} submodules have work of their own, which --raw doesn't upload",
+        "警告：{} 个子模块有自己的改动，--raw 不会上传它们",
+    ),
+    (
+        "up.submodule_uploaded",
+        "Submodule {} ({}) uploaded as: {}",
+        "子模块 {}（{}）已上传为：{}",
+    ),
+    (
+        "up.payload_unchanged",
+        "{} already holds this payload; not uploading it again",
+        "{} 已包含相同的内容，不再重复上传",
+    ),
+    (
+        "up.would_upload_lfs",
+        "Would also upload {} Git LFS objects",
+        "还将上传 {} 个 Git LFS 对象",
+    ),
+    (
+        "up.lfs_raw",
+        "Warning: {} Git LFS objects aren't uploaded with --raw; the pack only has their pointers",
+        "警告：--raw 不会上传 {} 个 Git LFS 对象；pack 中只有它们的指针",
+    ),
+    (
+        "up.lfs_missing",
+        "Warning: {} is a Git LFS pointer to {}, which isn't stored here; skipped",
+        "警告：{} 是指向 {} 的 Git LFS 指针，但本地没有该对象；已跳过",
+    ),
+    (
+        "up.lfs_uploaded",
+        "Git LFS objects in place: {} ({})",
+        "Git LFS 对象已就绪：{} 个（{}）",
+    ),
+    (
+        "up.bad_base",
+        "--base {} doesn't name a commit: {}",
+        "--base {} 不是一个提交：{}",
+    ),
+    ("up.branch_header", "== {} ==", "== {} =="),
+    (
+        "up.branches_done",
+        "Handled {} branches",
+        "已处理 {} 个分支",
+    ),
+    ("up.branch", "Using current branch: {}", "使用当前分支：{}"),
+    (
+        "up.no_such_branch",
+        "There is no local branch named {}",
+        "没有名为 {} 的本地分支",
+    ),
+    (
+        "up.selected",
+        "Packing {0} selected commits as {1} (`down --name {1}` applies them)",
+        "打包 {0} 个选定的提交，存储为 {1}（用 `down --name {1}` 应用）",
+    ),
+    (
+        "up.bad_commit",
+        "--commits {} doesn't name a commit: {}",
+        "--commits {} 不是一个提交：{}",
+    ),
+    (
+        "up.bad_range",
+        "--range {} is not a range of commits: {}",
+        "--range {} 不是一个提交范围：{}",
+    ),
+    (
+        "up.nothing_selected",
+        "The selection holds no commits",
+        "所选范围不包含任何提交",
+    ),
+    (
+        "up.detached",
+        "HEAD is detached; storing the pack as {0} (`down --name {0}` applies it)",
+        "HEAD 处于分离状态；包存储为 {0}（用 `down --name {0}` 应用）",
+    ),
+    (
+        "up.already_uploaded",
+        "A concurrent packer process already uploaded this state, skipping",
+        "另一个 packer 进程已上传了当前状态，跳过",
+    ),
+    (
+        "up.would_upload",
+        "Would upload {} with these changes:",
+        "将上传 {}，包含以下改动：",
+    ),
+    (
+        "up.raw_uploaded",
+        "Raw pack data (size: {}) uploaded to S3 storage successfully as: {}",
+        "原始 pack 数据（大小：{}）已成功上传到 S3 存储：{}",
+    ),
+    (
+        "up.encryption_disabled",
+        "Encryption disabled by config, uploading plaintext pack",
+        "配置中已关闭加密，将上传明文 pack",
+    ),
+    (
+        "up.encrypted_uploaded",
+        "Encrypted pack data (size: {}) uploaded to S3 storage successfully as: {}",
+        "加密的 pack 数据（大小：{}）已成功上传到 S3 存储：{}",
+    ),
+    (
+        "up.plaintext_uploaded",
+        "Plaintext pack data (size: {}) uploaded to S3 storage successfully as: {}",
+        "明文 pack 数据（大小：{}）已成功上传到 S3 存储：{}",
+    ),
+    (
+        "up.git_config_included",
+        "Including {} git config entries in the manifest",
+        "清单中包含 {} 条 git 配置",
+    ),
+    (
+        "up.manifest_uploaded",
+        "Manifest uploaded as: {}",
+        "清单已上传：{}",
+    ),
+    (
+        "up.record_failed",
+        "Could not record the uploaded commit locally: {}",
+        "无法在本地记录已上传的提交：{}",
+    ),
+    (
+        "link.valid_for",
+        "Download URL (valid for {}): {}",
+        "下载链接（{0} 内有效）：{1}",
+    ),
+    // down
+    (
+        "down.downloading",
+        "Downloading pack file: {}",
+        "正在下载 pack 文件：{}",
+    ),
+    (
+        "down.uploaded_by",
+        "Uploaded by {} from {}",
+        "由 {0} 从 {1} 上传",
+    ),
+    (
+        "down.uploaded_by_unverified",
+        "Uploaded by {} from {}, as the unsigned manifest claims",
+        "未签名的清单声称由 {0} 从 {1} 上传",
+    ),
+    (
+        "down.teammate_unsigned",
+        "--user {0} needs a pack signed by a device in sync.TrustedDevices whose manifest names {0}",
+        "--user {0} 需要由 sync.TrustedDevices 中的设备签名、且清单中用户为 {0} 的 pack",
+    ),
+    ("down.unknown_host", "unknown host", "未知主机"),
+    ("down.pack_info", "Pack: {}", "包：{}"),
+    (
+        "down.signature_verified",
+        "Signature verified: signed by device {}",
+        "签名已验证：由设备 {} 签名",
+    ),
+    (
+        "down.damaged_pack",
+        "The pack is damaged ({}); nothing was applied. Upload it again, or retry if the download was cut short",
+        "pack 已损坏（{}）；未应用任何内容。请重新上传，或在下载中断时重试",
+    ),
+    (
+        "down.downloaded_size",
+        "Downloaded encrypted pack file, size: {}",
+        "已下载加密的 pack 文件，大小：{}",
+    ),
+    (
+        "down.decrypted",
+        "Data decrypted successfully: {} encrypted → {} original",
+        "数据解密成功：加密 {} → 原始 {}",
+    ),
+    (
+        "down.applying_pack",
+        "Applying pack file to repository",
+        "正在将 pack 文件应用到仓库",
+    ),
+    (
+        "down.pack_commit",
+        "Using commit SHA: {}",
+        "使用提交 SHA：{}",
+    ),
+    (
+        "down.promisor",
+        "Partial clone detected (promisor remote: {}), indexing as promisor pack",
+        "检测到部分克隆（promisor 远程：{}），按 promisor pack 建立索引",
+    ),
+    (
+        "down.index_failed",
+        "Failed to apply pack: {}",
+        "应用 pack 失败：{}",
+    ),
+    (
+        "down.pack_applied",
+        "Pack applied to object database: pack-{}",
+        "pack 已写入对象数据库：pack-{}",
+    ),
+    (
+        "down.refusing",
+        "Refusing to apply {}: {}",
+        "拒绝应用 {0}：{1}",
+    ),
+    (
+        "down.would_apply",
+        "Applying the pack would make these changes:",
+        "应用该 pack 将产生以下改动：",
+    ),
+    (
+        "down.strategy_needs_current",
+        "--strategy {} applies a pack to the checked-out branch; check it out first",
+        "--strategy {} 只能应用到当前检出的分支；请先检出该分支",
+    ),
+    (
+        "down.strategy_bare",
+        "--strategy {} needs a working tree; a bare repository only takes reset",
+        "--strategy {} 需要工作区；裸仓库只支持 reset",
+    ),
+    (
+        "down.unverified_commit",
+        "Refusing the pack: commit {} failed `git verify-commit` (sync.VerifyCommits): {}",
+        "拒绝该 pack：提交 {} 未通过 `git verify-commit`（sync.VerifyCommits）：{}",
+    ),
+    (
+        "down.would_lose",
+        "Applying the pack would discard uncommitted changes to {} files; commit or stash them, or run again with --force: {}",
+        "应用该 pack 会丢弃 {} 个文件的未提交修改；请先提交或 stash，或使用 --force 重新运行：{}",
+    ),
+    (
+        "down.autostashed",
+        "Stashed local changes as {}",
+        "已将本地修改 stash 为 {}",
+    ),
+    (
+        "down.autostash_applied",
+        "Reapplied the stashed local changes",
+        "已恢复 stash 的本地修改",
+    ),
+    (
+        "down.autostash_conflicts",
+        "The stashed local changes couldn't be reapplied cleanly and are kept in the stash as {}; once any conflicts are resolved, bring them back with `git stash pop`, or `git stash drop` them if they already are",
+        "stash 的本地修改未能干净地恢复，已作为 {} 保留在 stash 中；解决冲突后用 `git stash pop` 取回，若已恢复则运行 `git stash drop`",
+    ),
+    (
+        "down.autostash_failed",
+        "Couldn't stash local changes: {}",
+        "无法 stash 本地修改：{}",
+    ),
+    ("down.would_lose_more", "... and {} more", "……以及另外 {} 个"),
+    (
+        "down.diverged",
+        "{0} and the pack have diverged: {1} local commits ahead, {2} received commits behind. Keep the local ones with --merge or --rebase-local, or drop them with --strategy reset",
+        "{0} 与 pack 已分叉：本地领先 {1} 个提交，落后收到的 {2} 个提交。使用 --merge 或 --rebase-local 保留本地提交，或使用 --strategy reset 丢弃它们",
+    ),
+    (
+        "down.strategy_dirty",
+        "Can't {} with uncommitted changes; commit or stash them first",
+        "有未提交的修改，无法 {}；请先提交或 stash",
+    ),
+    (
+        "down.strategy_stopped",
+        "git {0} stopped with conflicts; resolve them and run `git {0} --continue`, then `packer down` again for the uncommitted changes",
+        "git {0} 因冲突中止；解决冲突并运行 `git {0} --continue` 后，再次运行 `packer down` 取回未提交的修改",
+    ),
+    (
+        "down.strategy_done",
+        "Finished git {} with {}",
+        "已完成 git {}：{}",
+    ),
+    (
+        "down.layers_conflict",
+        "The uploaded uncommitted changes conflict with the result; `packer down --branch-off <name>` puts them ({}) on a branch instead",
+        "上传的未提交修改与结果冲突；可用 `packer down --branch-off <名称>` 将其（{}）放到新分支上",
+    ),
+    (
+        "down.layers_skipped",
+        "The branch isn't checked out: leaving out the pack's uncommitted changes",
+        "该分支未检出：不包含 pack 中未提交的修改",
+    ),
+    (
+        "down.no_commits",
+        "The pack holds no commits, only changes to a branch that had none; there is no commit to point the branch at",
+        "该 pack 不含提交，只有一个尚无提交的分支上的修改；没有可供分支指向的提交",
+    ),
+    (
+        "down.no_switch_current",
+        "{} is checked out; --no-switch is for other branches",
+        "{} 已检出；--no-switch 用于其他分支",
+    ),
+    (
+        "down.branch_updated",
+        "Updated {} to {}",
+        "已将 {} 更新到 {}",
+    ),
+    (
+        "down.branch_off_taken",
+        "Can't branch off to {}: not a valid branch name, or the branch already exists",
+        "无法新建分支 {}：分支名无效或分支已存在",
+    ),
+    (
+        "down.fetched",
+        "Fetched {0} into FETCH_HEAD and {1}; the current branch and worktree are unchanged",
+        "已将 {0} 获取到 FETCH_HEAD 和 {1}；当前分支和工作区未改动",
+    ),
+    (
+        "down.fetched_layers",
+        "The uncommitted changes sit on top of it in {0}, e.g. `git diff FETCH_HEAD {0}`",
+        "未提交的改动位于其上方的 {0} 中，例如 `git diff FETCH_HEAD {0}`",
+    ),
+    (
+        "down.fetched_layers_only",
+        "The pack holds only uncommitted changes; fetched them into {0}",
+        "包中只有未提交的改动；已获取到 {0}",
+    ),
+    (
+        "down.branched_off",
+        "Created branch {} at {}; the current branch and worktree are unchanged",
+        "已在 {1} 创建分支 {0}；当前分支和工作区未改动",
+    ),
+    (
+        "down.teammate_branch",
+        "{}'s work-in-progress is available on local branch {}",
+        "{0} 的进行中工作已放到本地分支 {1}",
+    ),
+    (
+        "stash.not_found",
+        "No stash entry {}; see `git stash list`",
+        "没有 stash 条目 {}；请查看 `git stash list`",
+    ),
+    (
+        "stash.empty",
+        "No stash entries to upload",
+        "没有可上传的 stash 条目",
+    ),
+    (
+        "stash.uploaded",
+        "Uploaded {} stash entries ({} objects, {}) to {}",
+        "已上传 {} 个 stash 条目（{} 个对象，{}）到 {}",
+    ),
+    (
+        "stash.malformed",
+        "{} is not a stash upload",
+        "{} 不是 stash 上传的内容",
+    ),
+    (
+        "stash.already_present",
+        "Already in the stash: {}",
+        "stash 中已存在：{}",
+    ),
+    (
+        "stash.restored",
+        "Added to the stash: {}",
+        "已加入 stash：{}",
+    ),
+    (
+        "bundle.would_upload",
+        "Would upload a bundle of {} branches and tags ({} objects, {}) to {}",
+        "将上传包含 {} 个分支和标签的 bundle（{} 个对象，{}）到 {}",
+    ),
+    (
+        "bundle.uploaded",
+        "Uploaded a bundle of {} branches and tags ({} objects, {}) to {}",
+        "已上传包含 {} 个分支和标签的 bundle（{} 个对象，{}）到 {}",
+    ),
+    (
+        "bundle.init_hint",
+        "Set the repository up elsewhere with: packer init-from {}",
+        "在其他机器上创建该仓库：packer init-from {}",
+    ),
+    (
+        "bundle.malformed",
+        "{} is not a git bundle",
+        "{} 不是 git bundle",
+    ),
+    (
+        "bundle.missing_prerequisite",
+        "The bundle builds on commit {}, which this repository doesn't have; fetch from origin first",
+        "该 bundle 依赖本仓库没有的提交 {}，请先从 origin 获取",
+    ),
+    (
+        "bundle.not_empty",
+        "{} already exists and is not empty",
+        "{} 已存在且不为空",
+    ),
+    (
+        "bundle.initialized",
+        "Created {} with {} branches and tags",
+        "已创建 {}，包含 {} 个分支和标签",
+    ),
+    (
+        "down.would_apply_submodule",
+        "Would also update submodule {}",
+        "还将更新子模块 {}",
+    ),
+    (
+        "down.submodule_applied",
+        "Updated submodule {}",
+        "已更新子模块 {}",
+    ),
+    (
+        "down.submodule_missing",
+        "Warning: submodule {} isn't checked out here; run `git submodule update --init` and `packer down` again",
+        "警告：子模块 {} 在此处未检出；请运行 `git submodule update --init` 后再次执行 `packer down`",
+    ),
+    (
+        "down.lfs_missing",
+        "Warning: Git LFS object {} is missing from storage; its file stays a pointer",
+        "警告：存储中缺少 Git LFS 对象 {}；对应文件将保持为指针",
+    ),
+    (
+        "down.lfs_fetched",
+        "Fetched {} Git LFS objects",
+        "已获取 {} 个 Git LFS 对象",
+    ),
+    (
+        "down.layers",
+        "Restored the uploaded index and working tree separately ({})",
+        "已分别恢复上传时的暂存区和工作区（{}）",
+    ),
+    (
+        "down.applied",
+        "Pack file successfully applied to repository",
+        "pack 文件已成功应用到仓库",
+    ),
+    (
+        "down.created_branch",
+        "Created local branch {} at {}",
+        "已在 {1} 创建本地分支 {0}",
+    ),
+    ("down.tracking", "Tracking {}", "跟踪 {}"),
+    ("down.switched", "Switched to branch {}", "已切换到分支 {}"),
+    ("down.mirrored", "Recorded {} as {}", "已将 {0} 记录为 {1}"),
+    // s / get
+    (
+        "s.uploading",
+        "Uploading file: {} ({})",
+        "正在上传文件：{}（{}）",
+    ),
+    (
+        "s.gpg_encrypted",
+        "Encrypted to {} GPG recipient(s); decrypt the download with `gpg -d`",
+        "已为 {} 个 GPG 接收者加密；下载后请用 `gpg -d` 解密",
+    ),
+    (
+        "s.compressed",
+        "Compressed with {} for transfer: {} → {}",
+        "已用 {} 压缩以便传输：{} → {}",
+    ),
+    (
+        "s.uploaded",
+        "File uploaded to S3 storage successfully as: {}",
+        "文件已成功上传到 S3 存储：{}",
+    ),
+    (
+        "link.bad_lifetime",
+        "{} must be between 1s and 7d, got {}",
+        "{} 必须在 1s 到 7d 之间，当前为 {}",
+    ),
+    (
+        "get.downloading",
+        "Downloading object: {}",
+        "正在下载对象：{}",
+    ),
+    (
+        "get.saving",
+        "Saving to local path: {}",
+        "保存到本地路径：{}",
+    ),
+    (
+        "get.decoded",
+        "Decoded {} content: {}",
+        "已解码 {} 内容：{}",
+    ),
+    (
+        "get.downloaded",
+        "File '{}' downloaded successfully to {}",
+        "文件 '{}' 已成功下载到 {}",
+    ),
+    (
+        "get.unsafe_name",
+        "Refusing to save to unsafe file name {}",
+        "拒绝保存为不安全的文件名 {}",
+    ),
+    (
+        "get.link_failed",
+        "   Error generating download URL: {}",
+        "   生成下载链接失败：{}",
+    ),
+    // shared
+    (
+        "checksum.verified",
+        "Checksum verified: {}",
+        "校验和已验证：{}",
+    ),
+    (
+        "checksum.plaintext_verified",
+        "Plaintext checksum verified: {}",
+        "明文校验和已验证：{}",
+    ),
+    (
+        "checksum.payload_verified",
+        "Payload checksum verified: {}",
+        "载荷校验和已验证：{}",
+    ),
+    (
+        "checksum.unknown",
+        "Skipping checksum with unknown algorithm: {}",
+        "跳过未知算法的校验和：{}",
+    ),
+    ("object.not_found", "Object not found: {}", "对象不存在：{}"),
+    (
+        "gc.removed",
+        "Released {} temporary commits; `git gc` deletes what nothing else references",
+        "已释放 {} 个临时提交；`git gc` 会删除不再被引用的部分",
+    ),
+    (
+        "patch.merges_skipped",
+        "Leaving out {} merge commits, which patches can't carry",
+        "跳过 {} 个合并提交，补丁无法表示它们",
+    ),
+    (
+        "patch.nothing",
+        "{} has no commits its base doesn't have; nothing to send as patches",
+        "{} 没有其基准缺少的提交；没有可作为补丁发送的内容",
+    ),
+    (
+        "patch.uncommitted",
+        "Uncommitted changes are not sent as patches; commit them first",
+        "未提交的修改不会作为补丁发送；请先提交",
+    ),
+    (
+        "patch.would_upload",
+        "Would upload {} patches as {}:",
+        "将上传 {} 个补丁到 {}：",
+    ),
+    (
+        "patch.uploaded",
+        "Uploaded {} patches ({}) as {}",
+        "已上传 {} 个补丁（{}）到 {}",
+    ),
+    (
+        "patch.malformed",
+        "{} is not a patch series",
+        "{} 不是补丁序列",
+    ),
+    (
+        "patch.unsigned",
+        "patch series aren't signed, and sync.TrustedDevices only accepts signed packs",
+        "补丁序列没有签名，而 sync.TrustedDevices 只接受已签名的 pack",
+    ),
+    (
+        "patch.would_apply",
+        "Would apply {} patches:",
+        "将应用 {} 个补丁：",
+    ),
+    (
+        "patch.dirty",
+        "The worktree has uncommitted changes; commit or stash them before applying patches",
+        "工作区有未提交的修改；请在应用补丁前提交或储藏",
+    ),
+    (
+        "patch.failed",
+        "Patch {0} ({1}) does not apply: {2}; the {3} before it were applied",
+        "补丁 {0}（{1}）无法应用：{2}；之前的 {3} 个补丁已应用",
+    ),
+    (
+        "patch.already_applied",
+        "Already applied, skipping: {}",
+        "已应用过，跳过：{}",
+    ),
+    ("patch.applied", "Applied {} patches", "已应用 {} 个补丁"),
+    (
+        "import.unsigned",
+        "files written by `export` aren't signed, and sync.TrustedDevices only accepts signed packs",
+        "`export` 写出的文件没有签名，而 sync.TrustedDevices 只接受已签名的 pack",
+    ),
+    (
+        "lock.waiting",
+        "Waiting for another packer process ({})",
+        "正在等待另一个 packer 进程（{}）",
+    ),
+    ("hook.running", "Running {} hook: {}", "正在运行 {} 钩子：{}"),
+    (
+        "hook.failed",
+        "The {} hook failed ({})",
+        "{} 钩子执行失败（{}）",
+    ),
+    ("hooks.installed", "Installed {}", "已安装 {}"),
+    ("hooks.removed", "Removed {}", "已移除 {}"),
+    (
+        "hooks.unquotable",
+        "Cannot write {} into a hook script: its path is not valid UTF-8",
+        "无法将 {} 写入钩子脚本：其路径不是有效的 UTF-8",
+    ),
+    (
+        "hooks.foreign",
+        "{} was not written by packer; leaving it alone",
+        "{} 不是 packer 写入的，保持不变",
+    ),
+    (
+        "deadline.exceeded",
+        "Deadline of {} exceeded during {}",
+        "在 {1} 阶段超出了 {0} 的时限",
+    ),
+];
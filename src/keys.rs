@@ -0,0 +1,738 @@
+use crate::config::{Config, SyncConfig};
+use crate::hardware::Token;
+use crate::kms;
+use crate::{envelope, passphrase};
+use aes_gcm::{
+    aead::{Aead, OsRng},
+    Aes256Gcm, Key, KeyInit,
+};
+use std::path::{Path, PathBuf};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+// Second-round key of blobs written before the passphrase-derived key;
+// only used to decrypt those
+const FIXED_KEY: &[u8; 32] = b"eZ4Ro3aish5zeitei!cau2aegei|Gh3a";
+
+// Leading byte of blobs whose second-round key is derived from the passphrase,
+// followed by the Argon2 salt. Legacy blobs start directly with the nonce.
+const FORMAT_PASSPHRASE: u8 = 0x02;
+// Leading byte of blobs whose second-round key was read from a key file, or
+// came from the security key (sync.HardwareKeyCredential)
+const FORMAT_KEY_FILE: u8 = 0x03;
+// Leading byte of blobs whose data key is wrapped once per recipient:
+// 0x04 | slot count | slots | nonce | payload ciphertext
+// where each slot is
+//   0x02 | salt (16) | nonce | wrapped data key   (passphrase)
+//   0x03 | key id (8) | nonce | wrapped data key  (key file or security key)
+//   0x05 | key id (8) | ephemeral public key (32) | nonce | wrapped data key
+//                                                  (X25519 public key)
+//   0x06 | provider (1) | blob size (2, BE) | KMS data key blob | nonce
+//        | wrapped data key                        (KMS master key)
+// Only the slots change when keys are rotated.
+const FORMAT_WRAPPED: u8 = 0x04;
+const FORMAT_X25519: u8 = 0x05;
+const FORMAT_KMS: u8 = 0x06;
+
+const NONCE_SIZE: usize = 12;
+const KEY_SIZE: usize = 32;
+const KEY_ID_SIZE: usize = 8;
+// Data key plus the GCM tag
+const WRAPPED_KEY_SIZE: usize = KEY_SIZE + 16;
+// Mixed into the X25519 shared secret to get the slot's wrapping key
+const X25519_CONTEXT: &str = "packer 2024 x25519 key slot";
+
+/// The first encryption round: the payload encrypted under a random data key,
+/// which is wiped when this is dropped.
+pub struct Sealed {
+    pub key: Zeroizing<[u8; KEY_SIZE]>,
+    pub nonce: [u8; NONCE_SIZE],
+    pub ciphertext: Vec<u8>,
+}
+
+/// Something the data key can be wrapped for.
+pub enum Recipient {
+    Passphrase,
+    /// The enrolled FIDO2 security key
+    HardwareKey(Token),
+    KeyFile(PathBuf),
+    /// Hex X25519 public key; the private half stays on the machines that
+    /// download, as their key file
+    PublicKey(String),
+    /// KMS master key id, ARN or alias. Each upload asks the KMS for a data
+    /// key to wrap with, and only the KMS's encrypted copy of it is stored.
+    Kms(kms::Provider, String),
+}
+
+/// The recipients in sync.Recipients: `passphrase` for the pack passphrase,
+/// `hardware-key` for the security key, `x25519:<hex>` for a public key,
+/// `aws-kms:<key>` or `aliyun-kms:<key>` for a KMS master key, anything else
+/// is the path of a key file.
+pub fn recipients(sync: &SyncConfig) -> Result<Vec<Recipient>, Box<dyn std::error::Error>> {
+    sync.recipients
+        .iter()
+        .map(|entry| recipient(sync, entry))
+        .collect()
+}
+
+fn recipient(sync: &SyncConfig, entry: &str) -> Result<Recipient, Box<dyn std::error::Error>> {
+    Ok(match entry {
+        "passphrase" => Recipient::Passphrase,
+        "hardware-key" => Recipient::HardwareKey(Token::from_config(sync).ok_or(
+            "sync.Recipients lists hardware-key but no security key is enrolled; \
+             run `packer keys enroll-token`",
+        )?),
+        _ => {
+            if let Some(public_key) = entry.strip_prefix("x25519:") {
+                Recipient::PublicKey(public_key.to_string())
+            } else if let Some((provider, key_id)) = kms::Provider::parse_recipient(entry) {
+                Recipient::Kms(provider, key_id.to_string())
+            } else {
+                Recipient::KeyFile(PathBuf::from(entry))
+            }
+        }
+    })
+}
+
+/// What may be used to unwrap a data key.
+pub struct Credentials {
+    key_files: Vec<PathBuf>,
+    /// Security key to try once the key files don't fit (asks for a touch)
+    token: Option<Token>,
+    passphrases: Vec<String>,
+    /// Fall back to the pack passphrase (which may prompt)
+    pack_passphrase: bool,
+}
+
+impl Credentials {
+    /// The key file and recipient key files from the config, then the pack
+    /// passphrase.
+    pub fn for_config(sync: &SyncConfig) -> Credentials {
+        let mut key_files: Vec<PathBuf> = sync.key_file().map(PathBuf::from).into_iter().collect();
+        // A broken entry is reported when something is encrypted, not here
+        for entry in &sync.recipients {
+            if let Ok(Recipient::KeyFile(path)) = recipient(sync, entry) {
+                key_files.push(path);
+            }
+        }
+        Credentials {
+            key_files,
+            token: Token::from_config(sync),
+            passphrases: Vec::new(),
+            pack_passphrase: true,
+        }
+    }
+
+    // Calls `open` with each passphrase until it succeeds, asking for the pack
+    // passphrase only if the explicit ones don't work
+    fn try_passphrases<T>(
+        &self,
+        mut open: impl FnMut(&str) -> Result<Option<T>, Box<dyn std::error::Error>>,
+    ) -> Result<Option<T>, Box<dyn std::error::Error>> {
+        for passphrase in &self.passphrases {
+            if let Some(value) = open(passphrase)? {
+                return Ok(Some(value));
+            }
+        }
+        if self.pack_passphrase {
+            return open(passphrase::pack_passphrase()?);
+        }
+        Ok(None)
+    }
+}
+
+/// Protects the data key the way the config asks: one slot per recipient if
+/// sync.Recipients is set, otherwise a second round under the security key,
+/// key file or passphrase key as before.
+pub fn wrap(sync: &SyncConfig, sealed: &Sealed) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let recipients = recipients(sync)?;
+    if !recipients.is_empty() {
+        return wrap_for(&recipients, sealed);
+    }
+
+    let mut combined = Zeroizing::new(Vec::with_capacity(
+        NONCE_SIZE + KEY_SIZE + sealed.ciphertext.len(),
+    ));
+    combined.extend_from_slice(&sealed.nonce);
+    combined.extend_from_slice(sealed.key.as_slice());
+    combined.extend_from_slice(&sealed.ciphertext);
+
+    // The format byte (and salt) in front tell the receiving side which key to use
+    let (mut data, outer_key) = match (Token::from_config(sync), sync.key_file()) {
+        (Some(token), _) => (vec![FORMAT_KEY_FILE], Zeroizing::new(token.outer_key()?)),
+        (None, Some(path)) => (vec![FORMAT_KEY_FILE], passphrase::read_key_file(path)?),
+        (None, None) => {
+            let salt = passphrase::random_salt();
+            let mut header = vec![FORMAT_PASSPHRASE];
+            header.extend_from_slice(&salt);
+            (
+                header,
+                passphrase::derive_key(passphrase::pack_passphrase()?, &salt)?,
+            )
+        }
+    };
+    data.extend_from_slice(
+        &passphrase::seal_with_key(&outer_key, &combined)
+            .map_err(|e| format!("Second round encryption failed: {}", e))?,
+    );
+    Ok(data)
+}
+
+/// Wraps the data key separately for each recipient.
+pub fn wrap_for(
+    recipients: &[Recipient],
+    sealed: &Sealed,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let count = u8::try_from(recipients.len())
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or("sync.Recipients must list between 1 and 255 recipients")?;
+
+    let mut data = vec![FORMAT_WRAPPED, count];
+    for recipient in recipients {
+        match recipient {
+            Recipient::Passphrase => {
+                let salt = passphrase::random_salt();
+                let key = passphrase::derive_key(passphrase::pack_passphrase()?, &salt)?;
+                data.push(FORMAT_PASSPHRASE);
+                data.extend_from_slice(&salt);
+                data.extend_from_slice(&passphrase::seal_with_key(&key, sealed.key.as_slice())?);
+            }
+            Recipient::HardwareKey(token) => {
+                let key = token.outer_key()?;
+                data.push(FORMAT_KEY_FILE);
+                data.extend_from_slice(&key_id(&key));
+                data.extend_from_slice(&passphrase::seal_with_key(&key, sealed.key.as_slice())?);
+            }
+            Recipient::KeyFile(path) => {
+                let key = passphrase::read_key_file(path)?;
+                data.push(FORMAT_KEY_FILE);
+                data.extend_from_slice(&key_id(&key));
+                data.extend_from_slice(&passphrase::seal_with_key(&key, sealed.key.as_slice())?);
+            }
+            Recipient::PublicKey(hex) => {
+                let public = parse_public_key(hex)?;
+                let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+                let ephemeral_public = PublicKey::from(&ephemeral);
+                let shared = ephemeral.diffie_hellman(&public);
+                if !shared.was_contributory() {
+                    return Err(format!("x25519:{} is not a usable public key", hex).into());
+                }
+                let key = x25519_wrapping_key(shared.as_bytes(), &ephemeral_public, &public);
+                data.push(FORMAT_X25519);
+                data.extend_from_slice(&key_id(public.as_bytes()));
+                data.extend_from_slice(ephemeral_public.as_bytes());
+                data.extend_from_slice(&passphrase::seal_with_key(&key, sealed.key.as_slice())?);
+            }
+            Recipient::Kms(provider, key_id) => {
+                let data_key = kms::generate_data_key(*provider, key_id)?;
+                let blob_size = u16::try_from(data_key.ciphertext.len())
+                    .map_err(|_| format!("{} returned an oversized data key", provider.name()))?;
+                data.push(FORMAT_KMS);
+                data.push(provider.id());
+                data.extend_from_slice(&blob_size.to_be_bytes());
+                data.extend_from_slice(&data_key.ciphertext);
+                data.extend_from_slice(&passphrase::seal_with_key(
+                    &data_key.plaintext,
+                    sealed.key.as_slice(),
+                )?);
+            }
+        }
+    }
+    data.extend_from_slice(&sealed.nonce);
+    data.extend_from_slice(&sealed.ciphertext);
+    Ok(data)
+}
+
+/// Recovers the data key and payload ciphertext from any format [`wrap`]
+/// has ever written.
+///
+/// `allow_legacy` accepts blobs sealed with the fixed key, which anyone can
+/// forge. Only objects without an envelope, or with a version 1 one, can be
+/// such blobs; pass `false` for everything newer.
+pub fn unwrap(
+    credentials: &Credentials,
+    data: &[u8],
+    allow_legacy: bool,
+) -> Result<Sealed, Box<dyn std::error::Error>> {
+    if data.len() <= NONCE_SIZE {
+        return Err("Encrypted data too short".into());
+    }
+
+    let unwrapped = match data[0] {
+        FORMAT_PASSPHRASE | FORMAT_KEY_FILE | FORMAT_WRAPPED => unwrap_format(credentials, data),
+        _ if allow_legacy => return unwrap_legacy(data),
+        format => return Err(format!("Unknown key format {:#04x}", format).into()),
+    };
+    if !allow_legacy {
+        return unwrapped;
+    }
+    // A legacy blob starts with its random nonce, so a few of them look like
+    // one of the formats; GCM authentication tells those apart
+    unwrapped.or_else(|e| unwrap_legacy(data).map_err(|_| e))
+}
+
+// Legacy blobs: random nonce followed by data encrypted with the fixed key
+fn unwrap_legacy(data: &[u8]) -> Result<Sealed, Box<dyn std::error::Error>> {
+    let fixed_cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(FIXED_KEY));
+    let combined = fixed_cipher
+        .decrypt(data[0..NONCE_SIZE].into(), &data[NONCE_SIZE..])
+        .map_err(|e| format!("Second round decryption failed: {}", e))?;
+    split_combined(combined)
+}
+
+fn unwrap_format(
+    credentials: &Credentials,
+    data: &[u8],
+) -> Result<Sealed, Box<dyn std::error::Error>> {
+    match data[0] {
+        FORMAT_PASSPHRASE if data.len() > 1 + passphrase::SALT_SIZE => {
+            let salt = &data[1..1 + passphrase::SALT_SIZE];
+            let sealed = &data[1 + passphrase::SALT_SIZE..];
+            let combined = credentials.try_passphrases(|passphrase| {
+                let key = passphrase::derive_key(passphrase, salt)?;
+                Ok(passphrase::open_with_key(&key, sealed).ok())
+            })?;
+            split_combined(combined.ok_or("Second round decryption failed (wrong passphrase?)")?)
+        }
+        FORMAT_KEY_FILE => {
+            if credentials.key_files.is_empty() && credentials.token.is_none() {
+                return Err(
+                    "Data was encrypted with a key file; pass --key-file or set sync.KeyFile \
+                     (see `packer help encryption`)"
+                        .into(),
+                );
+            }
+            for path in &credentials.key_files {
+                let key = passphrase::read_key_file(path)?;
+                if let Ok(combined) = passphrase::open_with_key(&key, &data[1..]) {
+                    return split_combined(combined);
+                }
+            }
+            if let Some(token) = &credentials.token {
+                if let Ok(combined) = passphrase::open_with_key(&token.outer_key()?, &data[1..]) {
+                    return split_combined(combined);
+                }
+            }
+            Err("Second round decryption failed (wrong key file or security key?)".into())
+        }
+        FORMAT_WRAPPED => unwrap_slots(credentials, data),
+        _ => Err("Encrypted data too short".into()),
+    }
+}
+
+// Legacy layout after the second round: nonce | data key | payload ciphertext
+fn split_combined(combined: Vec<u8>) -> Result<Sealed, Box<dyn std::error::Error>> {
+    let combined = Zeroizing::new(combined);
+    if combined.len() <= NONCE_SIZE + KEY_SIZE {
+        return Err("Decrypted data from second round too short".into());
+    }
+    Ok(Sealed {
+        nonce: combined[..NONCE_SIZE].try_into()?,
+        key: Zeroizing::new(combined[NONCE_SIZE..NONCE_SIZE + KEY_SIZE].try_into()?),
+        ciphertext: combined[NONCE_SIZE + KEY_SIZE..].to_vec(),
+    })
+}
+
+struct Slot<'a> {
+    kind: u8,
+    /// Argon2 salt for passphrase slots, key id for key file slots, key id
+    /// and ephemeral public key for X25519 slots, provider, size and blob for
+    /// KMS slots
+    selector: &'a [u8],
+    wrapped: &'a [u8],
+}
+
+fn unwrap_slots(
+    credentials: &Credentials,
+    data: &[u8],
+) -> Result<Sealed, Box<dyn std::error::Error>> {
+    let truncated =
+        || -> Box<dyn std::error::Error> { "Encrypted data truncated in key slots".into() };
+
+    let count = *data.get(1).ok_or_else(truncated)?;
+    let mut pos = 2;
+    let mut slots = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let kind = *data.get(pos).ok_or_else(truncated)?;
+        let selector_size = match kind {
+            FORMAT_PASSPHRASE => passphrase::SALT_SIZE,
+            FORMAT_KEY_FILE => KEY_ID_SIZE,
+            FORMAT_X25519 => KEY_ID_SIZE + 32,
+            FORMAT_KMS => {
+                let size = data.get(pos + 2..pos + 4).ok_or_else(truncated)?;
+                3 + u16::from_be_bytes([size[0], size[1]]) as usize
+            }
+            _ => return Err(format!("Unknown key slot type {}; upgrade packer", kind).into()),
+        };
+        let end = pos + 1 + selector_size + NONCE_SIZE + WRAPPED_KEY_SIZE;
+        let slot = data.get(pos + 1..end).ok_or_else(truncated)?;
+        slots.push(Slot {
+            kind,
+            selector: &slot[..selector_size],
+            wrapped: &slot[selector_size..],
+        });
+        pos = end;
+    }
+
+    let payload = data
+        .get(pos..)
+        .filter(|p| p.len() > NONCE_SIZE)
+        .ok_or_else(truncated)?;
+    let sealed = |key: Vec<u8>| -> Result<Sealed, Box<dyn std::error::Error>> {
+        let key = Zeroizing::new(key);
+        Ok(Sealed {
+            key: Zeroizing::new(key.as_slice().try_into()?),
+            nonce: payload[..NONCE_SIZE].try_into()?,
+            ciphertext: payload[NONCE_SIZE..].to_vec(),
+        })
+    };
+
+    // Key files first, since matching them by id never prompts
+    for path in &credentials.key_files {
+        let Ok(key) = passphrase::read_key_file(path) else {
+            continue;
+        };
+        let id = key_id(&key);
+        for slot in slots
+            .iter()
+            .filter(|s| s.kind == FORMAT_KEY_FILE && s.selector == id)
+        {
+            if let Ok(data_key) = passphrase::open_with_key(&key, slot.wrapped) {
+                return sealed(data_key);
+            }
+        }
+
+        // The same key file may be the private half of an X25519 recipient
+        let secret = StaticSecret::from(*key);
+        let public = PublicKey::from(&secret);
+        let id = key_id(public.as_bytes());
+        for slot in slots
+            .iter()
+            .filter(|s| s.kind == FORMAT_X25519 && s.selector[..KEY_ID_SIZE] == id)
+        {
+            let ephemeral_public: [u8; 32] = slot.selector[KEY_ID_SIZE..].try_into()?;
+            let ephemeral_public = PublicKey::from(ephemeral_public);
+            let shared = secret.diffie_hellman(&ephemeral_public);
+            let key = x25519_wrapping_key(shared.as_bytes(), &ephemeral_public, &public);
+            if let Ok(data_key) = passphrase::open_with_key(&key, slot.wrapped) {
+                return sealed(data_key);
+            }
+        }
+    }
+
+    // Only touch the security key if it may hold one of the slots
+    if let Some(token) = &credentials.token {
+        if slots.iter().any(|s| s.kind == FORMAT_KEY_FILE) {
+            let key = token.outer_key()?;
+            let id = key_id(&key);
+            for slot in slots
+                .iter()
+                .filter(|s| s.kind == FORMAT_KEY_FILE && s.selector == id)
+            {
+                if let Ok(data_key) = passphrase::open_with_key(&key, slot.wrapped) {
+                    return sealed(data_key);
+                }
+            }
+        }
+    }
+
+    // The KMS decides by the blob's master key and the CLI's credentials
+    // whether this machine may have the data key
+    let mut kms_error = None;
+    for slot in slots.iter().filter(|s| s.kind == FORMAT_KMS) {
+        let Some(provider) = kms::Provider::from_id(slot.selector[0]) else {
+            continue;
+        };
+        match kms::decrypt(provider, &slot.selector[3..]) {
+            Ok(key) => {
+                if let Ok(data_key) = passphrase::open_with_key(&key, slot.wrapped) {
+                    return sealed(data_key);
+                }
+            }
+            Err(e) => kms_error = Some(e),
+        }
+    }
+
+    let passphrase_slots: Vec<&Slot> = slots
+        .iter()
+        .filter(|s| s.kind == FORMAT_PASSPHRASE)
+        .collect();
+    if !passphrase_slots.is_empty() {
+        let data_key = credentials.try_passphrases(|passphrase| {
+            for slot in &passphrase_slots {
+                let key = passphrase::derive_key(passphrase, slot.selector)?;
+                if let Ok(data_key) = passphrase::open_with_key(&key, slot.wrapped) {
+                    return Ok(Some(data_key));
+                }
+            }
+            Ok(None)
+        })?;
+        if let Some(data_key) = data_key {
+            return sealed(data_key);
+        }
+    }
+
+    Err(format!(
+        "None of the {} key slots can be opened with the available key files, security key, \
+         KMS or passphrase{} (see `packer help encryption`)",
+        count,
+        kms_error
+            .map(|e| format!("; KMS: {}", e))
+            .unwrap_or_default()
+    )
+    .into())
+}
+
+fn parse_public_key(hex: &str) -> Result<PublicKey, Box<dyn std::error::Error>> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("x25519:{} is not a 64 hex character public key", hex).into());
+    }
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(PublicKey::from(bytes))
+}
+
+// Binds the wrapping key to both public keys, not just the shared secret
+fn x25519_wrapping_key(
+    shared: &[u8; 32],
+    ephemeral_public: &PublicKey,
+    recipient: &PublicKey,
+) -> [u8; KEY_SIZE] {
+    let mut material = Vec::with_capacity(96);
+    material.extend_from_slice(shared);
+    material.extend_from_slice(ephemeral_public.as_bytes());
+    material.extend_from_slice(recipient.as_bytes());
+    blake3::derive_key(X25519_CONTEXT, &material)
+}
+
+/// Writes a new X25519 private key to `output`, in key file format, and
+/// prints the recipient that uploading machines put in sync.Recipients.
+pub fn generate(output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if output.exists() {
+        return Err(format!("{} already exists", output.display()).into());
+    }
+    let secret = StaticSecret::random_from_rng(OsRng);
+    crate::config::write_private(output, crate::hash::to_hex(secret.as_bytes()) + "\n")?;
+    let public = PublicKey::from(&secret);
+
+    println!("Private key written to {}", output.display());
+    println!("x25519:{}", crate::hash::to_hex(public.as_bytes()));
+    println!(
+        "Add the line above to sync.Recipients on the machines that upload; only \
+         machines with this key file (sync.KeyFile or --key-file) can download"
+    );
+    Ok(())
+}
+
+// Short fingerprint stored in key file slots so decryption can pick the right
+// slot without trying every key
+fn key_id(key: &[u8; KEY_SIZE]) -> [u8; KEY_ID_SIZE] {
+    let mut id = [0u8; KEY_ID_SIZE];
+    id.copy_from_slice(&blake3::hash(key).as_bytes()[..KEY_ID_SIZE]);
+    id
+}
+
+/// Re-wraps the data key of every encrypted object under the prefix for the
+/// configured recipients, unwrapping with the current credentials plus the
+/// old ones given. The payload ciphertext is carried over untouched, but as
+/// the key slots live in the object's envelope each object is rewritten.
+/// Objects that can't be unwrapped are left alone and listed at the end; a
+/// run that stops partway picks up where it left off when run again.
+pub fn rotate(
+    old_key_files: &[PathBuf],
+    old_passphrase: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    if !dry_run {
+        config.oss.ensure_writable()?;
+    }
+    let mut recipients = recipients(&config.sync)?;
+    if recipients.is_empty() {
+        // Rotating to the single key the config already uses
+        recipients.push(
+            match (Token::from_config(&config.sync), config.sync.key_file()) {
+                (Some(token), _) => Recipient::HardwareKey(token),
+                (None, Some(path)) => Recipient::KeyFile(path.to_path_buf()),
+                (None, None) => Recipient::Passphrase,
+            },
+        );
+    }
+
+    let mut credentials = Credentials::for_config(&config.sync);
+    credentials.key_files.extend(old_key_files.iter().cloned());
+    if old_passphrase {
+        let passphrase = match std::env::var("SYNC_OLD_PASSPHRASE") {
+            Ok(passphrase) if !passphrase.is_empty() => passphrase,
+            _ => rpassword::prompt_password("Old pack passphrase: ")?,
+        };
+        credentials.passphrases.push(passphrase);
+    }
+
+    let prefix = config.sync.object_key("");
+    let objects = crate::list_objects_with_prefix(&config.oss, &prefix)?;
+    println!("Checking {} objects under {:?}", objects.len(), prefix);
+
+    let mut progress = RotateProgress::open(&config, &prefix)?;
+    if !progress.done.is_empty() {
+        println!(
+            "Resuming an interrupted rotation: {} objects are already done",
+            progress.done.len()
+        );
+    }
+
+    let (mut rotated, mut skipped) = (0, 0);
+    let mut failed = Vec::new();
+    for object in objects {
+        if progress.done.contains(&object.key) {
+            rotated += 1;
+            continue;
+        }
+        let Some(data) = crate::try_download(&config.oss, &object.key)? else {
+            continue;
+        };
+        let Some(envelope::Envelope {
+            version,
+            header,
+            body,
+        }) = envelope::unwrap(&data)?
+        else {
+            skipped += 1;
+            continue;
+        };
+        // age and GPG objects have their own recipients
+        if header.cipher != envelope::Cipher::Aes {
+            skipped += 1;
+            continue;
+        }
+
+        let sealed = match unwrap(&credentials, body, version == 1) {
+            Ok(sealed) => sealed,
+            Err(e) => {
+                println!("Cannot unwrap {}: {}", object.key, e);
+                failed.push(object.key);
+                continue;
+            }
+        };
+        if dry_run {
+            println!("Would rotate {}", object.key);
+        } else {
+            let rewrapped = envelope::wrap(&header, wrap_for(&recipients, &sealed)?)?;
+            // The content doesn't change, so its metadata (plaintext checksum,
+            // pack commit, ...) carries over; only the stored checksum is new
+            let metadata = crate::storage::backend(&config.oss)
+                .head(&object.key)?
+                .map(|info| info.metadata)
+                .unwrap_or_default();
+            crate::upload_object(
+                &config.oss,
+                &object.key,
+                rewrapped,
+                config.sync.hash_algorithm,
+                &crate::storage::ObjectHeaders {
+                    metadata: metadata
+                        .iter()
+                        .filter(|(name, _)| name.as_str() != "sync-checksum")
+                        .map(|(name, value)| (name.as_str(), value.as_str()))
+                        .collect(),
+                    // Tags can't be read back through every backend; the key
+                    // at least tells packs from shared files
+                    tags: vec![("kind", object_kind(&object.key))],
+                    ..Default::default()
+                },
+            )?;
+            progress.record(&object.key)?;
+            println!("Rotated {}", object.key);
+        }
+        rotated += 1;
+    }
+
+    println!(
+        "{} {} objects, skipped {} that are unencrypted or use age/GPG",
+        if dry_run { "Would rotate" } else { "Rotated" },
+        rotated,
+        skipped
+    );
+    if !failed.is_empty() {
+        return Err(format!(
+            "{} objects could not be unwrapped with the keys given and were left as they were: {}; \
+             run again with the keys they need to finish",
+            failed.len(),
+            failed.join(", ")
+        )
+        .into());
+    }
+    if !dry_run {
+        progress.finish()?;
+    }
+    Ok(())
+}
+
+/// Objects an unfinished `rotate` already rewrote, kept in the cache
+/// directory per bucket and prefix. It only counts for the same
+/// sync.Recipients, so changing them starts over.
+struct RotateProgress {
+    path: PathBuf,
+    /// First line, naming the recipients
+    header: String,
+    done: std::collections::HashSet<String>,
+}
+
+impl RotateProgress {
+    fn open(config: &Config, prefix: &str) -> Result<RotateProgress, Box<dyn std::error::Error>> {
+        let target = format!(
+            "{} {} {} {}",
+            config.oss.backend.name(),
+            config.oss.endpoint,
+            config.oss.bucket_name,
+            prefix
+        );
+        let path = crate::paths::cache_dir()
+            .ok_or("Could not determine the cache directory")?
+            .join("rotate")
+            .join(&blake3::hash(target.as_bytes()).to_hex()[..16]);
+        let header = format!("# recipients: {}", config.sync.recipients.join(" "));
+        let done = match std::fs::read_to_string(&path) {
+            Ok(content) if content.lines().next() == Some(header.as_str()) => {
+                content.lines().skip(1).map(str::to_string).collect()
+            }
+            Ok(_) => std::collections::HashSet::new(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => std::collections::HashSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(RotateProgress { path, header, done })
+    }
+
+    fn record(&mut self, key: &str) -> Result<(), std::io::Error> {
+        use std::io::Write;
+        if self.done.is_empty() {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&self.path, format!("{}\n", self.header))?;
+        }
+        let mut file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}", key)?;
+        self.done.insert(key.to_string());
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), std::io::Error> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+// `kind` tag for an object rewritten in place
+fn object_kind(key: &str) -> &'static str {
+    match key.ends_with(".pack") || key.ends_with(".manifest") {
+        true => "pack",
+        false => "file",
+    }
+}
@@ -0,0 +1,193 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Key management service holding the master key of an `aws-kms:` or
+/// `aliyun-kms:` recipient. Requests go through the provider's CLI, so its
+/// usual credentials, profiles and region settings apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    Aws,
+    Aliyun,
+}
+
+impl Provider {
+    /// Prefix of the sync.Recipients entry, followed by the key id, ARN or alias
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Aws => "aws-kms",
+            Provider::Aliyun => "aliyun-kms",
+        }
+    }
+
+    /// Identifier stored in the key slot. Never reuse a retired id.
+    pub fn id(&self) -> u8 {
+        match self {
+            Provider::Aws => 1,
+            Provider::Aliyun => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Provider> {
+        match id {
+            1 => Some(Provider::Aws),
+            2 => Some(Provider::Aliyun),
+            _ => None,
+        }
+    }
+
+    /// Splits `aws-kms:<key>` / `aliyun-kms:<key>` into provider and key.
+    pub fn parse_recipient(entry: &str) -> Option<(Provider, &str)> {
+        [Provider::Aws, Provider::Aliyun]
+            .into_iter()
+            .find_map(|provider| {
+                entry
+                    .strip_prefix(provider.name())
+                    .and_then(|rest| rest.strip_prefix(':'))
+                    .map(|key_id| (provider, key_id))
+            })
+    }
+}
+
+/// A fresh key from GenerateDataKey: the plaintext to wrap with, and the
+/// ciphertext only the KMS can turn back into it.
+pub struct DataKey {
+    pub plaintext: Zeroizing<[u8; 32]>,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response {
+    plaintext: String,
+    #[serde(default)]
+    ciphertext_blob: String,
+}
+
+impl Drop for Response {
+    fn drop(&mut self) {
+        self.plaintext.zeroize();
+    }
+}
+
+pub fn generate_data_key(
+    provider: Provider,
+    key_id: &str,
+) -> Result<DataKey, Box<dyn std::error::Error>> {
+    let response = match provider {
+        Provider::Aws => run(
+            "aws",
+            &[
+                "kms",
+                "generate-data-key",
+                "--key-id",
+                key_id,
+                "--key-spec",
+                "AES_256",
+                "--output",
+                "json",
+            ],
+        )?,
+        Provider::Aliyun => run(
+            "aliyun",
+            &[
+                "kms",
+                "GenerateDataKey",
+                "--KeyId",
+                key_id,
+                "--KeySpec",
+                "AES_256",
+            ],
+        )?,
+    };
+
+    let ciphertext = match provider {
+        Provider::Aws => general_purpose::STANDARD.decode(&response.ciphertext_blob)?,
+        // Aliyun hands the blob around as an opaque string
+        Provider::Aliyun => response.ciphertext_blob.clone().into_bytes(),
+    };
+    if ciphertext.is_empty() {
+        return Err(format!("{} returned no CiphertextBlob", provider.name()).into());
+    }
+    Ok(DataKey {
+        plaintext: plaintext_key(provider, &response)?,
+        ciphertext,
+    })
+}
+
+/// Asks the KMS to decrypt a [`DataKey::ciphertext`].
+pub fn decrypt(
+    provider: Provider,
+    ciphertext: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+    let response = match provider {
+        Provider::Aws => {
+            // The CLI reads binary blobs from files; a base64 argument would
+            // depend on its cli_binary_format setting
+            let mut blob = tempfile::NamedTempFile::new()?;
+            blob.write_all(ciphertext)?;
+            let blob_arg = format!("fileb://{}", blob.path().display());
+            run(
+                "aws",
+                &[
+                    "kms",
+                    "decrypt",
+                    "--ciphertext-blob",
+                    &blob_arg,
+                    "--output",
+                    "json",
+                ],
+            )?
+        }
+        Provider::Aliyun => {
+            let blob = std::str::from_utf8(ciphertext)
+                .map_err(|_| "The aliyun-kms key slot is corrupted")?;
+            run("aliyun", &["kms", "Decrypt", "--CiphertextBlob", blob])?
+        }
+    };
+    plaintext_key(provider, &response)
+}
+
+fn plaintext_key(
+    provider: Provider,
+    response: &Response,
+) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+    let plaintext = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(&response.plaintext)
+            .map_err(|e| {
+                format!(
+                    "{} returned an unreadable Plaintext: {}",
+                    provider.name(),
+                    e
+                )
+            })?,
+    );
+    let key: [u8; 32] = plaintext.as_slice().try_into().map_err(|_| {
+        format!(
+            "{} returned a {}-byte key; expected 32",
+            provider.name(),
+            plaintext.len()
+        )
+    })?;
+    Ok(Zeroizing::new(key))
+}
+
+// Runs the provider CLI and parses its JSON output. Errors from the CLI
+// (credentials, permissions, unknown key) are printed on the terminal.
+fn run(program: &str, args: &[&str]) -> Result<Response, Box<dyn std::error::Error>> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("cannot run {} ({}); is its CLI installed?", program, e))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return Err(format!("{} {} failed ({})", program, args[1], output.status).into());
+    }
+    serde_json::from_slice(&stdout)
+        .map_err(|e| format!("Unexpected output from {} {}: {}", program, args[1], e).into())
+}
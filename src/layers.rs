@@ -0,0 +1,608 @@
+use crate::syncignore::SyncIgnore;
+use git2::{Commit, Oid, Repository, Signature, Tree};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Command;
+
+/// What `up` takes from the working directory on top of the index.
+#[derive(Clone, Copy)]
+pub struct Snapshot<'a> {
+    /// Files git doesn't track yet, except those .gitignore excludes
+    pub untracked: bool,
+    /// Edits and deletions of tracked files that aren't staged
+    pub unstaged: bool,
+    /// Pathspecs limiting the changes taken, staged ones included; all of
+    /// them when empty
+    pub paths: &'a [String],
+}
+
+impl Snapshot<'static> {
+    /// What `export` and autosave pack: the index plus untracked files
+    pub const DEFAULT: Snapshot<'static> = Snapshot {
+        untracked: true,
+        unstaged: false,
+        paths: &[],
+    };
+}
+
+// Changes the layers leave out: paths .syncignore excludes and those outside
+// the snapshot's pathspecs
+struct Filter {
+    ignore: SyncIgnore,
+    pathspec: Option<git2::Pathspec>,
+}
+
+impl Filter {
+    fn new(repo: &Repository, snapshot: Snapshot) -> Result<Filter, Box<dyn std::error::Error>> {
+        let pathspec = match snapshot.paths {
+            [] => None,
+            paths => Some(git2::Pathspec::new(paths)?),
+        };
+        Ok(Filter {
+            ignore: SyncIgnore::load(repo)?,
+            pathspec,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ignore.is_empty() && self.pathspec.is_none()
+    }
+
+    fn excludes(&self, path: &[u8]) -> bool {
+        let outside = self.pathspec.as_ref().is_some_and(|pathspec| {
+            !pathspec.matches_path(&repo_path(path), git2::PathspecFlags::DEFAULT)
+        });
+        outside || self.ignore.excludes(&String::from_utf8_lossy(path))
+    }
+}
+
+/// One temporary commit of a pack. They are stacked on HEAD in this order,
+/// each holding the one before plus its own changes, so `down` can put every
+/// change back where it was instead of staging everything.
+#[derive(Clone, Copy, PartialEq)]
+enum Layer {
+    /// The index
+    Staged,
+    /// Edits and deletions that aren't staged
+    Worktree,
+    /// Files git doesn't track yet
+    Untracked,
+}
+
+// Trailer naming a commit's layer in its message
+const LAYER_TRAILER: &str = "Sync-Layer: ";
+
+// Refs keeping track of stacks `commit` made until their pack is uploaded,
+// so the commits can be deleted afterwards, or by `gc-local` when the upload
+// never finished
+const TEMPORARY_REFS: &str = "refs/sync/tmp/";
+
+impl Layer {
+    fn name(self) -> &'static str {
+        match self {
+            Layer::Staged => "staged",
+            Layer::Worktree => "worktree",
+            Layer::Untracked => "untracked",
+        }
+    }
+
+    /// The layer `commit` holds; `None` for real commits and for the single
+    /// unmarked temporary commit of packs from older versions.
+    fn of(commit: &Commit) -> Option<Layer> {
+        let name = commit
+            .message()?
+            .lines()
+            .find_map(|line| line.strip_prefix(LAYER_TRAILER))?;
+        [Layer::Staged, Layer::Worktree, Layer::Untracked]
+            .into_iter()
+            .find(|layer| layer.name() == name.trim())
+    }
+}
+
+/// The temporary commits of a pack.
+pub struct Stack {
+    /// Topmost layer, the commit a pack carries
+    pub tip: Oid,
+    /// Its tree, i.e. everything the pack restores
+    pub tree: Oid,
+    pub untracked_count: usize,
+    pub unstaged_count: usize,
+}
+
+/// Commits the index on top of `head`, then the working-directory changes
+/// `snapshot` asks for as further layers. Layers without changes are left
+/// out, except the staged one. Nothing is staged in the repository itself;
+/// the trees are built in an in-memory copy of the index. On a branch with
+/// no commits yet (`head` None) the staged layer is a root commit. Changes
+/// to paths `.syncignore` excludes, or outside `snapshot.paths`, stay out of
+/// every layer.
+pub fn commit(
+    repo: &Repository,
+    head: Option<&Commit>,
+    snapshot: Snapshot,
+) -> Result<Stack, Box<dyn std::error::Error>> {
+    let stack = commit_stack(repo, head, snapshot)?;
+    repo.reference(
+        &format!("{}{}", TEMPORARY_REFS, stack.tip),
+        stack.tip,
+        true,
+        "packer: temporary commits",
+    )?;
+    Ok(stack)
+}
+
+/// The tree [`commit`] would give the topmost layer, without committing
+/// anything, so callers can tell whether there is anything new to pack.
+pub fn tree(
+    repo: &Repository,
+    head: Option<&Commit>,
+    snapshot: Snapshot,
+) -> Result<Oid, Box<dyn std::error::Error>> {
+    let trees = layer_trees(repo, head, snapshot)?;
+    Ok(trees.layers.last().map_or(trees.staged, |(_, tree)| *tree))
+}
+
+fn commit_stack(
+    repo: &Repository,
+    head: Option<&Commit>,
+    snapshot: Snapshot,
+) -> Result<Stack, Box<dyn std::error::Error>> {
+    let trees = layer_trees(repo, head, snapshot)?;
+    let mut stack = Stack {
+        tip: commit_layer(repo, head, Layer::Staged, trees.staged)?,
+        tree: trees.staged,
+        untracked_count: trees.untracked_count,
+        unstaged_count: trees.unstaged_count,
+    };
+    for (layer, tree) in trees.layers {
+        stack.push(repo, layer, tree)?;
+    }
+    if snapshot.untracked || snapshot.unstaged {
+        info!(
+            "Added {} unstaged and {} untracked files to the pack",
+            stack.unstaged_count, stack.untracked_count
+        );
+    }
+    if trees.excluded_count > 0 {
+        info!(
+            "Left out {} files that .syncignore or the pathspecs exclude",
+            trees.excluded_count
+        );
+    }
+
+    Ok(stack)
+}
+
+// Trees of the layers of a stack, before anything is committed
+struct LayerTrees {
+    staged: Oid,
+    /// The layers on top of the staged one that have changes
+    layers: Vec<(Layer, Oid)>,
+    untracked_count: usize,
+    unstaged_count: usize,
+    excluded_count: usize,
+}
+
+fn layer_trees(
+    repo: &Repository,
+    head: Option<&Commit>,
+    snapshot: Snapshot,
+) -> Result<LayerTrees, Box<dyn std::error::Error>> {
+    let filter = Filter::new(repo, snapshot)?;
+    let mut staged_tree = repo.index()?.write_tree()?;
+    if !filter.is_empty() {
+        staged_tree = unstage_excluded(repo, head, staged_tree, &filter)?;
+    }
+    let mut trees = LayerTrees {
+        staged: staged_tree,
+        layers: Vec::new(),
+        untracked_count: 0,
+        unstaged_count: 0,
+        excluded_count: 0,
+    };
+    let workdir = match repo.workdir() {
+        Some(workdir) if snapshot.untracked || snapshot.unstaged => workdir,
+        _ => return Ok(trees),
+    };
+
+    let mut status_options = git2::StatusOptions::new();
+    status_options
+        .include_untracked(snapshot.untracked)
+        .recurse_untracked_dirs(snapshot.untracked)
+        .include_ignored(false)
+        .exclude_submodules(true);
+    let modified = git2::Status::WT_MODIFIED | git2::Status::WT_TYPECHANGE;
+    let mut unstaged = Vec::new();
+    let mut deleted = Vec::new();
+    let mut untracked = Vec::new();
+    for entry in repo.statuses(Some(&mut status_options))?.iter() {
+        let path = entry.path_bytes();
+        if filter.excludes(path) {
+            trees.excluded_count += 1;
+            continue;
+        }
+        let status = entry.status();
+        if status.contains(git2::Status::WT_NEW) && snapshot.untracked {
+            untracked.push(path.to_vec());
+        } else if status.intersects(modified) && snapshot.unstaged {
+            unstaged.push(path.to_vec());
+        } else if status.contains(git2::Status::WT_DELETED) && snapshot.unstaged {
+            deleted.push(path.to_vec());
+        }
+    }
+
+    let mut index = git2::Index::new()?;
+    index.read_tree(&repo.find_tree(staged_tree)?)?;
+    if !unstaged.is_empty() || !deleted.is_empty() {
+        for path in &deleted {
+            index.remove_path(&repo_path(path))?;
+        }
+        for path in &unstaged {
+            add_from_workdir(repo, &mut index, workdir, path)?;
+        }
+        trees.unstaged_count = unstaged.len() + deleted.len();
+        trees
+            .layers
+            .push((Layer::Worktree, index.write_tree_to(repo)?));
+    }
+    if !untracked.is_empty() {
+        for path in &untracked {
+            add_from_workdir(repo, &mut index, workdir, path)?;
+        }
+        trees.untracked_count = untracked.len();
+        trees
+            .layers
+            .push((Layer::Untracked, index.write_tree_to(repo)?));
+    }
+
+    Ok(trees)
+}
+
+// The staged tree with the changes to excluded paths undone, i.e. those
+// paths as `head` has them
+fn unstage_excluded(
+    repo: &Repository,
+    head: Option<&Commit>,
+    staged_tree: Oid,
+    filter: &Filter,
+) -> Result<Oid, Box<dyn std::error::Error>> {
+    let head_tree = head.map(Commit::tree).transpose()?;
+    let staged = repo.find_tree(staged_tree)?;
+    let diff = repo.diff_tree_to_tree(head_tree.as_ref(), Some(&staged), None)?;
+    let mut index = git2::Index::new()?;
+    index.read_tree(&staged)?;
+    let mut changed = false;
+    for delta in diff.deltas() {
+        let Some(path) = delta.new_file().path_bytes() else {
+            continue;
+        };
+        if !filter.excludes(path) {
+            continue;
+        }
+        let old_file = delta.old_file();
+        if old_file.id().is_zero() {
+            index.remove_path(&repo_path(path))?;
+        } else {
+            index.add(&index_entry(path, old_file.id(), old_file.mode().into(), 0))?;
+        }
+        changed = true;
+    }
+    match changed {
+        true => Ok(index.write_tree_to(repo)?),
+        false => Ok(staged_tree),
+    }
+}
+
+/// Deletes the refs under refs/sync/tmp/ that kept the temporary commits
+/// of unfinished stacks. The commits themselves are left to `git gc`, which
+/// only prunes them if nothing else (a branch `down` created, a stash, a
+/// reflog) still reaches them. Returns how many commits were let go.
+pub fn cleanup(repo: &Repository) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut references = Vec::new();
+    for reference in repo.references_glob(&format!("{}*", TEMPORARY_REFS))? {
+        references.push(reference?);
+    }
+    // Stacks made twice from the same trees in the same second share their
+    // commits
+    let mut released = HashSet::new();
+    for mut reference in references {
+        let mut commit = reference
+            .target()
+            .and_then(|tip| repo.find_commit(tip).ok());
+        // Stops at the real commit the stack sits on
+        while let Some(layer_commit) = commit.take().filter(|commit| Layer::of(commit).is_some()) {
+            commit = layer_commit.parents().next();
+            released.insert(layer_commit.id());
+        }
+        reference.delete()?;
+    }
+    Ok(released.len())
+}
+
+impl Stack {
+    fn push(&mut self, repo: &Repository, layer: Layer, tree: Oid) -> Result<(), git2::Error> {
+        let parent = repo.find_commit(self.tip)?;
+        self.tip = commit_layer(repo, Some(&parent), layer, tree)?;
+        self.tree = tree;
+        Ok(())
+    }
+}
+
+fn commit_layer(
+    repo: &Repository,
+    parent: Option<&Commit>,
+    layer: Layer,
+    tree: Oid,
+) -> Result<Oid, git2::Error> {
+    // The user's own identity, as the commit may be kept after `down`
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now("Git Pack Generator", "noreply@example.com"))?;
+    let message = format!(
+        "Temporary commit for pack generation\n\n{}{}\n",
+        LAYER_TRAILER,
+        layer.name()
+    );
+    if repo.config()?.get_bool("commit.gpgSign").unwrap_or(false) {
+        let oid = commit_signed(repo, parent, &message, tree)?;
+        info!(
+            "Created signed temporary commit for {} changes: {}",
+            layer.name(),
+            oid
+        );
+        return Ok(oid);
+    }
+    let oid = repo.commit(
+        None, // Don't update any references
+        &signature,
+        &signature,
+        &message,
+        &repo.find_tree(tree)?,
+        &parent.into_iter().collect::<Vec<_>>(),
+    )?;
+    info!(
+        "Created temporary commit for {} changes: {}",
+        layer.name(),
+        oid
+    );
+    Ok(oid)
+}
+
+// Leaves the signing to `git commit-tree -S`, so whatever gpg.format and
+// user.signingKey git is set up with signs the commit as it would any other
+fn commit_signed(
+    repo: &Repository,
+    parent: Option<&Commit>,
+    message: &str,
+    tree: Oid,
+) -> Result<Oid, git2::Error> {
+    let mut command = Command::new("git");
+    command
+        .args(["commit-tree", "-S", "-m", message])
+        .arg(tree.to_string())
+        .current_dir(repo.path());
+    if let Some(parent) = parent {
+        command.arg("-p").arg(parent.id().to_string());
+    }
+    let output = command
+        .output()
+        .map_err(|e| git2::Error::from_str(&format!("Failed to run git commit-tree: {}", e)))?;
+    if !output.status.success() {
+        return Err(git2::Error::from_str(&format!(
+            "Signing the temporary commit failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Oid::from_str(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+/// Checks the signatures of the temporary commits under `tip`, or of `tip`
+/// itself when it has none, with `git verify-commit`: against the gpg
+/// keyring, or gpg.ssh.allowedSignersFile for SSH signatures.
+pub fn verify(repo: &Repository, tip: Oid) -> Result<(), Box<dyn std::error::Error>> {
+    let mut commit = repo.find_commit(tip)?;
+    loop {
+        let output = Command::new("git")
+            .args(["verify-commit", &commit.id().to_string()])
+            .current_dir(repo.path())
+            .output()?;
+        if !output.status.success() {
+            // git says nothing about a commit that isn't signed at all
+            let reason = match String::from_utf8_lossy(&output.stderr).trim() {
+                "" => "not signed".to_string(),
+                reason => reason.to_string(),
+            };
+            return Err(t!(
+                "down.unverified_commit",
+                &commit.id().to_string()[..12],
+                reason
+            )
+            .into());
+        }
+        match commit.parents().next() {
+            Some(parent) if Layer::of(&parent).is_some() => commit = parent,
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn add_from_workdir(
+    repo: &Repository,
+    index: &mut git2::Index,
+    workdir: &Path,
+    path: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let full_path = workdir.join(repo_path(path));
+    let metadata = std::fs::symlink_metadata(&full_path)?;
+    let (id, mode) = if metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(&full_path)?;
+        (repo.blob(&link_target(target))?, 0o120000)
+    } else {
+        let staged_mode = index.get_path(&repo_path(path), 0).map(|entry| entry.mode);
+        (
+            repo.blob_path(&full_path)?,
+            file_mode(&metadata, staged_mode),
+        )
+    };
+    index.add(&index_entry(path, id, mode, metadata.len() as u32))?;
+    Ok(())
+}
+
+// A path as git stores it, which is bytes, as a file system path. On Unix
+// any bytes name a file; elsewhere git keeps paths in UTF-8.
+#[cfg(unix)]
+fn repo_path(path: &[u8]) -> Cow<'_, Path> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(Path::new(std::ffi::OsStr::from_bytes(path)))
+}
+
+#[cfg(not(unix))]
+fn repo_path(path: &[u8]) -> Cow<'_, Path> {
+    match String::from_utf8_lossy(path) {
+        Cow::Borrowed(path) => Cow::Borrowed(Path::new(path)),
+        Cow::Owned(path) => Cow::Owned(path.into()),
+    }
+}
+
+// Content of the blob for a symlink: its target, byte for byte
+#[cfg(unix)]
+fn link_target(target: std::path::PathBuf) -> Vec<u8> {
+    use std::os::unix::ffi::OsStringExt;
+    target.into_os_string().into_vec()
+}
+
+#[cfg(not(unix))]
+fn link_target(target: std::path::PathBuf) -> Vec<u8> {
+    target.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+// Stat data is left zero; the index only serves to write trees
+fn index_entry(path: &[u8], id: Oid, mode: u32, file_size: u32) -> git2::IndexEntry {
+    git2::IndexEntry {
+        ctime: git2::IndexTime::new(0, 0),
+        mtime: git2::IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        file_size,
+        id,
+        flags: path.len().min(0xfff) as u16,
+        flags_extended: 0,
+        path: path.to_vec(),
+    }
+}
+
+// Blob mode for a regular file, keeping the executable bit where there is
+// one. Elsewhere the mode already staged for the path stands.
+fn file_mode(metadata: &std::fs::Metadata, staged_mode: Option<u32>) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = staged_mode;
+        match metadata.permissions().mode() & 0o111 {
+            0 => 0o100644,
+            _ => 0o100755,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        match staged_mode {
+            Some(0o100755) => 0o100755,
+            _ => 0o100644,
+        }
+    }
+}
+
+/// The real commit a pack's layers sit on; `tip` itself for packs without
+/// layers, `None` for packs of a branch that had no commits.
+pub fn base(repo: &Repository, tip: Oid) -> Result<Option<Oid>, git2::Error> {
+    let mut commit = Some(repo.find_commit(tip)?);
+    while let Some(layer_commit) = commit.as_ref().filter(|commit| Layer::of(commit).is_some()) {
+        commit = layer_commit.parents().next();
+    }
+    Ok(commit.map(|commit| commit.id()))
+}
+
+// The tree a layer's changes are relative to; the empty tree for the root
+// layer of a branch that had no commits
+fn parent_tree<'r>(repo: &'r Repository, commit: &Commit<'r>) -> Result<Tree<'r>, git2::Error> {
+    match commit.parents().next() {
+        Some(parent) => parent.tree(),
+        None => repo.find_tree(repo.treebuilder(None)?.write()?),
+    }
+}
+
+/// Replays the layers above `tip`'s base onto `onto`, for a `down` that
+/// merged or rebased instead of resetting. Returns the new tip, or `None`
+/// when the changes conflict with `onto`.
+pub fn restack(
+    repo: &Repository,
+    tip: Oid,
+    onto: Oid,
+) -> Result<Option<Oid>, Box<dyn std::error::Error>> {
+    let mut layers = Vec::new();
+    let mut commit = Some(repo.find_commit(tip)?);
+    while let Some(layer) = commit.as_ref().and_then(Layer::of) {
+        let layer_commit = commit.take().expect("checked above");
+        commit = layer_commit.parents().next();
+        layers.push((layer, layer_commit));
+    }
+
+    let mut new_tip = repo.find_commit(onto)?;
+    for (layer, commit) in layers.into_iter().rev() {
+        let mut index = repo.merge_trees(
+            &parent_tree(repo, &commit)?,
+            &new_tip.tree()?,
+            &commit.tree()?,
+            None,
+        )?;
+        if index.has_conflicts() {
+            return Ok(None);
+        }
+        let tree = index.write_tree_to(repo)?;
+        new_tip = repo.find_commit(commit_layer(repo, Some(&new_tip), layer, tree)?)?;
+    }
+    Ok(Some(new_tip.id()))
+}
+
+/// After a hard reset to a pack's tip, which leaves every change staged,
+/// moves the branch back to the real commit under the layers and sets the
+/// index to the staged layer. Unstaged edits and untracked files then show
+/// up as such again. Returns the names of the layers found, none for packs
+/// from older versions, which stay as the reset left them. Packs of a branch
+/// that had no commits leave it without any again.
+pub fn unstack(repo: &Repository, tip: Oid) -> Result<Vec<&'static str>, git2::Error> {
+    let mut commit = Some(repo.find_commit(tip)?);
+    let mut staged: Option<Tree> = None;
+    let mut found = Vec::new();
+    while let Some(layer) = commit.as_ref().and_then(Layer::of) {
+        let layer_commit = commit.take().expect("checked above");
+        if layer == Layer::Staged {
+            staged = Some(layer_commit.tree()?);
+        }
+        found.push(layer.name());
+        commit = layer_commit.parents().next();
+    }
+    let Some(staged) = staged else {
+        return Ok(found);
+    };
+
+    match commit {
+        Some(commit) => repo.reset(commit.as_object(), git2::ResetType::Soft, None)?,
+        None => {
+            // Back to an unborn branch: HEAD still names it, the ref goes
+            if let Some(branch) = repo.find_reference("HEAD")?.symbolic_target() {
+                repo.find_reference(branch)?.delete()?;
+            }
+        }
+    }
+    let mut index = repo.index()?;
+    index.read_tree(&staged)?;
+    index.write()?;
+    found.reverse();
+    Ok(found)
+}
@@ -0,0 +1,188 @@
+use crate::config::Config;
+use crate::hash::HashAlgorithm;
+use crate::manifest::LfsObject;
+use crate::storage::ObjectHeaders;
+use git2::{Repository, Tree};
+use std::path::PathBuf;
+
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+// Git LFS never writes pointers larger than this
+const MAX_POINTER_SIZE: usize = 1024;
+
+/// A Git LFS pointer file: the blob git stores in place of the content.
+pub struct Pointer {
+    /// SHA-256 of the content, hex
+    pub oid: String,
+    pub size: u64,
+    /// Where the pointer was found, for messages
+    pub path: String,
+}
+
+fn parse(data: &[u8]) -> Option<(String, u64)> {
+    let text = std::str::from_utf8(data).ok()?;
+    let mut lines = text.lines();
+    if lines.next()? != POINTER_VERSION {
+        return None;
+    }
+    let mut oid = None;
+    let mut size = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("oid sha256:") {
+            oid = Some(value.to_string());
+        } else if let Some(value) = line.strip_prefix("size ") {
+            size = value.parse().ok();
+        }
+    }
+    let oid = oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))?;
+    Some((oid, size?))
+}
+
+/// The LFS pointers added or changed between `base` (the upstream's tree, if
+/// any) and `tree`.
+pub fn pointers(
+    repo: &Repository,
+    base: Option<&Tree>,
+    tree: &Tree,
+) -> Result<Vec<Pointer>, git2::Error> {
+    let diff = repo.diff_tree_to_tree(base, Some(tree), None)?;
+    let mut pointers = Vec::new();
+    for delta in diff.deltas() {
+        let file = delta.new_file();
+        if file.id().is_zero() || file.size() as usize > MAX_POINTER_SIZE {
+            continue;
+        }
+        let blob = match repo.find_blob(file.id()) {
+            Ok(blob) => blob,
+            // Submodule gitlinks and the like
+            Err(_) => continue,
+        };
+        if let Some((oid, size)) = parse(blob.content()) {
+            if !pointers.iter().any(|pointer: &Pointer| pointer.oid == oid) {
+                pointers.push(Pointer {
+                    oid,
+                    size,
+                    path: file
+                        .path()
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+    }
+    Ok(pointers)
+}
+
+/// Where Git LFS keeps an object locally: `.git/lfs/objects/ab/cd/abcd…`.
+/// Linked worktrees share the main repository's store.
+fn object_path(repo: &Repository, oid: &str) -> PathBuf {
+    crate::git_common_dir(repo)
+        .join("lfs")
+        .join("objects")
+        .join(&oid[0..2])
+        .join(&oid[2..4])
+        .join(oid)
+}
+
+/// Uploads the content behind `pointers` under `{pack_dir}/lfs/<oid>`,
+/// skipping objects already in the bucket. Objects this machine never
+/// fetched are reported and left out.
+pub fn upload(
+    repo: &Repository,
+    config: &Config,
+    pack_dir: &str,
+    pointers: &[Pointer],
+    tags: &[(&str, &str)],
+) -> Result<Vec<LfsObject>, Box<dyn std::error::Error>> {
+    let mut uploaded = Vec::new();
+    for pointer in pointers {
+        let key = format!("{}/lfs/{}", pack_dir, pointer.oid);
+        let object = LfsObject {
+            oid: pointer.oid.clone(),
+            size: pointer.size,
+            key: key.clone(),
+        };
+        // Content-addressed, so an existing object is the same object
+        if crate::head_object(&config.oss, &key)?.is_some() {
+            uploaded.push(object);
+            continue;
+        }
+        let data = match std::fs::read(object_path(repo, &pointer.oid)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("{}", t!("up.lfs_missing", pointer.path, pointer.oid));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let data = crate::seal_payload(&config.sync, data, Some(&key))?;
+        crate::upload_object(
+            &config.oss,
+            &key,
+            data,
+            config.sync.hash_algorithm,
+            &ObjectHeaders {
+                tags: tags.to_vec(),
+                ..Default::default()
+            },
+        )?;
+        uploaded.push(object);
+    }
+    if !uploaded.is_empty() {
+        let total: u64 = uploaded.iter().map(|object| object.size).sum();
+        println!(
+            "{}",
+            t!(
+                "up.lfs_uploaded",
+                uploaded.len(),
+                crate::units::format_size(total)
+            )
+        );
+    }
+    Ok(uploaded)
+}
+
+/// Puts the LFS objects a pack needs into this repository's LFS store, so
+/// the checkout that follows finds them instead of leaving pointers behind.
+pub fn download(
+    repo: &Repository,
+    config: &Config,
+    objects: &[LfsObject],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut fetched = 0;
+    for object in objects {
+        let path = object_path(repo, &object.oid);
+        if path.is_file() {
+            continue;
+        }
+        let Some(data) = crate::try_download(&config.oss, &object.key)? else {
+            println!("{}", t!("down.lfs_missing", object.oid));
+            continue;
+        };
+        let data = crate::open_payload(&config.sync, data, Some(&object.key))?;
+        if !HashAlgorithm::Sha256
+            .digest(&data)
+            .eq_ignore_ascii_case(&object.oid)
+        {
+            return Err(t!(
+                "down.refusing",
+                object.key,
+                "LFS object doesn't match its oid"
+            )
+            .into());
+        }
+
+        let parent = path.parent().ok_or("LFS object path has no parent")?;
+        std::fs::create_dir_all(parent)?;
+        // Git LFS trusts whatever sits at the final path, so never leave a
+        // partial file there
+        let mut temp_file = tempfile::NamedTempFile::new_in(parent)?;
+        std::io::Write::write_all(&mut temp_file, &data)?;
+        temp_file.persist(&path)?;
+        fetched += 1;
+    }
+    if fetched > 0 {
+        println!("{}", t!("down.lfs_fetched", fetched));
+    }
+    Ok(())
+}
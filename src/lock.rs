@@ -0,0 +1,78 @@
+use git2::Repository;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// How often a lock held elsewhere is tried again while a deadline runs
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive advisory lock, held until dropped. Keeps a hook-triggered
+/// `up`, an autosave watcher and a manual command from interleaving.
+pub struct Lock {
+    _file: File,
+    /// Another process held the lock and we waited for it
+    pub waited: bool,
+}
+
+/// Locks `path` (created if missing), waiting for other holders, but not
+/// past the deadline (`--deadline`, sync.Deadlines).
+pub fn exclusive(path: &Path) -> Result<Lock, Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("Cannot open lock file {}: {}", path.display(), e))?;
+
+    let waited = match file.try_lock() {
+        Ok(()) => false,
+        Err(TryLockError::WouldBlock) => {
+            info!("{}", t!("lock.waiting", path.display()));
+            wait(&file, path)?;
+            true
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(format!("Cannot lock {}: {}", path.display(), e).into())
+        }
+    };
+
+    Ok(Lock {
+        _file: file,
+        waited,
+    })
+}
+
+// Blocks until `file` is locked; with a deadline, polls so it can give up
+fn wait(file: &File, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(mut remaining) = crate::deadline::remaining() else {
+        file.lock()?;
+        return Ok(());
+    };
+    loop {
+        std::thread::sleep(remaining.min(POLL_INTERVAL));
+        match file.try_lock() {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(format!("Cannot lock {}: {}", path.display(), e).into())
+            }
+        }
+        crate::deadline::check("lock")?;
+        remaining = crate::deadline::remaining().unwrap_or(POLL_INTERVAL);
+    }
+}
+
+/// Serializes operations that upload from or write to this repository.
+pub fn repository(repo: &Repository) -> Result<Lock, Box<dyn std::error::Error>> {
+    exclusive(&repo.path().join("sync").join("lock"))
+}
+
+/// Lock guarding read-modify-write of a state file in the cache.
+pub fn state_file(path: &Path) -> Result<Lock, Box<dyn std::error::Error>> {
+    let mut lock_path = PathBuf::from(path);
+    lock_path.as_mut_os_string().push(".lock");
+    exclusive(&lock_path)
+}
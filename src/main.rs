@@ -1,385 +1,2789 @@
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
     Aes256Gcm, Key,
 };
-use aws_sdk_s3::config::Region;
-use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
-use aws_sdk_s3::Client;
-use clap::{Parser, Subcommand};
-use git2::{Buf, Repository, Signature};
-use hostname;
-use serde::Deserialize;
-use std::path::Path;
-use tokio::runtime::Runtime;
-
-// Include the credentials file directly at compile time
-const CONFIG_TOML: &str = include_str!("cred.toml");
-// Fixed encryption key for second round (32 bytes for AES-256)
-const FIXED_KEY: &[u8; 32] = b"eZ4Ro3aish5zeitei!cau2aegei|Gh3a";
+use clap::{CommandFactory, Parser, Subcommand};
+use compression::{Compression, ContentEncoding};
+use config::{Config, Encryption, OssConfig, SyncConfig};
+use envelope::Cipher;
+use git2::{BranchType, Buf, Repository};
+use hash::HashAlgorithm;
+use layers::Snapshot;
+use manifest::{GitConfigSnapshot, Manifest, PackedTag, SubmodulePack};
+use pack_metadata::PackMetadata;
+use serde::Serialize;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use storage::{ListedObject, ObjectHeaders};
+use zeroize::Zeroizing;
+
+/// Formats message `id` from the catalog in [`i18n`] in the current locale.
+macro_rules! t {
+    ($id:literal $(, $arg:expr)* $(,)?) => {
+        crate::i18n::format($id, &[$(&$arg as &dyn std::fmt::Display),*])
+    };
+}
+
+// Status messages on code paths shared with `export -`, which move to stderr
+// while stdout carries the pack
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if crate::output::stdout_reserved() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod age_encryption;
+mod audit;
+mod auto;
+mod azure;
+mod backup;
+mod bundle;
+mod changes;
+mod compression;
+mod config;
+mod deadline;
+mod dedup;
+mod doctor;
+mod envelope;
+mod fs_backend;
+mod gcs;
+mod gpg;
+mod hardware;
+mod hash;
+mod help;
+mod hooks;
+mod i18n;
+mod keys;
+mod kms;
+mod layers;
+mod lfs;
+mod lock;
+mod manifest;
+mod mirror;
+mod names;
+mod object_url;
+mod output;
+mod pack_metadata;
+mod passphrase;
+mod patches;
+mod paths;
+mod progress;
+mod proxy;
+mod rest;
+mod retry;
+mod rpc;
+mod s3;
+mod sftp;
+mod signing;
+mod stash;
+mod storage;
+mod submodules;
+mod syncignore;
+mod timeouts;
+mod units;
+
+// Download speed cap in bytes per second, set from `--limit-rate`
+static LIMIT_RATE: OnceLock<u64> = OnceLock::new();
 
 #[derive(Parser)]
 #[command(name = "packer")]
 #[command(about = "Git pack generator and uploader", long_about = None)]
+#[command(disable_help_subcommand = true)]
+#[command(
+    after_help = "Run `packer help <command>` for examples, or `packer help topics` for how \
+                  encryption, the object envelope and the bucket layout work."
+)]
 struct Cli {
+    /// Emit machine-readable NDJSON progress events on stderr
+    #[arg(long, global = true)]
+    progress_json: bool,
+
+    /// Use this config file instead of searching the default locations
+    #[arg(long, global = true, env = "SYNC_CONFIG", value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// File holding the 32-byte outer encryption key (overrides sync.KeyFile)
+    #[arg(long, global = true, value_name = "PATH")]
+    key_file: Option<PathBuf>,
+
+    /// Abort if the command takes longer than this, e.g. 2m (overrides
+    /// sync.Deadlines)
+    #[arg(long, global = true, env = "SYNC_DEADLINE", value_name = "DURATION")]
+    deadline: Option<String>,
+
+    /// Cap download speed, e.g. 500k or 2M (bytes per second)
+    #[arg(long, global = true, value_name = "SIZE")]
+    limit_rate: Option<String>,
+
+    /// Require a subcommand instead of deciding between up and down
+    #[arg(long)]
+    no_auto: bool,
+
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Upload a pack file with changes between local and remote branches
+    #[command(after_long_help = "\
+Examples:
+  packer up                   encrypt and upload the current branch
+  packer up --dry-run         list the changes that would be uploaded
+  packer up --worktree        also carry edits that aren't staged
+  packer up --all-branches    also upload other branches with local commits
+  packer up --branch feature  upload another branch without switching to it
+  packer up --format patch    send the commits as patches, for `git am` or
+                              `down --format patch`
+  packer up --format bundle --raw
+                              upload an incremental bundle and print a link;
+                              `git fetch <file>` takes it without packer
+  packer up --base origin/main
+                              cut the pack against another upstream
+  packer up --bundle          upload the whole repository for `init-from`
+  packer up --name bisect     upload under a name, e.g. from a detached HEAD
+  packer up -- crates/core    take only the changes under crates/core
+  packer up --commits 1a2b3c4,5d6e7f8
+                              hand over two commits, without the worktree
+  packer up --no-include-untracked
+                              leave files git doesn't track yet behind
+  packer up --git-config      also carry branch tracking and sparse-checkout
+  packer up --raw             upload an unencrypted pack and print a link
+
+Changes to paths matching .syncignore (gitignore syntax, at the top of the
+working directory) are left out.
+
+See also: packer help layout, packer help encryption")]
     Up {
         /// Upload raw pack file without encryption
         #[arg(long)]
         raw: bool,
+        /// Include selected local git config (branch tracking, sparse-checkout,
+        /// info/exclude) in the manifest so `down` restores it
+        #[arg(long)]
+        git_config: bool,
+        /// Show what would be uploaded without uploading anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also pack files git doesn't track yet, except those .gitignore
+        /// excludes (the default)
+        #[arg(long, overrides_with = "no_include_untracked")]
+        include_untracked: bool,
+        /// Pack only committed and staged changes, leaving untracked files out
+        #[arg(long)]
+        no_include_untracked: bool,
+        /// Pack the working tree as it is, including edits and deletions that
+        /// aren't staged
+        #[arg(long)]
+        worktree: bool,
+        /// Upload every local branch with commits its upstream doesn't have,
+        /// each to its own pack, instead of only the current one
+        #[arg(long)]
+        all_branches: bool,
+        /// Leave out what this ref, tag or SHA already has instead of what
+        /// origin's copy of the branch has; `none` packs the whole history
+        #[arg(long, value_name = "REF")]
+        base: Option<String>,
+        /// Upload every branch and tag with their whole history as one
+        /// bundle, for `init-from` on a machine without a clone
+        #[arg(long, conflicts_with_all = ["raw", "git_config", "all_branches", "base"])]
+        bundle: bool,
+        /// Store the pack under this name instead of the branch's; a
+        /// detached HEAD is otherwise stored as detached-<sha>
+        #[arg(long, conflicts_with_all = ["all_branches", "bundle"])]
+        name: Option<String>,
+        /// How long the printed download link stays valid, e.g. 36h or 7d
+        /// (at most 7d; 48h by default)
+        #[arg(long, value_name = "DURATION", conflicts_with = "bundle")]
+        ttl: Option<String>,
+        /// Upload this local branch as committed instead of the current one,
+        /// without checking it out
+        #[arg(
+            long,
+            value_name = "NAME",
+            conflicts_with_all = ["worktree", "all_branches", "bundle", "paths"]
+        )]
+        branch: Option<String>,
+        /// Pack exactly these commits, comma-separated, and the objects they
+        /// bring instead of the branch; the last one is the pack's commit
+        #[arg(
+            long,
+            value_name = "SHA",
+            value_delimiter = ',',
+            conflicts_with_all = ["range", "worktree", "all_branches", "base", "bundle", "paths", "branch"]
+        )]
+        commits: Vec<String>,
+        /// Pack the commits of A..B instead of the branch, as `git log A..B`
+        /// lists them
+        #[arg(
+            long,
+            value_name = "A..B",
+            conflicts_with_all = ["worktree", "all_branches", "base", "bundle", "paths", "branch"]
+        )]
+        range: Option<String>,
+        /// What to upload: a git `pack`; the pack as an incremental `bundle`,
+        /// which plain `git fetch` takes too; or the commits as a `patch`
+        /// series in a mailbox, as `git format-patch` writes it
+        #[arg(
+            long,
+            default_value = "pack",
+            value_parser = ["pack", "bundle", "patch"],
+            conflicts_with_all = ["bundle", "commits"]
+        )]
+        format: String,
+        /// Take only the staged, unstaged and untracked changes under these
+        /// paths; commits are packed whole
+        #[arg(last = true, value_name = "PATHSPEC")]
+        paths: Vec<String>,
     },
     /// Download and apply a pack file from remote storage
-    Down,
+    #[command(after_long_help = "\
+Examples:
+  packer down                 reset the current branch to the uploaded state
+  packer down --dry-run       show what would change first
+  packer down --branch main   fetch and check out another branch
+  packer down --branch main --no-switch
+                              update another branch, staying on this one
+  packer down --name bisect   apply a pack `up --name` stored, detaching HEAD
+  packer down --branch-off review
+                              put the pack on a new branch to look at first
+  packer down --rebase-local  keep local commits, replaying them on top
+  packer down --merge         merge when both sides have new commits
+  packer down --fetch-only    only fetch, then e.g. `git merge FETCH_HEAD`
+  packer down --autostash     stash local changes first and reapply them after
+  packer down --format bundle apply what `up --format bundle` uploaded
+  packer down --user alice    fetch a teammate's work into wip/alice/<branch>
+  packer down https://<bucket>.<endpoint>/<owner>/<repo>/main/head.pack
+                              apply a pack by the URL `up` printed
+
+See also: packer help encryption (sync.TrustedDevices)")]
+    Down {
+        /// Pack to apply, as an object key or URL (default: the current
+        /// branch's pack)
+        pack: Option<String>,
+        /// Fetch a teammate's work-in-progress into the local branch
+        /// wip/<user>/<branch> instead of resetting the current branch; the
+        /// pack must be signed by a device in sync.TrustedDevices
+        #[arg(long)]
+        user: Option<String>,
+        /// Branch to download instead of the current one; it is checked out,
+        /// and created at the received commit if it doesn't exist locally
+        #[arg(long)]
+        branch: Option<String>,
+        /// Name a pack was stored under with `up --name`, or detached-<sha>;
+        /// HEAD is detached at the received commit
+        #[arg(long, conflicts_with = "branch")]
+        name: Option<String>,
+        /// Create this new local branch at the received commit instead of
+        /// resetting, leaving the current branch and worktree untouched
+        #[arg(long, value_name = "NAME", conflicts_with = "user")]
+        branch_off: Option<String>,
+        /// How to bring the current branch to the received commit: `reset`
+        /// it, losing local commits made since, or `merge` or `rebase` them.
+        /// By default it is reset unless both sides have commits the other
+        /// doesn't.
+        #[arg(long, value_parser = ["reset", "merge", "rebase"])]
+        strategy: Option<String>,
+        /// Replay local commits the received commit doesn't have on top of
+        /// it instead of discarding them; short for `--strategy rebase`
+        #[arg(long, conflicts_with_all = ["strategy", "user", "branch_off"])]
+        rebase_local: bool,
+        /// Merge the received commit into the current branch when they have
+        /// diverged; short for `--strategy merge`
+        #[arg(long, conflicts_with_all = ["strategy", "rebase_local", "user", "branch_off"])]
+        merge: bool,
+        /// Only add the pack's objects: the received commit goes to FETCH_HEAD
+        /// and refs/sync/incoming, for merging or cherry-picking by hand
+        #[arg(
+            long,
+            conflicts_with_all = ["user", "branch_off", "strategy", "rebase_local", "merge", "dry_run"]
+        )]
+        fetch_only: bool,
+        /// With --branch, create or update the local branch at the received
+        /// commit without checking it out; uncommitted changes in the pack
+        /// are left out
+        #[arg(
+            long,
+            requires = "branch",
+            conflicts_with_all = ["user", "branch_off", "strategy", "rebase_local", "merge", "fetch_only"]
+        )]
+        no_switch: bool,
+        /// What was uploaded: a `pack`, a `bundle` from `up --format bundle`
+        /// or `git bundle create`, or a `patch` series from
+        /// `up --format patch`, which is applied on top of HEAD as `git am`
+        /// would
+        #[arg(long, default_value = "pack", value_parser = ["pack", "bundle", "patch"])]
+        format: String,
+        /// Reset even when that throws away uncommitted changes the pack
+        /// doesn't have
+        #[arg(long)]
+        force: bool,
+        /// Stash uncommitted changes to tracked files before applying the
+        /// pack and reapply them afterwards, as `git pull --autostash` does;
+        /// on conflicts they stay in the stash
+        #[arg(
+            long,
+            conflicts_with_all = ["user", "branch_off", "fetch_only", "no_switch", "dry_run"]
+        )]
+        autostash: bool,
+        /// Show what applying the pack would change without touching the worktree
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Create a repository from the bundle `up --bundle` uploaded, like
+    /// `git clone` but from the bucket
+    #[command(after_long_help = "\
+Examples:
+  packer init-from https://github.com/me/project.git
+  packer init-from git@github.com:me/project.git ~/src/project")]
+    InitFrom {
+        /// The repository's origin URL, as on the machine that uploaded it
+        url: String,
+        /// Where to create it (default: a directory named after the repository)
+        directory: Option<PathBuf>,
+    },
+    /// Write the pack for the current branch to a file instead of the bucket,
+    /// e.g. `packer export - | ssh other-machine packer import -`
+    #[command(after_long_help = "\
+Examples:
+  packer export wip.pack
+  packer export - | ssh other-machine 'cd repo && packer import -'")]
+    Export {
+        /// Output file, or `-` for stdout
+        output: String,
+    },
+    /// Apply a pack written by `export`
+    Import {
+        /// Input file, or `-` for stdin
+        input: String,
+        /// Reset even when that throws away uncommitted changes the pack
+        /// doesn't have
+        #[arg(long)]
+        force: bool,
+    },
+    /// Upload stash entries, including their staged and untracked parts
+    #[command(after_long_help = "\
+Examples:
+  packer stash-up             upload every entry of `git stash list`
+  packer stash-up 0 2         upload stash@{0} and stash@{2}
+  packer stash-down           on the other machine, add them to its stash")]
+    StashUp {
+        /// Entries to upload, e.g. 0 or stash@{1}; all of them if none are given
+        stashes: Vec<String>,
+    },
+    /// Add the stash entries uploaded by `stash-up` to this repository's stash
+    StashDown,
     /// Upload a file to OSS and generate a download link
+    #[command(after_long_help = "\
+Examples:
+  packer s report.pdf                   stored as from/<hostname>/report.pdf
+  packer s build.log logs/today.log     stored under a chosen key
+  packer s dump.sql --compress zstd --expires 7d")]
     S {
         /// Local file path to upload
-        local_file: String,
+        local_file: PathBuf,
         /// Remote object key (path in OSS)
         #[arg(required = false)]
         object_key: Option<String>,
+        /// How long the download link stays valid, e.g. 36h or 7d (at most 7d)
+        #[arg(long, default_value = "48h")]
+        expires: String,
+        /// Store the file with this Content-Encoding (gzip or zstd); browsers
+        /// opening the link decompress it transparently
+        #[arg(long, value_name = "ENCODING")]
+        compress: Option<String>,
     },
     /// List all files in the bucket with download links
     Ls {
         /// Show download URLs along with file names
         #[arg(short, long)]
         long: bool,
+        /// Also show what each uploaded pack contains (reads the manifests)
+        #[arg(short, long)]
+        snapshots: bool,
     },
     /// Download a file from OSS to the current directory
+    #[command(after_long_help = "\
+Examples:
+  packer get from/laptop/report.pdf     saved as ./report.pdf
+  packer get 'https://<bucket>.<endpoint>/from/laptop/report.pdf?X-Amz-...'
+                                        the link printed by `s`")]
     Get {
-        /// Remote object key (path in OSS) to download
+        /// Remote object key (path in OSS) to download, or its URL
         #[arg(required = true)]
         object_key: String,
+        /// How long the printed download link stays valid, e.g. 36h or 7d
+        /// (at most 7d; 48h by default)
+        #[arg(long, value_name = "DURATION")]
+        ttl: Option<String>,
+    },
+    /// Show the local branch state and whether a pack exists remotely
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
     },
+    /// Serve core operations over JSON-RPC for editor integrations
+    Rpc {
+        /// Use newline-delimited JSON-RPC 2.0 over stdin/stdout
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+    /// Periodically upload snapshots of uncommitted work to the autosave/ prefix
+    #[command(after_long_help = "\
+Examples:
+  packer autosave                       snapshot every 15 minutes
+  packer autosave --every 5m --keep 30
+  packer autosave --once                from cron or a file watcher")]
+    Autosave {
+        /// Interval between snapshots, e.g. 15m, 1h, 90s
+        #[arg(long, default_value = "15m")]
+        every: String,
+        /// Number of snapshots to keep per branch
+        #[arg(long, default_value_t = 10)]
+        keep: usize,
+        /// Delete snapshots older than this (0 keeps them regardless of age)
+        #[arg(long, default_value = "1d")]
+        max_age: String,
+        /// Take a single snapshot and exit (for cron or an external watcher)
+        #[arg(long)]
+        once: bool,
+    },
+    /// Install or remove git hooks that upload after every commit and
+    /// branch switch
+    #[command(after_long_help = "\
+Examples:
+  packer hooks install      run `packer up` in the background after commits
+  packer hooks uninstall
+
+Output goes to packer-hook.log in the git directory.")]
+    Hooks {
+        #[command(subcommand)]
+        action: HooksAction,
+    },
+    /// Check the configuration, bucket access and repository state
+    Doctor,
+    /// Delete temporary commits that interrupted uploads left behind
+    GcLocal,
+    /// Download stored objects, verify their checksums and check that packs
+    /// and manifests still decode
+    Audit {
+        /// Check only this many randomly chosen objects instead of all of them
+        #[arg(long, value_name = "N")]
+        sample: Option<usize>,
+    },
+    /// Manage the keys protecting encrypted objects
+    #[command(after_long_help = "\
+Examples:
+  packer keys device                    print the key to trust elsewhere
+  packer keys generate ~/.config/packer/x25519.key
+  packer keys enroll-token --pin
+  packer keys rotate --old-key-file old.key --dry-run
+
+See also: packer help encryption")]
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// Bundle config, keyring secrets, key file and local state into one
+    /// passphrase-encrypted file for setting up another machine
+    BackupConfig {
+        /// File to write the backup to
+        output: PathBuf,
+    },
+    /// Restore a bundle written by backup-config
+    RestoreConfig {
+        /// Backup file to read
+        input: PathBuf,
+        /// Replace an existing config file and key file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Set up or edit the configuration file
+    #[command(after_long_help = "\
+Examples:
+  packer config init
+  packer config set sync.Recipients '[\"passphrase\", \"hardware-key\"]'
+  packer config get oss.BucketName
+  packer config store-secret")]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Show help for a command, or for a topic such as `encryption`,
+    /// `envelope` or `layout` (`help topics` lists them)
+    Help {
+        /// Topic, or command and subcommand names
+        topic: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Interactively create the config file
+    Init,
+    /// Set a config value, e.g. `config set oss.BucketName my-bucket`
+    Set {
+        /// Dotted key (section.Key)
+        key: String,
+        /// New value; parsed as TOML if possible, otherwise stored as a string
+        value: String,
+    },
+    /// Print a config value from the config file
+    Get {
+        /// Dotted key (section.Key)
+        key: String,
+    },
+    /// Move AccessKeySecret into the OS keyring and out of the config file
+    StoreSecret,
+    /// Remove AccessKeySecret from the OS keyring
+    ForgetSecret,
+    /// Store the pack encryption passphrase in the OS keyring
+    StorePassphrase,
+    /// Remove the pack encryption passphrase from the OS keyring
+    ForgetPassphrase,
+    /// Encrypt the config file with a passphrase (Argon2id + AES-GCM)
+    Encrypt,
+    /// Store the config file as plaintext again
+    Decrypt,
+    /// Forget the config passphrase cached for this session
+    Lock,
 }
 
-#[derive(Deserialize)]
-struct Config {
-    oss: OssConfig,
+#[derive(Subcommand)]
+enum HooksAction {
+    /// Write post-commit and post-checkout hooks running `packer up`
+    Install,
+    /// Remove the hooks `hooks install` wrote
+    Uninstall,
 }
 
-#[derive(Deserialize)]
-struct OssConfig {
-    #[serde(rename = "BucketName")]
-    bucket_name: String,
-    #[serde(rename = "Endpoint")]
-    endpoint: String,
-    #[serde(rename = "AccessKeyId")]
-    access_key_id: String,
-    #[serde(rename = "AccessKeySecret")]
-    access_key_secret: String,
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Print this device's public signing key, creating the key if needed
+    Device,
+    /// Register a FIDO2 security key (e.g. a YubiKey) whose hmac-secret
+    /// protects pack keys; needs the libfido2 tools
+    EnrollToken {
+        /// Device path, e.g. /dev/hidraw4 (default: the first key found)
+        #[arg(long)]
+        device: Option<String>,
+        /// Also require the security key's PIN to unlock
+        #[arg(long)]
+        pin: bool,
+    },
+    /// Create an X25519 key pair so uploads can be encrypted to a public key
+    /// that only this machine can decrypt
+    Generate {
+        /// Where to write the private key
+        output: PathBuf,
+    },
+    /// Re-wrap the data key of every encrypted object for the recipients in
+    /// sync.Recipients, without re-encrypting the payload
+    Rotate {
+        /// Key file the objects are currently encrypted with (repeatable)
+        #[arg(long, value_name = "PATH")]
+        old_key_file: Vec<PathBuf>,
+        /// Also try the previous pack passphrase (SYNC_OLD_PASSPHRASE or a prompt)
+        #[arg(long)]
+        old_passphrase: bool,
+        /// List the objects that would be rotated without rewriting them
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    deadline::start();
     let cli = Cli::parse();
 
-    match &cli.command {
-        Commands::Up { raw } => cmd_up(*raw)?,
-        Commands::Down => cmd_down()?,
-        Commands::Ls { long } => cmd_ls(*long)?,
-        Commands::Get { object_key } => cmd_get(object_key)?,
+    if cli.progress_json {
+        progress::enable();
+    }
+    if let Some(path) = &cli.config {
+        config::set_config_path(path.clone());
+    }
+    if let Some(path) = &cli.key_file {
+        config::set_key_file(path.clone());
+    }
+    if let Some(limit) = &cli.deadline {
+        deadline::set(units::parse_duration(limit)?);
+    }
+    if let Some(rate) = &cli.limit_rate {
+        let rate = units::parse_size(rate)?;
+        if rate == 0 {
+            return Err("--limit-rate must be greater than zero".into());
+        }
+        let _ = LIMIT_RATE.set(rate);
+    }
+
+    let result = run(&cli);
+    if let Err(e) = &result {
+        progress::error(&e.to_string());
+    }
+    result
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(command) = &cli.command else {
+        if cli.no_auto {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::MissingSubcommand,
+                    "a subcommand is required with --no-auto",
+                )
+                .exit();
+        }
+        return auto::run();
+    };
+
+    match command {
+        Commands::Up {
+            raw,
+            git_config,
+            dry_run,
+            include_untracked: _,
+            no_include_untracked,
+            worktree,
+            all_branches,
+            base,
+            bundle,
+            name,
+            ttl,
+            branch,
+            commits,
+            range,
+            format,
+            paths,
+        } => {
+            reject_with_patch_format(
+                format,
+                &[
+                    ("--raw", *raw),
+                    ("--worktree", *worktree),
+                    ("--all-branches", *all_branches),
+                    ("--range", range.is_some()),
+                    ("--ttl", ttl.is_some()),
+                    ("a pathspec", !paths.is_empty()),
+                ],
+            );
+            cmd_up(&UpOptions {
+                raw: *raw,
+                git_config: *git_config,
+                dry_run: *dry_run,
+                snapshot: Snapshot {
+                    untracked: !no_include_untracked,
+                    unstaged: *worktree,
+                    paths,
+                },
+                all_branches: *all_branches,
+                base: base.as_deref(),
+                bundle: *bundle,
+                name: name.as_deref(),
+                link_lifetime: link_lifetime("--ttl", ttl.as_deref())?,
+                branch: branch.as_deref(),
+                commits,
+                range: range.as_deref(),
+                format,
+            })?
+        }
+        Commands::Down {
+            pack,
+            user,
+            branch,
+            name,
+            branch_off,
+            strategy,
+            rebase_local,
+            merge,
+            fetch_only,
+            no_switch,
+            format,
+            force,
+            autostash,
+            dry_run,
+        } => {
+            reject_with_patch_format(
+                format,
+                &[
+                    ("--branch-off", branch_off.is_some()),
+                    ("--strategy", strategy.is_some()),
+                    ("--rebase-local", *rebase_local),
+                    ("--merge", *merge),
+                    ("--fetch-only", *fetch_only),
+                    ("--no-switch", *no_switch),
+                ],
+            );
+            cmd_down(&DownOptions {
+                pack: pack.as_deref(),
+                user: user.as_deref(),
+                branch: branch.as_deref(),
+                name: name.as_deref(),
+                branch_off: branch_off.as_deref(),
+                strategy: match (*rebase_local, *merge) {
+                    (true, _) => Some("rebase"),
+                    (_, true) => Some("merge"),
+                    _ => strategy.as_deref(),
+                },
+                fetch_only: *fetch_only,
+                no_switch: *no_switch,
+                format,
+                force: *force,
+                autostash: *autostash,
+                dry_run: *dry_run,
+            })?
+        }
+        Commands::InitFrom { url, directory } => bundle::init_from(url, directory.as_deref())?,
+        Commands::Export { output } => cmd_export(output)?,
+        Commands::Import { input, force } => cmd_import(input, *force)?,
+        Commands::StashUp { stashes } => stash::up(stashes)?,
+        Commands::StashDown => stash::down()?,
+        Commands::Ls { long, snapshots } => cmd_ls(*long, *snapshots)?,
+        Commands::Get { object_key, ttl } => {
+            cmd_get(object_key, link_lifetime("--ttl", ttl.as_deref())?)?
+        }
+        Commands::Status { json } => cmd_status(*json)?,
+        Commands::Rpc { .. } => rpc::serve_stdio()?,
+        Commands::Autosave {
+            every,
+            keep,
+            max_age,
+            once,
+        } => cmd_autosave(every, *keep, max_age, *once)?,
+        Commands::Hooks { action } => {
+            let repo = Repository::open(std::env::current_dir()?)?;
+            match action {
+                HooksAction::Install => hooks::install(&repo)?,
+                HooksAction::Uninstall => hooks::uninstall(&repo)?,
+            }
+        }
+        Commands::Doctor => doctor::run()?,
+        Commands::GcLocal => cmd_gc_local()?,
+        Commands::Audit { sample } => audit::run(*sample)?,
+        Commands::Keys { action } => match action {
+            KeysAction::Device => {
+                println!("{}", signing::device_public_key()?);
+                eprintln!(
+                    "Trust it on other machines with: packer config set sync.TrustedDevices.<name> <key>"
+                );
+            }
+            KeysAction::Generate { output } => keys::generate(output)?,
+            KeysAction::EnrollToken { device, pin } => hardware::enroll(device.as_deref(), *pin)?,
+            KeysAction::Rotate {
+                old_key_file,
+                old_passphrase,
+                dry_run,
+            } => keys::rotate(old_key_file, *old_passphrase, *dry_run)?,
+        },
+        Commands::BackupConfig { output } => backup::backup(output)?,
+        Commands::RestoreConfig { input, force } => backup::restore(input, *force)?,
+        Commands::Config { action } => cmd_config(action)?,
+        Commands::Help { topic } => help::show(topic)?,
         Commands::S {
             local_file,
             object_key,
+            expires,
+            compress,
         } => {
             // If object_key is not provided, generate a default one
             let key = match object_key {
                 Some(key) => key.clone(),
                 None => {
-                    let hostname = hostname::get()
-                        .unwrap_or_else(|_| "unknown".into())
-                        .to_string_lossy()
-                        .to_string();
+                    let hostname = hostname::get().unwrap_or_else(|_| "unknown".into());
 
-                    let file_name = std::path::Path::new(local_file)
+                    let file_name = local_file
                         .file_name()
-                        .unwrap_or_else(|| std::ffi::OsStr::new("file"))
-                        .to_string_lossy();
+                        .unwrap_or_else(|| std::ffi::OsStr::new("file"));
 
-                    format!("from/{}/{}", hostname, file_name)
+                    format!(
+                        "from/{}/{}",
+                        names::encode(&hostname),
+                        names::encode(file_name)
+                    )
                 }
             };
 
-            cmd_s(local_file, &key)?
+            cmd_s(local_file, &key, expires, compress.as_deref())?
         }
     }
-    Ok(()) // Ensure main returns Ok(()) at the end
+    Ok(())
 }
 
-fn cmd_up(raw: bool) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse config from the included string
-    let config: Config = toml::from_str(CONFIG_TOML)?;
-
-    let repo = Repository::open(std::env::current_dir().unwrap())?;
-
-    // Get the current branch
-    let head = repo.head()?;
-    if !head.is_branch() {
-        return Err(Box::new(git2::Error::from_str(
-            "HEAD is not a branch (detached HEAD state)",
-        )));
+// A patch series only carries commits, one branch's at a time. Clap can't
+// tell `--format patch` from `--format bundle`, so the conflicts are checked
+// here, failing the way clap does.
+fn reject_with_patch_format(format: &str, flags: &[(&str, bool)]) {
+    if format != "patch" {
+        return;
     }
+    if let Some((flag, _)) = flags.iter().find(|(_, set)| *set) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("--format patch cannot be used with {}", flag),
+            )
+            .exit();
+    }
+}
 
-    // Extract the branch name from the reference
-    let branch_name = head
-        .shorthand()
-        .ok_or_else(|| git2::Error::from_str("Failed to get branch name from HEAD"))?;
-
-    // Get the target commit id of the current branch
-    let head_commit_oid = head
-        .target()
-        .ok_or_else(|| git2::Error::from_str("Branch reference is not a direct reference"))?;
-
-    // Get the HEAD commit for parent reference
-    let head_commit = repo.find_commit(head_commit_oid)?;
+/// What `up` packs and how it uploads it; see the flags of `Commands::Up`.
+struct UpOptions<'a> {
+    raw: bool,
+    git_config: bool,
+    dry_run: bool,
+    snapshot: Snapshot<'a>,
+    all_branches: bool,
+    base: Option<&'a str>,
+    bundle: bool,
+    name: Option<&'a str>,
+    link_lifetime: std::time::Duration,
+    branch: Option<&'a str>,
+    commits: &'a [String],
+    range: Option<&'a str>,
+    format: &'a str,
+}
 
-    // Create a tree from the index (staged changes)
-    let mut index = repo.index()?;
-    let staged_tree_oid = index.write_tree()?;
-    let staged_tree = repo.find_tree(staged_tree_oid)?;
+impl UpOptions<'static> {
+    /// A plain `packer up`
+    const DEFAULT: UpOptions<'static> = UpOptions {
+        raw: false,
+        git_config: false,
+        dry_run: false,
+        snapshot: Snapshot::DEFAULT,
+        all_branches: false,
+        base: None,
+        bundle: false,
+        name: None,
+        link_lifetime: DEFAULT_LINK_LIFETIME,
+        branch: None,
+        commits: &[],
+        range: None,
+        format: "pack",
+    };
+}
 
-    // Create a temporary commit to represent the staged changes
-    let signature = Signature::now("Git Pack Generator", "noreply@example.com")?;
-    let message = "Temporary commit for pack generation";
-
-    // Create a commit with the staged tree and the HEAD as parent
-    let staged_commit_oid = repo.commit(
-        None, // Don't update any references
-        &signature,
-        &signature,
-        message,
-        &staged_tree,
-        &[&head_commit],
-    )?;
+fn cmd_up(options: &UpOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let &UpOptions {
+        dry_run,
+        snapshot,
+        all_branches,
+        base,
+        bundle,
+        name,
+        branch,
+        commits,
+        range,
+        format,
+        ..
+    } = options;
+    let repo = Repository::open(std::env::current_dir().unwrap())?;
 
-    println!(
-        "Created temporary commit for staged changes: {}",
-        staged_commit_oid
-    );
+    // Load config, with the repository's .sync.toml merged over the global one
+    let config = Config::load_for_repo(&repo)?;
+    if !dry_run {
+        config.oss.ensure_writable()?;
+    }
+    deadline::set_default("up", &config.sync)?;
+    let repo_lock = lock::repository(&repo)?;
 
-    // 2. Create and Configure Revwalk
-    let mut revwalk = repo.revwalk()?;
-    revwalk.push(staged_commit_oid)?; // Start from staged changes
+    // Get repository info to construct the pack filename
+    let repo_info = extract_repo_info(&repo)?;
 
-    // Find the corresponding remote branch
-    let remote_branch_name = format!("refs/remotes/origin/{}", branch_name);
-    let remote_branch_exists = repo.find_reference(&remote_branch_name).is_ok();
-
-    if remote_branch_exists {
-        // If remote branch exists, only include commits not in the remote
-        println!("Found remote branch: {}", remote_branch_name);
-        let remote_branch_ref = repo.find_reference(&remote_branch_name)?;
-        let remote_branch_oid = remote_branch_ref.target().ok_or_else(|| {
-            git2::Error::from_str("Remote branch reference is not a direct reference")
-        })?;
-        revwalk.hide(remote_branch_oid)?; // Exclude commits reachable from origin/branch
-    } else {
-        // If remote branch doesn't exist, include all commits
-        println!(
-            "Remote branch not found: {}. Including all commits.",
-            remote_branch_name
-        );
-        // We don't hide any commits in this case, so all commits will be included
+    if !dry_run {
+        // Before anything is packed, so what the hook changes, e.g. a
+        // formatter, goes along
+        let (hook_branch, hook_commit) = hook_target(&repo, branch);
+        hooks::run(
+            &repo,
+            "pre-up",
+            &config.sync.pre_up,
+            &hook_branch,
+            &hook_commit,
+        )?;
+    }
+    if bundle {
+        bundle::up(&repo, &config, &repo_info, snapshot, dry_run)?;
+        return remove_temporary_commits(&repo);
+    }
+    if format == "patch" {
+        return patches::up(&repo, &config, &repo_info, branch, base, name, dry_run);
+    }
+    if let Some(branch) = branch {
+        let pack = create_other_branch_pack(&repo, branch, base, name)?;
+        println!("{}", t!("up.branch", pack.branch_name));
+        return upload_pack(&repo, &config, &repo_info, pack, options);
+    }
+    if !commits.is_empty() || range.is_some() {
+        let pack = create_selected_pack(&repo, commits, range, name)?;
+        println!("{}", t!("up.selected", pack.commit_count, pack.branch_name));
+        return upload_pack(&repo, &config, &repo_info, pack, options);
+    }
+    if !all_branches {
+        let mut pack = create_pack(&repo, snapshot, base, name)?;
+        pack.submodules = submodules::pack_all(&repo, snapshot)?;
+        if name.is_none() && repo.head_detached()? {
+            println!("{}", t!("up.detached", pack.branch_name));
+        } else {
+            println!("{}", t!("up.branch", pack.branch_name));
+        }
+        if repo_lock.waited
+            && !dry_run
+            && already_uploaded(&repo, &repo_info, &pack.branch_name, pack.commit_oid)
+        {
+            println!("{}", t!("up.already_uploaded"));
+            return remove_temporary_commits(&repo);
+        }
+        upload_pack(&repo, &config, &repo_info, pack, options)?;
+        return remove_temporary_commits(&repo);
     }
 
-    revwalk.set_sorting(git2::Sort::TIME)?; // Optional: sort commits
-
-    // 3. Create PackBuilder
-    let mut packbuilder = repo.packbuilder()?;
+    let packs = create_branch_packs(&repo, snapshot, base)?;
+    if packs.is_empty() {
+        println!("{}", t!("up.no_branches"));
+        return remove_temporary_commits(&repo);
+    }
+    let count = packs.len();
+    for pack in packs {
+        deadline::check("upload")?;
+        println!("{}", t!("up.branch_header", pack.branch_name));
+        upload_pack(&repo, &config, &repo_info, pack, options)?;
+    }
+    println!("{}", t!("up.branches_done", count));
+    remove_temporary_commits(&repo)
+}
 
-    // 4. Insert Commits into PackBuilder - using insert_walk method
-    packbuilder.insert_walk(&mut revwalk)?;
+// Once the packs are uploaded, the commits they were made from aren't needed
+fn remove_temporary_commits(repo: &Repository) -> Result<(), Box<dyn std::error::Error>> {
+    let removed = layers::cleanup(repo)?;
+    if removed > 0 {
+        info!("Released {} temporary commits for `git gc`", removed);
+    }
+    Ok(())
+}
 
-    // 5. Create a memory buffer for the pack data
-    let mut buf = Buf::new();
+fn cmd_gc_local() -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(std::env::current_dir()?)?;
+    // Not while an upload still needs its commits
+    let _lock = lock::repository(&repo)?;
+    println!("{}", t!("gc.removed", layers::cleanup(&repo)?));
+    Ok(())
+}
 
-    // 6. Write pack data directly to the buffer
-    packbuilder.write_buf(&mut buf)?;
+// Uploads one branch's pack, with its manifest unless `raw`
+fn upload_pack(
+    repo: &Repository,
+    config: &Config,
+    repo_info: &RepoInfo,
+    pack: PackOutput,
+    options: &UpOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let &UpOptions {
+        raw,
+        git_config,
+        dry_run,
+        link_lifetime,
+        format,
+        ..
+    } = options;
+    let PackOutput {
+        branch_name,
+        commit_oid: staged_commit_oid,
+        tree_oid: staged_tree_oid,
+        data: buf,
+        object_count,
+        commit_count,
+        log,
+        base_oid,
+        untracked_count,
+        unstaged_count,
+        tags: packed_tags,
+        submodules: packed_submodules,
+    } = pack;
+    if unstaged_count > 0 {
+        println!("{}", t!("up.unstaged", unstaged_count));
+    }
+    if untracked_count > 0 {
+        println!("{}", t!("up.untracked", untracked_count));
+    }
+    let branch_name = branch_name.as_str();
 
     // Extract the SHA string from the beginning of the pack data
     let staged_commit_sha = staged_commit_oid.to_string();
 
-    // Get repository info to construct the pack filename
-    let repo_info = extract_repo_info(&repo)?;
+    // In team mode packs go to the uploader's own namespace
+    let pack_dir = pack_dir(config, repo_info, branch_name, config.sync.user());
 
-    // Generate a filename for the pack
+    // Generate a filename for the pack; bundles get their own, so one
+    // doesn't replace the branch's pack
+    let extension = if format == "bundle" { "bundle" } else { "pack" };
     let pack_file_name = if raw {
         // For raw pack files: {repo_author}/{repo_name}/{branch_name}/head-{commit_sha}.pack
-        format!(
-            "{}/{}/{}/head-{}.pack",
-            repo_info.author, repo_info.name, branch_name, staged_commit_sha
-        )
+        format!("{}/head-{}.{}", pack_dir, staged_commit_sha, extension)
     } else {
         // For encrypted pack files: {repo_author}/{repo_name}/{branch_name}/head.pack
-        format!(
-            "{}/{}/{}/head.pack",
-            repo_info.author, repo_info.name, branch_name
-        )
+        format!("{}/head.{}", pack_dir, extension)
+    };
+
+    println!(
+        "{}",
+        t!("up.pack_generated", units::format_size(buf.len() as u64))
+    );
+    println!("{}", t!("up.pack_contents", object_count, commit_count));
+    let upstream_tree = match base_oid {
+        Some(oid) => Some(repo.find_commit(oid)?.tree()?),
+        None => None,
     };
+    let staged_tree = repo.find_tree(staged_tree_oid)?;
+    // Content of LFS files isn't in the pack, only their pointers
+    let lfs_pointers = lfs::pointers(repo, upstream_tree.as_ref(), &staged_tree)?;
+
+    if dry_run {
+        let diff = changes::diff_for_upload(repo, upstream_tree.as_ref(), &staged_tree)?;
+
+        println!("{}", t!("up.would_upload", pack_file_name));
+        changes::print_summary(&diff)?;
+        for submodule in &packed_submodules {
+            println!("{}", t!("up.would_upload_submodule", submodule.path));
+        }
+        if !lfs_pointers.is_empty() {
+            println!("{}", t!("up.would_upload_lfs", lfs_pointers.len()));
+        }
+        return Ok(());
+    }
 
-    println!("Pack data generated, size: {} bytes", buf.len());
-    println!("Using current branch: {}", branch_name);
+    // Lets `ls --long`, `status` and `down` describe the pack without fetching it
+    let pack_metadata = PackMetadata::new(staged_commit_oid, base_oid, branch_name).to_headers();
+    let mut metadata: Vec<(&str, &str)> = pack_metadata
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let repo_name = repo_info.to_string();
+    let tags = pack_tags(&repo_name, branch_name);
+    let bundle = match format {
+        "bundle" => Some(bundle::incremental(
+            repo,
+            branch_name,
+            staged_commit_oid,
+            base_oid,
+            &buf,
+        )?),
+        _ => None,
+    };
 
     if raw {
-        let pack_data = buf.to_vec();
+        if !packed_submodules.is_empty() {
+            println!("{}", t!("up.submodules_raw", packed_submodules.len()));
+        }
+        if !lfs_pointers.is_empty() {
+            println!("{}", t!("up.lfs_raw", lfs_pointers.len()));
+        }
+        let pack_data = bundle.unwrap_or(buf);
 
-        // Calculate human-readable size
-        let size_str = if pack_data.len() < 1024 {
-            format!("{} bytes", pack_data.len())
-        } else if pack_data.len() < 1024 * 1024 {
-            format!("{:.2} KB", pack_data.len() as f64 / 1024.0)
-        } else {
-            format!("{:.2} MB", pack_data.len() as f64 / (1024.0 * 1024.0))
-        };
+        let size_str = units::format_size(pack_data.len() as u64);
 
         // Upload the raw pack data to S3
-        upload_pack_to_s3(&config.oss, &pack_file_name, pack_data)?;
-
+        upload_object(
+            &config.oss,
+            &pack_file_name,
+            pack_data,
+            config.sync.hash_algorithm,
+            &ObjectHeaders {
+                metadata,
+                tags,
+                ..Default::default()
+            },
+        )?;
+
+        println!("{}", t!("up.raw_uploaded", size_str, pack_file_name));
+
+        // Generate a pre-signed URL for the uploaded file
+        let url = presigned_url(&config.oss, &pack_file_name, link_lifetime.as_secs())?;
         println!(
-            "Raw pack data (size: {}) uploaded to S3 storage successfully as: {}",
-            size_str, pack_file_name
+            "{}",
+            t!("link.valid_for", units::format_duration(link_lifetime), url)
         );
-
-        // Create a tokio runtime for async operations only when needed
-        let rt = Runtime::new()?;
-        // Use the runtime to execute our async function for presigned URL
-        rt.block_on(async {
-            // Generate a pre-signed URL for the uploaded file (expires in 48 hours)
-            let presigned_url =
-                generate_presigned_url(&config.oss, &pack_file_name, 3600 * 48).await?;
-            println!("Download URL (valid for 48 hours): {}", presigned_url);
-            Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
     } else {
-        // For encrypted pack files, prepend SHA and encrypt before uploading
-        let mut pack_data_with_sha = staged_commit_sha.into_bytes();
-        pack_data_with_sha.extend_from_slice(&buf.to_vec());
-
-        // Encrypt the pack data using two-round AES encryption
-        let encrypted_data = encrypt_pack_data(pack_data_with_sha)?;
-
-        // Calculate human-readable size
-        let size_str = if encrypted_data.len() < 1024 {
-            format!("{} bytes", encrypted_data.len())
-        } else if encrypted_data.len() < 1024 * 1024 {
-            format!("{:.2} KB", encrypted_data.len() as f64 / 1024.0)
+        // For encrypted pack files, prepend SHA and encrypt before uploading;
+        // a bundle names its commits itself
+        let pack_data_with_sha = bundle.unwrap_or_else(|| {
+            let mut pack_data_with_sha = staged_commit_sha.clone().into_bytes();
+            pack_data_with_sha.extend_from_slice(&buf);
+            pack_data_with_sha
+        });
+
+        // Compress, then encrypt using two-round AES encryption unless the
+        // (repository) config turned encryption off
+        if !config.sync.encrypt {
+            println!("{}", t!("up.encryption_disabled"));
+        }
+        deadline::check("pack")?;
+        let payload_digest = blake3::hash(&pack_data_with_sha);
+        // Lets `down` catch a truncated or corrupted pack before index-pack
+        let plaintext_checksum = HashAlgorithm::Sha256.checksum(&pack_data_with_sha);
+        metadata.push(("sync-plaintext-checksum", &plaintext_checksum));
+        let dedup = dedup::Index::open(config)?;
+        let checksum = config.sync.hash_algorithm.checksum(&pack_data_with_sha);
+        if dedup.holds(config, &pack_file_name, &checksum)? {
+            println!("{}", t!("up.payload_unchanged", pack_file_name));
         } else {
-            format!("{:.2} MB", encrypted_data.len() as f64 / (1024.0 * 1024.0))
-        };
+            let encrypted_data = seal_checksummed_payload(
+                &config.sync,
+                pack_data_with_sha,
+                checksum.clone(),
+                Some(&pack_file_name),
+            )?;
+            deadline::check("encryption")?;
+
+            let size_str = units::format_size(encrypted_data.len() as u64);
+
+            // 7. Upload the encrypted pack data to S3
+            upload_object(
+                &config.oss,
+                &pack_file_name,
+                encrypted_data,
+                config.sync.hash_algorithm,
+                &ObjectHeaders {
+                    metadata,
+                    tags: tags.clone(),
+                    ..Default::default()
+                },
+            )?;
+            dedup.record(config, &pack_file_name, &checksum);
+
+            if config.sync.encrypt {
+                println!("{}", t!("up.encrypted_uploaded", size_str, pack_file_name));
+            } else {
+                println!("{}", t!("up.plaintext_uploaded", size_str, pack_file_name));
+            }
+        }
 
-        // 7. Upload the encrypted pack data to S3
-        upload_pack_to_s3(&config.oss, &pack_file_name, encrypted_data)?;
+        let submodules = packed_submodules
+            .into_iter()
+            .map(|submodule| upload_submodule(config, &pack_dir, submodule, &tags))
+            .collect::<Result<Vec<_>, _>>()?;
+        let lfs = lfs::upload(repo, config, &pack_dir, &lfs_pointers, &tags)?;
+
+        let mut manifest = Manifest {
+            commit: staged_commit_sha.clone(),
+            branch: branch_name.to_string(),
+            user: config.sync.user().map(str::to_string),
+            hostname: Some(
+                hostname::get()
+                    .unwrap_or_else(|_| "unknown".into())
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            object_count: Some(object_count),
+            commit_count: Some(commit_count),
+            log,
+            tags: packed_tags,
+            submodules,
+            lfs,
+            ..Default::default()
+        };
+        if !manifest.tags.is_empty() {
+            println!("{}", t!("up.tags_included", manifest.tags.len()));
+        }
+        if git_config || config.sync.snapshot_git_config {
+            let patterns = config.sync.git_config_patterns();
+            let snapshot = GitConfigSnapshot::capture(repo, branch_name, &patterns)?;
+            println!("{}", t!("up.git_config_included", snapshot.entries.len()));
+            manifest.git_config = Some(snapshot);
+        }
+        // Last, so the signature covers every field
+        manifest.signature = Some(signing::sign(&manifest, &payload_digest)?);
+
+        // Upload the manifest alongside the pack, encrypted the same way
+        let manifest_key = manifest::manifest_key_for(&pack_file_name);
+        let manifest_data = toml::to_string(&manifest)?.into_bytes();
+        let uploaded = seal_payload(&config.sync, manifest_data, Some(&manifest_key)).and_then(
+            |encrypted_manifest| {
+                upload_object(
+                    &config.oss,
+                    &manifest_key,
+                    encrypted_manifest,
+                    config.sync.hash_algorithm,
+                    &ObjectHeaders {
+                        tags,
+                        ..Default::default()
+                    },
+                )
+            },
+        );
+        if let Err(e) = uploaded {
+            // The previous manifest now describes the wrong pack; drop it
+            // rather than let status and down trust it
+            deadline::cleanup(|| delete_object(&config.oss, &manifest_key)).map_err(|cleanup| {
+                format!(
+                    "{} (and removing the stale manifest failed: {})",
+                    e, cleanup
+                )
+            })?;
+            return Err(e);
+        }
+        println!("{}", t!("up.manifest_uploaded", manifest_key));
 
+        // Generate a pre-signed URL for the uploaded file
+        let url = presigned_url(&config.oss, &pack_file_name, link_lifetime.as_secs())?;
         println!(
-            "Encrypted pack data (size: {}) uploaded to S3 storage successfully as: {}",
-            size_str, pack_file_name
+            "{}",
+            t!("link.valid_for", units::format_duration(link_lifetime), url)
         );
+    }
 
-        // Create a tokio runtime for async operations only when needed
-        let rt = Runtime::new()?;
-        // Use the runtime to execute our async function for presigned URL
-        rt.block_on(async {
-            // Generate a pre-signed URL for the uploaded file (expires in 48 hours)
-            let presigned_url =
-                generate_presigned_url(&config.oss, &pack_file_name, 3600 * 48).await?;
-            println!("Download URL (valid for 48 hours): {}", presigned_url);
-            Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
+    // Only informational, so a read-only cache dir mustn't fail the upload
+    if let Err(e) = record_last_upload(repo_info, branch_name, &staged_commit_sha) {
+        println!("{}", t!("up.record_failed", e));
     }
 
     Ok(())
 }
 
-fn cmd_down() -> Result<(), Box<dyn std::error::Error>> {
-    // Parse config from the included string
-    let config: Config = toml::from_str(CONFIG_TOML)?;
+// Uploads a submodule's pack next to the superproject's, sealed the same way
+fn upload_submodule(
+    config: &Config,
+    pack_dir: &str,
+    submodule: submodules::PackedSubmodule,
+    tags: &[(&str, &str)],
+) -> Result<SubmodulePack, Box<dyn std::error::Error>> {
+    let key = format!("{}/submodules/{}.pack", pack_dir, submodule.path);
+    let checksum = HashAlgorithm::Sha256.checksum(&submodule.payload);
+    let dedup = dedup::Index::open(config)?;
+    let payload_checksum = config.sync.hash_algorithm.checksum(&submodule.payload);
+    if dedup.holds(config, &key, &payload_checksum)? {
+        println!("{}", t!("up.payload_unchanged", key));
+    } else {
+        let data = seal_checksummed_payload(
+            &config.sync,
+            submodule.payload,
+            payload_checksum.clone(),
+            Some(&key),
+        )?;
+        let size = units::format_size(data.len() as u64);
+        upload_object(
+            &config.oss,
+            &key,
+            data,
+            config.sync.hash_algorithm,
+            &ObjectHeaders {
+                tags: tags.to_vec(),
+                ..Default::default()
+            },
+        )?;
+        dedup.record(config, &key, &payload_checksum);
+        println!("{}", t!("up.submodule_uploaded", submodule.path, size, key));
+    }
 
-    let repo = Repository::open(std::env::current_dir().unwrap())?;
+    Ok(SubmodulePack {
+        path: submodule.path,
+        key,
+        commit: submodule.commit.to_string(),
+        branch: submodule.branch,
+        checksum,
+    })
+}
 
-    // Get the current branch
-    let head = repo.head()?;
-    if !head.is_branch() {
-        return Err(Box::new(git2::Error::from_str(
-            "HEAD is not a branch (detached HEAD state)",
-        )));
-    }
+struct PackOutput {
+    branch_name: String,
+    /// Topmost temporary commit, holding the staged changes and whatever was
+    /// taken from the working directory
+    commit_oid: git2::Oid,
+    tree_oid: git2::Oid,
+    data: Vec<u8>,
+    object_count: usize,
+    /// Real commits in the pack, i.e. without the temporary one
+    commit_count: usize,
+    /// Subjects of the newest of those commits
+    log: Vec<String>,
+    /// Commit the pack was cut against: origin's tip unless `up --base` chose
+    /// another; `None` when the pack holds the whole history
+    base_oid: Option<git2::Oid>,
+    /// Untracked files added to the temporary commit
+    untracked_count: usize,
+    /// Files whose unstaged edits or deletions the temporary commit records
+    unstaged_count: usize,
+    /// Local tags on the packed commits
+    tags: Vec<PackedTag>,
+    /// Submodules with work of their own; only `up` fills these in, and
+    /// only for the current branch
+    submodules: Vec<submodules::PackedSubmodule>,
+}
 
-    // Extract the branch name from the reference
-    let branch_name = head
-        .shorthand()
-        .ok_or_else(|| git2::Error::from_str("Failed to get branch name from HEAD"))?;
+// Packs the current branch, or a detached HEAD, stored under `name` when
+// given
+fn create_pack(
+    repo: &Repository,
+    snapshot: Snapshot,
+    base: Option<&str>,
+    name: Option<&str>,
+) -> Result<PackOutput, Box<dyn std::error::Error>> {
+    // Get the current branch; None when HEAD is detached, as during a bisect,
+    // a rebase or in a CI checkout
+    let head = match repo.head() {
+        Ok(head) => Some(head),
+        // A branch with no commits yet
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+    let branch = match &head {
+        Some(head) if head.is_branch() => Some(
+            head.shorthand()
+                .ok_or_else(|| git2::Error::from_str("Failed to get branch name from HEAD"))?
+                .to_string(),
+        ),
+        Some(_) => None,
+        None => unborn_branch(repo),
+    };
 
-    // Get repository info to construct the pack filename
-    let repo_info = extract_repo_info(&repo)?;
+    // Get the HEAD commit for parent reference
+    let head_commit = head.map(|head| head.peel_to_commit()).transpose()?;
+    let head_commit_oid = head_commit.as_ref().map(|commit| commit.id());
+    let branch_name = match (name, &branch, head_commit_oid) {
+        (Some(name), _, _) => name.to_string(),
+        (None, Some(branch), _) => branch.clone(),
+        (None, None, Some(oid)) => detached_name(oid),
+        (None, None, None) => return Err("HEAD names no branch".into()),
+    };
 
-    // Generate a filename for the pack following the pattern: {repo_author}/{repo_name}/{branch_name}/head.pack
-    let pack_file_name = format!(
-        "{}/{}/{}/head.pack",
-        repo_info.author, repo_info.name, branch_name
-    );
+    // Commit the staged changes, and what `snapshot` takes from the working
+    // directory, as temporary commits on top of HEAD
+    let stack = layers::commit(repo, head_commit.as_ref(), snapshot)?;
+
+    Ok(PackOutput {
+        untracked_count: stack.untracked_count,
+        unstaged_count: stack.unstaged_count,
+        ..pack_branch(
+            repo,
+            &branch_name,
+            head_commit_oid,
+            stack.tip,
+            resolve_base(repo, branch.as_deref(), base)?,
+        )?
+    })
+}
 
-    println!("Downloading pack file: {}", pack_file_name);
+// The branch HEAD names before its first commit
+fn unborn_branch(repo: &Repository) -> Option<String> {
+    let head = repo.find_reference("HEAD").ok()?;
+    let branch = head.symbolic_target()?.strip_prefix("refs/heads/")?;
+    Some(branch.to_string())
+}
 
-    // Download the encrypted pack data from S3
-    let encrypted_data = download_pack_from_s3(&config.oss, &pack_file_name)?;
+// Key segment for a pack of a detached HEAD, in place of the branch name
+fn detached_name(oid: git2::Oid) -> String {
+    format!("detached-{}", &oid.to_string()[..12])
+}
 
-    // Decrypt the pack data
-    let pack_data = decrypt_pack_data(encrypted_data)?;
+// Pack for `up --branch`: the local branch `branch` as committed, cut against
+// its own upstream, worked out from refs alone so nothing is checked out
+fn create_other_branch_pack(
+    repo: &Repository,
+    branch: &str,
+    base: Option<&str>,
+    name: Option<&str>,
+) -> Result<PackOutput, Box<dyn std::error::Error>> {
+    let tip = repo
+        .find_branch(branch, BranchType::Local)
+        .ok()
+        .and_then(|local| local.get().target())
+        .ok_or_else(|| t!("up.no_such_branch", branch))?;
+    let base_oid = resolve_base(repo, Some(branch), base)?;
+    pack_branch(repo, name.unwrap_or(branch), Some(tip), tip, base_oid)
+}
 
-    // Apply the pack to the repository
-    apply_pack_to_repo(&repo, pack_data)?;
+// Packs for `up --commits` and `up --range`: the selected commits and the
+// objects they add to their parents, nothing from the working directory.
+// Stored under `name`, or commits-<sha> of the pack's commit.
+fn create_selected_pack(
+    repo: &Repository,
+    commits: &[String],
+    range: Option<&str>,
+    name: Option<&str>,
+) -> Result<PackOutput, Box<dyn std::error::Error>> {
+    // Each walk pushes one commit and hides what the other machine is
+    // expected to have already
+    let mut walks = Vec::new();
+    let mut selected = Vec::new();
+    let (tip, base_oid) = match range {
+        Some(range) => {
+            let spec = repo
+                .revparse(range)
+                .map_err(|e| t!("up.bad_range", range, e.message()))?;
+            let (Some(from), Some(to)) = (spec.from(), spec.to()) else {
+                return Err(t!("up.bad_range", range, "expected A..B").into());
+            };
+            let to = to.peel_to_commit()?.id();
+            let mut from = from.peel_to_commit()?.id();
+            if spec.mode().contains(git2::RevparseMode::MERGE_BASE) {
+                from = repo.merge_base(from, to)?;
+            }
+            let mut revwalk = repo.revwalk()?;
+            revwalk.push(to)?;
+            revwalk.hide(from)?;
+            revwalk.set_sorting(git2::Sort::TIME)?;
+            selected = revwalk.collect::<Result<Vec<_>, _>>()?;
+            walks.push((to, vec![from]));
+            (Some(to), Some(from))
+        }
+        None => {
+            for sha in commits {
+                let commit = repo
+                    .revparse_single(sha)
+                    .and_then(|object| object.peel_to_commit())
+                    .map_err(|e| t!("up.bad_commit", sha, e.message()))?;
+                walks.push((commit.id(), commit.parent_ids().collect()));
+                // Newest first, as in the log of a range
+                selected.insert(0, commit.id());
+            }
+            // The pack's commit is the last one given, whatever the dates
+            (
+                walks.last().map(|(oid, _)| *oid),
+                walks
+                    .first()
+                    .and_then(|(_, parents)| parents.first().copied()),
+            )
+        }
+    };
+    let tip = tip
+        .filter(|_| !selected.is_empty())
+        .ok_or_else(|| t!("up.nothing_selected"))?;
 
-    println!("Pack file successfully applied to repository");
+    progress::start("pack", None);
+    let mut packbuilder = repo.packbuilder()?;
+    // One walk per commit, since hiding a parent would also hide selected
+    // commits further down
+    for (oid, hidden) in &walks {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(*oid)?;
+        for hidden in hidden {
+            revwalk.hide(*hidden)?;
+        }
+        packbuilder.insert_walk(&mut revwalk)?;
+    }
+    let mut buf = Buf::new();
+    packbuilder.write_buf(&mut buf)?;
+    progress::finish("pack", buf.len() as u64);
+
+    let mut log = Vec::new();
+    for oid in selected.iter().take(manifest::LOG_LENGTH) {
+        let commit = repo.find_commit(*oid)?;
+        log.push(format!(
+            "{} {}",
+            &oid.to_string()[..7],
+            commit.summary().unwrap_or("")
+        ));
+    }
+    Ok(PackOutput {
+        branch_name: name
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("commits-{}", &tip.to_string()[..12])),
+        commit_oid: tip,
+        tree_oid: repo.find_commit(tip)?.tree_id(),
+        data: buf.to_vec(),
+        object_count: packbuilder.object_count(),
+        commit_count: selected.len(),
+        log,
+        base_oid,
+        untracked_count: 0,
+        unstaged_count: 0,
+        tags: Vec::new(),
+        submodules: Vec::new(),
+    })
+}
 
-    Ok(())
+// Packs everything `revwalk` reaches, returning the pack and its object count
+fn write_walk(
+    repo: &Repository,
+    revwalk: &mut git2::Revwalk,
+) -> Result<(Vec<u8>, usize), Box<dyn std::error::Error>> {
+    progress::start("pack", None);
+    let mut packbuilder = repo.packbuilder()?;
+    packbuilder.insert_walk(revwalk)?;
+    let mut buf = Buf::new();
+    packbuilder.write_buf(&mut buf)?;
+    progress::finish("pack", buf.len() as u64);
+    Ok((buf.to_vec(), packbuilder.object_count()))
 }
 
-fn cmd_s(local_file: &str, object_key: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse config from the included string
-    let config: Config = toml::from_str(CONFIG_TOML)?;
+// Finds the local tags on the commits leading to `tip` that `base_oid`
+// doesn't have, adding annotated tag objects to the pack
+fn tags_on_packed_commits(
+    repo: &Repository,
+    packbuilder: &mut git2::PackBuilder,
+    tip: git2::Oid,
+    base_oid: Option<git2::Oid>,
+) -> Result<Vec<PackedTag>, Box<dyn std::error::Error>> {
+    let mut walk = repo.revwalk()?;
+    walk.push(tip)?;
+    if let Some(base_oid) = base_oid {
+        walk.hide(base_oid)?;
+    }
+    let packed = walk.collect::<Result<std::collections::HashSet<_>, _>>()?;
 
-    // Read the file
-    let file_data = std::fs::read(local_file)?;
+    let mut tagged = Vec::new();
+    repo.tag_foreach(|oid, name| {
+        tagged.push((oid, String::from_utf8_lossy(name).to_string()));
+        true
+    })?;
+    let mut tags = Vec::new();
+    for (oid, ref_name) in tagged {
+        let object = repo.find_object(oid, None)?;
+        if !packed.contains(&object.peel_to_commit()?.id()) {
+            continue;
+        }
+        // An annotated tag can point at another one; pack the whole chain
+        let mut object = object;
+        while let Some(tag) = object.as_tag() {
+            packbuilder.insert_object(tag.id(), None)?;
+            object = tag.target()?;
+        }
+        tags.push(PackedTag {
+            name: ref_name.trim_start_matches("refs/tags/").to_string(),
+            target: oid.to_string(),
+        });
+    }
+    Ok(tags)
+}
 
-    // Calculate human-readable size
-    let size_str = if file_data.len() < 1024 {
-        format!("{} bytes", file_data.len())
-    } else if file_data.len() < 1024 * 1024 {
-        format!("{:.2} KB", file_data.len() as f64 / 1024.0)
-    } else {
-        format!("{:.2} MB", file_data.len() as f64 / (1024.0 * 1024.0))
-    };
+// Packs for `up --all-branches`: the current branch as `create_pack` does,
+// then every other local branch with commits its upstream doesn't have, as
+// it stands
+fn create_branch_packs(
+    repo: &Repository,
+    snapshot: Snapshot,
+    base: Option<&str>,
+) -> Result<Vec<PackOutput>, Box<dyn std::error::Error>> {
+    let current_branch = repo
+        .head()
+        .ok()
+        .filter(|head| head.is_branch())
+        .and_then(|head| head.shorthand().map(str::to_string));
+
+    let mut packs = Vec::new();
+    if current_branch.is_some() {
+        let mut pack = create_pack(repo, snapshot, base, None)?;
+        pack.submodules = submodules::pack_all(repo, snapshot)?;
+        packs.push(pack);
+    }
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(branch_name) = branch.name()? else {
+            continue;
+        };
+        let Some(tip) = branch.get().target() else {
+            continue;
+        };
+        if current_branch.as_deref() == Some(branch_name) {
+            continue;
+        }
+        let base_oid = resolve_base(repo, Some(branch_name), base)?;
+        if let Some(base_oid) = base_oid {
+            if repo.graph_ahead_behind(tip, base_oid)?.0 == 0 {
+                info!(
+                    "Skipping {}: nothing that its base doesn't have",
+                    branch_name
+                );
+                continue;
+            }
+        }
+        packs.push(pack_branch(repo, branch_name, Some(tip), tip, base_oid)?);
+    }
+    Ok(packs)
+}
 
-    println!("Uploading file: {} ({})", local_file, size_str);
+// The commit a pack is cut against: `base` when given, with `none` for no
+// base at all, otherwise origin's copy of the branch if there is one. A
+// detached HEAD (`branch_name` None) is cut against origin's default branch.
+fn resolve_base(
+    repo: &Repository,
+    branch_name: Option<&str>,
+    base: Option<&str>,
+) -> Result<Option<git2::Oid>, Box<dyn std::error::Error>> {
+    match base {
+        Some("none") => Ok(None),
+        Some(base) => {
+            let commit = repo
+                .revparse_single(base)
+                .and_then(|object| object.peel_to_commit())
+                .map_err(|e| t!("up.bad_base", base, e.message()))?;
+            Ok(Some(commit.id()))
+        }
+        None => {
+            let remote_branch_name = match branch_name {
+                Some(branch_name) => format!("refs/remotes/origin/{}", branch_name),
+                None => "refs/remotes/origin/HEAD".to_string(),
+            };
+            match repo.refname_to_id(&remote_branch_name) {
+                Ok(oid) => {
+                    info!("Found remote branch: {}", remote_branch_name);
+                    Ok(Some(oid))
+                }
+                Err(_) => {
+                    info!(
+                        "Remote branch not found: {}. Including all commits.",
+                        remote_branch_name
+                    );
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
 
-    // Upload the file to S3
-    upload_pack_to_s3(&config.oss, object_key, file_data)?;
+// Packs `tip` and whatever leads to it that `base_oid` doesn't have. `head`
+// is the branch's own commit, which is `tip` unless temporary commits sit on
+// top, and None before its first commit.
+fn pack_branch(
+    repo: &Repository,
+    branch_name: &str,
+    head_commit_oid: Option<git2::Oid>,
+    staged_commit_oid: git2::Oid,
+    base_oid: Option<git2::Oid>,
+) -> Result<PackOutput, Box<dyn std::error::Error>> {
+    let staged_tree_oid = repo.find_commit(staged_commit_oid)?.tree_id();
 
-    println!(
-        "File uploaded to S3 storage successfully as: {}",
-        object_key
-    );
+    // 2. Create and Configure Revwalk
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(staged_commit_oid)?; // Start from staged changes
+    if let Some(base_oid) = base_oid {
+        revwalk.hide(base_oid)?; // Exclude commits the other machine already has
+    }
 
-    // Create a tokio runtime for async operations only when needed
-    let rt = Runtime::new()?;
-    // Use the runtime to execute our async function for presigned URL
-    rt.block_on(async {
-        // Generate a pre-signed URL for the uploaded file (expires in 48 hours)
-        let presigned_url = generate_presigned_url(&config.oss, object_key, 3600 * 48).await?;
-        println!("Download URL (valid for 48 hours): {}", presigned_url);
-        Ok::<(), Box<dyn std::error::Error>>(())
-    })?;
+    revwalk.set_sorting(git2::Sort::TIME)?; // Optional: sort commits
+
+    // Summarize the real commits for the manifest, leaving out the temporary one
+    let mut summary_walk = repo.revwalk()?;
+    if let Some(head_commit_oid) = head_commit_oid {
+        summary_walk.push(head_commit_oid)?;
+    }
+    if let Some(base_oid) = base_oid {
+        summary_walk.hide(base_oid)?;
+    }
+    summary_walk.set_sorting(git2::Sort::TIME)?;
+    let mut commit_count = 0;
+    let mut log = Vec::new();
+    for oid in summary_walk {
+        let oid = oid?;
+        commit_count += 1;
+        if log.len() < manifest::LOG_LENGTH {
+            let commit = repo.find_commit(oid)?;
+            log.push(format!(
+                "{} {}",
+                &oid.to_string()[..7],
+                commit.summary().unwrap_or("")
+            ));
+        }
+    }
+
+    progress::start("pack", None);
+
+    // 3. Create PackBuilder
+    let mut packbuilder = repo.packbuilder()?;
+
+    // 4. Insert Commits into PackBuilder - using insert_walk method
+    packbuilder.insert_walk(&mut revwalk)?;
+    let tags = tags_on_packed_commits(repo, &mut packbuilder, staged_commit_oid, base_oid)?;
+
+    // 5. Create a memory buffer for the pack data
+    let mut buf = Buf::new();
+
+    // 6. Write pack data directly to the buffer
+    packbuilder.write_buf(&mut buf)?;
+    progress::finish("pack", buf.len() as u64);
+    let object_count = packbuilder.object_count();
+
+    Ok(PackOutput {
+        branch_name: branch_name.to_string(),
+        commit_oid: staged_commit_oid,
+        tree_oid: staged_tree_oid,
+        data: buf.to_vec(),
+        object_count,
+        commit_count,
+        log,
+        base_oid,
+        untracked_count: 0,
+        unstaged_count: 0,
+        tags,
+        submodules: Vec::new(),
+    })
+}
+
+fn cmd_export(output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let to_stdout = output == "-";
+    if to_stdout {
+        if std::io::stdout().is_terminal() {
+            return Err("Refusing to write a pack to a terminal; redirect or pipe stdout".into());
+        }
+        output::reserve_stdout();
+    }
+
+    let repo = Repository::open(std::env::current_dir()?)?;
+    // Nothing is uploaded, so storage credentials aren't needed
+    let config = Config::load_local_for_repo(&repo)?;
+    let _lock = lock::repository(&repo)?;
+
+    let pack = create_pack(&repo, Snapshot::DEFAULT, None, None)?;
+    remove_temporary_commits(&repo)?;
+
+    // Same payload as `up` uploads: commit SHA followed by the pack
+    let mut payload = pack.commit_oid.to_string().into_bytes();
+    payload.extend_from_slice(&pack.data);
+    let data = seal_payload(&config.sync, payload, None)?;
+
+    if to_stdout {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&data)?;
+        stdout.flush()?;
+    } else {
+        std::fs::write(output, &data)?;
+    }
+
+    info!(
+        "Exported branch {} ({}) to {}",
+        pack.branch_name,
+        units::format_size(data.len() as u64),
+        if to_stdout { "stdout" } else { output }
+    );
+    Ok(())
+}
+
+fn cmd_import(input: &str, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let data = if input == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        std::fs::read(input)?
+    };
+    if data.is_empty() {
+        return Err("No pack data received".into());
+    }
+
+    let repo = Repository::open(std::env::current_dir()?)?;
+    let config = Config::load_local_for_repo(&repo)?;
+    let _lock = lock::repository(&repo)?;
+    // Nothing comes with the file that a device could have signed
+    if !config.sync.trusted_devices.is_empty() {
+        let source = if input == "-" { "stdin" } else { input };
+        return Err(t!("down.refusing", source, t!("import.unsigned")).into());
+    }
+
+    let pack_data = open_payload(&config.sync, data, None)?;
+    let sha = index_pack_into_repo(&repo, pack_data)?;
+    if !force {
+        let branch_name = match repo.head() {
+            Ok(head) if head.is_branch() => head.shorthand().unwrap_or_default().to_string(),
+            Ok(head) => detached_name(head.peel_to_commit()?.id()),
+            Err(_) => unborn_branch(&repo).unwrap_or_default(),
+        };
+        check_nothing_lost(&repo, &branch_name, &sha)?;
+    }
+    checkout_pack(&repo, &sha)?;
+
+    println!("{}", t!("down.applied"));
+    Ok(())
+}
+
+// Downloads the manifest stored next to a pack. Packs uploaded by older
+// versions have none.
+fn fetch_manifest(
+    config: &Config,
+    pack_key: &str,
+) -> Result<Option<Manifest>, Box<dyn std::error::Error>> {
+    let manifest_key = manifest::manifest_key_for(pack_key);
+    match try_download(&config.oss, &manifest_key)? {
+        Some(encrypted_manifest) => {
+            let manifest_data =
+                open_payload(&config.sync, encrypted_manifest, Some(&manifest_key))?;
+            Ok(Some(toml::from_str::<Manifest>(&String::from_utf8(
+                manifest_data,
+            )?)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Which pack `down` applies and how; see the flags of `Commands::Down`.
+struct DownOptions<'a> {
+    pack: Option<&'a str>,
+    user: Option<&'a str>,
+    branch: Option<&'a str>,
+    name: Option<&'a str>,
+    branch_off: Option<&'a str>,
+    /// `None` resets, refusing if local commits would be lost to a
+    /// received commit that doesn't have them
+    strategy: Option<&'a str>,
+    fetch_only: bool,
+    no_switch: bool,
+    format: &'a str,
+    force: bool,
+    autostash: bool,
+    dry_run: bool,
+}
+
+impl DownOptions<'static> {
+    /// A plain `packer down`
+    const DEFAULT: DownOptions<'static> = DownOptions {
+        pack: None,
+        user: None,
+        branch: None,
+        name: None,
+        branch_off: None,
+        strategy: None,
+        fetch_only: false,
+        no_switch: false,
+        format: "pack",
+        force: false,
+        autostash: false,
+        dry_run: false,
+    };
+}
+
+fn cmd_down(options: &DownOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let &DownOptions {
+        pack,
+        user,
+        branch,
+        name,
+        branch_off,
+        strategy,
+        fetch_only,
+        no_switch,
+        format,
+        force,
+        autostash,
+        dry_run,
+    } = options;
+    let repo = Repository::open(std::env::current_dir().unwrap())?;
+    if let Some(new_branch) = branch_off {
+        // Checked before downloading anything
+        if !git2::Branch::name_is_valid(new_branch)?
+            || repo.find_branch(new_branch, BranchType::Local).is_ok()
+        {
+            return Err(t!("down.branch_off_taken", new_branch).into());
+        }
+    }
+
+    // Load config, with the repository's .sync.toml merged over the global one
+    let config = Config::load_for_repo(&repo)?;
+    deadline::set_default("down", &config.sync)?;
+    let _lock = lock::repository(&repo)?;
+
+    // Get the current branch; with --branch or --name, HEAD may be detached
+    // or unborn
+    let head = match repo.head() {
+        Ok(head) => Some(head),
+        Err(e)
+            if branch.is_none() && name.is_none() && e.code() != git2::ErrorCode::UnbornBranch =>
+        {
+            return Err(e.into())
+        }
+        Err(_) => None,
+    };
+    let current_branch = match &head {
+        Some(head) if head.is_branch() => head.shorthand().map(str::to_string),
+        Some(_) => None,
+        // A branch with no commits yet; the pack gives it its first
+        None => unborn_branch(&repo),
+    };
+
+    // Packs of a detached HEAD are applied with HEAD detached, as they were
+    // uploaded
+    let detached = branch.is_none() && (name.is_some() || current_branch.is_none());
+    let branch_name = match (branch, name, &current_branch, &head) {
+        (Some(branch), _, _, _) => branch.to_string(),
+        (None, Some(name), _, _) => name.to_string(),
+        (None, None, Some(current), _) => current.clone(),
+        (None, None, None, Some(head)) => detached_name(head.peel_to_commit()?.id()),
+        (None, None, None, None) => return Err("HEAD names no branch".into()),
+    };
+    let branch_name = branch_name.as_str();
+    let refuse_diverged = strategy.is_none();
+    let strategy = strategy.unwrap_or("reset");
+    // Merging and rebasing keep what the checked-out branch has; anything
+    // else has nothing local to keep
+    let keep_local = strategy != "reset"
+        && user.is_none()
+        && branch_off.is_none()
+        && !fetch_only
+        && head.is_some();
+    if keep_local && repo.is_bare() {
+        return Err(t!("down.strategy_bare", strategy).into());
+    }
+    if keep_local && (detached || current_branch.as_deref() != Some(branch_name)) {
+        return Err(t!("down.strategy_needs_current", strategy).into());
+    }
+    // Moving the checked-out branch under the worktree would leave it looking
+    // like it undid the pack
+    if no_switch && !repo.is_bare() && current_branch.as_deref() == Some(branch_name) {
+        return Err(t!("down.no_switch_current", branch_name).into());
+    }
+    // Only the branch ref changes, as there is no worktree to check it out in
+    let ref_only = no_switch || repo.is_bare();
+
+    // Get repository info to construct the pack filename
+    let repo_info = extract_repo_info(&repo)?;
+
+    // Someone else's namespace when pulling a teammate's work, otherwise our own
+    let namespace_user = user.or(config.sync.user());
+
+    if format == "patch" {
+        let key = match pack {
+            Some(pack) => object_url::resolve(&config.oss, pack)?,
+            None => existing_pack_key(
+                &config,
+                &repo_info,
+                branch_name,
+                namespace_user,
+                "head.mbox",
+            )?,
+        };
+        let stash = match autostash && !dry_run {
+            true => stash_local_changes(&repo)?,
+            false => None,
+        };
+        let applied = patches::down(&repo, &config, &key, dry_run);
+        if let Some(stash) = stash {
+            pop_local_changes(&repo, stash)?;
+        }
+        applied?;
+        if !dry_run {
+            let (_, commit) = hook_target(&repo, None);
+            hooks::run(
+                &repo,
+                "post-down",
+                &config.sync.post_down,
+                branch_name,
+                &commit,
+            )?;
+        }
+        return Ok(());
+    }
+
+    // Generate a filename for the pack following the pattern: {repo_author}/{repo_name}/{branch_name}/head.pack
+    let pack_file_name = match pack {
+        Some(pack) => object_url::resolve(&config.oss, pack)?,
+        None => existing_pack_key(
+            &config,
+            &repo_info,
+            branch_name,
+            namespace_user,
+            if format == "bundle" {
+                "head.bundle"
+            } else {
+                "head.pack"
+            },
+        )?,
+    };
+
+    println!("{}", t!("down.downloading", pack_file_name));
+
+    // Download the encrypted pack data from S3
+    let encrypted_data = download_pack(&config.oss, &pack_file_name)?;
+    let remote_pack = head_object(&config.oss, &pack_file_name)?;
+    if let Some(pack) = remote_pack.as_ref().and_then(|info| info.pack.as_ref()) {
+        println!("{}", t!("down.pack_info", pack));
+    }
+
+    let manifest = fetch_manifest(&config, &pack_file_name)?;
+    // Raw packs have no manifest, but their metadata names the host too
+    let uploading_host = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.hostname.clone())
+        .or_else(|| {
+            let pack = remote_pack.as_ref()?.pack.as_ref()?;
+            Some(pack.hostname.clone()).filter(|hostname| !hostname.is_empty())
+        });
+    let uploading_host = uploading_host.as_deref();
+
+    // Decrypt and decompress the pack data as described by its envelope
+    let pack_data = open_payload(&config.sync, encrypted_data, Some(&pack_file_name))?;
+
+    // Packs uploaded by older versions carry no plaintext checksum
+    if let Some(checksum) = remote_pack.and_then(|info| info.plaintext_checksum) {
+        verify_plaintext(&checksum, &pack_data)
+            .map_err(|e| t!("down.refusing", pack_file_name, e))?;
+    }
+
+    // Nothing from an untrusted pack goes into the repository
+    let verified = match signing::verify(&config.sync, manifest.as_ref(), &pack_data) {
+        Ok(Some(device)) => {
+            println!("{}", t!("down.signature_verified", device));
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            return Err(t!("down.refusing", pack_file_name, e).into());
+        }
+    };
+    // Only a signature vouches for who uploaded it
+    if let Some(teammate) = user {
+        let uploader = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.user.as_deref());
+        if !verified || uploader != Some(teammate) {
+            return Err(t!(
+                "down.refusing",
+                pack_file_name,
+                t!("down.teammate_unsigned", teammate)
+            )
+            .into());
+        }
+    }
+    if let Some(manifest) = &manifest {
+        if let Some(uploader) = &manifest.user {
+            let unknown_host = t!("down.unknown_host");
+            let host = manifest.hostname.as_deref().unwrap_or(&unknown_host);
+            match verified {
+                true => println!("{}", t!("down.uploaded_by", uploader, host)),
+                false => println!("{}", t!("down.uploaded_by_unverified", uploader, host)),
+            }
+        }
+    }
+    // From here on a bundle is applied like the pack inside it
+    let pack_data = match format {
+        "bundle" => bundle::to_pack_payload(&repo, &pack_data, &pack_file_name)?,
+        _ => pack_data,
+    };
+
+    if dry_run {
+        // Indexing only adds objects; the branch and worktree stay as they are
+        let sha = index_pack_into_repo(&repo, pack_data)?;
+        let mut target = git2::Oid::from_str(&sha)?;
+        if ref_only {
+            // Only the real commit lands on the branch
+            target = layers::base(&repo, target)?.ok_or_else(|| t!("down.no_commits"))?;
+        } else if refuse_diverged && !detached {
+            check_diverged(&repo, branch_name, target)?;
+        }
+        let target_tree = repo.find_commit(target)?.tree()?;
+        let diff = match ref_only {
+            true => changes::diff_for_branch(&repo, branch_name, &target_tree)?,
+            false => changes::diff_for_apply(&repo, &target_tree)?,
+        };
+
+        println!("{}", t!("down.would_apply"));
+        changes::print_summary(&diff)?;
+        for submodule in manifest.iter().flat_map(|manifest| &manifest.submodules) {
+            println!("{}", t!("down.would_apply_submodule", submodule.path));
+        }
+        return Ok(());
+    }
+
+    if user.is_some() || branch_off.is_some() || fetch_only {
+        // Reviewing someone else's work, or our own before taking it, must
+        // not touch our own branch or worktree
+        let sha = index_pack_into_repo(&repo, pack_data)?;
+        if let Some(manifest) = &manifest {
+            lfs::download(&repo, &config, &manifest.lfs)?;
+        }
+        let commit = repo.find_commit(git2::Oid::from_str(&sha)?)?;
+        if let Some(new_branch) = branch_off {
+            repo.branch(new_branch, &commit, false)?;
+            println!("{}", t!("down.branched_off", new_branch, &sha[..12]));
+        } else if let Some(teammate) = user {
+            let wip_branch = format!("wip/{}/{}", names::ref_segment(teammate), branch_name);
+            repo.branch(&wip_branch, &commit, true)?;
+            println!("{}", t!("down.teammate_branch", teammate, wip_branch));
+        } else {
+            write_fetch_head(&repo, branch_name, &pack_file_name, commit.id())?;
+        }
+        if config.sync.mirror_refs {
+            mirror_ref(&repo, uploading_host, branch_name, &sha)?;
+        }
+        return Ok(());
+    }
+
+    // Past this point the worktree changes; don't start if time is already up
+    deadline::check("download")?;
+
+    if ref_only {
+        return update_branch(
+            &repo,
+            &config,
+            manifest.as_ref(),
+            uploading_host,
+            branch_name,
+            pack_data,
+        );
+    }
+
+    // Apply the pack to the repository
+    let sha = index_pack_into_repo(&repo, pack_data)?;
+    // Only objects were added so far, so these can still stop it
+    if config.sync.verify_commits {
+        layers::verify(&repo, git2::Oid::from_str(&sha)?)?;
+    }
+    if refuse_diverged && !detached {
+        check_diverged(&repo, branch_name, git2::Oid::from_str(&sha)?)?;
+    }
+    // After the checks above, which leave the worktree alone
+    let stash = match autostash {
+        true => stash_local_changes(&repo)?,
+        false => None,
+    };
+    // Whatever stops it, the stash is popped again below
+    let apply = || -> Result<(), Box<dyn std::error::Error>> {
+        if !keep_local && !force {
+            check_nothing_lost(&repo, branch_name, &sha)?;
+        }
+        if let Some(snapshot) = manifest.as_ref().and_then(|m| m.git_config.as_ref()) {
+            // Restore config before applying so sparse-checkout affects the reset
+            snapshot.restore(&repo, branch_name, &config.sync.git_config_patterns())?;
+        }
+        if let Some(manifest) = &manifest {
+            // Before the checkout, so Git LFS finds the objects when it runs
+            lfs::download(&repo, &config, &manifest.lfs)?;
+        }
+        if keep_local {
+            merge_pack(&repo, strategy, &sha)?;
+        } else {
+            if detached {
+                repo.set_head_detached(git2::Oid::from_str(&sha)?)?;
+            } else if current_branch.as_deref() != Some(branch_name) {
+                switch_to_branch(&repo, branch_name, &sha)?;
+            }
+            checkout_pack(&repo, &sha)?;
+        }
+        if let Some(manifest) = &manifest {
+            manifest::restore_tags(&repo, &manifest.tags)?;
+            submodules::apply(&repo, &config, &manifest.submodules)?;
+        }
+        println!("{}", t!("down.applied"));
+        Ok(())
+    };
+    let applied = apply();
+    if let Some(stash) = stash {
+        pop_local_changes(&repo, stash)?;
+    }
+    applied?;
+
+    if config.sync.mirror_refs {
+        mirror_ref(&repo, uploading_host, branch_name, &sha)?;
+    }
+    hooks::run(
+        &repo,
+        "post-down",
+        &config.sync.post_down,
+        branch_name,
+        &sha,
+    )
+}
+
+// The branch and commit a hook is told about: `branch`, or what HEAD names.
+// Empty when there is no commit yet, or for the branch of a detached HEAD.
+fn hook_target(repo: &Repository, branch: Option<&str>) -> (String, String) {
+    let reference = match branch {
+        Some(branch) => repo
+            .find_branch(branch, BranchType::Local)
+            .map(|branch| branch.into_reference()),
+        None => repo.head(),
+    };
+    match reference {
+        Ok(reference) => (
+            match reference.is_branch() {
+                true => reference.shorthand().unwrap_or("").to_string(),
+                false => String::new(),
+            },
+            reference
+                .target()
+                .map(|oid| oid.to_string())
+                .unwrap_or_default(),
+        ),
+        Err(_) => (
+            branch
+                .map(str::to_string)
+                .or_else(|| unborn_branch(repo))
+                .unwrap_or_default(),
+            String::new(),
+        ),
+    }
+}
 
+// Stops a reset that would throw away uncommitted work. What earlier packs
+// of the branch restored, per their mirror refs, doesn't count as such.
+fn check_nothing_lost(
+    repo: &Repository,
+    branch_name: &str,
+    sha: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = repo.find_commit(git2::Oid::from_str(sha)?)?.tree()?;
+    let mut received = Vec::new();
+    for reference in repo.references_glob(&format!("{}*/{}", MIRROR_REFS, branch_name))? {
+        received.push(reference?.peel_to_tree()?);
+    }
+    let lost = changes::lost_changes(repo, &target, &received)?;
+    if lost.is_empty() {
+        return Ok(());
+    }
+    // Enough to recognize the work without flooding the terminal
+    const SHOWN: usize = 10;
+    let mut list = lost.iter().take(SHOWN).cloned().collect::<Vec<_>>();
+    if lost.len() > SHOWN {
+        list.push(t!("down.would_lose_more", lost.len() - SHOWN));
+    }
+    Err(t!("down.would_lose", lost.len(), list.join(", ")).into())
+}
+
+// Stops a reset of the local branch when it and the received commit both
+// have commits the other doesn't, as the local ones would be lost
+fn check_diverged(
+    repo: &Repository,
+    branch_name: &str,
+    tip: git2::Oid,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Ok(local) = repo.refname_to_id(&format!("refs/heads/{}", branch_name)) else {
+        return Ok(());
+    };
+    // Packs of a branch that had no commits have nothing to diverge from
+    let Some(received) = layers::base(repo, tip)? else {
+        return Ok(());
+    };
+    let (ahead, behind) = repo.graph_ahead_behind(local, received)?;
+    if ahead > 0 && behind > 0 {
+        return Err(t!("down.diverged", branch_name, ahead, behind).into());
+    }
     Ok(())
 }
 
-struct RepoInfo {
-    author: String,
-    name: String,
+// Where `down --fetch-only` leaves the pack's commit, uncommitted changes
+// included
+const INCOMING_REF: &str = "refs/sync/incoming";
+
+// Records the received commit as `git fetch` would, for `down --fetch-only`.
+// FETCH_HEAD gets the real commit, since merging the temporary ones would put
+// them in the branch's history; refs/sync/incoming gets the whole pack.
+fn write_fetch_head(
+    repo: &Repository,
+    branch_name: &str,
+    pack_key: &str,
+    tip: git2::Oid,
+) -> Result<(), Box<dyn std::error::Error>> {
+    repo.reference(INCOMING_REF, tip, true, "packer down --fetch-only")?;
+    let Some(received) = layers::base(repo, tip)? else {
+        // Only uncommitted changes, on a branch that had no commits
+        println!("{}", t!("down.fetched_layers_only", INCOMING_REF));
+        return Ok(());
+    };
+    std::fs::write(
+        repo.path().join("FETCH_HEAD"),
+        format!("{}\t\tbranch '{}' of {}\n", received, branch_name, pack_key),
+    )?;
+    println!(
+        "{}",
+        t!("down.fetched", &received.to_string()[..12], INCOMING_REF)
+    );
+    if received != tip {
+        println!("{}", t!("down.fetched_layers", INCOMING_REF));
+    }
+    Ok(())
+}
+
+// Lands a pack on a branch that isn't checked out, as in a bare repository
+// such as a central mirror: the branch is pointed at the real commit. There
+// is no worktree for the uncommitted changes, so they are left out.
+fn update_branch(
+    repo: &Repository,
+    config: &Config,
+    manifest: Option<&Manifest>,
+    uploading_host: Option<&str>,
+    branch_name: &str,
+    pack_data: Vec<u8>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sha = index_pack_into_repo(repo, pack_data)?;
+    let tip = git2::Oid::from_str(&sha)?;
+    if config.sync.verify_commits {
+        layers::verify(repo, tip)?;
+    }
+    if let Some(manifest) = manifest {
+        // So a mirror can serve them, or a later checkout find them
+        lfs::download(repo, config, &manifest.lfs)?;
+    }
+    let received = layers::base(repo, tip)?.ok_or_else(|| t!("down.no_commits"))?;
+    if received != tip {
+        println!("{}", t!("down.layers_skipped"));
+    }
+    let ref_name = format!("refs/heads/{}", branch_name);
+    repo.reference(&ref_name, received, true, "packer down: landed a pack")?;
+    println!(
+        "{}",
+        t!("down.branch_updated", ref_name, &received.to_string()[..12])
+    );
+    if let Some(manifest) = manifest {
+        manifest::restore_tags(repo, &manifest.tags)?;
+    }
+
+    if config.sync.mirror_refs {
+        mirror_ref(repo, uploading_host, branch_name, &received.to_string())?;
+    }
+    Ok(())
+}
+
+// `git stash push` for `down --autostash`, returning the stash commit.
+// Untracked files are left out, as applying a pack keeps them. `None` when
+// there was nothing to stash.
+fn stash_local_changes(repo: &Repository) -> Result<Option<git2::Oid>, Box<dyn std::error::Error>> {
+    if repo.is_bare() {
+        return Ok(None);
+    }
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(false);
+    if repo.statuses(Some(&mut status_options))?.is_empty() {
+        return Ok(None);
+    }
+    let output = std::process::Command::new("git")
+        .args(["stash", "push", "-m", "packer down --autostash"])
+        .current_dir(repo.workdir().unwrap_or(repo.path()))
+        .output()?;
+    if !output.status.success() {
+        return Err(t!(
+            "down.autostash_failed",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let stash = repo.refname_to_id("refs/stash")?;
+    println!("{}", t!("down.autostashed", &stash.to_string()[..12]));
+    Ok(Some(stash))
+}
+
+// Reapplies what `stash_local_changes` stashed, whether or not the pack was
+// applied. Conflicts are reported rather than returned, so they don't hide
+// why `down` stopped; git keeps the stash then.
+fn pop_local_changes(
+    repo: &Repository,
+    stash: git2::Oid,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let output = std::process::Command::new("git")
+        .args(["stash", "pop"])
+        .current_dir(repo.workdir().unwrap_or(repo.path()))
+        .output()?;
+    if output.status.success() {
+        println!("{}", t!("down.autostash_applied"));
+    } else {
+        print!("{}", String::from_utf8_lossy(&output.stdout));
+        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+        println!("{}", t!("down.autostash_conflicts", stash));
+    }
+    Ok(())
+}
+
+// Merges the pack's real commit into the current branch, or rebases the
+// branch onto it, then puts the pack's uncommitted changes on top as
+// `checkout_pack` would
+fn merge_pack(
+    repo: &Repository,
+    strategy: &str,
+    sha_str: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(false);
+    if !repo.statuses(Some(&mut status_options))?.is_empty() {
+        return Err(t!("down.strategy_dirty", strategy).into());
+    }
+
+    let tip = git2::Oid::from_str(sha_str)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    // Packs of a branch that had no commits have nothing to merge
+    let received = layers::base(repo, tip)?;
+    if let Some(received) = received {
+        if head != received && !repo.graph_descendant_of(head, received)? {
+            let output = std::process::Command::new("git")
+                .args([strategy, &received.to_string()])
+                .args(if strategy == "merge" {
+                    &["--no-edit"][..]
+                } else {
+                    &[]
+                })
+                .current_dir(repo.workdir().unwrap_or(repo.path()))
+                .output()?;
+            if !output.status.success() {
+                print!("{}", String::from_utf8_lossy(&output.stdout));
+                return Err(t!("down.strategy_stopped", strategy).into());
+            }
+            println!(
+                "{}",
+                t!("down.strategy_done", strategy, &received.to_string()[..12])
+            );
+        }
+    }
+
+    if Some(tip) != received {
+        let head = repo.head()?.peel_to_commit()?.id();
+        let new_tip = layers::restack(repo, tip, head)?
+            .ok_or_else(|| t!("down.layers_conflict", &sha_str[..12]))?;
+        checkout_pack(repo, &new_tip.to_string())?;
+    }
+    Ok(())
+}
+
+// Where mirror refs go, one directory per host, apart from the fixed refs
+// such as refs/sync/tmp/ and refs/sync/incoming that a host could be named
+// after
+const MIRROR_REFS: &str = "refs/sync/hosts/";
+
+// Records a received commit like a remote-tracking ref, so plain git can
+// compare against the machine that uploaded it between syncs, e.g.
+// `git log ..refs/sync/hosts/laptop/main`
+fn mirror_ref(
+    repo: &Repository,
+    host: Option<&str>,
+    branch_name: &str,
+    sha: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let host: String = host
+        .unwrap_or("unknown")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    let ref_name = format!("{}{}/{}", MIRROR_REFS, host, branch_name);
+    repo.reference(
+        &ref_name,
+        git2::Oid::from_str(sha)?,
+        true,
+        "packer down: received from another machine",
+    )?;
+    // Older versions kept it directly under refs/sync/
+    if host != "tmp" {
+        if let Ok(mut legacy) = repo.find_reference(&format!("refs/sync/{}/{}", host, branch_name))
+        {
+            legacy.delete()?;
+        }
+    }
+    println!("{}", t!("down.mirrored", &sha[..12], ref_name));
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct StatusReport {
+    branch: String,
+    head: String,
+    upstream: Option<String>,
+    /// Commits on HEAD that the upstream doesn't have
+    ahead: Option<usize>,
+    dirty: bool,
+    repo_config: Vec<String>,
+    pack_key: String,
+    remote_pack: Option<RemoteObjectInfo>,
+    /// What the remote pack contains, from its manifest
+    remote_snapshot: Option<SnapshotSummary>,
+    /// Commit last uploaded for this branch from this machine
+    last_uploaded: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SnapshotSummary {
+    commit: String,
+    hostname: Option<String>,
+    user: Option<String>,
+    object_count: Option<usize>,
+    commit_count: Option<usize>,
+    log: Vec<String>,
+}
+
+impl From<Manifest> for SnapshotSummary {
+    fn from(manifest: Manifest) -> Self {
+        SnapshotSummary {
+            commit: manifest.commit,
+            hostname: manifest.hostname,
+            user: manifest.user,
+            object_count: manifest.object_count,
+            commit_count: manifest.commit_count,
+            log: manifest.log,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RemoteObjectInfo {
+    size: i64,
+    last_modified: Option<String>,
+    /// File name recorded by `s`, percent-encoded
+    #[serde(skip)]
+    original_name: Option<String>,
+    #[serde(skip)]
+    content_encoding: Option<String>,
+    /// SHA-256 checksum of the decrypted, decompressed content
+    #[serde(skip)]
+    plaintext_checksum: Option<String>,
+    /// Commit, branch and origin of a pack uploaded by `up`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pack: Option<PackMetadata>,
+}
+
+fn collect_status() -> Result<StatusReport, Box<dyn std::error::Error>> {
+    let repo = Repository::open(std::env::current_dir()?)?;
+    let config = Config::load_for_repo(&repo)?;
+
+    let head = repo.head()?;
+    let head_oid = head.peel_to_commit()?.id();
+    // A detached HEAD is stored under a name derived from its commit
+    let branch_name = match head.is_branch() {
+        true => head
+            .shorthand()
+            .ok_or_else(|| git2::Error::from_str("Failed to get branch name from HEAD"))?
+            .to_string(),
+        false => detached_name(head_oid),
+    };
+
+    let remote_branch_name = format!("refs/remotes/origin/{}", branch_name);
+    let (upstream, ahead) = match repo
+        .find_reference(&remote_branch_name)
+        .ok()
+        .and_then(|r| r.target())
+    {
+        Some(remote_oid) => {
+            let mut revwalk = repo.revwalk()?;
+            revwalk.push(head_oid)?;
+            revwalk.hide(remote_oid)?;
+            (Some(remote_branch_name), Some(revwalk.count()))
+        }
+        None => (None, None),
+    };
+
+    let mut status_options = git2::StatusOptions::new();
+    status_options
+        .include_untracked(true)
+        .include_ignored(false);
+    let dirty = !repo.statuses(Some(&mut status_options))?.is_empty();
+
+    let repo_info = extract_repo_info(&repo)?;
+    let pack_key = existing_pack_key(
+        &config,
+        &repo_info,
+        &branch_name,
+        config.sync.user(),
+        "head.pack",
+    )?;
+    let remote_pack = head_object(&config.oss, &pack_key)?;
+    let remote_snapshot = match remote_pack {
+        Some(_) => fetch_manifest(&config, &pack_key)?.map(SnapshotSummary::from),
+        None => None,
+    };
+    let last_uploaded = last_upload(&repo_info, &branch_name);
+
+    Ok(StatusReport {
+        branch: branch_name,
+        head: head_oid.to_string(),
+        upstream,
+        ahead,
+        dirty,
+        repo_config: config::repo_config_files(&repo)
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect(),
+        pack_key,
+        remote_pack,
+        remote_snapshot,
+        last_uploaded,
+    })
+}
+
+fn print_snapshot_summary(snapshot: &SnapshotSummary) {
+    let mut parts = Vec::new();
+    if let Some(commits) = snapshot.commit_count {
+        parts.push(format!("{} commits", commits));
+    }
+    if let Some(objects) = snapshot.object_count {
+        parts.push(format!("{} objects", objects));
+    }
+    if let Some(host) = &snapshot.hostname {
+        parts.push(format!("from {}", host));
+    }
+    if let Some(user) = &snapshot.user {
+        parts.push(format!("by {}", user));
+    }
+    if !parts.is_empty() {
+        println!("  {}", parts.join(", "));
+    }
+    for line in &snapshot.log {
+        println!("    {}", line);
+    }
+}
+
+fn cmd_status(json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let status = collect_status()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    println!("Branch: {} ({})", status.branch, status.head);
+    match (&status.upstream, status.ahead) {
+        (Some(upstream), Some(ahead)) => {
+            println!("Upstream: {} ({} commits ahead)", upstream, ahead)
+        }
+        _ => println!("Upstream: none"),
+    }
+    println!(
+        "Working tree: {}",
+        if status.dirty { "dirty" } else { "clean" }
+    );
+    for path in &status.repo_config {
+        println!("Repository config: {}", path);
+    }
+    match &status.remote_pack {
+        Some(info) => println!(
+            "Remote pack: {} ({}, last modified {})",
+            status.pack_key,
+            units::format_size(u64::try_from(info.size).unwrap_or(0)),
+            info.last_modified.as_deref().unwrap_or("unknown")
+        ),
+        None => println!("Remote pack: {} (not uploaded)", status.pack_key),
+    }
+    if let Some(pack) = status
+        .remote_pack
+        .as_ref()
+        .and_then(|info| info.pack.as_ref())
+    {
+        println!("  {}", pack);
+    }
+    if let Some(snapshot) = &status.remote_snapshot {
+        print_snapshot_summary(snapshot);
+    }
+    if let Some(sha) = &status.last_uploaded {
+        println!("Last uploaded from this machine: {}", sha);
+    }
+
+    Ok(())
+}
+
+fn cmd_s(
+    local_file: &Path,
+    object_key: &str,
+    expires: &str,
+    compress: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let expires = link_lifetime("--expires", Some(expires))?;
+    let compress = compress.map(ContentEncoding::from_name).transpose()?;
+
+    // Load config from the config file (or the embedded fallback)
+    let config = Config::load()?;
+    config.oss.ensure_writable()?;
+
+    // Read the file
+    let mut file_data = std::fs::read(local_file)?;
+
+    let size_str = units::format_size(file_data.len() as u64);
+
+    println!("{}", t!("s.uploading", local_file.display(), size_str));
+
+    // What `get` should end up with; GPG shares are saved still encrypted, so
+    // there is nothing for it to compare those against
+    let plaintext_checksum = HashAlgorithm::Sha256.checksum(&file_data);
+
+    // Shares are downloaded through a plain URL, so only GPG mode encrypts
+    // them: the recipient decrypts with `gpg -d` and no packer install
+    let gpg_mode = config.sync.encrypt && config.sync.encryption == Encryption::Gpg;
+    if gpg_mode && compress.is_some() {
+        // A Content-Encoding over the OpenPGP message would be undone by the
+        // browser before `gpg -d` runs; gpg compresses on its own anyway
+        return Err("--compress can't be combined with GPG-encrypted shares".into());
+    }
+    if gpg_mode {
+        file_data = gpg::encrypt(&config.sync.gpg_recipients, &file_data)?;
+        println!(
+            "{}",
+            t!("s.gpg_encrypted", config.sync.gpg_recipients.len())
+        );
+    }
+
+    if let Some(encoding) = compress {
+        let encoded = encoding.encode(&file_data)?;
+        println!(
+            "{}",
+            t!(
+                "s.compressed",
+                encoding.name(),
+                size_str,
+                units::format_size(encoded.len() as u64)
+            )
+        );
+        file_data = encoded;
+    }
+
+    // Keep the exact file name, which the object key may only approximate
+    let original_name = local_file
+        .file_name()
+        .map(names::encode_ascii)
+        .unwrap_or_default();
+    // Lets a browser show the file rather than just save it, which matters
+    // once it decompresses the body itself
+    let content_type = (!gpg_mode).then(|| {
+        mime_guess::from_path(local_file)
+            .first_or_octet_stream()
+            .to_string()
+    });
+
+    // Upload the file to S3
+    upload_object(
+        &config.oss,
+        object_key,
+        file_data,
+        config.sync.hash_algorithm,
+        &ObjectHeaders {
+            metadata: if gpg_mode {
+                vec![("sync-original-name", &original_name)]
+            } else {
+                vec![
+                    ("sync-original-name", &original_name),
+                    ("sync-plaintext-checksum", &plaintext_checksum),
+                ]
+            },
+            content_type: content_type.as_deref(),
+            content_encoding: compress.map(|encoding| encoding.name()),
+            tags: vec![("kind", "file")],
+        },
+    )?;
+
+    println!("{}", t!("s.uploaded", object_key));
+
+    // Generate a pre-signed URL for the uploaded file
+    let url = presigned_url(&config.oss, object_key, expires.as_secs())?;
+    println!(
+        "{}",
+        t!("link.valid_for", units::format_duration(expires), url)
+    );
+
+    Ok(())
+}
+
+struct RepoInfo {
+    author: String,
+    name: String,
+}
+
+impl RepoInfo {
+    /// `{author}/{name}` as object keys use it. Hosts treat owner and
+    /// repository names case-insensitively, so they are lowercased.
+    fn key_dir(&self) -> String {
+        format!(
+            "{}/{}",
+            names::key_segment(&self.author.to_lowercase()),
+            names::key_segment(&self.name.to_lowercase())
+        )
+    }
+
+    /// `{author}/{name}` as keys had it before names were encoded and
+    /// lowercased.
+    fn legacy_key_dir(&self) -> String {
+        format!("{}/{}", self.author, self.name)
+    }
+}
+
+impl std::fmt::Display for RepoInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.author, self.name)
+    }
 }
 
 fn extract_repo_info(repo: &Repository) -> Result<RepoInfo, git2::Error> {
@@ -405,6 +2809,11 @@ fn extract_repo_info(repo: &Repository) -> Result<RepoInfo, git2::Error> {
         }
     };
 
+    Ok(repo_info_from_url(url))
+}
+
+// Author and name of a repository from its origin URL
+fn repo_info_from_url(url: &str) -> RepoInfo {
     // Parse the URL to extract author and repo name
     // Example URLs:
     // https://github.com/author/repo.git
@@ -439,8 +2848,13 @@ fn extract_repo_info(repo: &Repository) -> Result<RepoInfo, git2::Error> {
             }
         }
     } else {
-        // Fallback for other Git hosting services
-        let path_parts: Vec<&str> = url.split('/').collect();
+        // Fallback for other Git hosting services; scp-like URLs
+        // (git@host:owner/repo.git) separate the host with a colon
+        let url = match url.contains("://") {
+            true => url.to_string(),
+            false => url.replacen(':', "/", 1),
+        };
+        let path_parts: Vec<&str> = url.trim_end_matches('/').split('/').collect();
         if path_parts.len() >= 2 {
             let name = path_parts[path_parts.len() - 1]
                 .trim_end_matches(".git")
@@ -452,397 +2866,1165 @@ fn extract_repo_info(repo: &Repository) -> Result<RepoInfo, git2::Error> {
         }
     };
 
-    Ok(RepoInfo { author, name })
+    RepoInfo { author, name }
 }
 
-fn upload_pack_to_s3(
-    config: &OssConfig,
-    file_name: &str,
-    data: Vec<u8>,
+// Remembers the commit last uploaded for a branch so `status` can show it
+fn record_last_upload(
+    repo_info: &RepoInfo,
+    branch: &str,
+    sha: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Create a tokio runtime for async operations
-    let rt = Runtime::new()?;
-
-    // Use the runtime to execute our async function
-    rt.block_on(async {
-        // Create S3 client with proper credentials
-        let credentials_provider = aws_sdk_s3::config::Credentials::new(
-            &config.access_key_id,
-            &config.access_key_secret,
-            None,
-            None,
-            "Static",
-        );
+    let path = paths::last_upload_file(&repo_info.author, &repo_info.name)
+        .ok_or("Could not determine the cache directory")?;
+    let _lock = lock::state_file(&path)?;
+
+    let mut table = match std::fs::read_to_string(&path) {
+        Ok(content) => content.parse::<toml::Table>().unwrap_or_default(),
+        Err(_) => toml::Table::new(),
+    };
+    table.insert(branch.to_string(), toml::Value::String(sha.to_string()));
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(&table)?)?;
+    Ok(())
+}
+
+// Whether the last upload of `branch` has the same parent and tree as the
+// temporary commit just created, i.e. someone uploaded this exact state
+fn already_uploaded(
+    repo: &Repository,
+    repo_info: &RepoInfo,
+    branch: &str,
+    staged: git2::Oid,
+) -> bool {
+    let Some(last) = last_upload(repo_info, branch)
+        .and_then(|sha| git2::Oid::from_str(&sha).ok())
+        .and_then(|oid| repo.find_commit(oid).ok())
+    else {
+        return false;
+    };
+    let Ok(staged) = repo.find_commit(staged) else {
+        return false;
+    };
+    last.tree_id() == staged.tree_id() && last.parent_ids().eq(staged.parent_ids())
+}
 
-        let region = Region::new("cn-beijing");
-        let s3_config = aws_sdk_s3::Config::builder()
-            .region(region)
-            .endpoint_url(&config.endpoint)
-            .credentials_provider(credentials_provider)
-            .build();
+fn last_upload(repo_info: &RepoInfo, branch: &str) -> Option<String> {
+    let path = paths::last_upload_file(&repo_info.author, &repo_info.name)?;
+    let table = std::fs::read_to_string(path)
+        .ok()?
+        .parse::<toml::Table>()
+        .ok()?;
+    table.get(branch)?.as_str().map(str::to_string)
+}
 
-        let client = Client::from_conf(s3_config);
+// Object key directory for a branch's packs:
+// {prefix}/{repo_author}/{repo_name}/{branch_name}[/users/{user}]
+fn pack_dir(config: &Config, repo_info: &RepoInfo, branch: &str, user: Option<&str>) -> String {
+    let mut dir = format!("{}/{}", repo_info.key_dir(), names::key_segment(branch));
+    if let Some(user) = user {
+        dir = format!("{}/users/{}", dir, names::key_segment(user));
+    }
+    config.sync.object_key(&dir)
+}
 
-        // Upload the data directly from memory
-        let response = client
-            .put_object()
-            .bucket(&config.bucket_name)
-            .key(file_name)
-            .body(data.into())
-            .send()
-            .await?;
+// Where `pack_dir` was before names were encoded, e.g. `feature/foo` as two
+// segments, so `down` still finds packs uploaded then
+fn legacy_pack_dir(
+    config: &Config,
+    repo_info: &RepoInfo,
+    branch: &str,
+    user: Option<&str>,
+) -> String {
+    let mut dir = format!("{}/{}", repo_info.legacy_key_dir(), branch);
+    if let Some(user) = user {
+        dir = format!("{}/users/{}", dir, user);
+    }
+    config.sync.object_key(&dir)
+}
 
-        println!("Upload response: {:?}", response);
+// `file` in the branch's pack directory, or in its legacy one when only that
+// has it
+fn existing_pack_key(
+    config: &Config,
+    repo_info: &RepoInfo,
+    branch: &str,
+    user: Option<&str>,
+    file: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let key = format!("{}/{}", pack_dir(config, repo_info, branch, user), file);
+    let legacy = format!(
+        "{}/{}",
+        legacy_pack_dir(config, repo_info, branch, user),
+        file
+    );
+    existing_key(config, key, legacy)
+}
 
-        Ok::<(), Box<dyn std::error::Error>>(())
-    })
+// `file` among what belongs to the repository rather than a branch, such as
+// the bundle and the stashes
+fn repo_key(config: &Config, repo_info: &RepoInfo, file: &str) -> String {
+    let mut dir = repo_info.key_dir();
+    if let Some(user) = config.sync.user() {
+        dir = format!("{}/users/{}", dir, names::key_segment(user));
+    }
+    config.sync.object_key(&format!("{}/{}", dir, file))
 }
 
-async fn generate_presigned_url(
+// `repo_key`, or where it was before names were encoded when only that has it
+fn existing_repo_key(
+    config: &Config,
+    repo_info: &RepoInfo,
+    file: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut legacy = repo_info.legacy_key_dir();
+    if let Some(user) = config.sync.user() {
+        legacy = format!("{}/users/{}", legacy, user);
+    }
+    let legacy = config.sync.object_key(&format!("{}/{}", legacy, file));
+    existing_key(config, repo_key(config, repo_info, file), legacy)
+}
+
+// `key`, or `legacy` when only that exists
+fn existing_key(
+    config: &Config,
+    key: String,
+    legacy: String,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if key == legacy || head_object(&config.oss, &key)?.is_some() {
+        return Ok(key);
+    }
+    match head_object(&config.oss, &legacy)? {
+        Some(_) => {
+            info!("Found {} under its legacy key {}", key, legacy);
+            Ok(legacy)
+        }
+        None => Ok(key),
+    }
+}
+
+// Object tags for what `up` and `autosave` store for a branch
+fn pack_tags<'a>(repo_name: &'a str, branch_name: &'a str) -> Vec<(&'static str, &'a str)> {
+    vec![
+        ("repo", repo_name),
+        ("branch", branch_name),
+        ("kind", "pack"),
+    ]
+}
+
+fn upload_object(
+    config: &OssConfig,
+    file_name: &str,
+    data: Vec<u8>,
+    hash: HashAlgorithm,
+    headers: &ObjectHeaders,
+) -> Result<(), Box<dyn std::error::Error>> {
+    config.ensure_writable()?;
+
+    // Checksum of the stored bytes, recorded so downloads can detect corruption
+    let checksum = hash.checksum(&data);
+    let mut metadata = vec![("sync-checksum", checksum.as_str())];
+    metadata.extend(headers.metadata.iter().copied());
+
+    storage::backend(config).put(
+        file_name,
+        data,
+        &ObjectHeaders {
+            metadata,
+            content_type: headers.content_type,
+            content_encoding: headers.content_encoding,
+            tags: headers.tags.clone(),
+        },
+    )
+}
+
+// How long the links `up`, `s` and `get` print stay valid unless asked otherwise
+const DEFAULT_LINK_LIFETIME: std::time::Duration = std::time::Duration::from_secs(48 * 3600);
+
+// Parses the lifetime given to `flag`, if any, for a download link
+fn link_lifetime(
+    flag: &str,
+    value: Option<&str>,
+) -> Result<std::time::Duration, Box<dyn std::error::Error>> {
+    let Some(value) = value else {
+        return Ok(DEFAULT_LINK_LIFETIME);
+    };
+    let lifetime = units::parse_duration(value)?;
+    // SigV4 presigned URLs can't outlive a week
+    if lifetime.is_zero() || lifetime > std::time::Duration::from_secs(7 * 86400) {
+        return Err(t!("link.bad_lifetime", flag, units::format_duration(lifetime)).into());
+    }
+    Ok(lifetime)
+}
+
+// A download link for an object that expires after `expires_in_seconds`
+fn presigned_url(
     config: &OssConfig,
     file_name: &str,
     expires_in_seconds: u64,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // No need for a separate runtime here, assumes it's called within one
-
-    // Create S3 client with proper credentials
-    let credentials_provider = aws_sdk_s3::config::Credentials::new(
-        &config.access_key_id,
-        &config.access_key_secret,
-        None,
-        None,
-        "Static",
-    );
+    storage::backend(config).sign_url(
+        file_name,
+        std::time::Duration::from_secs(expires_in_seconds),
+    )
+}
+
+fn download_pack(
+    config: &OssConfig,
+    file_name: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let data = try_download(config, file_name)?.ok_or_else(|| t!("object.not_found", file_name))?;
 
-    let region = Region::new("cn-beijing");
-    let s3_config = aws_sdk_s3::Config::builder()
-        .region(region)
-        .endpoint_url(&config.endpoint)
-        .credentials_provider(credentials_provider)
-        .build();
+    println!(
+        "{}",
+        t!(
+            "down.downloaded_size",
+            units::format_size(data.len() as u64)
+        )
+    );
 
-    // Create a presigner
-    let presigning_config = aws_sdk_s3::presigning::PresigningConfig::builder()
-        .expires_in(std::time::Duration::from_secs(expires_in_seconds))
-        .build()?;
+    Ok(data)
+}
 
-    let client = Client::from_conf(s3_config);
+// Downloads an object, returning None instead of an error if the key doesn't exist
+fn try_download(
+    config: &OssConfig,
+    file_name: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let Some(object) = storage::backend(config).get(file_name)? else {
+        return Ok(None);
+    };
 
-    // Generate a presigned URL for GetObject operation
-    let presigned_request = client
-        .get_object()
-        .bucket(&config.bucket_name)
-        .key(file_name)
-        .presigned(presigning_config)
-        .await?;
+    // Objects uploaded by older versions carry no checksum
+    if let Some(checksum) = object.metadata.get("sync-checksum") {
+        match hash::verify_checksum(checksum, &object.data)? {
+            true => println!("{}", t!("checksum.verified", checksum)),
+            false => println!("{}", t!("checksum.unknown", checksum)),
+        }
+    }
 
-    Ok::<String, Box<dyn std::error::Error>>(presigned_request.uri().to_string())
+    Ok(Some(object.data))
 }
 
-fn download_pack_from_s3(
+// Returns size and modification time of an object, or None if it doesn't exist
+fn head_object(
     config: &OssConfig,
     file_name: &str,
+) -> Result<Option<RemoteObjectInfo>, Box<dyn std::error::Error>> {
+    Ok(storage::backend(config)
+        .head(file_name)?
+        .map(|info| RemoteObjectInfo {
+            size: info.size,
+            last_modified: info.last_modified,
+            original_name: info.metadata.get("sync-original-name").cloned(),
+            content_encoding: info.content_encoding,
+            plaintext_checksum: info.metadata.get("sync-plaintext-checksum").cloned(),
+            pack: PackMetadata::from_headers(&info.metadata),
+        }))
+}
+
+// Compresses and (unless disabled) encrypts a payload, recording both and the
+// payload's checksum in the envelope
+// Pass the object key the result is uploaded to so AES payloads are bound to
+// it; `None` for data that leaves storage, like `export`.
+fn seal_payload(
+    sync: &SyncConfig,
+    payload: Vec<u8>,
+    object_key: Option<&str>,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Create a tokio runtime for async operations
-    let rt = Runtime::new()?;
-
-    // Use the runtime to execute our async function
-    rt.block_on(async {
-        // Create S3 client with proper credentials
-        let credentials_provider = aws_sdk_s3::config::Credentials::new(
-            &config.access_key_id,
-            &config.access_key_secret,
-            None,
-            None,
-            "Static",
-        );
+    let checksum = sync.hash_algorithm.checksum(&payload);
+    seal_checksummed_payload(sync, payload, checksum, object_key)
+}
 
-        let region = Region::new("cn-beijing");
-        let s3_config = aws_sdk_s3::Config::builder()
-            .region(region)
-            .endpoint_url(&config.endpoint)
-            .credentials_provider(credentials_provider)
-            .build();
+// `seal_payload` for a payload whose checksum the caller already computed
+fn seal_checksummed_payload(
+    sync: &SyncConfig,
+    payload: Vec<u8>,
+    checksum: String,
+    object_key: Option<&str>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let compression = sync.compression;
+    progress::start("compress", Some(payload.len() as u64));
+    let compressed = compression.compress(&payload)?;
+    progress::finish("compress", payload.len() as u64);
+    if compression != Compression::None {
+        info!(
+            "Compressed with {}: {} → {}",
+            compression.name(),
+            units::format_size(payload.len() as u64),
+            units::format_size(compressed.len() as u64)
+        );
+    }
 
-        let client = Client::from_conf(s3_config);
+    let mut flags = 0;
+    let (cipher, body) = match sync.encryption {
+        _ if !sync.encrypt => (Cipher::None, compressed),
+        Encryption::Aes => {
+            let aad = match object_key {
+                Some(object_key) => {
+                    flags |= envelope::FLAG_BOUND_TO_KEY;
+                    envelope::associated_data(object_key)
+                }
+                None => Vec::new(),
+            };
+            (Cipher::Aes, encrypt_pack_data(sync, compressed, &aad)?)
+        }
+        Encryption::Age => (
+            Cipher::Age,
+            age_encryption::encrypt(&sync.age_recipients, &compressed)?,
+        ),
+        Encryption::Gpg => (
+            Cipher::Gpg,
+            gpg::encrypt(&sync.gpg_recipients, &compressed)?,
+        ),
+    };
 
-        // Download the data
-        let response = client
-            .get_object()
-            .bucket(&config.bucket_name)
-            .key(file_name)
-            .send()
-            .await?;
+    envelope::wrap(
+        &envelope::Header {
+            cipher,
+            compression,
+            flags,
+            checksum: Some(checksum),
+        },
+        body,
+    )
+}
 
-        // Convert the response body to bytes
-        let data = response.body.collect().await?.into_bytes().to_vec();
+// Reverses `seal_payload`, given the key the object was downloaded from.
+// Objects from before the envelope existed are decrypted according to the
+// local config, as they always were.
+fn open_payload(
+    sync: &SyncConfig,
+    data: Vec<u8>,
+    object_key: Option<&str>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let Some(envelope::Envelope {
+        version,
+        header,
+        body,
+    }) = envelope::unwrap(&data)?
+    else {
+        return if sync.encrypt {
+            decrypt_pack_data(sync, data, &[], true)
+        } else {
+            Ok(data)
+        };
+    };
 
-        println!("Downloaded encrypted pack file, size: {} bytes", data.len());
+    let aad = if header.flags & envelope::FLAG_BOUND_TO_KEY != 0 {
+        let object_key =
+            object_key.ok_or("This object is bound to its storage key; download it with packer")?;
+        envelope::associated_data(object_key)
+    } else {
+        Vec::new()
+    };
 
-        Ok::<Vec<u8>, Box<dyn std::error::Error>>(data)
-    })
+    // The header names the cipher, so every object decodes regardless of the
+    // local Encryption setting. The decrypted intermediate is wiped after
+    // decompressing.
+    let compressed = Zeroizing::new(match header.cipher {
+        Cipher::None => body.to_vec(),
+        // Only version 1 predates the formats that replaced the fixed key
+        Cipher::Aes => {
+            decrypt_pack_data(sync, body.to_vec(), &aad, version == 1).map_err(
+                |e| match object_key.filter(|_| !aad.is_empty()) {
+                    Some(object_key) => format!(
+                        "{} (the object may have been moved or copied to {} from elsewhere; see \
+                         `packer help envelope`)",
+                        e, object_key
+                    )
+                    .into(),
+                    None => e,
+                },
+            )?
+        }
+        Cipher::Age => age_encryption::decrypt(sync.age_identity_file(), body)?,
+        Cipher::Gpg => gpg::decrypt(body)?,
+    });
+    let payload = header.compression.decompress(&compressed)?;
+
+    // Envelopes written before version 3 carry no checksum
+    if let Some(checksum) = &header.checksum {
+        match hash::verify_checksum(checksum, &payload)? {
+            true => info!("{}", t!("checksum.payload_verified", checksum)),
+            false => println!("{}", t!("checksum.unknown", checksum)),
+        }
+    }
+    Ok(payload)
 }
 
-fn encrypt_pack_data(pack_data: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+fn encrypt_pack_data(
+    sync: &SyncConfig,
+    pack_data: Vec<u8>,
+    aad: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    // The plaintext and the random key are wiped once they're no longer needed
+    let pack_data = Zeroizing::new(pack_data);
+    progress::start("encrypt", Some(pack_data.len() as u64));
+
     // Generate a random key for first round encryption
-    let random_key = Aes256Gcm::generate_key(OsRng);
+    let mut random_key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(random_key.as_mut());
 
     // First round encryption
-    let cipher = Aes256Gcm::new(&random_key);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(random_key.as_slice()));
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
     let first_round_encrypted = cipher
-        .encrypt(&nonce, pack_data.as_ref())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &pack_data,
+                aad,
+            },
+        )
         .map_err(|e| format!("First round encryption failed: {}", e))?;
 
-    // Combine the encrypted data with the nonce and random key for second round
-    let mut combined_data = Vec::new();
-    combined_data.extend_from_slice(&nonce);
-    combined_data.extend_from_slice(&random_key);
-    combined_data.extend_from_slice(&first_round_encrypted);
-
-    // Second round encryption with fixed key
-    let fixed_key = Key::<Aes256Gcm>::from_slice(FIXED_KEY);
-    let fixed_cipher = Aes256Gcm::new(fixed_key);
-    let fixed_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-    let second_round_encrypted = fixed_cipher
-        .encrypt(&fixed_nonce, combined_data.as_ref())
-        .map_err(|e| format!("Second round encryption failed: {}", e))?;
-
-    // Prepend the fixed nonce to the final encrypted data
-    let mut final_data = Vec::new();
-    final_data.extend_from_slice(&fixed_nonce);
-    final_data.extend_from_slice(&second_round_encrypted);
+    // Second round: protect the random key for the configured recipients
+    let final_data = keys::wrap(
+        sync,
+        &keys::Sealed {
+            key: random_key,
+            nonce: nonce.into(),
+            ciphertext: first_round_encrypted,
+        },
+    )?;
 
-    println!(
-        "Data encrypted successfully: {} bytes original → {} bytes encrypted",
-        pack_data.len(),
-        final_data.len()
+    info!(
+        "Data encrypted successfully: {} original → {} encrypted",
+        units::format_size(pack_data.len() as u64),
+        units::format_size(final_data.len() as u64)
     );
+    progress::finish("encrypt", pack_data.len() as u64);
 
     Ok(final_data)
 }
 
-fn decrypt_pack_data(encrypted_data: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // AES-GCM nonce size is 12 bytes
-    const NONCE_SIZE: usize = 12;
-    // AES-256 key size is 32 bytes
-    const KEY_SIZE: usize = 32;
-
-    if encrypted_data.len() <= NONCE_SIZE {
-        return Err("Encrypted data too short".into());
-    }
-
-    // Extract the fixed nonce (first NONCE_SIZE bytes)
-    let fixed_nonce = &encrypted_data[0..NONCE_SIZE];
-    // The rest is the second round encrypted data
-    let second_round_encrypted = &encrypted_data[NONCE_SIZE..];
-
-    // Decrypt the second round with the fixed key
-    let fixed_key = Key::<Aes256Gcm>::from_slice(FIXED_KEY);
-    let fixed_cipher = Aes256Gcm::new(fixed_key);
-    let combined_data = fixed_cipher
-        .decrypt(fixed_nonce.into(), second_round_encrypted)
-        .map_err(|e| format!("Second round decryption failed: {}", e))?;
-
-    if combined_data.len() <= NONCE_SIZE + KEY_SIZE {
-        return Err("Decrypted data from second round too short".into());
-    }
-
-    // Extract the components from the combined data
-    let first_round_nonce = &combined_data[0..NONCE_SIZE];
-    let random_key_bytes = &combined_data[NONCE_SIZE..(NONCE_SIZE + KEY_SIZE)];
-    let first_round_encrypted = &combined_data[(NONCE_SIZE + KEY_SIZE)..];
+fn decrypt_pack_data(
+    sync: &SyncConfig,
+    encrypted_data: Vec<u8>,
+    aad: &[u8],
+    allow_legacy: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    progress::start("decrypt", Some(encrypted_data.len() as u64));
 
-    // Reconstruct the random key
-    let random_key = Key::<Aes256Gcm>::from_slice(random_key_bytes);
+    // Recover the random key, whichever format protects it
+    let sealed = keys::unwrap(
+        &keys::Credentials::for_config(sync),
+        &encrypted_data,
+        allow_legacy,
+    )?;
 
     // Decrypt the first round with the random key
-    let cipher = Aes256Gcm::new(random_key);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(sealed.key.as_slice()));
     let original_data = cipher
-        .decrypt(first_round_nonce.into(), first_round_encrypted)
+        .decrypt(
+            sealed.nonce.as_slice().into(),
+            Payload {
+                msg: &sealed.ciphertext,
+                aad,
+            },
+        )
         .map_err(|e| format!("First round decryption failed: {}", e))?;
 
     println!(
-        "Data decrypted successfully: {} bytes encrypted → {} bytes original",
-        encrypted_data.len(),
-        original_data.len()
+        "{}",
+        t!(
+            "down.decrypted",
+            units::format_size(encrypted_data.len() as u64),
+            units::format_size(original_data.len() as u64)
+        )
     );
+    progress::finish("decrypt", encrypted_data.len() as u64);
 
     Ok(original_data)
 }
 
-fn apply_pack_to_repo(
+// Resets the branch, index and worktree to what the pack's commit recorded:
+// the real commit it sits on, with staged, unstaged and untracked changes
+// each put back as such
+fn checkout_pack(repo: &Repository, sha_str: &str) -> Result<(), Box<dyn std::error::Error>> {
+    reset_worktree_to(repo, sha_str)?;
+    let restored = layers::unstack(repo, git2::Oid::from_str(sha_str)?)?;
+    if !restored.is_empty() {
+        println!("{}", t!("down.layers", restored.join(", ")));
+    }
+    Ok(())
+}
+
+// Points HEAD at `branch_name`, creating the branch at `sha` if this machine
+// doesn't have it yet. The caller's reset then moves the branch and worktree.
+fn switch_to_branch(
     repo: &Repository,
-    pack_data: Vec<u8>,
+    branch_name: &str,
+    sha: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.find_branch(branch_name, BranchType::Local).is_err() {
+        let commit = repo.find_commit(git2::Oid::from_str(sha)?)?;
+        let mut branch = repo.branch(branch_name, &commit, false)?;
+        println!("{}", t!("down.created_branch", branch_name, sha));
+
+        let upstream = format!("origin/{}", branch_name);
+        if repo.find_branch(&upstream, BranchType::Remote).is_ok() {
+            branch.set_upstream(Some(&upstream))?;
+            println!("{}", t!("down.tracking", upstream));
+        }
+    }
+
+    repo.set_head(&format!("refs/heads/{}", branch_name))?;
+    println!("{}", t!("down.switched", branch_name));
+    Ok(())
+}
+
+// Writes the pack into the object database and returns the commit SHA it carries
+fn index_pack_into_repo(
+    repo: &Repository,
+    pack_data: Vec<u8>,
+) -> Result<String, Box<dyn std::error::Error>> {
     // Extract the SHA string from the beginning of the pack data
     // SHA is a 40 character hex string
-    let sha_str = String::from_utf8_lossy(&pack_data[0..40]).to_string();
+    let sha_str = pack_data
+        .get(..40)
+        .and_then(|sha| std::str::from_utf8(sha).ok())
+        .filter(|sha| git2::Oid::from_str(sha).is_ok())
+        .ok_or_else(|| t!("down.damaged_pack", "no commit SHA in front of the pack"))?
+        .to_string();
     let pack_data = &pack_data[40..]; // Remove the SHA from pack data
 
-    // Create a temporary file to store the pack data
-    let mut temp_file = tempfile::NamedTempFile::new()?;
-    std::io::Write::write_all(&mut temp_file, pack_data)?;
-    let temp_path = temp_file.path().to_str().unwrap();
+    println!("{}", t!("down.applying_pack"));
+    println!("{}", t!("down.pack_commit", sha_str));
+    index_pack(repo, pack_data)?;
 
-    println!("Applying pack file to repository");
-    println!("Using commit SHA: {}", sha_str);
+    Ok(sha_str)
+}
 
-    // Apply the pack to the repository's object database
-    let output = std::process::Command::new("git")
-        .args(&["index-pack", "--stdin", "--fix-thin"])
-        .current_dir(repo.path().parent().unwrap_or(repo.path()))
-        .stdin(std::process::Stdio::from(std::fs::File::open(temp_path)?))
-        .output()?;
+// Writes a bare pack into the repository's object database, completing thin
+// packs from the objects it already has
+fn index_pack(repo: &Repository, pack_data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    // A truncated or corrupted download would otherwise only fail part way
+    // through indexing
+    verify_pack(pack_data).map_err(|reason| t!("down.damaged_pack", reason))?;
+
+    // In a partial clone, objects filtered out by the promisor remote are missing
+    // on purpose. Mark the pack as a promisor pack so those missing links aren't
+    // treated as corruption; the connectivity check can't tell them apart.
+    let promisor = find_promisor_remote(repo)?;
+    if let Some(promisor) = &promisor {
+        println!("{}", t!("down.promisor", promisor));
+    }
 
-    if !output.status.success() {
-        return Err(format!(
-            "Failed to apply pack: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )
-        .into());
+    progress::start("index", Some(pack_data.len() as u64));
+
+    // Apply the pack to the repository's object database
+    let pack_dir = git_common_dir(repo).join("objects").join("pack");
+    let odb = repo.odb()?;
+    let mut indexer = git2::Indexer::new(Some(&odb), &pack_dir, 0, promisor.is_none())?;
+    let name = std::io::Write::write_all(&mut indexer, pack_data)
+        .map_err(|e| e.to_string())
+        .and_then(|()| indexer.commit().map_err(|e| e.message().to_string()))
+        .map_err(|e| t!("down.index_failed", e))?;
+    if promisor.is_some() {
+        std::fs::write(pack_dir.join(format!("pack-{}.promisor", name)), "sync\n")?;
     }
 
-    println!(
-        "Pack applied to object database: {}",
-        String::from_utf8_lossy(&output.stdout)
-    );
+    println!("{}", t!("down.pack_applied", name));
+    progress::finish("index", pack_data.len() as u64);
 
-    // If we can't create a branch, just update the working directory with the changes
-    let output = std::process::Command::new("git")
-        .args(&["reset", "--hard", &sha_str])
-        .current_dir(repo.path().parent().unwrap_or(repo.path()))
-        .output()?;
+    Ok(())
+}
 
-    if !output.status.success() {
-        return Err(format!(
-            "Failed to update working directory: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )
-        .into());
+// Checks a pack's header and the SHA-1 of its contents that ends it, which
+// is what a truncated or corrupted download gets wrong
+fn verify_pack(pack_data: &[u8]) -> Result<(), String> {
+    // "PACK", version and object count, then the trailing checksum
+    const HEADER_LEN: usize = 12;
+    const CHECKSUM_LEN: usize = 20;
+    if pack_data.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(format!("only {} bytes", pack_data.len()));
+    }
+    if &pack_data[..4] != b"PACK" {
+        return Err("no PACK signature".to_string());
     }
+    let version = u32::from_be_bytes([pack_data[4], pack_data[5], pack_data[6], pack_data[7]]);
+    if version != 2 && version != 3 {
+        return Err(format!("unsupported pack version {}", version));
+    }
+    let (contents, checksum) = pack_data.split_at(pack_data.len() - CHECKSUM_LEN);
+    let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, contents);
+    if digest.as_ref() != checksum {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(())
+}
+
+// Hard-resets the current branch and working tree to the given commit
+fn reset_worktree_to(repo: &Repository, sha_str: &str) -> Result<(), Box<dyn std::error::Error>> {
+    progress::start("checkout", None);
+
+    if needs_git_checkout(repo)? {
+        let output = std::process::Command::new("git")
+            .args(["reset", "--hard", sha_str])
+            .current_dir(repo.workdir().unwrap_or(repo.path()))
+            .output()?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to update working directory: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+    } else {
+        let commit = repo.find_commit(git2::Oid::from_str(sha_str)?)?;
+        repo.reset(commit.as_object(), git2::ResetType::Hard, None)
+            .map_err(|e| format!("Failed to update working directory: {}", e.message()))?;
+    }
+    progress::finish("checkout", 0);
 
     Ok(())
 }
 
-async fn list_files_in_bucket(
-    config: &OssConfig,
-) -> Result<ListObjectsV2Output, Box<dyn std::error::Error>> {
-    // Create S3 client with proper credentials
-    let credentials_provider = aws_sdk_s3::config::Credentials::new(
-        &config.access_key_id,
-        &config.access_key_secret,
-        None,
-        None,
-        "Static",
-    );
+// libgit2 neither honors sparse-checkout nor runs filter drivers such as Git
+// LFS's, so those checkouts still go through git
+fn needs_git_checkout(repo: &Repository) -> Result<bool, git2::Error> {
+    let config = repo.config()?;
+    if config.get_bool("core.sparseCheckout").unwrap_or(false) {
+        return Ok(true);
+    }
+    let mut filters = config.entries(Some(r"filter\..*\.(smudge|process)"))?;
+    Ok(filters.next().is_some())
+}
+
+// The directory holding the objects and refs every worktree shares;
+// `repo.path()` itself except in linked worktrees
+fn git_common_dir(repo: &Repository) -> PathBuf {
+    let git_dir = repo.path();
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(relative) => git_dir.join(relative.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+// Returns the name of the promisor remote if the repository is a partial clone
+fn find_promisor_remote(repo: &Repository) -> Result<Option<String>, git2::Error> {
+    let config = repo.config()?;
 
-    let region = Region::new("cn-beijing"); // Consider making region configurable
-    let s3_config = aws_sdk_s3::Config::builder()
-        .region(region)
-        .endpoint_url(&config.endpoint)
-        .credentials_provider(credentials_provider)
-        .build();
+    if let Ok(remote) = config.get_string("extensions.partialclone") {
+        return Ok(Some(remote));
+    }
 
-    let client = Client::from_conf(s3_config);
+    // Newer git versions mark each promisor remote individually
+    let mut entries = config.entries(Some(r"remote\..*\.promisor"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let is_promisor = entry
+            .value()
+            .map(|v| matches!(v, "true" | "yes" | "on" | "1"))
+            .unwrap_or(false);
+        if is_promisor {
+            let name = entry.name().unwrap_or_default();
+            let remote = name
+                .trim_start_matches("remote.")
+                .trim_end_matches(".promisor");
+            return Ok(Some(remote.to_string()));
+        }
+    }
 
-    // List objects in the bucket
-    let resp = client
-        .list_objects_v2()
-        .bucket(&config.bucket_name)
-        .send()
-        .await?;
+    Ok(None)
+}
 
-    Ok(resp)
+// Lists every object under `prefix`
+fn list_objects_with_prefix(
+    config: &OssConfig,
+    prefix: &str,
+) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>> {
+    storage::backend(config).list(prefix)
 }
 
-fn cmd_ls(long: bool) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse config from the included string
-    let config: Config = toml::from_str(CONFIG_TOML)?;
+// Checks content after decryption and decoding against the checksum recorded
+// when it was uploaded
+fn verify_plaintext(checksum: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    match hash::verify_checksum(checksum, data)? {
+        true => println!("{}", t!("checksum.plaintext_verified", checksum)),
+        false => println!("{}", t!("checksum.unknown", checksum)),
+    }
+    Ok(())
+}
 
-    // Create a tokio runtime for async operations
-    let rt = Runtime::new()?;
+fn delete_object(config: &OssConfig, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    config.ensure_writable()?;
+    storage::backend(config).delete(key)
+}
 
-    // Use the runtime to execute our async function
-    rt.block_on(async {
-        println!("Listing files in bucket: {}", config.oss.bucket_name);
+fn cmd_ls(long: bool, snapshots: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // Load config from the config file (or the embedded fallback)
+    let config = Config::load()?;
 
-        // List files
-        let list_output = list_files_in_bucket(&config.oss).await?;
+    println!("Listing files in bucket: {}", config.oss.bucket_name);
+    let objects = list_objects_with_prefix(&config.oss, "")?;
+    if objects.is_empty() {
+        println!("Bucket is empty.");
+        return Ok(());
+    }
 
-        if let Some(contents) = list_output.contents {
-            if contents.is_empty() {
-                println!("Bucket is empty.");
-                return Ok(());
+    let mut pack_keys = Vec::new();
+    println!("Files:");
+    for object in objects {
+        let key = object.key;
+        if let Some(pack_key) = key.strip_suffix(".manifest") {
+            match pack_key.ends_with(".bundle") {
+                true => pack_keys.push(pack_key.to_string()),
+                false => pack_keys.push(format!("{}.pack", pack_key)),
             }
-            println!("Files:");
-            // Use futures::future::join_all for potential concurrency if needed
-            for object in contents {
-                if let Some(key) = object.key {
-                    if long {
-                        // Generate presigned URL (30 minutes = 1800 seconds)
-                        match generate_presigned_url(&config.oss, &key, 1800).await {
-                            Ok(url) => println!(" - {}: {}", key, url),
-                            Err(e) => eprintln!("   Error generating URL for {}: {}", key, e),
-                        }
-                    } else {
-                        println!(" - {}", key)
-                    }
+        }
+        if long {
+            // Generate presigned URL (30 minutes = 1800 seconds)
+            match presigned_url(&config.oss, &key, 1800) {
+                Ok(url) => println!(" - {}: {}", key, url),
+                Err(e) => eprintln!("   Error generating URL for {}: {}", key, e),
+            }
+            if key.ends_with(".pack") {
+                match head_object(&config.oss, &key) {
+                    Ok(Some(RemoteObjectInfo {
+                        pack: Some(pack), ..
+                    })) => println!("   {}", pack),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("   Error reading metadata of {}: {}", key, e),
                 }
             }
         } else {
-            println!("Bucket is empty or no contents found.");
+            println!(" - {}", key)
         }
+    }
 
-        Ok::<(), Box<dyn std::error::Error>>(()) // Ensure the async block returns the correct type
-    })?; // Add ? to propagate errors from the async block
+    if snapshots && !pack_keys.is_empty() {
+        println!("Snapshots:");
+        for pack_key in &pack_keys {
+            println!(" - {}", pack_key);
+            match fetch_manifest(&config, pack_key) {
+                Ok(Some(manifest)) => print_snapshot_summary(&manifest.into()),
+                Ok(None) => println!("  (manifest disappeared)"),
+                Err(e) => println!("  (unreadable manifest: {})", e),
+            }
+        }
+    }
 
     Ok(())
 }
 
-fn cmd_get(object_key: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse config from the included string
-    let config: Config = toml::from_str(CONFIG_TOML)?;
+fn cmd_get(
+    object_key: &str,
+    link_lifetime: std::time::Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Load config from the config file (or the embedded fallback)
+    let config = Config::load()?;
+    let object_key = &object_url::resolve(&config.oss, object_key)?;
 
-    println!("Downloading object: {}", object_key);
+    println!("{}", t!("get.downloading", object_key));
 
     // Download the file data using the existing function
-    let data = download_pack_from_s3(&config.oss, object_key)?;
+    let mut data = download_pack(&config.oss, object_key)?;
+    let info = head_object(&config.oss, object_key)?;
+
+    // Shares made with `s --compress` are stored encoded; save what the
+    // browser would have shown
+    if let Some(encoding) = info
+        .as_ref()
+        .and_then(|info| info.content_encoding.as_deref())
+        .and_then(ContentEncoding::from_header)
+    {
+        data = encoding.decode(&data)?;
+        println!(
+            "{}",
+            t!(
+                "get.decoded",
+                encoding.name(),
+                units::format_size(data.len() as u64)
+            )
+        );
+    }
+    if let Some(checksum) = info
+        .as_ref()
+        .and_then(|info| info.plaintext_checksum.as_deref())
+    {
+        verify_plaintext(checksum, &data)?;
+    }
 
-    // Extract the filename from the object key
-    let file_name = Path::new(object_key)
-        .file_name()
-        .ok_or_else(|| format!("Could not extract filename from object key: {}", object_key))?
-        .to_string_lossy()
-        .to_string(); // Convert Cow<str> to String
+    // Prefer the name recorded by `s`, else take it from the object key
+    let key_name = object_key
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| format!("Could not extract filename from object key: {}", object_key))?;
+    let file_name = info
+        .and_then(|info| info.original_name)
+        .filter(|name| !name.is_empty())
+        .map(|name| names::decode(&name))
+        .unwrap_or_else(|| names::decode(key_name));
+    if Path::new(&file_name).file_name() != Some(file_name.as_os_str()) {
+        return Err(t!("get.unsafe_name", format!("{:?}", file_name)).into());
+    }
 
     // Construct the local path in the current directory
     let local_path = std::env::current_dir()?.join(&file_name);
 
-    println!("Saving to local path: {}", local_path.display());
+    println!("{}", t!("get.saving", local_path.display()));
 
     // Save the file to the current directory
     std::fs::write(&local_path, data)?;
 
-    println!(
-        "File '{}' downloaded successfully to {}",
-        object_key,
-        local_path.display()
+    println!("{}", t!("get.downloaded", object_key, local_path.display()));
+
+    // Generate a pre-signed URL for the downloaded file
+    match presigned_url(&config.oss, object_key, link_lifetime.as_secs()) {
+        Ok(url) => println!(
+            "{}",
+            t!("link.valid_for", units::format_duration(link_lifetime), url)
+        ),
+        Err(e) => eprintln!("{}", t!("get.link_failed", e)),
+    }
+
+    Ok(())
+}
+
+fn cmd_config(action: &ConfigAction) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::config_file_path()?;
+
+    match action {
+        ConfigAction::Init => {
+            let mut table = config::read_table(&path)?;
+            println!("Writing configuration to {}", path.display());
+            println!("Press Enter to keep the current value shown in brackets.");
+
+            let fields = [
+                ("oss.Endpoint", "Endpoint URL", false),
+                ("oss.BucketName", "Bucket name", false),
+                ("oss.AccessKeyId", "Access key ID", false),
+                ("oss.AccessKeySecret", "Access key secret", true),
+            ];
+
+            for (key, label, secret) in fields {
+                let current = config::get_key(&table, key)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+
+                let input = if secret {
+                    let hint = if current.is_empty() {
+                        ""
+                    } else {
+                        " [********]"
+                    };
+                    rpassword::prompt_password(format!("{}{}: ", label, hint))?
+                } else {
+                    let hint = if current.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [{}]", current)
+                    };
+                    print!("{}{}: ", label, hint);
+                    std::io::stdout().flush()?;
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                    line
+                };
+
+                let input = input.trim();
+                if !input.is_empty() {
+                    config::set_key(&mut table, key, toml::Value::String(input.to_string()))?;
+                } else if current.is_empty() {
+                    return Err(format!("{} is required", label).into());
+                }
+            }
+
+            config::write_table(&path, &table)?;
+            println!("Configuration saved to {}", path.display());
+        }
+        ConfigAction::Set { key, value } => {
+            let mut table = config::read_table(&path)?;
+            config::set_key(&mut table, key, config::parse_value(value))?;
+            config::write_table(&path, &table)?;
+            println!("Set {} in {}", key, path.display());
+        }
+        ConfigAction::StoreSecret => {
+            let mut table = config::read_table(&path)?;
+            let access_key_id = std::env::var("SYNC_ACCESS_KEY_ID")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .or_else(|| {
+                    config::get_key(&table, "oss.AccessKeyId")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string)
+                })
+                .ok_or("oss.AccessKeyId must be configured before storing its secret")?;
+
+            // Move an existing plaintext secret, otherwise ask for it
+            let existing = config::get_key(&table, "oss.AccessKeySecret")
+                .and_then(|v| v.as_str())
+                .filter(|v| !v.is_empty())
+                .map(str::to_string);
+            let secret = match existing {
+                Some(secret) => secret,
+                None => rpassword::prompt_password("Access key secret: ")?,
+            };
+            if secret.trim().is_empty() {
+                return Err("Access key secret must not be empty".into());
+            }
+
+            config::keyring_entry(&access_key_id)?.set_password(secret.trim())?;
+
+            if let Some(oss) = table.get_mut("oss").and_then(|v| v.as_table_mut()) {
+                oss.remove("AccessKeySecret");
+            }
+            config::set_key(
+                &mut table,
+                "oss.SecretStorage",
+                toml::Value::String("keyring".to_string()),
+            )?;
+            config::write_table(&path, &table)?;
+
+            println!(
+                "Secret for {} stored in the system keyring; removed it from {}",
+                access_key_id,
+                path.display()
+            );
+        }
+        ConfigAction::ForgetSecret => {
+            let mut table = config::read_table(&path)?;
+            let access_key_id = config::get_key(&table, "oss.AccessKeyId")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+                .or_else(|| std::env::var("SYNC_ACCESS_KEY_ID").ok())
+                .ok_or("oss.AccessKeyId is not configured")?;
+
+            match config::keyring_entry(&access_key_id)?.delete_password() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if let Some(oss) = table.get_mut("oss").and_then(|v| v.as_table_mut()) {
+                oss.remove("SecretStorage");
+            }
+            config::write_table(&path, &table)?;
+
+            println!(
+                "Secret for {} removed from the system keyring; set oss.AccessKeySecret or \
+                 SYNC_ACCESS_KEY_SECRET to keep using static credentials",
+                access_key_id
+            );
+        }
+        ConfigAction::StorePassphrase => {
+            let passphrase = rpassword::prompt_password("Pack passphrase: ")?;
+            if passphrase.is_empty() {
+                return Err("Passphrase must not be empty".into());
+            }
+            if rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+                return Err("Passphrases do not match".into());
+            }
+
+            config::passphrase_keyring_entry()?.set_password(&passphrase)?;
+            println!("Pack passphrase stored in the system keyring");
+        }
+        ConfigAction::ForgetPassphrase => {
+            match config::passphrase_keyring_entry()?.delete_password() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(e.into()),
+            }
+            println!("Pack passphrase removed from the system keyring");
+        }
+        ConfigAction::Encrypt => {
+            if config::is_encrypted_file(&path) {
+                return Err(format!("{} is already encrypted", path.display()).into());
+            }
+            let table = config::read_table(&path)?;
+
+            let passphrase = rpassword::prompt_password("New config passphrase: ")?;
+            if passphrase.is_empty() {
+                return Err("Passphrase must not be empty".into());
+            }
+            if rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+                return Err("Passphrases do not match".into());
+            }
+
+            config::write_table_encrypted(&path, &table, &passphrase)?;
+            println!("Encrypted {}", path.display());
+        }
+        ConfigAction::Decrypt => {
+            if !config::is_encrypted_file(&path) {
+                return Err(format!("{} is not encrypted", path.display()).into());
+            }
+            let table = config::read_table(&path)?;
+            config::write_table_plain(&path, &table)?;
+            config::forget_session_key();
+            println!("Decrypted {}", path.display());
+        }
+        ConfigAction::Lock => {
+            config::forget_session_key();
+            println!("Config passphrase forgotten for this session");
+        }
+        ConfigAction::Get { key } => {
+            let table = config::read_table(&path)?;
+            match config::get_key(&table, key) {
+                Some(toml::Value::String(value)) => println!("{}", value),
+                Some(value) => println!("{}", value),
+                None => return Err(format!("{} is not set in {}", key, path.display()).into()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_autosave(
+    every: &str,
+    keep: usize,
+    max_age: &str,
+    once: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let interval = units::parse_duration(every)?;
+    if interval.is_zero() && !once {
+        return Err("--every must be greater than zero".into());
+    }
+    let max_age = units::parse_duration(max_age)?;
+    Config::load()?.oss.ensure_writable()?;
+
+    // Tree of the last snapshot, so unchanged work isn't uploaded again
+    let mut last_tree = None;
+
+    loop {
+        // A deadline bounds each snapshot, not the whole watcher
+        deadline::restart();
+        match autosave_snapshot(&mut last_tree, keep, max_age) {
+            Ok(()) => {}
+            // A failed snapshot shouldn't stop the safety net from retrying later
+            Err(e) if !once => eprintln!("Autosave failed: {}", e),
+            Err(e) => return Err(e),
+        }
+
+        if once {
+            return Ok(());
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+fn autosave_snapshot(
+    last_tree: &mut Option<git2::Oid>,
+    keep: usize,
+    max_age: std::time::Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(std::env::current_dir()?)?;
+    let config = Config::load_for_repo(&repo)?;
+    deadline::set_default("autosave", &config.sync)?;
+    let _lock = lock::repository(&repo)?;
+
+    // Compared before committing anything, so an idle repository isn't
+    // littered with a stack of temporary commits every tick
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+    let tree = layers::tree(&repo, head.as_ref(), Snapshot::DEFAULT)?;
+    if *last_tree == Some(tree) {
+        println!("No changes since the last autosave, skipping");
+        return Ok(());
+    }
+
+    let pack = create_pack(&repo, Snapshot::DEFAULT, None, None)?;
+    // The pack holds everything the snapshot needs
+    remove_temporary_commits(&repo)?;
+
+    let repo_info = extract_repo_info(&repo)?;
+    let snapshot_prefix = config.sync.object_key(&format!(
+        "autosave/{}/{}/",
+        repo_info.key_dir(),
+        names::key_segment(&pack.branch_name)
+    ));
+    // Where snapshots went before names were encoded; they are pruned along
+    // with the new ones
+    let legacy_prefix = config.sync.object_key(&format!(
+        "autosave/{}/{}/",
+        repo_info.legacy_key_dir(),
+        pack.branch_name
+    ));
+
+    // Timestamped keys sort chronologically, which retention relies on
+    let snapshot_key = format!(
+        "{}{}-{}.pack",
+        snapshot_prefix,
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ"),
+        &pack.commit_oid.to_string()[..12]
     );
 
-    // Create a tokio runtime for the async presigned URL generation
-    let rt = Runtime::new()?;
+    // Same layout as `up`: commit SHA followed by the pack
+    let mut payload = pack.commit_oid.to_string().into_bytes();
+    payload.extend_from_slice(&pack.data);
+    let payload = seal_payload(&config.sync, payload, Some(&snapshot_key))?;
+
+    let pack_metadata =
+        PackMetadata::new(pack.commit_oid, pack.base_oid, &pack.branch_name).to_headers();
+    let repo_name = repo_info.to_string();
+    upload_object(
+        &config.oss,
+        &snapshot_key,
+        payload,
+        config.sync.hash_algorithm,
+        &ObjectHeaders {
+            metadata: pack_metadata
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect(),
+            tags: pack_tags(&repo_name, &pack.branch_name),
+            ..Default::default()
+        },
+    )?;
+    println!("Autosaved snapshot: {}", snapshot_key);
+    *last_tree = Some(pack.tree_oid);
+
+    prune_autosaves(
+        &config.oss,
+        &[snapshot_prefix, legacy_prefix],
+        keep,
+        max_age,
+    )
+}
 
-    // Use the runtime to generate and print the presigned URL
-    rt.block_on(async {
-        // Generate a pre-signed URL for the downloaded file (expires in 48 hours)
-        match generate_presigned_url(&config.oss, object_key, 3600 * 48).await {
-            Ok(url) => println!("Download URL (valid for 48 hours): {}", url),
-            Err(e) => eprintln!("   Error generating download URL: {}", e),
+// Keeps the newest `keep` snapshots under `prefixes` and drops anything
+// older than `max_age`
+fn prune_autosaves(
+    config: &OssConfig,
+    prefixes: &[String],
+    keep: usize,
+    max_age: std::time::Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut snapshots = Vec::new();
+    for (index, prefix) in prefixes.iter().enumerate() {
+        // Legacy prefixes are the same when nothing needed encoding
+        if !prefixes[..index].contains(prefix) {
+            snapshots.extend(list_objects_with_prefix(config, prefix)?);
+        }
+    }
+    // By file name, which starts with the time, whatever the prefix
+    let file_name = |key: &str| key.rsplit('/').next().unwrap_or(key).to_string();
+    snapshots.sort_by_key(|snapshot| std::cmp::Reverse(file_name(&snapshot.key)));
+
+    let now = chrono::Utc::now().timestamp();
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let expired = !max_age.is_zero()
+            && snapshot
+                .last_modified
+                .map(|t| now - t > max_age.as_secs() as i64)
+                .unwrap_or(false);
+
+        // Never delete the snapshot that was just written
+        if index > 0 && (index >= keep || expired) {
+            delete_object(config, &snapshot.key)?;
+            println!("Pruned old autosave: {}", snapshot.key);
         }
-        // The async block needs to return a Result compatible type, even if it's just Ok(()) for success
-        Ok::<(), Box<dyn std::error::Error>>(())
-    })?; // Propagate potential errors from the async block
+    }
 
     Ok(())
 }
@@ -0,0 +1,318 @@
+use git2::{ConfigLevel, Repository};
+use serde::{Deserialize, Serialize};
+
+/// Describes an uploaded pack. Stored encrypted next to the pack as
+/// `<pack key without .pack>.manifest` so it can be read without the pack.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Manifest {
+    pub commit: String,
+    pub branch: String,
+    /// Uploader in team mode (`sync.User`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_config: Option<GitConfigSnapshot>,
+    /// Objects in the pack (absent in manifests from older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_count: Option<usize>,
+    /// Commits ahead of the upstream branch, not counting the staged changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_count: Option<usize>,
+    /// Subjects of the most recent of those commits, newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<String>,
+    /// Local tags on the packed commits, recreated by `down`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<PackedTag>,
+    /// Packs of submodules with work the other machine doesn't have
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submodules: Vec<SubmodulePack>,
+    /// Git LFS objects behind pointers among the changes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lfs: Vec<LfsObject>,
+    /// This device's signature over the pack and the fields above
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<PackSignature>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackSignature {
+    /// Hex Ed25519 public key of the uploading device
+    pub device: String,
+    /// Hex Ed25519 signature
+    pub signature: String,
+}
+
+/// Number of commit subjects kept in [`Manifest::log`]
+pub const LOG_LENGTH: usize = 5;
+
+/// Local git configuration captured on `up` and restored on `down`, so the
+/// receiving checkout behaves the same and not just contains the same files.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct GitConfigSnapshot {
+    #[serde(default)]
+    pub entries: Vec<GitConfigEntry>,
+    /// Contents of `.git/info/sparse-checkout`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse_checkout: Option<String>,
+    /// Contents of `.git/info/exclude`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info_exclude: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GitConfigEntry {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackedTag {
+    /// Name without `refs/tags/`
+    pub name: String,
+    /// The tag object for annotated tags, otherwise the commit
+    pub target: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SubmodulePack {
+    /// Relative to the superproject's root; file names that aren't UTF-8
+    /// are percent-encoded (`names::encode`)
+    pub path: String,
+    /// Object key of the submodule's pack
+    pub key: String,
+    pub commit: String,
+    /// Branch checked out in the submodule; absent when detached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Checksum of the pack's plaintext, so the signature over this
+    /// manifest covers the submodule packs too
+    pub checksum: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LfsObject {
+    /// SHA-256 of the content, which `down` checks
+    pub oid: String,
+    pub size: u64,
+    /// Object key of the (sealed) content
+    pub key: String,
+}
+
+/// Creates the tags `up` found on the packed commits. A tag that already
+/// exists here is left alone, with a warning if it points elsewhere.
+pub fn restore_tags(
+    repo: &Repository,
+    tags: &[PackedTag],
+) -> Result<(), Box<dyn std::error::Error>> {
+    for tag in tags {
+        let ref_name = format!("refs/tags/{}", tag.name);
+        let target = git2::Oid::from_str(&tag.target)?;
+        match repo.refname_to_id(&ref_name) {
+            Ok(existing) if existing == target => {}
+            Ok(_) => println!(
+                "Warning: tag {} already exists here and points elsewhere; left as is",
+                tag.name
+            ),
+            Err(_) => {
+                repo.reference(
+                    &ref_name,
+                    target,
+                    false,
+                    "packer down: tag from another machine",
+                )?;
+                println!("Restored tag: {}", tag.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Config keys captured when no patterns are configured. `{branch}` is
+/// replaced with the (regex-escaped) current branch name.
+pub const DEFAULT_GIT_CONFIG_PATTERNS: &[&str] = &[
+    r"^branch\.{branch}\.(remote|merge|rebase)$",
+    r"^core\.sparsecheckout(cone)?$",
+];
+
+pub fn manifest_key_for(pack_key: &str) -> String {
+    format!("{}.manifest", pack_key.trim_end_matches(".pack"))
+}
+
+impl GitConfigSnapshot {
+    /// Captures the local (repository level) config entries matching `patterns`
+    /// along with the sparse-checkout and exclude files.
+    pub fn capture(
+        repo: &Repository,
+        branch: &str,
+        patterns: &[String],
+    ) -> Result<GitConfigSnapshot, git2::Error> {
+        let local_config = repo.config()?.open_level(ConfigLevel::Local)?;
+        let escaped_branch = escape_regex(branch);
+
+        let mut entries = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.replace("{branch}", &escaped_branch);
+            let mut matches = local_config.entries(Some(&pattern))?;
+            while let Some(entry) = matches.next() {
+                let entry = entry?;
+                if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+                    entries.push(GitConfigEntry {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    });
+                }
+            }
+        }
+
+        let info_dir = repo.path().join("info");
+        Ok(GitConfigSnapshot {
+            entries,
+            sparse_checkout: std::fs::read_to_string(info_dir.join("sparse-checkout")).ok(),
+            info_exclude: std::fs::read_to_string(info_dir.join("exclude")).ok(),
+        })
+    }
+
+    /// Writes the captured entries and files into the repository's local
+    /// config. The snapshot may come from anyone who can upload, so only
+    /// entries matching this machine's own `patterns` are written, never
+    /// ones that make git run a command. Sparse-checkout patterns are only
+    /// taken when `patterns` allow core.sparseCheckout, and exclude lines
+    /// are added to the local ones rather than replacing them.
+    pub fn restore(
+        &self,
+        repo: &Repository,
+        branch: &str,
+        patterns: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let allowed = allowed_names(&self.entries, branch, patterns)?;
+        let mut local_config = repo.config()?.open_level(ConfigLevel::Local)?;
+        for entry in &self.entries {
+            if runs_commands(&entry.name) || !allowed.contains(&entry.name.to_lowercase()) {
+                println!("Skipped git config: {} (not allowed here)", entry.name);
+                continue;
+            }
+            local_config.set_str(&entry.name, &entry.value)?;
+            println!("Restored git config: {} = {}", entry.name, entry.value);
+        }
+
+        let info_dir = repo.path().join("info");
+        if let Some(sparse_checkout) = &self.sparse_checkout {
+            if allowed.contains(SPARSE_CHECKOUT_KEY) {
+                std::fs::create_dir_all(&info_dir)?;
+                std::fs::write(info_dir.join("sparse-checkout"), sparse_checkout)?;
+                println!("Restored .git/info/sparse-checkout");
+            }
+        }
+        if let Some(info_exclude) = &self.info_exclude {
+            let path = info_dir.join("exclude");
+            let mut local = match std::fs::read_to_string(&path) {
+                Ok(local) => local,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+            let missing: Vec<&str> = info_exclude
+                .lines()
+                .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+                .filter(|line| !local.lines().any(|existing| existing == *line))
+                .collect();
+            if !missing.is_empty() {
+                if !local.is_empty() && !local.ends_with('\n') {
+                    local.push('\n');
+                }
+                for line in missing {
+                    local.push_str(line);
+                    local.push('\n');
+                }
+                std::fs::create_dir_all(&info_dir)?;
+                std::fs::write(&path, local)?;
+                println!("Restored .git/info/exclude");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Stands for the sparse-checkout file when matching patterns
+const SPARSE_CHECKOUT_KEY: &str = "core.sparsecheckout";
+
+// The (lowercased) names among `entries`, plus core.sparsecheckout, that
+// match one of `patterns`. They are matched by git's own regex engine, as
+// `capture` selects them, through a scratch config file.
+fn allowed_names(
+    entries: &[GitConfigEntry],
+    branch: &str,
+    patterns: &[String],
+) -> Result<std::collections::HashSet<String>, Box<dyn std::error::Error>> {
+    let file = tempfile::NamedTempFile::new()?;
+    let mut scratch = git2::Config::open(file.path())?;
+    scratch.set_str(SPARSE_CHECKOUT_KEY, "true")?;
+    for entry in entries {
+        // Anything git can't store as a key can't be restored either
+        let _ = scratch.set_str(&entry.name, &entry.value);
+    }
+    let escaped_branch = escape_regex(branch);
+    let mut allowed = std::collections::HashSet::new();
+    for pattern in patterns {
+        let pattern = pattern.replace("{branch}", &escaped_branch);
+        let mut matches = scratch.entries(Some(&pattern))?;
+        while let Some(entry) = matches.next() {
+            if let Some(name) = entry?.name() {
+                allowed.insert(name.to_lowercase());
+            }
+        }
+    }
+    Ok(allowed)
+}
+
+// Keys whose values git runs, or that pull in more config, which would let
+// an uploader run code on this machine whatever the patterns say
+fn runs_commands(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let section = name.split('.').next().unwrap_or("");
+    let key = name.rsplit('.').next().unwrap_or("");
+    matches!(
+        section,
+        "alias" | "include" | "includeif" | "filter" | "pager" | "credential" | "gpg"
+    ) || matches!(
+        name.as_str(),
+        "core.hookspath"
+            | "core.fsmonitor"
+            | "core.sshcommand"
+            | "core.editor"
+            | "core.pager"
+            | "core.askpass"
+            | "core.gitproxy"
+            | "core.worktree"
+            | "sequence.editor"
+            | "diff.external"
+            | "uploadpack.packobjectshook"
+            | "web.browser"
+    ) || matches!(
+        key,
+        "textconv"
+            | "command"
+            | "cmd"
+            | "driver"
+            | "program"
+            | "helper"
+            | "sshcommand"
+            | "receivepack"
+            | "uploadpack"
+            | "path"
+    )
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
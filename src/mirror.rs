@@ -0,0 +1,137 @@
+use crate::config::OssConfig;
+use crate::storage::{self, ListedObject, ObjectHeaders, ObjectInfo, StorageBackend, StoredObject};
+use std::time::Duration;
+
+/// oss and each of oss.Mirrors as one backend: writes go to all of them at
+/// once and succeed if any target took the object; reads go to the first
+/// target that answers, so one unreachable provider doesn't stop a `down`.
+pub struct Mirrored<'a> {
+    targets: Vec<&'a OssConfig>,
+}
+
+impl<'a> Mirrored<'a> {
+    pub fn new(config: &'a OssConfig) -> Mirrored<'a> {
+        Mirrored {
+            targets: std::iter::once(config)
+                .chain(config.mirrors.iter())
+                .collect(),
+        }
+    }
+
+    // Asks each target in turn until one has the object. `None` only if no
+    // target has it and at least one could say so.
+    fn first<T>(
+        &self,
+        action: &str,
+        op: impl Fn(&dyn StorageBackend) -> Result<Option<T>, Box<dyn std::error::Error>>,
+    ) -> Result<Option<T>, Box<dyn std::error::Error>> {
+        let mut errors = Vec::new();
+        for &target in &self.targets {
+            match op(&*storage::single(target)) {
+                Ok(Some(value)) => return Ok(Some(value)),
+                Ok(None) => {}
+                Err(e) => {
+                    println!(
+                        "Warning: {} from {} failed: {}",
+                        action,
+                        describe(target),
+                        e
+                    );
+                    errors.push(format!("{}: {}", describe(target), e));
+                }
+            }
+        }
+        match errors.len() == self.targets.len() {
+            true => Err(format!("{} failed on every target: {}", action, errors.join("; ")).into()),
+            false => Ok(None),
+        }
+    }
+}
+
+impl StorageBackend for Mirrored<'_> {
+    fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        headers: &ObjectHeaders,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let results: Vec<Result<(), String>> = std::thread::scope(|scope| {
+            let uploads: Vec<_> = self
+                .targets
+                .iter()
+                .map(|&target| {
+                    let data = data.clone();
+                    scope.spawn(move || {
+                        storage::single(target)
+                            .put(key, data, headers)
+                            .map_err(|e| e.to_string())
+                    })
+                })
+                .collect();
+            uploads
+                .into_iter()
+                .map(|upload| {
+                    upload
+                        .join()
+                        .unwrap_or_else(|_| Err("upload thread panicked".to_string()))
+                })
+                .collect()
+        });
+
+        let mut errors = Vec::new();
+        for (target, result) in self.targets.iter().zip(results) {
+            if let Err(e) = result {
+                println!("Warning: upload to {} failed: {}", describe(target), e);
+                errors.push(format!("{}: {}", describe(target), e));
+            }
+        }
+        if errors.len() == self.targets.len() {
+            return Err(format!("Upload failed on every target: {}", errors.join("; ")).into());
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn std::error::Error>> {
+        self.first("download", |backend| backend.get(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error>> {
+        self.first("head", |backend| backend.head(key))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>> {
+        Ok(self
+            .first("listing", |backend| backend.list(prefix).map(Some))?
+            .unwrap_or_default())
+    }
+
+    // Every target, so pruned autosaves don't linger on a mirror
+    fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let errors: Vec<String> = self
+            .targets
+            .iter()
+            .filter_map(|&target| {
+                storage::single(target)
+                    .delete(key)
+                    .err()
+                    .map(|e| format!("{}: {}", describe(target), e))
+            })
+            .collect();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(format!("Delete of {} failed on {}", key, errors.join("; ")).into()),
+        }
+    }
+
+    fn sign_url(&self, key: &str, expires: Duration) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self
+            .first("signing a link", |backend| {
+                backend.sign_url(key, expires).map(Some)
+            })?
+            .expect("a successful sign_url returns a link"))
+    }
+}
+
+fn describe(target: &OssConfig) -> String {
+    format!("{} {}", target.backend.name(), target.bucket_name)
+}
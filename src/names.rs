@@ -0,0 +1,101 @@
+use std::ffi::{OsStr, OsString};
+use unicode_normalization::UnicodeNormalization;
+
+/// Turns a file or host name into text for an object key. Valid UTF-8 is
+/// kept as is; bytes that aren't, and `%` itself, become `%XX` so names that
+/// differ only in their invalid bytes don't collide. Reversed by [`decode`].
+pub fn encode(name: &OsStr) -> String {
+    percent_encode(name.as_encoded_bytes(), |c| c == '%')
+}
+
+/// Like [`encode`], but also escapes non-ASCII and control characters, for
+/// object metadata that travels in HTTP headers.
+pub fn encode_ascii(name: &OsStr) -> String {
+    percent_encode(name.as_encoded_bytes(), |c| {
+        c == '%' || !c.is_ascii() || c.is_ascii_control()
+    })
+}
+
+/// Turns a branch, repository or user name into one segment of an object
+/// key. Unicode is normalized (NFC), so a name typed on macOS and on Linux
+/// gives the same key; `/` and `\` become `%2F` and `%5C`, so `feature/foo`
+/// can't run into another branch's directory or the `users/` namespace, and
+/// `.` / `..` are escaped whole. Reversed by [`decode`].
+pub fn key_segment(name: &str) -> String {
+    let normalized: String = name.nfc().collect();
+    if normalized == "." || normalized == ".." {
+        return normalized.replace('.', "%2E");
+    }
+    percent_encode(normalized.as_bytes(), |c| {
+        matches!(c, '%' | '/' | '\\') || c.is_control()
+    })
+}
+
+/// Turns a user name into one component of a ref name, such as the `alice`
+/// of `wip/alice/main`. Anything but letters, digits, `-` and `_` becomes
+/// `%XX` (a `.` too, which rules out `..`, a leading `.` and `.lock`), so
+/// the result is always valid and names never collide.
+pub fn ref_segment(name: &str) -> String {
+    let normalized: String = name.nfc().collect();
+    percent_encode(normalized.as_bytes(), |c| {
+        !(c.is_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+/// Reverses [`encode`] and [`encode_ascii`]. A `%` not followed by two hex
+/// digits is kept literally, so keys named by hand (`100%.txt`) survive.
+pub fn decode(encoded: &str) -> OsString {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| encoded.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    os_string_from_bytes(decoded)
+}
+
+fn percent_encode(bytes: &[u8], escape: impl Fn(char) -> bool) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if escape(c) {
+                let mut buf = [0u8; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    encoded.push_str(&format!("%{:02X}", byte));
+                }
+            } else {
+                encoded.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+// Windows names are UTF-16; unpaired surrogates can't be rebuilt safely from
+// their bytes and come back as U+FFFD
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
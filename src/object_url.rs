@@ -0,0 +1,61 @@
+use crate::config::OssConfig;
+use hyper::Uri;
+
+/// Turns what the user pasted into an object key. Plain keys are returned
+/// as is; `http(s)://` URLs, including presigned links printed by `up` and
+/// `s`, must point into the configured bucket, either virtual-hosted
+/// (`https://<bucket>.<endpoint>/<key>`) or path-style
+/// (`https://<endpoint>/<bucket>/<key>`). The query string is ignored.
+pub fn resolve(oss: &OssConfig, key_or_url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    if !(key_or_url.starts_with("https://") || key_or_url.starts_with("http://")) {
+        return Ok(key_or_url.to_string());
+    }
+
+    let url: Uri = key_or_url
+        .parse()
+        .map_err(|e| format!("Invalid object URL {}: {}", key_or_url, e))?;
+    let host = url
+        .host()
+        .ok_or_else(|| format!("Object URL {} has no host", key_or_url))?
+        .to_ascii_lowercase();
+    let endpoint = endpoint_host(&oss.endpoint)
+        .ok_or("oss.Endpoint is not set, so object URLs can't be checked against it")?;
+    let bucket = oss.bucket_name.to_ascii_lowercase();
+    let path = url.path();
+
+    let key = if host == format!("{}.{}", bucket, endpoint) {
+        path.strip_prefix('/')
+    } else if host == endpoint {
+        path.strip_prefix('/')
+            .and_then(|path| path.split_once('/'))
+            .filter(|(url_bucket, _)| url_bucket.eq_ignore_ascii_case(&bucket))
+            .map(|(_, key)| key)
+    } else {
+        None
+    };
+    let key = key.filter(|key| !key.is_empty()).ok_or_else(|| {
+        format!(
+            "{} is not an object in bucket {} on {}",
+            key_or_url, oss.bucket_name, oss.endpoint
+        )
+    })?;
+
+    // Keys travel percent-encoded in URLs
+    crate::names::decode(key)
+        .into_string()
+        .map_err(|_| format!("Object URL {} does not decode to a UTF-8 key", key_or_url).into())
+}
+
+// Host of oss.Endpoint, which may be given with or without a scheme
+fn endpoint_host(endpoint: &str) -> Option<String> {
+    let endpoint = endpoint.trim();
+    let endpoint = match endpoint.contains("://") {
+        true => endpoint.to_string(),
+        false => format!("https://{}", endpoint),
+    };
+    endpoint
+        .parse::<Uri>()
+        .ok()?
+        .host()
+        .map(str::to_ascii_lowercase)
+}
@@ -0,0 +1,13 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set when stdout carries binary data (`export -`)
+static STDOUT_IS_DATA: AtomicBool = AtomicBool::new(false);
+
+/// Reserves stdout for data; [`info!`] messages go to stderr from now on.
+pub fn reserve_stdout() {
+    STDOUT_IS_DATA.store(true, Ordering::Relaxed);
+}
+
+pub fn stdout_reserved() -> bool {
+    STDOUT_IS_DATA.load(Ordering::Relaxed)
+}
@@ -0,0 +1,89 @@
+use crate::names;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+
+/// What a pack object says about itself in its metadata, so `ls --long`,
+/// `status` and `down` can describe it without downloading anything. Unlike
+/// the manifest this is not encrypted; it holds nothing the object key and
+/// the bucket's listing don't already give away, apart from the host name.
+#[derive(Serialize, Clone)]
+pub struct PackMetadata {
+    /// Commit `down` applies, including the staged changes
+    pub commit: String,
+    /// Commit the pack was cut against; `None` if it holds the whole history
+    pub base_commit: Option<String>,
+    pub branch: String,
+    pub hostname: String,
+    pub tool_version: String,
+    /// RFC 3339, UTC
+    pub uploaded_at: String,
+}
+
+impl PackMetadata {
+    pub fn new(commit: git2::Oid, base_commit: Option<git2::Oid>, branch: &str) -> PackMetadata {
+        PackMetadata {
+            commit: commit.to_string(),
+            base_commit: base_commit.map(|oid| oid.to_string()),
+            branch: branch.to_string(),
+            hostname: hostname::get()
+                .unwrap_or_else(|_| "unknown".into())
+                .to_string_lossy()
+                .to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            uploaded_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        }
+    }
+
+    /// The `x-amz-meta-`-style pairs for the upload. Branch and host names
+    /// are percent-encoded since headers only carry ASCII.
+    pub fn to_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("sync-commit", self.commit.clone()),
+            ("sync-branch", names::encode_ascii(OsStr::new(&self.branch))),
+            (
+                "sync-hostname",
+                names::encode_ascii(OsStr::new(&self.hostname)),
+            ),
+            ("sync-tool-version", self.tool_version.clone()),
+            ("sync-uploaded-at", self.uploaded_at.clone()),
+        ];
+        if let Some(base_commit) = &self.base_commit {
+            headers.push(("sync-base-commit", base_commit.clone()));
+        }
+        headers
+    }
+
+    /// Reads back [`to_headers`](Self::to_headers); `None` for objects
+    /// uploaded before packs carried metadata.
+    pub fn from_headers(metadata: &HashMap<String, String>) -> Option<PackMetadata> {
+        let field = |name: &str| metadata.get(name).cloned().unwrap_or_default();
+        let decoded = |name: &str| names::decode(&field(name)).to_string_lossy().into_owned();
+        Some(PackMetadata {
+            commit: metadata.get("sync-commit")?.clone(),
+            base_commit: metadata.get("sync-base-commit").cloned(),
+            branch: decoded("sync-branch"),
+            hostname: decoded("sync-hostname"),
+            tool_version: field("sync-tool-version"),
+            uploaded_at: field("sync-uploaded-at"),
+        })
+    }
+}
+
+fn short(sha: &str) -> &str {
+    sha.get(..12).unwrap_or(sha)
+}
+
+impl std::fmt::Display for PackMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on {}", short(&self.commit), self.branch)?;
+        if let Some(base_commit) = &self.base_commit {
+            write!(f, " (base {})", short(base_commit))?;
+        }
+        write!(
+            f,
+            ", from {} at {}, packer {}",
+            self.hostname, self.uploaded_at, self.tool_version
+        )
+    }
+}
@@ -0,0 +1,122 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
+use argon2::Argon2;
+use std::path::Path;
+use std::sync::OnceLock;
+use zeroize::Zeroizing;
+
+pub const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+/// Derives a 32-byte AES key from a passphrase with Argon2id (default parameters).
+pub fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+pub fn random_salt() -> [u8; SALT_SIZE] {
+    let mut salt = [0u8; SALT_SIZE];
+    aes_gcm::aead::rand_core::RngCore::fill_bytes(&mut OsRng, &mut salt);
+    salt
+}
+
+/// Encrypts with an already derived key. Output layout: nonce (12) | ciphertext.
+pub fn seal_with_key(
+    key: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Reverses [`seal_with_key`].
+pub fn open_with_key(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if sealed.len() < NONCE_SIZE {
+        return Err("Encrypted data too short".into());
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt(sealed[..NONCE_SIZE].into(), &sealed[NONCE_SIZE..])
+        .map_err(|_| "Decryption failed (wrong passphrase or corrupted data)".into())
+}
+
+/// Passphrase protecting pack encryption keys: SYNC_PASSPHRASE, then the
+/// system keyring (`config store-passphrase`), then a prompt. Resolved once
+/// per process so a long-running autosave only asks at startup.
+pub fn pack_passphrase() -> Result<&'static str, Box<dyn std::error::Error>> {
+    static PASSPHRASE: OnceLock<String> = OnceLock::new();
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+
+    let passphrase = match std::env::var("SYNC_PASSPHRASE") {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ => match crate::config::passphrase_keyring_entry().and_then(|entry| entry.get_password())
+        {
+            Ok(passphrase) => passphrase,
+            Err(e) => {
+                // Headless machines often have no keyring service at all
+                if !matches!(e, keyring::Error::NoEntry) {
+                    eprintln!("Could not read the system keyring ({}), asking instead", e);
+                }
+                rpassword::prompt_password("Pack passphrase: ").map_err(|e| {
+                    format!(
+                        "No pack passphrase available ({}); set SYNC_PASSPHRASE or run \
+                         `packer config store-passphrase`",
+                        e
+                    )
+                })?
+            }
+        },
+    };
+    if passphrase.is_empty() {
+        return Err("Pack passphrase must not be empty".into());
+    }
+
+    Ok(PASSPHRASE.get_or_init(|| passphrase))
+}
+
+/// Reads a 32-byte key from `path`, stored either raw or as 64 hex characters
+/// (surrounding whitespace allowed), e.g. from `openssl rand -hex 32`.
+pub fn read_key_file(path: &Path) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+    let content = Zeroizing::new(
+        std::fs::read(path)
+            .map_err(|e| format!("Failed to read key file {}: {}", path.display(), e))?,
+    );
+
+    if let Ok(key) = <[u8; 32]>::try_from(content.as_slice()) {
+        return Ok(Zeroizing::new(key));
+    }
+
+    let text = String::from_utf8_lossy(&content);
+    let hex = text.trim();
+    if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        let mut key = Zeroizing::new([0u8; 32]);
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+        }
+        return Ok(key);
+    }
+
+    Err(format!(
+        "Key file {} holds {} bytes; expected a 32-byte key (raw, or 64 hex characters)",
+        path.display(),
+        content.len()
+    )
+    .into())
+}
@@ -0,0 +1,382 @@
+use crate::config::Config;
+use crate::hash::HashAlgorithm;
+use crate::storage::ObjectHeaders;
+use crate::{units, RepoInfo};
+use git2::{BranchType, Commit, Diff, EmailCreateOptions, Oid, Repository, Signature, Time};
+use std::collections::HashSet;
+
+// How far back `down` looks for patches that are already applied
+const RECENT_COMMITS: usize = 1000;
+
+// Line `git format-patch` starts every message of the mailbox with, after
+// "From <sha>"
+const SEPARATOR_DATE: &[u8] = b" Mon Sep 17 00:00:00 2001\n";
+
+/// One message of a patch series, as `down` reads it back.
+struct Patch<'a> {
+    subject: String,
+    message: String,
+    author: Signature<'static>,
+    /// The `diff --git` part; empty for a commit that changed nothing
+    diff: &'a [u8],
+}
+
+/// Sends the commits of the current branch, or of `branch`, that the base
+/// doesn't have as a mailbox of patches, as `git format-patch` writes them.
+/// Unlike a pack it can be read, and applies to any copy of the files.
+pub fn up(
+    repo: &Repository,
+    config: &Config,
+    repo_info: &RepoInfo,
+    branch: Option<&str>,
+    base: Option<&str>,
+    name: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (branch_name, tip) = match branch {
+        Some(branch) => {
+            let tip = repo
+                .find_branch(branch, BranchType::Local)
+                .ok()
+                .and_then(|local| local.get().target())
+                .ok_or_else(|| t!("up.no_such_branch", branch))?;
+            (Some(branch.to_string()), tip)
+        }
+        None => {
+            let head = repo.head()?;
+            let branch = head.shorthand().filter(|_| head.is_branch());
+            (branch.map(str::to_string), head.peel_to_commit()?.id())
+        }
+    };
+    let base_oid = crate::resolve_base(repo, branch_name.as_deref(), base)?;
+    let name = match (name, &branch_name) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(branch)) => branch.clone(),
+        (None, None) => crate::detached_name(tip),
+    };
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(tip)?;
+    if let Some(base_oid) = base_oid {
+        revwalk.hide(base_oid)?;
+    }
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    let mut commits = Vec::new();
+    let mut merges = 0;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        // As with `git format-patch`, a merge has no single diff to send
+        match commit.parent_count() {
+            0 | 1 => commits.push(commit),
+            _ => merges += 1,
+        }
+    }
+    if merges > 0 {
+        println!("{}", t!("patch.merges_skipped", merges));
+    }
+    if commits.is_empty() {
+        return Err(t!("patch.nothing", name).into());
+    }
+    if branch.is_none() && has_changes(repo)? {
+        println!("{}", t!("patch.uncommitted"));
+    }
+
+    let mut mbox = Vec::new();
+    let mut opts = EmailCreateOptions::new();
+    for (index, commit) in commits.iter().enumerate() {
+        let parent_tree = match commit.parent_count() {
+            0 => None,
+            _ => Some(commit.parent(0)?.tree()?),
+        };
+        let mut diff_options = git2::DiffOptions::new();
+        // Binary files go as binary patches rather than "Binary files differ"
+        diff_options.show_binary(true);
+        let diff = repo.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&commit.tree()?),
+            Some(&mut diff_options),
+        )?;
+        let email = git2::Email::from_diff(
+            &diff,
+            index + 1,
+            commits.len(),
+            &commit.id(),
+            commit.summary().unwrap_or(""),
+            commit.body().unwrap_or(""),
+            &commit.author(),
+            &mut opts,
+        )?;
+        mbox.extend_from_slice(email.as_slice());
+    }
+
+    let key = format!(
+        "{}/head.mbox",
+        crate::pack_dir(config, repo_info, &name, config.sync.user())
+    );
+    if dry_run {
+        println!("{}", t!("patch.would_upload", commits.len(), key));
+        for commit in &commits {
+            println!("  {}", commit.summary().unwrap_or(""));
+        }
+        return Ok(());
+    }
+
+    // Lets `down` catch a truncated or corrupted series, as for packs
+    let plaintext_checksum = HashAlgorithm::Sha256.checksum(&mbox);
+    let data = crate::seal_payload(&config.sync, mbox, Some(&key))?;
+    let size = units::format_size(data.len() as u64);
+    let repo_name = repo_info.to_string();
+    crate::upload_object(
+        &config.oss,
+        &key,
+        data,
+        config.sync.hash_algorithm,
+        &ObjectHeaders {
+            metadata: vec![("sync-plaintext-checksum", &plaintext_checksum)],
+            tags: vec![("repo", &repo_name), ("branch", &name), ("kind", "patch")],
+            ..Default::default()
+        },
+    )?;
+    println!("{}", t!("patch.uploaded", commits.len(), size, key));
+    Ok(())
+}
+
+/// Applies the patch series stored at `key` on top of HEAD, one commit per
+/// patch with its author and message, as `git am` does. The worktree must
+/// be clean; if a patch doesn't apply, the ones before it stay applied.
+/// Series carry no device signature, so with sync.TrustedDevices set they
+/// are refused.
+pub fn down(
+    repo: &Repository,
+    config: &Config,
+    key: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.sync.trusted_devices.is_empty() {
+        return Err(t!("down.refusing", key, t!("patch.unsigned")).into());
+    }
+    println!("{}", t!("down.downloading", key));
+    let data = crate::download_pack(&config.oss, key)?;
+    let mbox = crate::open_payload(&config.sync, data, Some(key))?;
+    // Series uploaded by older versions carry no plaintext checksum
+    let remote = crate::head_object(&config.oss, key)?;
+    if let Some(checksum) = remote.and_then(|info| info.plaintext_checksum) {
+        crate::verify_plaintext(&checksum, &mbox).map_err(|e| t!("down.refusing", key, e))?;
+    }
+    let patches = split(&mbox)
+        .into_iter()
+        .map(parse)
+        .collect::<Option<Vec<_>>>()
+        .filter(|patches| !patches.is_empty())
+        .ok_or_else(|| t!("patch.malformed", key))?;
+
+    if dry_run {
+        println!("{}", t!("patch.would_apply", patches.len()));
+        for patch in &patches {
+            println!("  {}", patch.subject);
+        }
+        return Ok(());
+    }
+    if has_changes(repo)? {
+        return Err(t!("patch.dirty").into());
+    }
+
+    // Without user.name and user.email configured, the author stands in
+    let committer = repo.signature().ok();
+    let mut parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        // The series becomes the branch's first commits
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+    let present = recent_commits(repo, parent.as_ref())?;
+    let mut applied = 0;
+    for patch in &patches {
+        let id = (
+            patch.author.email().unwrap_or("").to_string(),
+            patch.author.when().seconds(),
+            patch.message.trim_end().to_string(),
+        );
+        if present.contains(&id) {
+            println!("{}", t!("patch.already_applied", patch.subject));
+            continue;
+        }
+        let tree = match &parent {
+            Some(parent) => parent.tree()?,
+            None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+        };
+        let applying = match patch.diff {
+            [] => git2::Index::new().and_then(|mut index| {
+                index.read_tree(&tree)?;
+                Ok(index)
+            }),
+            diff => Diff::from_buffer(diff).and_then(|diff| repo.apply_to_tree(&tree, &diff, None)),
+        };
+        let mut index = match applying {
+            Ok(index) => index,
+            Err(e) => {
+                // What applied so far is kept, as with `git am`
+                if applied > 0 {
+                    advance_head(repo, parent.as_ref().map(Commit::id))?;
+                }
+                return Err(t!(
+                    "patch.failed",
+                    applied + 1,
+                    patch.subject,
+                    e.message(),
+                    applied
+                )
+                .into());
+            }
+        };
+        let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+        let oid = repo.commit(
+            None,
+            &patch.author,
+            committer.as_ref().unwrap_or(&patch.author),
+            &patch.message,
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )?;
+        parent = Some(repo.find_commit(oid)?);
+        applied += 1;
+    }
+    if applied > 0 {
+        advance_head(repo, parent.as_ref().map(Commit::id))?;
+    }
+    println!("{}", t!("patch.applied", applied));
+    Ok(())
+}
+
+// Author email, time and message of the commits leading to `head`, so
+// applying a series twice doesn't commit it twice. Only the newest are
+// looked at; a series lands on top.
+fn recent_commits(
+    repo: &Repository,
+    head: Option<&Commit>,
+) -> Result<HashSet<(String, i64, String)>, git2::Error> {
+    let mut present = HashSet::new();
+    let Some(head) = head else {
+        return Ok(present);
+    };
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head.id())?;
+    for oid in revwalk.take(RECENT_COMMITS) {
+        let commit = repo.find_commit(oid?)?;
+        let author = commit.author();
+        present.insert((
+            author.email().unwrap_or("").to_string(),
+            author.when().seconds(),
+            commit.message().unwrap_or("").trim_end().to_string(),
+        ));
+    }
+    Ok(present)
+}
+
+// Whether the index or tracked files differ from HEAD
+fn has_changes(repo: &Repository) -> Result<bool, git2::Error> {
+    if repo.is_bare() {
+        return Ok(false);
+    }
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(false);
+    Ok(!repo.statuses(Some(&mut status_options))?.is_empty())
+}
+
+// Moves HEAD, or the branch it names, to `oid`, with the index and worktree
+// following outside a bare repository
+fn advance_head(repo: &Repository, oid: Option<Oid>) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(oid) = oid else {
+        return Ok(());
+    };
+    if !repo.is_bare() {
+        return crate::reset_worktree_to(repo, &oid.to_string());
+    }
+    let head = repo.find_reference("HEAD")?;
+    match head.symbolic_target() {
+        Some(branch) => {
+            repo.reference(branch, oid, true, "packer down: applied patches")?;
+        }
+        None => repo.set_head_detached(oid)?,
+    }
+    Ok(())
+}
+
+// The messages of a mailbox, each starting at its "From <sha> <date>" line
+fn split(mbox: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for line in mbox.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b"From ") && line.ends_with(SEPARATOR_DATE) {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    let ends = starts.iter().skip(1).copied().chain([mbox.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(&start, end)| &mbox[start..end])
+        .collect()
+}
+
+fn parse(email: &[u8]) -> Option<Patch<'_>> {
+    let header_end = find(email, b"\n\n")?;
+    let headers = std::str::from_utf8(&email[..header_end]).ok()?;
+    // Long headers continue on lines starting with whitespace
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    for line in headers.lines().skip(1) {
+        match (line.starts_with([' ', '\t']), fields.last_mut()) {
+            (true, Some((_, value))) => value.push_str(line),
+            _ => {
+                let (name, value) = line.split_once(": ")?;
+                fields.push((name, value.to_string()));
+            }
+        }
+    }
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+
+    let (author_name, author_email) = field("From")?.rsplit_once(" <")?;
+    let date = chrono::DateTime::parse_from_rfc2822(field("Date")?).ok()?;
+    let time = Time::new(date.timestamp(), date.offset().local_minus_utc() / 60);
+    let author = Signature::new(author_name, author_email.strip_suffix('>')?, &time).ok()?;
+    // "[PATCH 2/3] subject"
+    let subject = field("Subject")?;
+    let subject = match subject.strip_prefix('[') {
+        Some(rest) => rest.split_once("] ")?.1,
+        None => subject,
+    };
+
+    // The message ends at the "---" before the diffstat
+    let rest = &email[header_end + 2..];
+    let body_end = match rest.starts_with(b"---\n") {
+        true => 0,
+        false => find(rest, b"\n---\n")? + 1,
+    };
+    let body = std::str::from_utf8(&rest[..body_end]).ok()?.trim();
+    let message = match body.is_empty() {
+        true => format!("{}\n", subject),
+        false => format!("{}\n\n{}\n", subject, body),
+    };
+    let diff = match find(&rest[body_end..], b"\ndiff --git ") {
+        Some(start) => &rest[body_end + start + 1..],
+        None => &[],
+    };
+    Some(Patch {
+        subject: subject.to_string(),
+        message,
+        author,
+        diff,
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
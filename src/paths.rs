@@ -0,0 +1,39 @@
+use directories::ProjectDirs;
+use std::path::PathBuf;
+
+// Resolved per platform, e.g. for the config directory:
+// Linux:   $XDG_CONFIG_HOME/sync or ~/.config/sync
+// macOS:   ~/Library/Application Support/sync
+// Windows: %APPDATA%\sync\config
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from_path(PathBuf::from("sync"))
+}
+
+/// Directory holding `config.toml`.
+pub fn config_dir() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// Directory for state that can be recreated, such as the last uploaded commits.
+pub fn cache_dir() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.cache_dir().to_path_buf())
+}
+
+/// Per-user directory cleared at logout; only Linux has one.
+pub fn runtime_dir() -> Option<PathBuf> {
+    project_dirs().and_then(|dirs| dirs.runtime_dir().map(|dir| dir.to_path_buf()))
+}
+
+/// File recording the payloads last uploaded to a storage location, see `dedup`.
+pub fn dedup_index_file(location: &str) -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("dedup").join(format!("{}.toml", location)))
+}
+
+/// File recording the commit last uploaded for each branch of a repository.
+pub fn last_upload_file(author: &str, repo: &str) -> Option<PathBuf> {
+    cache_dir().map(|dir| {
+        dir.join("last-upload")
+            .join(author)
+            .join(format!("{}.toml", repo))
+    })
+}
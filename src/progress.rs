@@ -0,0 +1,74 @@
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set once from the command line before any command runs
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// One NDJSON line on stderr, e.g.
+/// `{"event":"finish","phase":"upload","bytes":1024,"total":1024}`
+#[derive(Serialize)]
+struct Event<'a> {
+    event: &'a str,
+    phase: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A phase (pack, encrypt, upload, ...) has started; `total` is the expected byte count if known.
+pub fn start(phase: &str, total: Option<u64>) {
+    emit(Event {
+        event: "start",
+        phase,
+        bytes: Some(0),
+        total,
+        message: None,
+    });
+}
+
+/// A phase has completed after processing `bytes` bytes.
+pub fn finish(phase: &str, bytes: u64) {
+    emit(Event {
+        event: "finish",
+        phase,
+        bytes: Some(bytes),
+        total: Some(bytes),
+        message: None,
+    });
+}
+
+/// The command failed; emitted once from `main` before exiting.
+pub fn error(message: &str) {
+    emit(Event {
+        event: "error",
+        phase: "command",
+        bytes: None,
+        total: None,
+        message: Some(message),
+    });
+}
+
+fn emit(event: Event) {
+    if !is_enabled() {
+        return;
+    }
+
+    if let Ok(line) = serde_json::to_string(&event) {
+        let mut stderr = std::io::stderr().lock();
+        // Progress reporting must never fail the actual operation
+        let _ = writeln!(stderr, "{}", line);
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,283 @@
+use crate::config::OssConfig;
+use crate::timeouts::{TimedStream, Timeouts};
+use base64::{engine::general_purpose, Engine as _};
+use hyper::service::Service;
+use hyper::Uri;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Longest CONNECT response we read before giving up on the proxy
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+/// Opens the TCP connections for storage requests, through oss.Proxy or,
+/// when that is empty, the usual `https_proxy` / `http_proxy` / `all_proxy`
+/// variables minus the hosts in `no_proxy`. HTTP proxies are asked to
+/// CONNECT; `socks5://` resolves names locally and `socks5h://` on the proxy.
+/// TLS runs inside the tunnel, so the proxy never sees the requests.
+/// Connections also keep to oss.ConnectTimeout and oss.ReadTimeout.
+#[derive(Clone)]
+pub struct ProxyConnector {
+    setting: String,
+    timeouts: Timeouts,
+}
+
+impl ProxyConnector {
+    pub fn new(config: &OssConfig) -> ProxyConnector {
+        ProxyConnector {
+            setting: config.proxy.trim().to_string(),
+            timeouts: Timeouts::new(config).unwrap_or_default(),
+        }
+    }
+
+    /// Never proxies, for link-local services such as cloud metadata servers.
+    pub fn direct() -> ProxyConnector {
+        ProxyConnector {
+            setting: "direct".to_string(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+/// Checks an oss.Proxy setting so a typo fails at startup rather than on
+/// the first request.
+pub fn validate(setting: &str) -> Result<(), String> {
+    match setting.trim() {
+        "" | "direct" => Ok(()),
+        url => parse(url).map(|_| ()).map_err(|e| e.to_string()),
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = TimedStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<TimedStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let setting = self.setting.clone();
+        let Timeouts {
+            connect: limit,
+            read,
+        } = self.timeouts;
+        Box::pin(async move {
+            let stream = match limit {
+                Some(limit) => tokio::time::timeout(limit, connect(setting, uri.clone()))
+                    .await
+                    .map_err(|_| {
+                        format!(
+                            "connecting to {} timed out after {} (oss.ConnectTimeout)",
+                            uri.host().unwrap_or_default(),
+                            crate::units::format_duration(limit)
+                        )
+                    })??,
+                None => connect(setting, uri).await?,
+            };
+            Ok(TimedStream::new(stream, read))
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Http,
+    Socks5,
+    Socks5h,
+}
+
+struct Proxy {
+    kind: Kind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+async fn connect(setting: String, uri: Uri) -> Result<TcpStream, BoxError> {
+    let host = uri
+        .host()
+        .ok_or_else(|| format!("{} has no host", uri))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("http") => 80,
+        _ => 443,
+    });
+
+    let stream = match proxy_for(&setting, &uri, &host)? {
+        None => TcpStream::connect((host.as_str(), port)).await?,
+        Some(proxy) if proxy.kind == Kind::Http => http_connect(&proxy, &host, port).await?,
+        Some(proxy) => {
+            let target = match proxy.kind {
+                Kind::Socks5 => tokio_socks::TargetAddr::Ip(
+                    tokio::net::lookup_host((host.as_str(), port))
+                        .await?
+                        .next()
+                        .ok_or_else(|| format!("cannot resolve {}", host))?,
+                ),
+                _ => tokio_socks::TargetAddr::Domain(host.clone().into(), port),
+            };
+            let address = (proxy.host.as_str(), proxy.port);
+            let stream = match &proxy.credentials {
+                Some((user, password)) => {
+                    tokio_socks::tcp::Socks5Stream::connect_with_password(
+                        address, target, user, password,
+                    )
+                    .await
+                }
+                None => tokio_socks::tcp::Socks5Stream::connect(address, target).await,
+            };
+            stream
+                .map_err(|e| format!("SOCKS proxy {}:{}: {}", proxy.host, proxy.port, e))?
+                .into_inner()
+        }
+    };
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+fn proxy_for(setting: &str, uri: &Uri, host: &str) -> Result<Option<Proxy>, BoxError> {
+    let url = match setting {
+        "direct" => return Ok(None),
+        "" => {
+            if bypasses_proxy(host) {
+                return Ok(None);
+            }
+            // Only the lowercase http_proxy, as curl does: HTTP_PROXY can be
+            // set by a request header in CGI environments
+            let names: &[&str] = match uri.scheme_str() {
+                Some("http") => &["http_proxy", "all_proxy", "ALL_PROXY"],
+                _ => &["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"],
+            };
+            let from_env = names
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find(|value| !value.trim().is_empty());
+            match from_env {
+                Some(url) => url,
+                None => return Ok(None),
+            }
+        }
+        url => url.to_string(),
+    };
+    parse(url.trim()).map(Some)
+}
+
+// no_proxy holds host names and domain suffixes, or `*` for everything
+fn bypasses_proxy(host: &str) -> bool {
+    let Some(list) = ["no_proxy", "NO_PROXY"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+    else {
+        return false;
+    };
+    list.split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+        })
+}
+
+fn parse(url: &str) -> Result<Proxy, BoxError> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    let kind = match scheme.to_ascii_lowercase().as_str() {
+        "http" => Kind::Http,
+        "socks5" => Kind::Socks5,
+        "socks5h" => Kind::Socks5h,
+        _ => {
+            return Err(format!(
+                "Unsupported proxy {}; use http://, socks5:// or socks5h://",
+                url
+            )
+            .into())
+        }
+    };
+
+    let authority = rest.split('/').next().unwrap_or_default();
+    let (credentials, address) = match authority.rsplit_once('@') {
+        Some((userinfo, address)) => {
+            let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+            let decode = |text| crate::names::decode(text).to_string_lossy().into_owned();
+            (Some((decode(user), decode(password))), address)
+        }
+        None => (None, authority),
+    };
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (
+            host,
+            port.parse()
+                .map_err(|_| format!("Invalid port in proxy {}", url))?,
+        ),
+        _ => (address, if kind == Kind::Http { 80 } else { 1080 }),
+    };
+    if host.is_empty() {
+        return Err(format!("Proxy {} has no host", url).into());
+    }
+
+    Ok(Proxy {
+        kind,
+        host: host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        port,
+        credentials,
+    })
+}
+
+// Asks an HTTP proxy for a tunnel to host:port
+async fn http_connect(proxy: &Proxy, host: &str, port: u16) -> Result<TcpStream, BoxError> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(|e| format!("cannot reach proxy {}:{}: {}", proxy.host, proxy.port, e))?;
+
+    let target = match host.contains(':') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((user, password)) = &proxy.credentials {
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            general_purpose::STANDARD.encode(format!("{}:{}", user, password))
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response head byte by byte so nothing after it is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_CONNECT_RESPONSE {
+            return Err(format!(
+                "proxy {}:{} sent an oversized response",
+                proxy.host, proxy.port
+            )
+            .into());
+        }
+        let byte = stream.read_u8().await?;
+        response.push(byte);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!(
+            "proxy {}:{} refused CONNECT {}: {}",
+            proxy.host, proxy.port, target, status
+        )
+        .into());
+    }
+    Ok(stream)
+}
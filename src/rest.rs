@@ -0,0 +1,91 @@
+use crate::config::OssConfig;
+use crate::proxy::ProxyConnector;
+use hyper::body::HttpBody;
+use hyper::{Body, Response};
+use hyper_rustls::HttpsConnector;
+use std::time::{Duration, Instant};
+
+// Helpers for the backends that speak their service's REST API directly
+// rather than through an SDK
+
+/// A client for `config`'s storage service, through its proxy if any.
+pub fn client(config: &OssConfig) -> hyper::Client<HttpsConnector<ProxyConnector>, Body> {
+    hyper::Client::builder().build(https(ProxyConnector::new(config)))
+}
+
+/// A client that never uses a proxy, for cloud metadata servers.
+pub fn direct_client() -> hyper::Client<HttpsConnector<ProxyConnector>, Body> {
+    hyper::Client::builder().build(https(ProxyConnector::direct()))
+}
+
+pub fn https(connector: ProxyConnector) -> HttpsConnector<ProxyConnector> {
+    hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(connector)
+}
+
+/// Collects a response body, no faster than --limit-rate when it is set.
+pub async fn read_body(mut body: Body) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk?);
+        if let Some(&rate) = crate::LIMIT_RATE.get() {
+            let due = Duration::from_secs_f64(data.len() as f64 / rate as f64);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+    Ok(data)
+}
+
+pub fn content_length(response: &Response<Body>) -> Option<u64> {
+    response
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Percent-encodes everything but unreserved characters and those in `keep`.
+pub fn encode(text: &str, keep: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric()
+            || b"-_.~".contains(&byte)
+            || keep.as_bytes().contains(&byte)
+        {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Contents of each `<tag>...</tag>` in `xml`, which is all the flat
+/// listing responses need.
+pub fn elements<'x>(xml: &'x str, tag: &str) -> Vec<&'x str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else { break };
+        found.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    found
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,125 @@
+use crate::config::{OssConfig, RetryPolicy};
+use crate::deadline;
+use crate::storage::{ListedObject, ObjectHeaders, ObjectInfo, StorageBackend, StoredObject};
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Marks an error that trying again cannot fix, such as a denied request or
+/// a missing bucket, so it is reported at once instead of after every attempt.
+#[derive(Debug)]
+pub struct Permanent(pub Box<dyn Error>);
+
+impl fmt::Display for Permanent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for Permanent {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+pub fn permanent(error: impl Into<Box<dyn Error>>) -> Box<dyn Error> {
+    Box::new(Permanent(error.into()))
+}
+
+/// Whether an HTTP status means the request itself is wrong. Timeouts and
+/// throttling are worth another try like any 5xx.
+pub fn is_permanent_status(status: u16) -> bool {
+    (400..500).contains(&status) && status != 408 && status != 429
+}
+
+/// A backend whose calls are retried with exponential backoff under
+/// oss.Retry, so one 5xx or dropped connection doesn't fail a whole sync.
+pub struct Retrying<'a> {
+    inner: Box<dyn StorageBackend + 'a>,
+    policy: RetryPolicy,
+    target: String,
+}
+
+impl<'a> Retrying<'a> {
+    pub fn new(config: &OssConfig, inner: Box<dyn StorageBackend + 'a>) -> Retrying<'a> {
+        Retrying {
+            inner,
+            policy: config.retry,
+            target: format!("{} {}", config.backend.name(), config.bucket_name),
+        }
+    }
+
+    fn run<T>(
+        &self,
+        action: &str,
+        op: impl Fn() -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let mut attempt = 1;
+        loop {
+            let error = match op() {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            if attempt >= self.policy.attempts || error.is::<Permanent>() {
+                return Err(error);
+            }
+            // Nothing left to wait for once the deadline has passed
+            deadline::check(action)?;
+
+            let delay = backoff(&self.policy, attempt);
+            println!(
+                "Warning: {} on {} failed ({}); retrying in {:.1}s ({}/{})",
+                action,
+                self.target,
+                error,
+                delay.as_secs_f64(),
+                attempt,
+                self.policy.attempts - 1
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+/// The wait after failed attempt number `attempt`: BaseDelayMs doubled per
+/// attempt up to MaxDelayMs; with jitter, a random wait between none and
+/// that ("full jitter").
+pub fn backoff(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let ceiling = policy
+        .base_delay_ms
+        .saturating_mul(1u64 << (attempt - 1).min(32))
+        .min(policy.max_delay_ms);
+    let millis = match policy.jitter && ceiling > 0 {
+        true => OsRng.next_u64() % (ceiling + 1),
+        false => ceiling,
+    };
+    Duration::from_millis(millis)
+}
+
+impl StorageBackend for Retrying<'_> {
+    fn put(&self, key: &str, data: Vec<u8>, headers: &ObjectHeaders) -> Result<(), Box<dyn Error>> {
+        self.run("upload", || self.inner.put(key, data.clone(), headers))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn Error>> {
+        self.run("download", || self.inner.get(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn Error>> {
+        self.run("head", || self.inner.head(key))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn Error>> {
+        self.run("listing", || self.inner.list(prefix))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.run("delete", || self.inner.delete(key))
+    }
+
+    fn sign_url(&self, key: &str, expires: Duration) -> Result<String, Box<dyn Error>> {
+        self.run("signing a link", || self.inner.sign_url(key, expires))
+    }
+}
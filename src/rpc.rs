@@ -0,0 +1,239 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const OPERATION_FAILED: i64 = -32000;
+
+type Output = Arc<Mutex<std::io::Stdout>>;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Serves newline-delimited JSON-RPC 2.0 on stdin/stdout until stdin closes
+/// or an `exit` request arrives.
+///
+/// Methods: `status`, `up` (`{raw, gitConfig, dryRun}`), `down`
+/// (`{branch, dryRun}`), `share` (`{file, key}`) and `exit`. While an
+/// operation runs, `progress` notifications carry the same events as
+/// `--progress-json`, and `log` notifications carry the human-readable output
+/// lines. Both include the `id` of the request.
+pub fn serve_stdio() -> Result<(), Box<dyn std::error::Error>> {
+    let out: Output = Arc::new(Mutex::new(std::io::stdout()));
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                send(
+                    &out,
+                    error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+                );
+                continue;
+            }
+        };
+
+        // Requests without an id are notifications and get no response
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Value::as_str);
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            Some("exit") => {
+                if let Some(id) = id {
+                    send(&out, success_response(id, Value::Null));
+                }
+                break;
+            }
+            Some(method) => handle(method, &params, id.as_ref().unwrap_or(&Value::Null), &out),
+            None => Err(RpcError::new(INVALID_REQUEST, "Missing method")),
+        };
+
+        if let Some(id) = id {
+            let response = match result {
+                Ok(value) => success_response(id, value),
+                Err(e) => error_response(id, e),
+            };
+            send(&out, response);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(method: &str, params: &Value, id: &Value, out: &Output) -> Result<Value, RpcError> {
+    match method {
+        "status" => {
+            let status = crate::collect_status()
+                .map_err(|e| RpcError::new(OPERATION_FAILED, e.to_string()))?;
+            serde_json::to_value(status).map_err(|e| RpcError::new(OPERATION_FAILED, e.to_string()))
+        }
+        "up" => {
+            let mut args = vec!["up".to_string()];
+            if bool_param(params, "raw") {
+                args.push("--raw".to_string());
+            }
+            if bool_param(params, "gitConfig") {
+                args.push("--git-config".to_string());
+            }
+            if bool_param(params, "dryRun") {
+                args.push("--dry-run".to_string());
+            }
+            run_subcommand(&args, id, out)
+        }
+        "down" => {
+            let mut args = vec!["down".to_string()];
+            if let Some(branch) = params.get("branch").and_then(Value::as_str) {
+                args.push("--branch".to_string());
+                args.push(branch.to_string());
+            }
+            if bool_param(params, "dryRun") {
+                args.push("--dry-run".to_string());
+            }
+            run_subcommand(&args, id, out)
+        }
+        "share" => {
+            let file = params
+                .get("file")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "share requires a `file` param"))?;
+            let mut args = vec!["s".to_string(), file.to_string()];
+            if let Some(key) = params.get("key").and_then(Value::as_str) {
+                args.push(key.to_string());
+            }
+            run_subcommand(&args, id, out)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+// Runs an operation in a child process of this binary so its human-readable
+// stdout can't corrupt the protocol stream, relaying its output as notifications
+fn run_subcommand(args: &[String], id: &Value, out: &Output) -> Result<Value, RpcError> {
+    let failed = |e: std::io::Error| RpcError::new(OPERATION_FAILED, e.to_string());
+
+    let exe = std::env::current_exe().map_err(failed)?;
+    let mut command = Command::new(exe);
+    command.arg("--progress-json");
+    if let Some(path) = crate::config::explicit_config_path() {
+        command.arg("--config").arg(path);
+    }
+    if let Some(path) = crate::config::explicit_key_file() {
+        command.arg("--key-file").arg(path);
+    }
+    if let Some(rate) = crate::LIMIT_RATE.get() {
+        command.arg("--limit-rate").arg(rate.to_string());
+    }
+    let mut child = command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let stderr_out = out.clone();
+    let stderr_id = id.clone();
+    let stderr_thread = std::thread::spawn(move || {
+        let mut last_error = None;
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            match serde_json::from_str::<Value>(&line) {
+                Ok(event) if event.get("event").is_some() => {
+                    if event["event"] == "error" {
+                        last_error = event["message"].as_str().map(str::to_string);
+                    }
+                    notify(
+                        &stderr_out,
+                        "progress",
+                        json!({ "id": stderr_id, "event": event }),
+                    );
+                }
+                _ => notify(
+                    &stderr_out,
+                    "log",
+                    json!({ "id": stderr_id, "stream": "stderr", "line": line }),
+                ),
+            }
+        }
+        last_error
+    });
+
+    let mut output = Vec::new();
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        notify(
+            out,
+            "log",
+            json!({ "id": id, "stream": "stdout", "line": line }),
+        );
+        output.push(line);
+    }
+
+    let status = child.wait().map_err(failed)?;
+    let last_error = stderr_thread.join().unwrap_or(None);
+
+    if status.success() {
+        Ok(json!({ "output": output }))
+    } else {
+        Err(RpcError::new(
+            OPERATION_FAILED,
+            last_error.unwrap_or_else(|| format!("{} failed ({})", args[0], status)),
+        ))
+    }
+}
+
+fn bool_param(params: &Value, name: &str) -> bool {
+    params.get(name).and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn success_response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+fn notify(out: &Output, method: &str, params: Value) {
+    send(
+        out,
+        json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+    );
+}
+
+fn send(out: &Output, message: Value) {
+    let mut out = out.lock().unwrap_or_else(|e| e.into_inner());
+    // A closed client pipe ends the session on the next read anyway
+    let _ = writeln!(out, "{}", message);
+    let _ = out.flush();
+}
@@ -0,0 +1,728 @@
+use crate::config::{CredentialSource, OssConfig, RetryPolicy, ServerSideEncryption};
+use crate::deadline;
+use crate::progress;
+use crate::proxy::ProxyConnector;
+use crate::rest;
+use crate::retry;
+use crate::storage::{self, ListedObject, ObjectHeaders, ObjectInfo, StorageBackend, StoredObject};
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::Region;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::put_object::builders::PutObjectFluentBuilder;
+use aws_sdk_s3::primitives::{ByteStream, DateTimeFormat};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, RequestPayer};
+use aws_sdk_s3::Client;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// S3 and S3-compatible services such as Aliyun OSS, MinIO or R2.
+pub struct S3Backend<'a> {
+    config: &'a OssConfig,
+}
+
+impl<'a> S3Backend<'a> {
+    pub fn new(config: &'a OssConfig) -> S3Backend<'a> {
+        S3Backend { config }
+    }
+}
+
+impl StorageBackend for S3Backend<'_> {
+    fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        headers: &ObjectHeaders,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let config = self.config;
+        let size = data.len() as u64;
+        progress::start("upload", Some(size));
+
+        // Create a tokio runtime for async operations
+        let rt = Runtime::new()?;
+
+        let part_size = part_size(config, size)?;
+        if size > part_size {
+            return rt.block_on(async {
+                let client = create_s3_client(config).await;
+                put_multipart(&client, config, key, data, headers, part_size).await?;
+                progress::finish("upload", size);
+                Ok(())
+            });
+        }
+
+        // Use the runtime to execute our async function
+        rt.block_on(deadline::bound("upload", async {
+            // Create S3 client with proper credentials
+            let client = create_s3_client(config).await;
+
+            // Upload the data directly from memory
+            let mut request = client
+                .put_object()
+                .bucket(&config.bucket_name)
+                .key(key)
+                .set_request_payer(request_payer(config));
+            for (key, value) in &headers.metadata {
+                request = request.metadata(*key, *value);
+            }
+            if let Some(content_type) = headers.content_type {
+                request = request.content_type(content_type);
+            }
+            if let Some(content_encoding) = headers.content_encoding {
+                request = request.content_encoding(content_encoding);
+            }
+            if config.tagging && !headers.tags.is_empty() {
+                request = request.tagging(storage::tag_query(&headers.tags));
+            }
+            let request = with_server_side_encryption(request, config);
+            let response = request.body(data.into()).send().await.map_err(classify)?;
+
+            println!("Upload response: {:?}", response);
+            progress::finish("upload", size);
+
+            Ok::<(), Box<dyn std::error::Error>>(())
+        }))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn std::error::Error>> {
+        let config = self.config;
+        // Create a tokio runtime for async operations
+        let rt = Runtime::new()?;
+
+        // Use the runtime to execute our async function
+        rt.block_on(deadline::bound("download", async {
+            // Create S3 client with proper credentials
+            let client = create_s3_client(config).await;
+
+            // Big objects come down as parallel ranges, unless --limit-rate
+            // wants them slow anyway
+            if crate::LIMIT_RATE.get().is_none() {
+                let head = client
+                    .head_object()
+                    .bucket(&config.bucket_name)
+                    .key(key)
+                    .set_request_payer(request_payer(config))
+                    .send()
+                    .await;
+                let head = match head {
+                    Ok(head) => head,
+                    Err(SdkError::ServiceError(e)) if e.err().is_not_found() => return Ok(None),
+                    Err(e) => return Err(classify(e)),
+                };
+                let size = u64::try_from(head.content_length()).unwrap_or(0);
+                let part_size = part_size(config, size)?;
+                if size > part_size {
+                    progress::start("download", Some(size));
+                    let e_tag = head.e_tag().map(str::to_string);
+                    let data = get_ranged(&client, config, key, size, e_tag, part_size).await?;
+                    progress::finish("download", size);
+                    return Ok(Some(StoredObject {
+                        data,
+                        metadata: head.metadata().cloned().unwrap_or_default(),
+                    }));
+                }
+            }
+
+            // Download the data
+            let result = client
+                .get_object()
+                .bucket(&config.bucket_name)
+                .key(key)
+                .set_request_payer(request_payer(config))
+                .send()
+                .await;
+
+            let response = match result {
+                Ok(response) => response,
+                Err(SdkError::ServiceError(e)) if e.err().is_no_such_key() => return Ok(None),
+                Err(e) => return Err(classify(e)),
+            };
+            progress::start("download", u64::try_from(response.content_length()).ok());
+
+            let metadata = response.metadata().cloned().unwrap_or_default();
+
+            // Convert the response body to bytes
+            let data = match crate::LIMIT_RATE.get() {
+                Some(&rate) => read_throttled(response.body, rate).await?,
+                None => response.body.collect().await?.into_bytes().to_vec(),
+            };
+            progress::finish("download", data.len() as u64);
+
+            Ok::<Option<StoredObject>, Box<dyn std::error::Error>>(Some(StoredObject {
+                data,
+                metadata,
+            }))
+        }))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error>> {
+        let config = self.config;
+        // Create a tokio runtime for async operations
+        let rt = Runtime::new()?;
+
+        rt.block_on(deadline::bound("head", async {
+            // Create S3 client with proper credentials
+            let client = create_s3_client(config).await;
+
+            let result = client
+                .head_object()
+                .bucket(&config.bucket_name)
+                .key(key)
+                .set_request_payer(request_payer(config))
+                .send()
+                .await;
+
+            let response = match result {
+                Ok(response) => response,
+                Err(SdkError::ServiceError(e)) if e.err().is_not_found() => return Ok(None),
+                Err(e) => return Err(classify(e)),
+            };
+
+            Ok::<Option<ObjectInfo>, Box<dyn std::error::Error>>(Some(ObjectInfo {
+                size: response.content_length(),
+                last_modified: response
+                    .last_modified()
+                    .and_then(|t| t.fmt(DateTimeFormat::DateTime).ok()),
+                content_encoding: response.content_encoding().map(str::to_string),
+                metadata: response.metadata().cloned().unwrap_or_default(),
+            }))
+        }))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>> {
+        let config = self.config;
+        let rt = Runtime::new()?;
+
+        rt.block_on(deadline::bound("listing", async {
+            let client = create_s3_client(config).await;
+
+            let mut objects = Vec::new();
+            let mut continuation_token = None;
+            loop {
+                let resp = client
+                    .list_objects_v2()
+                    .bucket(&config.bucket_name)
+                    .prefix(prefix)
+                    .set_request_payer(request_payer(config))
+                    .set_continuation_token(continuation_token)
+                    .send()
+                    .await
+                    .map_err(classify)?;
+
+                for object in resp.contents.unwrap_or_default() {
+                    if let Some(key) = object.key {
+                        objects.push(ListedObject {
+                            key,
+                            last_modified: object.last_modified.map(|t| t.secs()),
+                        });
+                    }
+                }
+
+                match resp.next_continuation_token {
+                    Some(token) if resp.is_truncated => continuation_token = Some(token),
+                    _ => break,
+                }
+            }
+
+            Ok::<_, Box<dyn std::error::Error>>(objects)
+        }))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let config = self.config;
+        let rt = Runtime::new()?;
+
+        rt.block_on(deadline::bound("delete", async {
+            let client = create_s3_client(config).await;
+            client
+                .delete_object()
+                .bucket(&config.bucket_name)
+                .key(key)
+                .set_request_payer(request_payer(config))
+                .send()
+                .await
+                .map_err(classify)?;
+            Ok::<(), Box<dyn std::error::Error>>(())
+        }))
+    }
+
+    fn sign_url(&self, key: &str, expires: Duration) -> Result<String, Box<dyn std::error::Error>> {
+        let config = self.config;
+        let rt = Runtime::new()?;
+
+        rt.block_on(async {
+            // Create S3 client with proper credentials
+            let client = create_s3_client(config).await;
+
+            // Sign with the backend's notion of "now" so a skewed local clock doesn't
+            // produce links that are already expired (or not yet valid)
+            let skew = clock_skew(config).await;
+            let start_time = if skew >= 0 {
+                std::time::SystemTime::now() + Duration::from_secs(skew as u64)
+            } else {
+                std::time::SystemTime::now() - Duration::from_secs(skew.unsigned_abs())
+            };
+
+            // Create a presigner
+            let presigning_config = aws_sdk_s3::presigning::PresigningConfig::builder()
+                .start_time(start_time)
+                .expires_in(expires)
+                .build()?;
+
+            // Generate a presigned URL for GetObject operation
+            let presigned_request = client
+                .get_object()
+                .bucket(&config.bucket_name)
+                .key(key)
+                .presigned(presigning_config)
+                .await?;
+
+            Ok::<String, Box<dyn std::error::Error>>(presigned_request.uri().to_string())
+        })
+    }
+}
+
+// Error codes for the service's own trouble, which goes away on retry
+const TRANSIENT_CODES: [&str; 5] = [
+    "InternalError",
+    "ServiceUnavailable",
+    "SlowDown",
+    "RequestTimeout",
+    "Throttling",
+];
+
+// Errors the service reports about the request itself (access denied, no
+// such bucket, bad arguments) fail at once; anything else is retried
+fn classify<E, R>(error: SdkError<E, R>) -> Box<dyn std::error::Error>
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: std::fmt::Debug + 'static,
+{
+    let permanent = match &error {
+        SdkError::ConstructionFailure(_) => true,
+        SdkError::ServiceError(e) => e
+            .err()
+            .code()
+            .is_some_and(|code| !TRANSIENT_CODES.contains(&code)),
+        _ => false,
+    };
+    match permanent {
+        true => retry::permanent(error),
+        false => error.into(),
+    }
+}
+
+/// `requester` for oss.RequesterPays, which a requester-pays bucket demands
+/// before it bills the request to us instead of its owner.
+pub fn request_payer(config: &OssConfig) -> Option<RequestPayer> {
+    config.requester_pays.then_some(RequestPayer::Requester)
+}
+
+/// Adds the oss.ServerSideEncryption headers to an upload.
+pub fn with_server_side_encryption(
+    request: PutObjectFluentBuilder,
+    config: &OssConfig,
+) -> PutObjectFluentBuilder {
+    let (encryption, kms_key_id) = server_side_encryption(config);
+    request
+        .set_server_side_encryption(encryption)
+        .set_ssekms_key_id(kms_key_id)
+}
+
+// oss.ServerSideEncryption and oss.SseKmsKeyId as the SDK takes them
+fn server_side_encryption(
+    config: &OssConfig,
+) -> (
+    Option<aws_sdk_s3::types::ServerSideEncryption>,
+    Option<String>,
+) {
+    match config.server_side_encryption {
+        ServerSideEncryption::None => (None, None),
+        ServerSideEncryption::Aes256 => {
+            (Some(aws_sdk_s3::types::ServerSideEncryption::Aes256), None)
+        }
+        ServerSideEncryption::Kms => (
+            Some(aws_sdk_s3::types::ServerSideEncryption::AwsKms),
+            Some(config.sse_kms_key_id.clone()).filter(|key_id| !key_id.is_empty()),
+        ),
+    }
+}
+
+// The smallest part S3 takes (but for the last) and the most parts per upload
+const MIN_PART_SIZE: u64 = 5 << 20;
+const MAX_PARTS: u64 = 10_000;
+const DEFAULT_PART_SIZE: u64 = 16 << 20;
+const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 4;
+
+// oss.PartSize, raised where a payload would otherwise need too many parts.
+// Payloads no bigger than one part go up in a single PutObject.
+fn part_size(config: &OssConfig, total: u64) -> Result<u64, Box<dyn std::error::Error>> {
+    let part_size = match config.part_size.trim() {
+        "" => DEFAULT_PART_SIZE,
+        value => crate::units::parse_size(value)?,
+    };
+    Ok(part_size.max(MIN_PART_SIZE).max(total.div_ceil(MAX_PARTS)))
+}
+
+// Uploads `data` as parts of `part_size`, oss.UploadConcurrency at a time,
+// each retried on its own under oss.Retry. Any failure aborts the upload so
+// the bucket isn't left holding (and billing for) orphaned parts.
+async fn put_multipart(
+    client: &Client,
+    config: &OssConfig,
+    key: &str,
+    data: Vec<u8>,
+    headers: &ObjectHeaders<'_>,
+    part_size: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (encryption, kms_key_id) = server_side_encryption(config);
+    let mut request = client
+        .create_multipart_upload()
+        .bucket(&config.bucket_name)
+        .key(key)
+        .set_request_payer(request_payer(config))
+        .set_server_side_encryption(encryption)
+        .set_ssekms_key_id(kms_key_id);
+    for (key, value) in &headers.metadata {
+        request = request.metadata(*key, *value);
+    }
+    if let Some(content_type) = headers.content_type {
+        request = request.content_type(content_type);
+    }
+    if let Some(content_encoding) = headers.content_encoding {
+        request = request.content_encoding(content_encoding);
+    }
+    if config.tagging && !headers.tags.is_empty() {
+        request = request.tagging(storage::tag_query(&headers.tags));
+    }
+    let upload_id = deadline::bound("upload", async {
+        let response = request.send().await.map_err(classify)?;
+        Ok(response
+            .upload_id()
+            .ok_or("CreateMultipartUpload returned no upload id")?
+            .to_string())
+    })
+    .await?;
+
+    let uploaded = deadline::bound("upload", async {
+        let parts = upload_parts(client, config, key, &upload_id, data, part_size).await?;
+        let response = client
+            .complete_multipart_upload()
+            .bucket(&config.bucket_name)
+            .key(key)
+            .upload_id(&upload_id)
+            .set_request_payer(request_payer(config))
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(classify)?;
+        println!("Upload response: {:?}", response);
+        Ok(())
+    })
+    .await;
+
+    if let Err(e) = uploaded {
+        let abort = client
+            .abort_multipart_upload()
+            .bucket(&config.bucket_name)
+            .key(key)
+            .upload_id(&upload_id)
+            .set_request_payer(request_payer(config))
+            .send();
+        match tokio::time::timeout(Duration::from_secs(30), abort).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(abort_error)) => {
+                return Err(format!(
+                    "{} (and aborting multipart upload {} failed: {})",
+                    e, upload_id, abort_error
+                )
+                .into())
+            }
+            Err(_) => {
+                return Err(format!(
+                    "{} (and aborting multipart upload {} timed out)",
+                    e, upload_id
+                )
+                .into())
+            }
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn upload_parts(
+    client: &Client,
+    config: &OssConfig,
+    key: &str,
+    upload_id: &str,
+    data: Vec<u8>,
+    part_size: u64,
+) -> Result<Vec<CompletedPart>, Box<dyn std::error::Error>> {
+    let concurrency = match config.upload_concurrency {
+        0 => DEFAULT_UPLOAD_CONCURRENCY,
+        n => n,
+    };
+    let data = Arc::new(data);
+    let slots = Arc::new(Semaphore::new(concurrency));
+    let part_size = part_size as usize;
+
+    // Dropping the set on the first error cancels the parts still running
+    let mut tasks = JoinSet::new();
+    for (index, start) in (0..data.len()).step_by(part_size).enumerate() {
+        let part_number = index as i32 + 1;
+        let range = start..(start + part_size).min(data.len());
+        let (client, data, slots) = (client.clone(), data.clone(), slots.clone());
+        let (bucket, key, upload_id) = (
+            config.bucket_name.clone(),
+            key.to_string(),
+            upload_id.to_string(),
+        );
+        let (payer, policy) = (request_payer(config), config.retry);
+
+        tasks.spawn(async move {
+            let _slot = slots.acquire_owned().await.map_err(|e| e.to_string())?;
+            let response = retry_part(policy, "part", part_number, || async {
+                client
+                    .upload_part()
+                    .bucket(&bucket)
+                    .key(&key)
+                    .upload_id(&upload_id)
+                    .part_number(part_number)
+                    .set_request_payer(payer.clone())
+                    .body(ByteStream::from(data[range.clone()].to_vec()))
+                    .send()
+                    .await
+                    .map_err(classify)
+            })
+            .await?;
+            let part = CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(response.e_tag().map(str::to_string))
+                .build();
+            Ok::<_, String>((part_number, part))
+        });
+    }
+
+    let mut parts = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        parts.push(joined??);
+    }
+    parts.sort_by_key(|(part_number, _)| *part_number);
+    Ok(parts.into_iter().map(|(_, part)| part).collect())
+}
+
+// One part of a multipart transfer, retried on its own under oss.Retry so a
+// failure late in a big pack doesn't start the whole transfer over
+async fn retry_part<T, F, Fut>(
+    policy: RetryPolicy,
+    what: &str,
+    number: i32,
+    op: F,
+) -> Result<T, String>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, Box<dyn std::error::Error>>>,
+{
+    let mut attempt = 1;
+    loop {
+        let (message, permanent) = match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) => (e.to_string(), e.is::<retry::Permanent>()),
+        };
+        if permanent || attempt >= policy.attempts {
+            return Err(format!("{} {} failed: {}", what, number, message));
+        }
+        tokio::time::sleep(retry::backoff(&policy, attempt)).await;
+        attempt += 1;
+    }
+}
+
+// Fetches the object as ranges of `part_size`, oss.DownloadConcurrency at a
+// time, into one buffer. Every range must come from the version the HEAD
+// saw; if the object is replaced meanwhile, the download fails (and is retried
+// as a whole) rather than stitching two versions together.
+async fn get_ranged(
+    client: &Client,
+    config: &OssConfig,
+    key: &str,
+    size: u64,
+    e_tag: Option<String>,
+    part_size: u64,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let concurrency = match config.download_concurrency {
+        0 => DEFAULT_DOWNLOAD_CONCURRENCY,
+        n => n,
+    };
+    let slots = Arc::new(Semaphore::new(concurrency));
+
+    let mut tasks = JoinSet::new();
+    for (index, start) in (0..size).step_by(part_size as usize).enumerate() {
+        let end = (start + part_size).min(size);
+        let (client, slots, e_tag) = (client.clone(), slots.clone(), e_tag.clone());
+        let (bucket, key) = (config.bucket_name.clone(), key.to_string());
+        let (payer, policy) = (request_payer(config), config.retry);
+
+        tasks.spawn(async move {
+            let _slot = slots.acquire_owned().await.map_err(|e| e.to_string())?;
+            let data = retry_part(policy, "range", index as i32 + 1, || async {
+                let response = client
+                    .get_object()
+                    .bucket(&bucket)
+                    .key(&key)
+                    .range(format!("bytes={}-{}", start, end - 1))
+                    .set_if_match(e_tag.clone())
+                    .set_request_payer(payer.clone())
+                    .send()
+                    .await
+                    .map_err(classify)?;
+                let data = response.body.collect().await?.into_bytes().to_vec();
+                match data.len() as u64 == end - start {
+                    true => Ok(data),
+                    false => {
+                        Err(format!("expected {} bytes, got {}", end - start, data.len()).into())
+                    }
+                }
+            })
+            .await?;
+            Ok::<_, String>((start, data))
+        });
+    }
+
+    let mut ranges = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        ranges.push(joined??);
+    }
+    ranges.sort_by_key(|(start, _)| *start);
+    let mut data = Vec::with_capacity(size as usize);
+    for (_, range) in ranges {
+        data.extend_from_slice(&range);
+    }
+    Ok(data)
+}
+
+// Builds an S3 client for the configured endpoint, using either the static keys
+// from the config or the standard AWS credential provider chain
+pub async fn create_s3_client(config: &OssConfig) -> Client {
+    let region = Region::new("cn-beijing");
+    // Retries happen a level up, under oss.Retry, for every backend alike
+    let builder = aws_sdk_s3::Config::builder()
+        .region(region)
+        .endpoint_url(&config.endpoint)
+        .retry_config(RetryConfig::disabled())
+        // Our connector, for oss.Proxy and for the timeouts: the SDK's own
+        // read timeout covers the whole request, so it would cut off big uploads
+        .http_connector(
+            aws_smithy_client::hyper_ext::Adapter::builder()
+                .build(rest::https(ProxyConnector::new(config))),
+        );
+
+    let s3_config = match config.credential_source {
+        CredentialSource::Static => {
+            let credentials_provider = aws_sdk_s3::config::Credentials::new(
+                &config.access_key_id,
+                &config.access_key_secret,
+                None,
+                None,
+                "Static",
+            );
+            builder.credentials_provider(credentials_provider).build()
+        }
+        CredentialSource::Chain => {
+            // Environment, ~/.aws/credentials, SSO, ECS/EC2 instance roles, ...
+            let chain = DefaultCredentialsChain::builder().build().await;
+            builder.credentials_provider(chain).build()
+        }
+    };
+
+    Client::from_conf(s3_config)
+}
+
+// Differences below this are Date header rounding and request latency
+const CLOCK_SKEW_TOLERANCE_SECS: i64 = 30;
+
+// Seconds the backend's clock is ahead of ours (negative if behind), or 0 when
+// it's within tolerance or can't be measured. Measured once per process.
+async fn clock_skew(config: &OssConfig) -> i64 {
+    static SKEW: tokio::sync::OnceCell<i64> = tokio::sync::OnceCell::const_new();
+
+    *SKEW
+        .get_or_init(|| async {
+            match measure_clock_skew(config).await {
+                Ok(skew) if skew.abs() >= CLOCK_SKEW_TOLERANCE_SECS => {
+                    println!(
+                        "Warning: local clock is {} seconds {} the storage backend; \
+                         adjusting presigned URLs to compensate",
+                        skew.abs(),
+                        if skew > 0 { "behind" } else { "ahead of" }
+                    );
+                    skew
+                }
+                Ok(_) => 0,
+                Err(e) => {
+                    println!("Could not check clock skew against the backend: {}", e);
+                    0
+                }
+            }
+        })
+        .await
+}
+
+// Compares the Date header of an unauthenticated HEAD request against the
+// local time halfway through the round trip. Any response carries a Date
+// header, so a 403 is as good as a 200 here.
+async fn measure_clock_skew(config: &OssConfig) -> Result<i64, Box<dyn std::error::Error>> {
+    let request = hyper::Request::head(&config.endpoint).body(hyper::Body::empty())?;
+
+    let sent = chrono::Utc::now();
+    let response = tokio::time::timeout(
+        Duration::from_secs(5),
+        rest::client(config).request(request),
+    )
+    .await??;
+    let received = chrono::Utc::now();
+
+    let date = response
+        .headers()
+        .get(hyper::header::DATE)
+        .ok_or("response has no Date header")?
+        .to_str()?;
+    let backend_time = chrono::DateTime::parse_from_rfc2822(date)?.with_timezone(&chrono::Utc);
+
+    let local_time = sent + (received - sent) / 2;
+    Ok((backend_time - local_time).num_seconds())
+}
+
+// Reads a response body no faster than `rate` bytes per second on average
+async fn read_throttled(
+    body: ByteStream,
+    rate: u64,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut reader = body.into_async_read();
+    let mut chunk = vec![0u8; 64 * 1024];
+    let started = std::time::Instant::now();
+    let mut data = Vec::new();
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&chunk[..read]);
+        let due = Duration::from_secs_f64(data.len() as f64 / rate as f64);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+    Ok(data)
+}
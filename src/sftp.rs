@@ -0,0 +1,294 @@
+use crate::config::OssConfig;
+use crate::progress;
+use crate::rest::encode;
+use crate::retry;
+use crate::storage::{
+    ListedObject, ObjectHeaders, ObjectInfo, Sidecar, StorageBackend, StoredObject, META_SUFFIX,
+};
+use crate::timeouts::Timeouts;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+// Exit status of the remote command when the object doesn't exist
+const NOT_FOUND: i32 = 44;
+
+/// Objects as plain files under oss.BucketName on an SSH server. Every call
+/// runs the `ssh` client in batch mode, so logins must work without a
+/// password (oss.SshKey, the agent or ~/.ssh/config), and the server needs
+/// a POSIX shell with GNU find and stat, as any Linux box has.
+pub struct SftpBackend<'a> {
+    config: &'a OssConfig,
+}
+
+impl<'a> SftpBackend<'a> {
+    pub fn new(config: &'a OssConfig) -> SftpBackend<'a> {
+        SftpBackend { config }
+    }
+
+    // `[user@]host` and the port from oss.Endpoint
+    fn destination(&self) -> Result<(String, u16), Box<dyn std::error::Error>> {
+        let endpoint = self.config.endpoint.trim().trim_end_matches('/');
+        let authority = endpoint.strip_prefix("sftp://").ok_or_else(|| {
+            retry::permanent(format!("oss.Endpoint {:?} is not an sftp:// URL", endpoint))
+        })?;
+        match authority.rsplit_once(':') {
+            Some((destination, port)) if !port.contains(']') => Ok((
+                destination.to_string(),
+                port.parse()
+                    .map_err(|_| format!("Invalid port in oss.Endpoint {:?}", endpoint))?,
+            )),
+            _ => Ok((authority.to_string(), 22)),
+        }
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!("{}/{}", self.config.bucket_name.trim_end_matches('/'), key)
+    }
+
+    // Runs `script` with sh on the server, feeding it `input`. Returns the
+    // exit status and stdout; ssh's own failures (255) are errors.
+    fn run(
+        &self,
+        script: &str,
+        input: &[u8],
+    ) -> Result<(i32, Vec<u8>), Box<dyn std::error::Error>> {
+        let (destination, port) = self.destination()?;
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes", "-p", &port.to_string()]);
+        if !self.config.ssh_key.is_empty() {
+            command.args(["-i", &self.config.ssh_key]);
+        }
+        // oss.ConnectTimeout and oss.ReadTimeout, as far as ssh has them: a
+        // server that stops answering keepalives three times is given up on
+        let timeouts = Timeouts::new(self.config).unwrap_or_default();
+        if let Some(connect) = timeouts.connect {
+            command
+                .arg("-o")
+                .arg(format!("ConnectTimeout={}", connect.as_secs().max(1)));
+        }
+        if let Some(read) = timeouts.read {
+            command
+                .arg("-o")
+                .arg(format!(
+                    "ServerAliveInterval={}",
+                    (read.as_secs() / 3).max(1)
+                ))
+                .args(["-o", "ServerAliveCountMax=3"]);
+        }
+        let mut child = command
+            .arg(&destination)
+            .arg(format!("sh -c {}", quote(script)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                retry::permanent(format!(
+                    "cannot run ssh ({}); is the OpenSSH client installed?",
+                    e
+                ))
+            })?;
+
+        // Feed stdin from another thread so a full stdout pipe can't deadlock us
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut output = Vec::new();
+        let mut chunk = vec![0u8; 64 * 1024];
+        let started = Instant::now();
+        loop {
+            let read = stdout.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            output.extend_from_slice(&chunk[..read]);
+            // --limit-rate applies to downloads; back-pressure slows ssh down
+            if let Some(&rate) = crate::LIMIT_RATE.get() {
+                let due = Duration::from_secs_f64(output.len() as f64 / rate as f64);
+                if let Some(wait) = due.checked_sub(started.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+        }
+
+        let result = child.wait_with_output()?;
+        let _ = writer.join();
+        let status = result.status.code().unwrap_or(-1);
+        if status == 255 || (status != 0 && status != NOT_FOUND) {
+            return Err(format!(
+                "ssh {} failed ({}): {}",
+                destination,
+                result.status,
+                String::from_utf8_lossy(&result.stderr).trim()
+            )
+            .into());
+        }
+        Ok((status, output))
+    }
+
+    fn read_sidecar(&self, key: &str) -> Result<Sidecar, Box<dyn std::error::Error>> {
+        let path = quote(&format!("{}{}", self.path(key), META_SUFFIX));
+        let (status, output) = self.run(
+            &format!("test -f {0} || exit {1}; cat {0}", path, NOT_FOUND),
+            &[],
+        )?;
+        // Files copied onto the server by hand have no sidecar
+        if status == NOT_FOUND {
+            return Ok(Sidecar::default());
+        }
+        Sidecar::parse(key, &output)
+    }
+}
+
+impl StorageBackend for SftpBackend<'_> {
+    fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        headers: &ObjectHeaders,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let size = data.len() as u64;
+        progress::start("upload", Some(size));
+
+        let sidecar = Sidecar::new(headers);
+        let path = self.path(key);
+        let meta_path = format!("{}{}", path, META_SUFFIX);
+        let dir = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or(".");
+        self.run(
+            &format!("mkdir -p {} && cat > {}", quote(dir), quote(&meta_path)),
+            &serde_json::to_vec(&sidecar)?,
+        )?;
+
+        // Readers never see a partly written object
+        let partial = format!("{}.part", path);
+        self.run(
+            &format!("cat > {0} && mv -f {0} {1}", quote(&partial), quote(&path)),
+            &data,
+        )?;
+        println!("Upload response: stored {}", path);
+        progress::finish("upload", size);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn std::error::Error>> {
+        let path = quote(&self.path(key));
+        progress::start("download", None);
+        let (status, data) = self.run(
+            &format!("test -f {0} || exit {1}; cat {0}", path, NOT_FOUND),
+            &[],
+        )?;
+        if status == NOT_FOUND {
+            return Ok(None);
+        }
+        progress::finish("download", data.len() as u64);
+
+        Ok(Some(StoredObject {
+            data,
+            metadata: self.read_sidecar(key)?.metadata,
+        }))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error>> {
+        let path = quote(&self.path(key));
+        let (status, output) = self.run(
+            &format!(
+                "test -f {0} || exit {1}; stat -c '%s %Y' {0}",
+                path, NOT_FOUND
+            ),
+            &[],
+        )?;
+        if status == NOT_FOUND {
+            return Ok(None);
+        }
+        let output = String::from_utf8_lossy(&output);
+        let (size, mtime) = output
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| format!("Unexpected stat output for {}: {}", key, output))?;
+
+        let sidecar = self.read_sidecar(key)?;
+        Ok(Some(ObjectInfo {
+            size: size.parse()?,
+            last_modified: chrono::DateTime::from_timestamp(mtime.parse()?, 0)
+                .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            content_encoding: sidecar.content_encoding,
+            metadata: sidecar.metadata,
+        }))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>> {
+        // Only walk the directory the prefix points into
+        let dir = prefix.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let root = self.config.bucket_name.trim_end_matches('/');
+        let (_, output) = self.run(
+            &format!(
+                "cd {} 2>/dev/null || exit 0; test -d {1} || exit 0; \
+                 find {1} -type f -printf '%T@ %P\\n'",
+                quote(root),
+                quote(if dir.is_empty() { "." } else { dir })
+            ),
+            &[],
+        )?;
+
+        let mut objects = Vec::new();
+        for line in String::from_utf8_lossy(&output).lines() {
+            let Some((mtime, relative)) = line.split_once(' ') else {
+                continue;
+            };
+            let key = match dir.is_empty() {
+                true => relative.to_string(),
+                false => format!("{}/{}", dir, relative),
+            };
+            if !key.starts_with(prefix) || key.ends_with(META_SUFFIX) || key.ends_with(".part") {
+                continue;
+            }
+            objects.push(ListedObject {
+                key,
+                last_modified: mtime.split('.').next().and_then(|secs| secs.parse().ok()),
+            });
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.path(key);
+        self.run(
+            &format!(
+                "rm -f {} {}",
+                quote(&path),
+                quote(&format!("{}{}", path, META_SUFFIX))
+            ),
+            &[],
+        )?;
+        Ok(())
+    }
+
+    // SSH has nothing like a presigned URL: oss.PublicUrl points at an HTTPS
+    // server for the directory, which controls access itself (the expiry is
+    // not enforced); otherwise the link is the scp command to fetch the file
+    fn sign_url(
+        &self,
+        key: &str,
+        _expires: Duration,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if !self.config.public_url.is_empty() {
+            return Ok(self.config.public_url.replace("{key}", &encode(key, "/")));
+        }
+        let (destination, port) = self.destination()?;
+        Ok(format!(
+            "scp -P {} {}:{} .",
+            port,
+            destination,
+            quote(&self.path(key))
+        ))
+    }
+}
+
+// Single-quotes `text` for a POSIX shell
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
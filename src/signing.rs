@@ -0,0 +1,206 @@
+use crate::config::SyncConfig;
+use crate::hash;
+use crate::manifest::{Manifest, PackSignature};
+use crate::paths;
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::path::PathBuf;
+
+// Prefix of every signed message, so a signature can't be replayed as
+// something other than a pack signature. v1 signatures covered only some
+// manifest fields and are no longer accepted.
+const DOMAIN: &str = "packer pack signature v2";
+
+/// Where this device's Ed25519 signing key (32-byte seed, hex) lives.
+pub fn device_key_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(paths::config_dir()
+        .ok_or("Could not determine the config directory")?
+        .join("device.key"))
+}
+
+/// Loads this device's signing key, creating one on first use.
+pub fn device_key() -> Result<SigningKey, Box<dyn std::error::Error>> {
+    let path = device_key_path()?;
+    if path.is_file() {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let seed = from_hex::<32>(content.trim())
+            .ok_or_else(|| format!("{} is not a device signing key", path.display()))?;
+        return Ok(SigningKey::from_bytes(&seed));
+    }
+
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    crate::config::write_private(&path, hash::to_hex(&seed) + "\n")?;
+    info!("Created device signing key: {}", path.display());
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Hex public key of this device, for other machines' `sync.TrustedDevices`.
+pub fn device_public_key() -> Result<String, Box<dyn std::error::Error>> {
+    Ok(hash::to_hex(device_key()?.verifying_key().as_bytes()))
+}
+
+/// Signs the whole of `manifest`, which must be complete but for the
+/// signature, together with the BLAKE3 digest of the plaintext payload
+/// (commit SHA + pack) with this device's key.
+pub fn sign(
+    manifest: &Manifest,
+    payload_digest: &blake3::Hash,
+) -> Result<PackSignature, Box<dyn std::error::Error>> {
+    let key = device_key()?;
+    let signature = key.sign(&message(manifest, payload_digest)?);
+    Ok(PackSignature {
+        device: hash::to_hex(key.verifying_key().as_bytes()),
+        signature: hash::to_hex(&signature.to_bytes()),
+    })
+}
+
+/// Checks that `payload`, a pack payload or a bundle, was signed by one of `sync.TrustedDevices` and
+/// returns that device's name. `None` when no devices are trusted, in which
+/// case nothing is verified.
+pub fn verify(
+    sync: &SyncConfig,
+    manifest: Option<&Manifest>,
+    payload: &[u8],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if sync.trusted_devices.is_empty() {
+        return Ok(None);
+    }
+
+    let manifest = manifest.ok_or("The pack has no manifest, so it carries no signature")?;
+    let signed = manifest
+        .signature
+        .as_ref()
+        .ok_or("The pack is not signed")?;
+    let (name, _) = sync
+        .trusted_devices
+        .iter()
+        .find(|(_, key)| key.trim().eq_ignore_ascii_case(&signed.device))
+        .ok_or_else(|| {
+            format!(
+                "The pack was signed by device {}, which is not in sync.TrustedDevices",
+                signed.device
+            )
+        })?;
+
+    let device = from_hex::<32>(&signed.device)
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or("The pack's signing key is malformed")?;
+    let signature = from_hex::<64>(&signed.signature)
+        .map(|bytes| Signature::from_bytes(&bytes))
+        .ok_or("The pack's signature is malformed")?;
+    // The digest covers the payload as uploaded; a bundle names its commit
+    // in a ref rather than up front
+    let holds_commit = match crate::bundle::tip(payload) {
+        Some(tip) => tip.to_string() == manifest.commit,
+        None => payload.starts_with(manifest.commit.as_bytes()),
+    };
+    if !holds_commit {
+        return Err("The pack doesn't hold the commit its manifest names".into());
+    }
+    device
+        .verify(&message(manifest, &blake3::hash(payload))?, &signature)
+        .map_err(|_| format!("The pack's signature from device {} does not match", name))?;
+
+    Ok(Some(name.clone()))
+}
+
+// The manifest as serialized without its signature, so every field is
+// covered, git config and LFS objects included. The verifier serializes the
+// manifest it parsed the same way; fields it doesn't know are dropped and
+// the signature fails rather than vouching for them. The payload digest
+// also covers objects in the pack that the commit doesn't reach.
+fn message(
+    manifest: &Manifest,
+    payload_digest: &blake3::Hash,
+) -> Result<Vec<u8>, toml::ser::Error> {
+    let unsigned = toml::to_string(&Manifest {
+        signature: None,
+        ..manifest.clone()
+    })?;
+    Ok(format!(
+        "{}\npayload {}\n{}",
+        DOMAIN,
+        payload_digest.to_hex(),
+        unsigned
+    )
+    .into_bytes())
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "1111111111111111111111111111111111111111";
+    const TIP: &str = "2222222222222222222222222222222222222222";
+
+    fn signed(payload: &[u8], commit: &str) -> (SyncConfig, Manifest) {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut manifest = Manifest {
+            commit: commit.to_string(),
+            branch: "main".to_string(),
+            ..Default::default()
+        };
+        let signature = key.sign(&message(&manifest, &blake3::hash(payload)).unwrap());
+        manifest.signature = Some(PackSignature {
+            device: hash::to_hex(key.verifying_key().as_bytes()),
+            signature: hash::to_hex(&signature.to_bytes()),
+        });
+        let mut sync = SyncConfig::default();
+        sync.trusted_devices.insert(
+            "laptop".to_string(),
+            hash::to_hex(key.verifying_key().as_bytes()),
+        );
+        (sync, manifest)
+    }
+
+    fn bundle() -> Vec<u8> {
+        let mut payload = format!(
+            "# v2 git bundle\n-{} base\n{} HEAD\n{} refs/heads/main\n{} refs/sync/tip\n\n",
+            BASE, BASE, BASE, TIP
+        )
+        .into_bytes();
+        payload.extend_from_slice(b"PACK");
+        payload
+    }
+
+    #[test]
+    fn verifies_signed_bundle() {
+        let payload = bundle();
+        let (sync, manifest) = signed(&payload, TIP);
+        assert_eq!(
+            verify(&sync, Some(&manifest), &payload).unwrap(),
+            Some("laptop".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_bundle_with_other_tip() {
+        let payload = bundle();
+        let (sync, manifest) = signed(&payload, BASE);
+        assert!(verify(&sync, Some(&manifest), &payload).is_err());
+    }
+
+    #[test]
+    fn verifies_signed_pack() {
+        let mut payload = TIP.as_bytes().to_vec();
+        payload.extend_from_slice(b"PACK");
+        let (sync, manifest) = signed(&payload, TIP);
+        assert_eq!(
+            verify(&sync, Some(&manifest), &payload).unwrap(),
+            Some("laptop".to_string())
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::storage::ObjectHeaders;
+use crate::{deadline, lock, units};
+use git2::{Oid, Repository};
+use std::collections::HashSet;
+
+// Payload layout: this line, one "<sha> <message>" line per stash, newest
+// first as in `git stash list`, an empty line, then the pack
+const HEADER: &str = "packer-stash v1";
+
+struct StashEntry {
+    oid: Oid,
+    message: String,
+}
+
+// Accepts `stash@{2}` as well as a bare `2`
+fn stash_index(name: &str) -> Option<usize> {
+    name.strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(name)
+        .parse()
+        .ok()
+}
+
+/// Packs the selected stash entries, all of them if none are named, along
+/// with their index and untracked-file commits, and uploads them.
+pub fn up(selected: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut repo = Repository::open(std::env::current_dir()?)?;
+    let config = Config::load_for_repo(&repo)?;
+    config.oss.ensure_writable()?;
+    deadline::set_default("stash-up", &config.sync)?;
+    let _lock = lock::repository(&repo)?;
+
+    let mut stashes = Vec::new();
+    repo.stash_foreach(|_, message, oid| {
+        stashes.push(StashEntry {
+            oid: *oid,
+            message: message.lines().next().unwrap_or_default().to_string(),
+        });
+        true
+    })?;
+    let entries: Vec<&StashEntry> = if selected.is_empty() {
+        stashes.iter().collect()
+    } else {
+        let mut indexes = Vec::new();
+        for name in selected {
+            let index = stash_index(name)
+                .filter(|index| *index < stashes.len())
+                .ok_or_else(|| t!("stash.not_found", name))?;
+            if !indexes.contains(&index) {
+                indexes.push(index);
+            }
+        }
+        indexes.sort_unstable();
+        indexes.iter().map(|index| &stashes[*index]).collect()
+    };
+    if entries.is_empty() {
+        return Err(t!("stash.empty").into());
+    }
+
+    // A stash commit's parents are the commit it was made on, the index and,
+    // with `git stash -u`, the untracked files, so walking from it collects
+    // all three. What origin already has is left out, as for `up`.
+    let mut revwalk = repo.revwalk()?;
+    for entry in &entries {
+        revwalk.push(entry.oid)?;
+    }
+    revwalk.hide_glob("refs/remotes/origin/*")?;
+    let (pack, object_count) = crate::write_walk(&repo, &mut revwalk)?;
+
+    let mut payload = format!("{}\n", HEADER);
+    for entry in &entries {
+        payload.push_str(&format!("{} {}\n", entry.oid, entry.message));
+    }
+    payload.push('\n');
+    let mut payload = payload.into_bytes();
+    payload.extend_from_slice(&pack);
+
+    // Stashes belong to the repository rather than a branch
+    let repo_info = crate::extract_repo_info(&repo)?;
+    let key = crate::repo_key(&config, &repo_info, "stash.pack");
+    let data = crate::seal_payload(&config.sync, payload, Some(&key))?;
+    let size = units::format_size(data.len() as u64);
+    let repo_name = repo_info.to_string();
+    crate::upload_object(
+        &config.oss,
+        &key,
+        data,
+        config.sync.hash_algorithm,
+        &ObjectHeaders {
+            tags: vec![("repo", &repo_name), ("kind", "stash")],
+            ..Default::default()
+        },
+    )?;
+
+    for entry in &entries {
+        println!("  {}", entry.message);
+    }
+    println!(
+        "{}",
+        t!("stash.uploaded", entries.len(), object_count, size, key)
+    );
+    Ok(())
+}
+
+/// Downloads the stashes uploaded by `stash-up` and stores them in this
+/// repository's stash, keeping their order. Entries already there are
+/// skipped, so running it twice doesn't duplicate them.
+pub fn down() -> Result<(), Box<dyn std::error::Error>> {
+    let mut repo = Repository::open(std::env::current_dir()?)?;
+    let config = Config::load_for_repo(&repo)?;
+    deadline::set_default("stash-down", &config.sync)?;
+    let _lock = lock::repository(&repo)?;
+
+    let repo_info = crate::extract_repo_info(&repo)?;
+    let key = crate::existing_repo_key(&config, &repo_info, "stash.pack")?;
+    println!("{}", t!("down.downloading", key));
+    let data = crate::download_pack(&config.oss, &key)?;
+    let payload = crate::open_payload(&config.sync, data, Some(&key))?;
+    let (entries, pack) = parse(&payload).ok_or_else(|| t!("stash.malformed", key))?;
+    crate::index_pack(&repo, pack)?;
+
+    let mut existing = HashSet::new();
+    repo.stash_foreach(|_, _, oid| {
+        existing.insert(*oid);
+        true
+    })?;
+    // `git stash store` pushes on top, so the oldest goes first
+    for entry in entries.iter().rev() {
+        if existing.contains(&entry.oid) {
+            println!("{}", t!("stash.already_present", entry.message));
+            continue;
+        }
+        let output = std::process::Command::new("git")
+            .args(["stash", "store", "-m", &entry.message])
+            .arg(entry.oid.to_string())
+            .current_dir(repo.workdir().unwrap_or(repo.path()))
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to store stash {}: {}",
+                entry.oid,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+        println!("{}", t!("stash.restored", entry.message));
+    }
+    Ok(())
+}
+
+fn parse(payload: &[u8]) -> Option<(Vec<StashEntry>, &[u8])> {
+    let end = payload.windows(2).position(|window| window == b"\n\n")?;
+    let header = std::str::from_utf8(&payload[..end]).ok()?;
+    let mut lines = header.lines();
+    if lines.next()? != HEADER {
+        return None;
+    }
+    let entries = lines
+        .map(|line| {
+            let (sha, message) = line.split_once(' ').unwrap_or((line, ""));
+            Some(StashEntry {
+                oid: Oid::from_str(sha).ok()?,
+                message: message.to_string(),
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((entries, &payload[end + 2..]))
+}
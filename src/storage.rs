@@ -0,0 +1,141 @@
+use crate::config::{Backend, OssConfig};
+use crate::rest::encode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// What `s` sets on top of the checksum for people opening the link
+#[derive(Default)]
+pub struct ObjectHeaders<'a> {
+    /// User metadata (x-amz-meta-* on S3)
+    pub metadata: Vec<(&'a str, &'a str)>,
+    pub content_type: Option<&'a str>,
+    pub content_encoding: Option<&'a str>,
+    /// Object tags (`repo`, `branch`, `kind`), set with oss.Tagging on S3
+    /// and as blob index tags on Azure; other backends have no tags
+    pub tags: Vec<(&'a str, &'a str)>,
+}
+
+/// Tags as the `k=v&k=v` query both S3 and Azure take. Characters neither
+/// service allows in tag values (branch names can hold anything) become `_`.
+pub fn tag_query(tags: &[(&str, &str)]) -> String {
+    tags.iter()
+        .map(|(name, value)| {
+            let value: String = value
+                .chars()
+                .map(
+                    |c| match c.is_ascii_alphanumeric() || " +-./:=_".contains(c) {
+                        true => c,
+                        false => '_',
+                    },
+                )
+                .take(256)
+                .collect();
+            format!("{}={}", encode(name, ""), encode(&value, ""))
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A downloaded object.
+pub struct StoredObject {
+    pub data: Vec<u8>,
+    pub metadata: HashMap<String, String>,
+}
+
+/// What a HEAD request tells about an object.
+pub struct ObjectInfo {
+    pub size: i64,
+    /// RFC 3339
+    pub last_modified: Option<String>,
+    pub content_encoding: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+pub struct ListedObject {
+    pub key: String,
+    /// Unix seconds
+    pub last_modified: Option<i64>,
+}
+
+/// Suffix of the file holding an object's [`Sidecar`], for backends that
+/// store objects as plain files.
+pub const META_SUFFIX: &str = ".sync-meta";
+
+/// Metadata and headers of a plain-file object, stored as JSON next to it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Sidecar {
+    pub metadata: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl Sidecar {
+    pub fn new(headers: &ObjectHeaders) -> Sidecar {
+        Sidecar {
+            metadata: headers
+                .metadata
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            content_encoding: headers.content_encoding.map(str::to_string),
+            content_type: headers.content_type.map(str::to_string),
+        }
+    }
+
+    pub fn parse(key: &str, json: &[u8]) -> Result<Sidecar, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_slice(json)
+            .map_err(|e| format!("Corrupted {}{}: {}", key, META_SUFFIX, e))?)
+    }
+}
+
+/// Where objects live. Implementations only move bytes; checksums,
+/// encryption and receive-only checks happen in the callers, so a new
+/// backend doesn't have to repeat them. Each call blocks until done and
+/// must not be made from inside an async runtime.
+pub trait StorageBackend {
+    fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        headers: &ObjectHeaders,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// The object, or `None` if `key` doesn't exist.
+    fn get(&self, key: &str) -> Result<Option<StoredObject>, Box<dyn std::error::Error>>;
+
+    /// Size and metadata without the content, or `None` if `key` doesn't exist.
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error>>;
+
+    /// Every object whose key starts with `prefix`, across all pages.
+    fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, Box<dyn std::error::Error>>;
+
+    fn delete(&self, key: &str) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// A URL anyone can download the object from until it expires.
+    fn sign_url(&self, key: &str, expires: Duration) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+/// The backend for the configured bucket, which also covers oss.Mirrors
+/// when there are any.
+pub fn backend(config: &OssConfig) -> Box<dyn StorageBackend + '_> {
+    match config.mirrors.is_empty() {
+        true => single(config),
+        false => Box::new(crate::mirror::Mirrored::new(config)),
+    }
+}
+
+/// The backend for `config` alone, without its mirrors, retrying failed
+/// calls under oss.Retry.
+pub fn single(config: &OssConfig) -> Box<dyn StorageBackend + '_> {
+    let backend: Box<dyn StorageBackend + '_> = match config.backend {
+        Backend::S3 => Box::new(crate::s3::S3Backend::new(config)),
+        Backend::Azure => Box::new(crate::azure::AzureBackend::new(config)),
+        Backend::Gcs => Box::new(crate::gcs::GcsBackend::new(config)),
+        Backend::Sftp => Box::new(crate::sftp::SftpBackend::new(config)),
+        Backend::Fs => Box::new(crate::fs_backend::FsBackend::new(config)),
+    };
+    Box::new(crate::retry::Retrying::new(config, backend))
+}
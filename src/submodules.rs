@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::layers::{self, Snapshot};
+use crate::manifest::SubmodulePack;
+use git2::{Oid, Repository};
+
+/// A submodule's pack before upload.
+pub struct PackedSubmodule {
+    /// Relative to the superproject's root, `/`-separated, with bytes that
+    /// aren't UTF-8 percent-encoded by `names::encode`
+    pub path: String,
+    /// Branch the submodule had checked out; `None` when detached, as
+    /// submodules usually are
+    pub branch: Option<String>,
+    /// Topmost temporary commit, as for the superproject
+    pub commit: Oid,
+    /// Commit SHA followed by the pack, like the superproject's payload
+    pub payload: Vec<u8>,
+}
+
+/// Packs every checked-out submodule, nested ones included, that has
+/// commits its origin doesn't have or changes `snapshot` takes. Without
+/// these the superproject's gitlinks would point at commits the other
+/// machine has never seen.
+pub fn pack_all(
+    repo: &Repository,
+    snapshot: Snapshot,
+) -> Result<Vec<PackedSubmodule>, Box<dyn std::error::Error>> {
+    let mut packed = Vec::new();
+    // Pathspecs name the superproject's paths, so submodules are packed whole
+    let snapshot = Snapshot {
+        paths: &[],
+        ..snapshot
+    };
+    pack_into(repo, "", snapshot, &mut packed)?;
+    Ok(packed)
+}
+
+fn pack_into(
+    repo: &Repository,
+    prefix: &str,
+    snapshot: Snapshot,
+    packed: &mut Vec<PackedSubmodule>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for submodule in repo.submodules()? {
+        let path = format!(
+            "{}{}",
+            prefix,
+            crate::names::encode(submodule.path().as_os_str())
+        );
+        let Ok(sub_repo) = submodule.open() else {
+            info!("Skipping submodule {}: not checked out", path);
+            continue;
+        };
+        if let Some(pack) = pack_one(&sub_repo, &path, snapshot)? {
+            packed.push(pack);
+        }
+        // Parents come first so `down` has checked them out before their
+        // own submodules
+        pack_into(&sub_repo, &format!("{}/", path), snapshot, packed)?;
+    }
+    Ok(())
+}
+
+fn pack_one(
+    repo: &Repository,
+    path: &str,
+    snapshot: Snapshot,
+) -> Result<Option<PackedSubmodule>, Box<dyn std::error::Error>> {
+    let head = repo.head()?;
+    let head_commit = head.peel_to_commit()?;
+    let branch = match head.is_branch() {
+        true => head.shorthand().map(str::to_string),
+        false => None,
+    };
+
+    let mut local_commits = repo.revwalk()?;
+    local_commits.push(head_commit.id())?;
+    local_commits.hide_glob("refs/remotes/origin/*")?;
+    let stack = layers::commit(repo, Some(&head_commit), snapshot)?;
+    if local_commits.next().is_none() && stack.tree == head_commit.tree_id() {
+        layers::cleanup(repo)?;
+        return Ok(None);
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(stack.tip)?;
+    revwalk.hide_glob("refs/remotes/origin/*")?;
+    let (pack, object_count) = crate::write_walk(repo, &mut revwalk)?;
+    info!("Packed submodule {}: {} objects", path, object_count);
+    // The submodule isn't locked, so its commits go as soon as they're packed
+    layers::cleanup(repo)?;
+
+    let mut payload = stack.tip.to_string().into_bytes();
+    payload.extend_from_slice(&pack);
+    Ok(Some(PackedSubmodule {
+        path: path.to_string(),
+        branch,
+        commit: stack.tip,
+        payload,
+    }))
+}
+
+/// Applies the submodule packs `up` recorded, after the superproject's own
+/// pack has set its gitlinks. Each submodule ends up on the commit, and
+/// with the changes, it had on the uploading machine.
+pub fn apply(
+    repo: &Repository,
+    config: &Config,
+    packs: &[SubmodulePack],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(workdir) = repo.workdir() else {
+        return Ok(());
+    };
+    for pack in packs {
+        let Ok(sub_repo) = Repository::open(workdir.join(crate::names::decode(&pack.path))) else {
+            println!("{}", t!("down.submodule_missing", pack.path));
+            continue;
+        };
+
+        let data = crate::download_pack(&config.oss, &pack.key)?;
+        let payload = crate::open_payload(&config.sync, data, Some(&pack.key))?;
+        crate::verify_plaintext(&pack.checksum, &payload)
+            .map_err(|e| t!("down.refusing", pack.key, e))?;
+        let sha = crate::index_pack_into_repo(&sub_repo, payload)?;
+        match &pack.branch {
+            Some(branch) => crate::switch_to_branch(&sub_repo, branch, &sha)?,
+            None => sub_repo.set_head_detached(Oid::from_str(&sha)?)?,
+        }
+        crate::checkout_pack(&sub_repo, &sha)?;
+        println!("{}", t!("down.submodule_applied", pack.path));
+    }
+    Ok(())
+}
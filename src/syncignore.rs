@@ -0,0 +1,162 @@
+use git2::Repository;
+
+// Read from the root of the working directory, like a top-level .gitignore
+const FILE_NAME: &str = ".syncignore";
+
+/// The patterns of a repository's `.syncignore`, in gitignore syntax. Local
+/// changes to matching paths stay out of packs: tracked files keep the
+/// version HEAD has and untracked ones are left behind.
+pub struct SyncIgnore {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: String,
+    /// `!pattern`: brings back what an earlier rule excluded
+    negated: bool,
+    /// `pattern/`: matches directories only
+    dir_only: bool,
+    /// Holds a `/` other than a trailing one, so it matches the whole path
+    /// rather than any file or directory name
+    anchored: bool,
+}
+
+impl SyncIgnore {
+    /// No rules when the repository is bare or has no `.syncignore`.
+    pub fn load(repo: &Repository) -> Result<SyncIgnore, std::io::Error> {
+        let content = match repo.workdir() {
+            Some(workdir) => match std::fs::read_to_string(workdir.join(FILE_NAME)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e),
+            },
+            None => String::new(),
+        };
+        Ok(SyncIgnore {
+            rules: content.lines().filter_map(Rule::parse).collect(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `path`, relative to the working directory and `/`-separated,
+    /// is excluded. As in git, nothing inside an excluded directory can be
+    /// brought back.
+    pub fn excludes(&self, path: &str) -> bool {
+        let mut dirs = path.match_indices('/').map(|(end, _)| &path[..end]);
+        dirs.any(|dir| self.matches(dir, true)) || self.matches(path, false)
+    }
+
+    // The last matching rule decides
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = line.strip_prefix('/').unwrap_or(line);
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let name = match self.anchored {
+            true => path,
+            false => path.rsplit('/').next().unwrap_or(path),
+        };
+        wildmatch(self.pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+// Glob matching as gitignore does it: `*` and `?` stop at `/`, `**` crosses
+// directories, `[...]` is a character class and `\` escapes
+fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // Zero or more whole directories
+            wildmatch(rest, text)
+                || (0..text.len()).any(|i| text[i] == b'/' && wildmatch(rest, &text[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| wildmatch(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => wildmatch(rest, text),
+            _ => false,
+        },
+        [b'[', class @ ..] => match (class_match(class, text.first().copied()), text) {
+            (Some((true, rest)), [_, text @ ..]) => wildmatch(rest, text),
+            (Some(_), _) => false,
+            // No closing bracket, so a literal one
+            (None, [b'[', text @ ..]) => wildmatch(class, text),
+            (None, _) => false,
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => wildmatch(rest, text),
+            _ => false,
+        },
+    }
+}
+
+// Matches `c` against the class after a `[`; returns whether it matched and
+// the pattern after the closing `]`, or `None` when there is none
+fn class_match(class: &[u8], c: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match class {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            // A `]` right after the `[` is part of the class
+            [b']', after @ ..] if !first => {
+                let matched = c.is_some_and(|c| c != b'/') && matched != negated;
+                return Some((matched, after));
+            }
+            [low, b'-', high, after @ ..] if *high != b']' => {
+                matched |= c.is_some_and(|c| (*low..=*high).contains(&c));
+                rest = after;
+            }
+            [member, after @ ..] => {
+                matched |= c == Some(*member);
+                rest = after;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
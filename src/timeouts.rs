@@ -0,0 +1,145 @@
+use crate::config::OssConfig;
+use hyper::client::connect::{Connected, Connection};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::{Instant, Sleep};
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// oss.ConnectTimeout and oss.ReadTimeout; `None` waits forever.
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(DEFAULT_CONNECT_TIMEOUT),
+            read: Some(DEFAULT_READ_TIMEOUT),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Both settings, checked when the config is loaded.
+    pub fn new(config: &OssConfig) -> Result<Timeouts, String> {
+        Ok(Timeouts {
+            connect: parse(
+                "oss.ConnectTimeout",
+                &config.connect_timeout,
+                DEFAULT_CONNECT_TIMEOUT,
+            )?,
+            read: parse(
+                "oss.ReadTimeout",
+                &config.read_timeout,
+                DEFAULT_READ_TIMEOUT,
+            )?,
+        })
+    }
+}
+
+// Empty for the default, 0 for no timeout
+fn parse(name: &str, value: &str, default: Duration) -> Result<Option<Duration>, String> {
+    if value.trim().is_empty() {
+        return Ok(Some(default));
+    }
+    let duration = crate::units::parse_duration(value).map_err(|e| format!("{}: {}", name, e))?;
+    Ok(Some(duration).filter(|duration| !duration.is_zero()))
+}
+
+/// A connection that fails once neither side has moved a byte for the read
+/// timeout. Unlike a limit on the whole request, this never cuts off a large
+/// pack that is still flowing, only a connection that has stalled.
+pub struct TimedStream {
+    inner: TcpStream,
+    timeout: Option<Duration>,
+    idle: Pin<Box<Sleep>>,
+}
+
+impl TimedStream {
+    pub fn new(inner: TcpStream, timeout: Option<Duration>) -> TimedStream {
+        let idle = Box::pin(tokio::time::sleep(timeout.unwrap_or_default()));
+        TimedStream {
+            inner,
+            timeout,
+            idle,
+        }
+    }
+
+    fn progressed(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.idle.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    // Turns a pending read or write into an error once the connection has
+    // been idle too long
+    fn check_idle<T>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        match self.timeout {
+            Some(timeout) if self.idle.as_mut().poll(cx).is_ready() => {
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "no data from the storage service for {} (oss.ReadTimeout)",
+                        crate::units::format_duration(timeout)
+                    ),
+                )))
+            }
+            _ => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for TimedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                self.progressed();
+                Poll::Ready(result)
+            }
+            Poll::Pending => self.check_idle(cx),
+        }
+    }
+}
+
+impl AsyncWrite for TimedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                self.progressed();
+                Poll::Ready(result)
+            }
+            Poll::Pending => self.check_idle(cx),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Connection for TimedStream {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+const SIZE_UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+
+/// Formats a byte count for people: `512 bytes`, `1.50 KB`, `3.20 GB`, ...
+/// (binary multiples, as elsewhere in the output).
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, SIZE_UNITS[unit])
+}
+
+/// Parses a byte count such as `500k`, `2M`, `1.5GB` or `4096`. Suffixes are
+/// case-insensitive binary multiples; a trailing `b`/`ib` is allowed.
+pub fn parse_size(value: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {}", value))?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let multiplier: u64 = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return Err(format!("Invalid size unit in {} (use k, M, G or T)", value).into()),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration such as `90s`, `15m`, `36h`, `7d`, `2w` or a combination
+/// like `1h30m`. A bare number is seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, Box<dyn std::error::Error>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("Invalid duration: empty".into());
+    }
+
+    let mut seconds = 0u64;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("Invalid duration: {}", value))?;
+        let multiplier = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 7 * 86400,
+            _ => {
+                return Err(
+                    format!("Invalid duration unit in {} (use s, m, h, d or w)", value).into(),
+                )
+            }
+        };
+        seconds = number
+            .checked_mul(multiplier)
+            .and_then(|part| seconds.checked_add(part))
+            .ok_or_else(|| format!("Invalid duration: {} is too long", value))?;
+        rest = tail;
+    }
+
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Formats a duration with its two largest units, e.g. `36h` → `1d 12h`.
+pub fn format_duration(duration: Duration) -> String {
+    let mut remaining = duration.as_secs();
+    let mut parts = Vec::new();
+    for (unit, seconds) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if remaining >= seconds && parts.len() < 2 {
+            parts.push(format!("{}{}", remaining / seconds, unit));
+            remaining %= seconds;
+        }
+    }
+
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}